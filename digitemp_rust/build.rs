@@ -0,0 +1,85 @@
+use std::path::PathBuf;
+
+// The CLI definition is shared with the binary. Its value parsers only matter at run time,
+// so the crate modules it refers to are stubbed here.
+#[allow(dead_code)]
+#[path = "src/cli.rs"]
+mod cli;
+
+mod config {
+    use std::time::Duration;
+
+    #[derive(Clone, Copy)]
+    pub struct Unit;
+
+    impl std::str::FromStr for Unit {
+        type Err = String;
+
+        fn from_str(_: &str) -> Result<Self, Self::Err> {
+            Ok(Unit)
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    pub struct CsvColumn;
+
+    impl std::str::FromStr for CsvColumn {
+        type Err = String;
+
+        fn from_str(_: &str) -> Result<Self, Self::Err> {
+            Ok(CsvColumn)
+        }
+    }
+
+    pub fn parse_duration(_: &str) -> Result<Duration, String> {
+        Ok(Duration::ZERO)
+    }
+}
+
+mod color {
+    #[derive(Clone, Copy)]
+    pub struct ColorChoice;
+
+    impl std::str::FromStr for ColorChoice {
+        type Err = String;
+
+        fn from_str(_: &str) -> Result<Self, Self::Err> {
+            Ok(ColorChoice)
+        }
+    }
+}
+
+// Render the man page to $OUT_DIR/digitemp_native.1, plus one page per subcommand
+fn main() -> std::io::Result<()> {
+    println!("cargo:rerun-if-changed=src/cli.rs");
+
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo"));
+    #[cfg(feature = "grpc")]
+    grpc_service(&out_dir);
+    let command = cli::build().name(cli::BIN_NAME);
+    clap_mangen::generate_to(command, &out_dir)
+}
+
+// Server code of the gRPC service in proto/digitemp.proto, to $OUT_DIR/digitemp.v1.Sensors.rs.
+// The messages are written by hand in src/grpc.rs, so no protoc is needed to build.
+#[cfg(feature = "grpc")]
+fn grpc_service(out_dir: &std::path::Path) {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    let method = |name: &str, route: &str, input: &str, output: &str| Method::builder()
+        .name(name)
+        .route_name(route)
+        .input_type(format!("crate::grpc::{}", input))
+        .output_type(format!("crate::grpc::{}", output))
+        .codec_path("tonic_prost::ProstCodec");
+    let service = Service::builder()
+        .name("Sensors")
+        .package("digitemp.v1")
+        .method(method("list_sensors", "ListSensors", "ListSensorsRequest", "ListSensorsResponse").build())
+        .method(method("get_reading", "GetReading", "GetReadingRequest", "Sensor").build())
+        .method(method("stream_readings", "StreamReadings", "StreamReadingsRequest", "ReadingEvent")
+            .server_streaming().build())
+        .method(method("trigger_scan", "TriggerScan", "TriggerScanRequest", "TriggerScanResponse").build())
+        .build();
+    Builder::new().build_client(false).out_dir(out_dir).compile(&[service]);
+}
@@ -0,0 +1,479 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::config::{format_duration, AlarmConfig};
+use crate::state::RaisedAlarm;
+
+// What an alarm is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlarmKind {
+    High,
+    Low,
+    // Warming or cooling faster than a rate
+    Rise,
+    Fall,
+    // No good reading for a while
+    Stale,
+    // Not answering on the bus
+    Missing,
+}
+
+impl AlarmKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            AlarmKind::High => "high",
+            AlarmKind::Low => "low",
+            AlarmKind::Rise => "rise",
+            AlarmKind::Fall => "fall",
+            AlarmKind::Stale => "stale",
+            AlarmKind::Missing => "missing",
+        }
+    }
+
+    // Unit of the threshold
+    pub fn unit(self) -> &'static str {
+        match self {
+            AlarmKind::High | AlarmKind::Low => "°C",
+            AlarmKind::Rise | AlarmKind::Fall => "°C/min",
+            AlarmKind::Stale | AlarmKind::Missing => "s",
+        }
+    }
+}
+
+// What happened to an alarm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlarmState {
+    Raised,
+    // Still raised and not acknowledged after `repeat`, the how-manieth time
+    Repeated(u32),
+    // Not acknowledged for `escalate_after`, passed on to the escalation sinks
+    Escalated,
+    Cleared,
+}
+
+impl AlarmState {
+    pub fn name(self) -> &'static str {
+        match self {
+            AlarmState::Raised => "raised",
+            AlarmState::Repeated(_) => "repeated",
+            AlarmState::Escalated => "escalated",
+            AlarmState::Cleared => "cleared",
+        }
+    }
+}
+
+// An alarm raised, notified again or cleared, with the reading that did it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlarmEvent {
+    pub kind: AlarmKind,
+    pub state: AlarmState,
+    // The reading that raised or cleared it; the last good one when repeated or escalated
+    pub celsius: Option<f32>,
+    // °C per minute over the window, for rise and fall alarms
+    pub rate: Option<f32>,
+    // In the kind's unit, as it was when the alarm was raised; none for missing alarms
+    pub threshold: Option<f32>,
+    // How long the alarm has been raised, once repeated, escalated or cleared
+    pub lasted: Option<Duration>,
+    // Passed on to the escalation sinks before, or now
+    pub escalated: bool,
+}
+
+impl AlarmEvent {
+    // Still raised after this
+    pub fn raised(&self) -> bool {
+        self.state != AlarmState::Cleared
+    }
+
+    // e.g. "rise alarm raised at 4.50 °C, +0.62 °C/min (threshold 0.50 °C/min)"
+    pub fn describe(&self) -> String {
+        let state = match self.state {
+            AlarmState::Repeated(_) => "still raised",
+            state => state.name(),
+        };
+        let mut text = format!("{} alarm {}", self.kind.name(), state);
+        if let Some(celsius) = self.celsius {
+            text.push_str(&format!(" at {:.2} °C", celsius));
+        }
+        if let Some(rate) = self.rate {
+            text.push_str(&format!(", {:+.2} °C/min", rate));
+        }
+        let raised = self.state == AlarmState::Raised;
+        match (self.kind, self.threshold) {
+            (AlarmKind::Stale, Some(threshold)) if raised => text.push_str(&format!(
+                ", no good reading for {}", format_duration(Duration::from_secs(threshold as u64)))),
+            (AlarmKind::Missing, _) if raised => text.push_str(", not answering on the bus"),
+            (AlarmKind::Stale | AlarmKind::Missing, _) => {}
+            (kind, Some(threshold)) => text.push_str(&format!(" (threshold {:.2} {})", threshold, kind.unit())),
+            (_, None) => {}
+        }
+        if let Some(lasted) = self.lasted {
+            text.push_str(&format!(" after {}", format_duration(Duration::from_secs(lasted.as_secs()))));
+        }
+        if let AlarmState::Repeated(repeat) = self.state {
+            text.push_str(&format!(", repeat {}", repeat));
+        }
+        text
+    }
+}
+
+// An alarm while raised
+#[derive(Debug)]
+struct Raised {
+    kind: AlarmKind,
+    // As it was when raised
+    threshold: Option<f32>,
+    since: Instant,
+    // Raised or repeated last
+    notified: Instant,
+    repeats: u32,
+    escalated: bool,
+    acknowledged: bool,
+}
+
+impl Raised {
+    fn new(kind: AlarmKind, threshold: Option<f32>, since: Instant) -> Self {
+        Raised { kind, threshold, since, notified: since, repeats: 0, escalated: false, acknowledged: false }
+    }
+
+    fn event(&self, state: AlarmState, lasted: Option<Duration>) -> AlarmEvent {
+        AlarmEvent {
+            kind: self.kind,
+            state,
+            celsius: None,
+            rate: None,
+            threshold: self.threshold,
+            lasted,
+            escalated: self.escalated,
+        }
+    }
+}
+
+// One alarm of a sensor: high or low, or rise or fall, which exclude each other; or stale,
+// or missing
+#[derive(Debug, Default)]
+struct Machine {
+    raised: Option<Raised>,
+    // The other state the readings have been in since then, until `min_duration` has passed
+    pending: Option<(Option<AlarmKind>, Instant)>,
+}
+
+impl Machine {
+    fn raised(&self) -> Option<AlarmKind> {
+        self.raised.as_ref().map(|raised| raised.kind)
+    }
+
+    // Move towards `target` once it has held for `min_duration`. From one alarm straight to
+    // the other, the first is cleared, and the other raised after `min_duration` from the
+    // next reading.
+    fn update(&mut self, target: Option<AlarmKind>, alarm: Option<&AlarmConfig>, min_duration: Duration,
+              now: Instant) -> Option<AlarmEvent> {
+        if target == self.raised() {
+            self.pending = None;
+            return None;
+        }
+        let since = match self.pending {
+            Some((pending, since)) if pending == target => since,
+            _ => now,
+        };
+        if now.duration_since(since) < min_duration {
+            self.pending = Some((target, since));
+            return None;
+        }
+        self.pending = None;
+        if let Some(raised) = self.raised.take() {
+            return Some(raised.event(AlarmState::Cleared, Some(now.duration_since(raised.since))));
+        }
+        let kind = target?;
+        let raised = Raised::new(kind, threshold(alarm?, kind), now);
+        let event = raised.event(AlarmState::Raised, None);
+        self.raised = Some(raised);
+        Some(event)
+    }
+
+    // Escalate a raised alarm nobody acknowledged within `escalate_after`, and notify it again
+    // every `repeat`, `max_repeats` times at most
+    fn remind(&mut self, alarm: &AlarmConfig, now: Instant) -> Option<AlarmEvent> {
+        let raised = self.raised.as_mut().filter(|raised| !raised.acknowledged)?;
+        let lasted = now.duration_since(raised.since);
+        if alarm.escalate_after.is_some_and(|after| lasted >= after) && !raised.escalated {
+            raised.escalated = true;
+            return Some(raised.event(AlarmState::Escalated, Some(lasted)));
+        }
+        let repeat = alarm.repeat.filter(|&repeat| now.duration_since(raised.notified) >= repeat)?;
+        if alarm.max_repeats.is_some_and(|max| raised.repeats >= max) || repeat.is_zero() {
+            return None;
+        }
+        raised.repeats += 1;
+        raised.notified = now;
+        Some(raised.event(AlarmState::Repeated(raised.repeats), Some(lasted)))
+    }
+}
+
+#[derive(Debug)]
+struct SensorAlarm {
+    level: Machine,
+    rate: Machine,
+    stale: Machine,
+    missing: Machine,
+    // Readings of the last `window`, and the one before, for the rate
+    samples: VecDeque<(Instant, f32)>,
+    // The last good reading, or when the sensor was first read
+    good: Instant,
+    // and what it read, for repeats
+    celsius: Option<f32>,
+}
+
+// Software alarms, evaluated on every reading of a sensor. A high or low alarm is raised once
+// its readings have been at or past its threshold for `min_duration`, and cleared once they
+// have been back by `hysteresis` for as long, so readings hovering at a threshold do not raise
+// and clear it over and over. A rise or fall alarm is about the slope of the readings over
+// `window`, and is cleared once the slope is back below it. Failed readings leave these as
+// they are, and raise the stale alarm once they have gone on for `stale`, and the missing
+// alarm once the sensor has not answered on the bus for `min_duration`. Both are cleared by
+// the next good reading.
+#[derive(Debug, Default)]
+pub struct AlarmTracker {
+    sensors: HashMap<[u8; 8], SensorAlarm>,
+}
+
+impl AlarmTracker {
+    // A sensor without an alarm any more, after a reload, has its raised alarms cleared
+    pub fn reading(&mut self, rom: &[u8; 8], alarm: Option<&AlarmConfig>, celsius: f32, now: Instant)
+                   -> Vec<AlarmEvent> {
+        let state = self.state(rom, now);
+        state.good = now;
+        state.celsius = Some(celsius);
+        let rate = alarm.and_then(|alarm| state.rate(alarm, celsius, now));
+        let level = alarm.and_then(|alarm| beyond(alarm, state.level.raised(), celsius));
+        let slope = alarm.zip(rate).and_then(|(alarm, rate)| too_fast(alarm, rate));
+        // Without enough readings for a slope yet, a raised rise or fall alarm stays
+        let slope = if alarm.is_some() && rate.is_none() { state.rate.raised() } else { slope };
+        let min_duration = alarm.map_or(Duration::ZERO, |alarm| alarm.min_duration);
+        let events = [
+            state.missing.update(None, alarm, Duration::ZERO, now),
+            state.stale.update(None, alarm, Duration::ZERO, now),
+            state.level.update(level, alarm, min_duration, now),
+            state.rate.update(slope, alarm, min_duration, now).map(|event| AlarmEvent { rate, ..event }),
+        ];
+        events.into_iter().flatten().map(|event| AlarmEvent { celsius: Some(celsius), ..event }).collect()
+    }
+
+    // A failed or discarded reading; `absent` when the sensor did not answer on the bus
+    pub fn failed(&mut self, rom: &[u8; 8], alarm: Option<&AlarmConfig>, absent: bool, now: Instant)
+                  -> Vec<AlarmEvent> {
+        let state = self.state(rom, now);
+        let stale = state.stale(alarm, now);
+        // Other errors say nothing about whether the sensor is there
+        let missing = match alarm {
+            Some(alarm) if alarm.missing && absent => Some(AlarmKind::Missing),
+            Some(alarm) if alarm.missing => state.missing.raised(),
+            _ => None,
+        };
+        let min_duration = alarm.map_or(Duration::ZERO, |alarm| alarm.min_duration);
+        let events = [stale, state.missing.update(missing, alarm, min_duration, now)];
+        events.into_iter().flatten().collect()
+    }
+
+    // An alarm raised before a restart, `age` ago; it is not raised again, and is cleared as
+    // if this run had raised it. Its next repeat is a full `repeat` away.
+    pub fn restore(&mut self, rom: &[u8; 8], alarm: &RaisedAlarm, age: Duration) {
+        let now = Instant::now();
+        let state = self.state(rom, now);
+        let mut raised = Raised::new(alarm.kind, alarm.threshold, now.checked_sub(age).unwrap_or(now));
+        raised.notified = now;
+        raised.repeats = alarm.repeats;
+        raised.escalated = alarm.escalated;
+        raised.acknowledged = alarm.acknowledged;
+        state.machine(alarm.kind).raised = Some(raised);
+    }
+
+    // Repeats and escalations due for a sensor's raised alarms, after its reading this pass
+    pub fn reminders(&mut self, rom: &[u8; 8], alarm: Option<&AlarmConfig>, now: Instant) -> Vec<AlarmEvent> {
+        let (Some(alarm), Some(state)) = (alarm, self.sensors.get_mut(rom)) else { return Vec::new() };
+        let celsius = state.celsius;
+        [&mut state.level, &mut state.rate, &mut state.stale, &mut state.missing].into_iter()
+            .filter_map(|machine| machine.remind(alarm, now))
+            .map(|event| AlarmEvent { celsius, ..event })
+            .collect()
+    }
+
+    // Stop repeating and escalating a sensor's raised alarms until they clear; the kinds
+    // acknowledged
+    pub fn acknowledge(&mut self, rom: &[u8; 8]) -> Vec<AlarmKind> {
+        let Some(state) = self.sensors.get_mut(rom) else { return Vec::new() };
+        [&mut state.level, &mut state.rate, &mut state.stale, &mut state.missing].into_iter()
+            .filter_map(|machine| machine.raised.as_mut().filter(|raised| !raised.acknowledged))
+            .map(|raised| {
+                raised.acknowledged = true;
+                raised.kind
+            })
+            .collect()
+    }
+
+    // A sensor the health tracker has backed off after failing, not read this pass
+    pub fn backed_off(&mut self, rom: &[u8; 8], alarm: Option<&AlarmConfig>, now: Instant) -> Option<AlarmEvent> {
+        self.state(rom, now).stale(alarm, now)
+    }
+
+    fn state(&mut self, rom: &[u8; 8], now: Instant) -> &mut SensorAlarm {
+        self.sensors.entry(*rom).or_insert_with(|| SensorAlarm {
+            level: Machine::default(),
+            rate: Machine::default(),
+            stale: Machine::default(),
+            missing: Machine::default(),
+            samples: VecDeque::new(),
+            good: now,
+            celsius: None,
+        })
+    }
+}
+
+impl SensorAlarm {
+    fn machine(&mut self, kind: AlarmKind) -> &mut Machine {
+        match kind {
+            AlarmKind::High | AlarmKind::Low => &mut self.level,
+            AlarmKind::Rise | AlarmKind::Fall => &mut self.rate,
+            AlarmKind::Stale => &mut self.stale,
+            AlarmKind::Missing => &mut self.missing,
+        }
+    }
+
+    fn stale(&mut self, alarm: Option<&AlarmConfig>, now: Instant) -> Option<AlarmEvent> {
+        let stale = alarm.and_then(|alarm| alarm.stale)
+            .is_some_and(|stale| now.duration_since(self.good) >= stale)
+            .then_some(AlarmKind::Stale);
+        self.stale.update(stale, alarm, Duration::ZERO, now)
+    }
+
+    // The least-squares slope of the readings over the window, in °C per minute; none until
+    // they span it. Only kept for alarms with a rise or fall.
+    fn rate(&mut self, alarm: &AlarmConfig, celsius: f32, now: Instant) -> Option<f32> {
+        if alarm.rise.is_none() && alarm.fall.is_none() {
+            self.samples.clear();
+            return None;
+        }
+        self.samples.push_back((now, celsius));
+        while self.samples.get(1).is_some_and(|&(at, _)| now.duration_since(at) >= alarm.window) {
+            self.samples.pop_front();
+        }
+        let &(first, _) = self.samples.front()?;
+        if self.samples.len() < 2 || now.duration_since(first) < alarm.window {
+            return None;
+        }
+        let minutes: Vec<(f64, f64)> = self.samples.iter()
+            .map(|&(at, celsius)| (at.duration_since(first).as_secs_f64() / 60.0, celsius as f64))
+            .collect();
+        let n = minutes.len() as f64;
+        let (mean_t, mean_c) = minutes.iter().fold((0.0, 0.0), |(t, c), &(mt, mc)| (t + mt / n, c + mc / n));
+        let (covariance, variance) = minutes.iter().fold((0.0, 0.0), |(cov, var), &(t, c)| {
+            (cov + (t - mean_t) * (c - mean_c), var + (t - mean_t) * (t - mean_t))
+        });
+        (variance > 0.0).then(|| (covariance / variance) as f32)
+    }
+}
+
+// The high or low alarm the reading calls for; a raised one stays until the reading is back
+// by the hysteresis
+fn beyond(alarm: &AlarmConfig, raised: Option<AlarmKind>, celsius: f32) -> Option<AlarmKind> {
+    let margin = |kind| if raised == Some(kind) { alarm.hysteresis } else { 0.0 };
+    if alarm.high.is_some_and(|high| celsius >= high - margin(AlarmKind::High)) {
+        Some(AlarmKind::High)
+    } else if alarm.low.is_some_and(|low| celsius <= low + margin(AlarmKind::Low)) {
+        Some(AlarmKind::Low)
+    } else {
+        None
+    }
+}
+
+// The rise or fall alarm the slope calls for
+fn too_fast(alarm: &AlarmConfig, rate: f32) -> Option<AlarmKind> {
+    if alarm.rise.is_some_and(|rise| rate >= rise) {
+        Some(AlarmKind::Rise)
+    } else if alarm.fall.is_some_and(|fall| -rate >= fall) {
+        Some(AlarmKind::Fall)
+    } else {
+        None
+    }
+}
+
+// In the kind's unit; seconds for stale
+fn threshold(alarm: &AlarmConfig, kind: AlarmKind) -> Option<f32> {
+    match kind {
+        AlarmKind::High => alarm.high,
+        AlarmKind::Low => alarm.low,
+        AlarmKind::Rise => alarm.rise,
+        AlarmKind::Fall => alarm.fall,
+        AlarmKind::Stale => alarm.stale.map(|stale| stale.as_secs_f32()),
+        AlarmKind::Missing => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 8] = [0x28, 1, 2, 3, 4, 5, 6, 7];
+
+    fn alarm(toml: &str) -> AlarmConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    // The kind and state of each event of a reading `secs` after `start`
+    fn read(tracker: &mut AlarmTracker, alarm: &AlarmConfig, start: Instant, secs: u64, celsius: f32)
+            -> Vec<(AlarmKind, AlarmState)> {
+        tracker.reading(&ROM, Some(alarm), celsius, start + Duration::from_secs(secs)).iter()
+            .map(|event| (event.kind, event.state))
+            .collect()
+    }
+
+    #[test]
+    fn hysteresis() {
+        let alarm = alarm("high = 30.0\nhysteresis = 1.0");
+        let (mut tracker, start) = (AlarmTracker::default(), Instant::now());
+        assert_eq!(read(&mut tracker, &alarm, start, 0, 29.9), []);
+        assert_eq!(read(&mut tracker, &alarm, start, 1, 30.0), [(AlarmKind::High, AlarmState::Raised)]);
+        // Back below the threshold, but not by the hysteresis
+        assert_eq!(read(&mut tracker, &alarm, start, 2, 29.5), []);
+        assert_eq!(read(&mut tracker, &alarm, start, 3, 29.0), []);
+        assert_eq!(read(&mut tracker, &alarm, start, 4, 28.9), [(AlarmKind::High, AlarmState::Cleared)]);
+        // Cleared, the threshold itself counts again
+        assert_eq!(read(&mut tracker, &alarm, start, 5, 29.5), []);
+        assert_eq!(read(&mut tracker, &alarm, start, 6, 30.2), [(AlarmKind::High, AlarmState::Raised)]);
+    }
+
+    #[test]
+    fn min_duration() {
+        let alarm = alarm("low = 5.0\nmin_duration = \"60s\"");
+        let (mut tracker, start) = (AlarmTracker::default(), Instant::now());
+        assert_eq!(read(&mut tracker, &alarm, start, 0, 4.0), []);
+        // A reading back in range starts the wait over
+        assert_eq!(read(&mut tracker, &alarm, start, 30, 6.0), []);
+        assert_eq!(read(&mut tracker, &alarm, start, 60, 4.0), []);
+        assert_eq!(read(&mut tracker, &alarm, start, 119, 4.0), []);
+        assert_eq!(read(&mut tracker, &alarm, start, 120, 4.0), [(AlarmKind::Low, AlarmState::Raised)]);
+        // Clearing waits as long
+        assert_eq!(read(&mut tracker, &alarm, start, 130, 8.0), []);
+        let events = tracker.reading(&ROM, Some(&alarm), 8.0, start + Duration::from_secs(190));
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].kind, events[0].state), (AlarmKind::Low, AlarmState::Cleared));
+        assert_eq!(events[0].lasted, Some(Duration::from_secs(70)));
+        assert_eq!(events[0].celsius, Some(8.0));
+    }
+
+    #[test]
+    fn clear_then_raise() {
+        let alarm = alarm("high = 30.0\nlow = 10.0\nmin_duration = \"60s\"");
+        let (mut tracker, start) = (AlarmTracker::default(), Instant::now());
+        assert_eq!(read(&mut tracker, &alarm, start, 0, 35.0), []);
+        assert_eq!(read(&mut tracker, &alarm, start, 60, 35.0), [(AlarmKind::High, AlarmState::Raised)]);
+        // Straight from high to low: high is cleared once low has held, and low is raised only
+        // after another `min_duration` from the next reading
+        assert_eq!(read(&mut tracker, &alarm, start, 100, 5.0), []);
+        assert_eq!(read(&mut tracker, &alarm, start, 160, 5.0), [(AlarmKind::High, AlarmState::Cleared)]);
+        assert_eq!(read(&mut tracker, &alarm, start, 170, 5.0), []);
+        assert_eq!(read(&mut tracker, &alarm, start, 229, 5.0), []);
+        assert_eq!(read(&mut tracker, &alarm, start, 230, 5.0), [(AlarmKind::Low, AlarmState::Raised)]);
+    }
+}
@@ -0,0 +1,609 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use crate::alarm;
+use crate::bus::Buses;
+use crate::color::{self, Level};
+use crate::config::{family_name, format_duration, format_rom, Config, SensorConfig};
+use crate::prometheus::{self, write_family, Exposition};
+use crate::stats::BusStats;
+use crate::{json, systemd, websocket, OneWireError, Reading};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// An immediate read waits for the pass in progress, if any, and the conversion
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+// Longest request line and header line accepted
+const MAX_LINE: usize = 8192;
+// Idle /events streams get a comment this often
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const METRICS_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// A configured sensor with its last reading, as returned by /sensors and /sensors/{id}
+#[derive(Clone, Serialize)]
+pub struct SensorView {
+    pub sensor: usize,
+    pub rom: String,
+    pub family: &'static str,
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub bus: String,
+    pub enabled: bool,
+    offset: f32,
+    gain: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interval: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schedule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    freshness: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warn: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crit: Option<f32>,
+    // Last accepted reading (°C) and when it was taken; none until the first one
+    pub celsius: Option<f64>,
+    pub raw_celsius: Option<f64>,
+    pub timestamp: Option<String>,
+    // Seconds since the last accepted reading, when the view was taken
+    pub age: Option<f64>,
+    #[serde(skip)]
+    read_at: Option<Instant>,
+    // Why the last read failed or was discarded, until the next accepted one
+    pub error: Option<String>,
+    pub reads: u64,
+    pub errors: u64,
+    // Where `serve --modbus` exposes the temperature
+    #[serde(skip)]
+    pub register: Option<u16>,
+    // Against warn/crit of the last accepted reading, for alarm events
+    #[serde(skip)]
+    level: Option<Level>,
+    // The software alarms raised: high or low, rise or fall, stale, missing
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    alarms: Vec<&'static str>,
+}
+
+// An event for streaming clients: its name (reading, error, alarm, raised or cleared) and JSON record
+pub type Event = (&'static str, String);
+
+// An open stream, such as a /ws or /events connection. Each is sent readings and errors, and
+// alarm changes and software alarms when it asked for them.
+struct Subscriber {
+    alarms: bool,
+    sender: Sender<Event>,
+}
+
+// What a client asks of the read loop, which owns the buses
+#[derive(Clone)]
+pub enum Action {
+    // Read a sensor, given as on the command line, at once
+    Read(String),
+    // Search every bus for devices
+    Scan,
+    // Take a pass at once, answered when it is over
+    Cycle,
+    // Skip passes, with the ports closed, until resumed; and take them up again
+    Pause,
+    Resume,
+    TogglePause,
+    // Re-read the config file, as SIGHUP does
+    Reload,
+    // Stop repeating and escalating a sensor's raised alarms
+    Ack(String),
+}
+
+// An action asked for by a client, answered with a status and a JSON body
+pub struct Request {
+    pub action: Action,
+    reply: Sender<(u16, String)>,
+}
+
+impl Request {
+    // Have the read loop carry out an action. A read is answered as /read/{id} is; a scan
+    // with the `scan --json` record.
+    pub fn send(sender: &Sender<Request>, action: Action) -> (u16, String) {
+        let (reply, answer) = mpsc::channel();
+        if sender.send(Request { action, reply }).is_err() {
+            return (503, message("the read loop has stopped"));
+        }
+        answer.recv_timeout(READ_TIMEOUT).unwrap_or_else(|_| (504, message("the read did not finish in time")))
+    }
+
+    pub fn reply(self, status: u16, body: String) {
+        // The client may have given up waiting
+        let _ = self.reply.send((status, body));
+    }
+}
+
+// Health of the read loop itself, for /metrics
+struct DaemonMetrics {
+    // Unix time the server started
+    started: i64,
+    passes: u64,
+    // Unix time and duration of the last completed pass
+    last_pass: Option<(i64, Duration)>,
+    // Counters of each bus since its adapter was opened; none for a bus that failed to open
+    buses: Vec<(String, Option<BusStats>)>,
+}
+
+// The digitemp-style line of a reading: the output template and decimals in effect
+#[derive(Clone, Default)]
+pub struct LineFormat {
+    pub template: String,
+    pub decimals: Option<usize>,
+}
+
+struct Shared {
+    views: Vec<SensorView>,
+    metrics: Exposition,
+    daemon: DaemonMetrics,
+    subscribers: Vec<Subscriber>,
+    line_format: LineFormat,
+}
+
+// Shared between the read loop, which records readings through the api sink, and the
+// connection threads
+#[derive(Clone)]
+pub struct ApiSink {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl ApiSink {
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        // A panicked connection thread leaves the state usable
+        self.shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn update(&self, index: usize, sensor: &SensorConfig, update: impl FnOnce(&mut SensorView)) {
+        let mut shared = self.lock();
+        if let Some(view) = shared.views.iter_mut()
+                .find(|view| view.sensor == index && view.rom == format_rom(&sensor.rom)) {
+            update(view);
+        }
+    }
+
+    pub fn reading(&self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading) {
+        self.lock().metrics.reading(bus, index, sensor, reading.celsius);
+        let level = color::level(sensor, reading.celsius);
+        let mut alarm = None;
+        self.update(index, sensor, |view| {
+            view.celsius = Some(json::round(reading.celsius, Some(4)));
+            view.raw_celsius = Some(json::round(reading.raw_celsius, Some(4)));
+            view.timestamp = Some(json::timestamp());
+            view.read_at = Some(Instant::now());
+            view.error = None;
+            view.reads += 1;
+            // A sensor already above a threshold on its first reading counts as a change
+            if level != view.level && (view.level.is_some() || level != Some(Level::Normal)) {
+                alarm = Some(view.level);
+            }
+            view.level = level;
+        });
+        // Events follow the update, so a client looking up the sensor finds the reading
+        self.broadcast("reading", &json::jsonl_reading(bus, index, sensor, reading, Some(4)));
+        if let (Some(previous), Some(level)) = (alarm, level) {
+            self.broadcast("alarm", &alarm_event(index, sensor, reading.celsius, level, previous));
+        }
+    }
+
+    // A failed reading, or a discarded one without error
+    pub fn error(&self, bus: &str, index: usize, sensor: &SensorConfig, error: Option<&OneWireError>) {
+        self.lock().metrics.error(bus, index, sensor);
+        self.update(index, sensor, |view| {
+            view.error = Some(error.map_or_else(|| "suspect reading discarded".to_string(), |e| e.to_string()));
+            view.reads += 1;
+            view.errors += 1;
+        });
+        self.broadcast("error", &match error {
+            Some(e) => json::error(index, sensor, json::error_kind(e), e.to_string()),
+            None => json::error(index, sensor, "suspect", "suspect reading discarded".to_string()),
+        });
+    }
+
+    // A software alarm raised, notified again or cleared, shown in /sensors while raised
+    pub fn alarm(&self, index: usize, sensor: &SensorConfig, event: &alarm::AlarmEvent) {
+        self.mark_alarm(index, sensor, event.kind, event.raised());
+        self.broadcast(event.state.name(), &json::alarm(index, sensor, event));
+    }
+
+    // An alarm still raised from before a restart, shown without an event
+    pub fn restore_alarm(&self, index: usize, sensor: &SensorConfig, kind: alarm::AlarmKind) {
+        self.mark_alarm(index, sensor, kind, true);
+    }
+
+    fn mark_alarm(&self, index: usize, sensor: &SensorConfig, kind: alarm::AlarmKind, raised: bool) {
+        self.update(index, sensor, |view| {
+            view.alarms.retain(|name| *name != kind.name());
+            if raised {
+                view.alarms.push(kind.name());
+            }
+        });
+    }
+
+    // Hand an event to every stream, forgetting the closed ones
+    fn broadcast(&self, event: &'static str, data: &str) {
+        let alarm = matches!(event, "alarm" | "raised" | "repeated" | "escalated" | "cleared");
+        self.lock().subscribers.retain(|subscriber| {
+            (alarm && !subscriber.alarms) || subscriber.sender.send((event, data.to_string())).is_ok()
+        });
+    }
+
+    // Events from now on, until the receiver is dropped
+    pub fn subscribe(&self, alarms: bool) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        self.lock().subscribers.push(Subscriber { alarms, sender });
+        receiver
+    }
+
+    // The configured sensors with their last readings
+    pub fn views(&self) -> Vec<SensorView> {
+        let mut views = self.lock().views.clone();
+        for view in &mut views {
+            view.age = view.read_at.map(|at| json::round(at.elapsed().as_secs_f32(), Some(1)));
+        }
+        views
+    }
+
+    // How `serve --tcp` answers a READ
+    pub fn line_format(&self) -> LineFormat {
+        self.lock().line_format.clone()
+    }
+
+    // The /metrics body: the textfile sink's sensor metrics, then the health of the daemon
+    fn metrics(&self) -> String {
+        let shared = self.lock();
+        let mut content = shared.metrics.render();
+        let daemon = &shared.daemon;
+        let single = |value: String| [(String::new(), value)];
+        write_family(&mut content, "ds18b20_start_time_seconds", "gauge", "Unix time the daemon started.",
+            &single(daemon.started.to_string()));
+        write_family(&mut content, "ds18b20_passes_total", "counter", "Read passes completed.",
+            &single(daemon.passes.to_string()));
+        if let Some((timestamp, duration)) = daemon.last_pass {
+            write_family(&mut content, "ds18b20_last_pass_timestamp_seconds", "gauge",
+                "Unix time the last read pass completed.", &single(timestamp.to_string()));
+            write_family(&mut content, "ds18b20_last_pass_duration_seconds", "gauge",
+                "How long the last read pass took.", &single(duration.as_secs_f64().to_string()));
+        }
+
+        let label = |bus: &str| format!("bus=\"{}\"", prometheus::escape(bus));
+        let up: Vec<(String, String)> = daemon.buses.iter()
+            .map(|(bus, stats)| (label(bus), u8::from(stats.is_some()).to_string())).collect();
+        write_family(&mut content, "ds18b20_bus_up", "gauge", "Whether the adapter of the bus is open.", &up);
+        let mut counter = |name: &str, help: &str, value: &dyn Fn(&BusStats) -> String| {
+            let samples: Vec<(String, String)> = daemon.buses.iter()
+                .filter_map(|(bus, stats)| stats.as_ref().map(|stats| (label(bus), value(stats)))).collect();
+            write_family(&mut content, &format!("ds18b20_bus_{}_total", name), "counter",
+                &format!("{} since the adapter was opened.", help), &samples);
+        };
+        counter("resets", "Bus resets", &|stats| stats.resets.to_string());
+        counter("presence_failures", "Resets no device answered", &|stats| stats.presence_failures.to_string());
+        counter("crc_errors", "Scratchpad and ROM CRC errors", &|stats| stats.crc_errors.to_string());
+        counter("retries", "Reads retried after a CRC error", &|stats| stats.retries.to_string());
+        counter("reads", "Sensor reads on the bus", &|stats| stats.reads.to_string());
+        counter("failed_reads", "Sensor reads that failed", &|stats| stats.failed_reads.to_string());
+        counter("read_seconds", "Time spent reading sensors",
+            &|stats| (stats.read_time_ms as f64 / 1000.0).to_string());
+        content
+    }
+}
+
+// Built-in HTTP server of `serve`: JSON over HTTP/1.1, one request per connection, each on
+// its own thread. Sensors can be given by index, ROM or label, as on the command line.
+//   GET /sensors           every configured sensor with its last reading
+//   GET /sensors/{id}      one sensor
+//   GET|POST /read/{id}    read the sensor now and return it as /sensors/{id} does
+//   POST /scan             search every bus and return what `scan --json` prints
+//   GET /metrics           sensor and daemon metrics in the Prometheus text format
+//   GET /ws                WebSocket with a JSON message for every new reading or error
+//   GET /events            Server-Sent Events: readings, errors, warn/crit alarm changes and software alarms
+pub struct Server {
+    sink: ApiSink,
+    sender: Sender<Request>,
+    requests: Receiver<Request>,
+}
+
+impl Server {
+    // Without an address no HTTP port is opened, for other servers such as the control socket
+    pub fn start(address: Option<&str>, config: &Config) -> Result<Self, String> {
+        let listener = address
+            .map(|address| systemd::activated_tcp(address, "http").unwrap_or_else(|| {
+                TcpListener::bind(address).map_err(|e| format!("Cannot listen on {}: {}", address, e))
+            }))
+            .transpose()?;
+        let sink = ApiSink { shared: Arc::new(Mutex::new(Shared {
+            views: Vec::new(),
+            metrics: Exposition::default(),
+            daemon: DaemonMetrics {
+                started: chrono::Utc::now().timestamp(),
+                passes: 0,
+                last_pass: None,
+                buses: Vec::new(),
+            },
+            subscribers: Vec::new(),
+            line_format: LineFormat::default(),
+        })) };
+        let (sender, requests) = mpsc::channel();
+        let server = Server { sink: sink.clone(), sender: sender.clone(), requests };
+        server.configure(config);
+
+        let Some(listener) = listener else { return Ok(server) };
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let (sink, sender) = (sink.clone(), sender.clone());
+                thread::spawn(move || {
+                    if let Err(e) = handle(stream, &sink, &sender) {
+                        // Clients that hang up or time out are not worth a message
+                        if !matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                                | io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset) {
+                            eprintln!("HTTP: {}", e);
+                        }
+                    }
+                });
+            }
+        });
+        Ok(server)
+    }
+
+    pub fn sink(&self) -> ApiSink {
+        self.sink.clone()
+    }
+
+    // For other servers that answer from the same readings, such as owserver
+    pub fn sender(&self) -> Sender<Request> {
+        self.sender.clone()
+    }
+
+    // List the sensors of a new or reloaded config, keeping the readings of those still in it.
+    // Sensor metrics start over, as the textfile sink's do on a reload.
+    pub fn configure(&self, config: &Config) {
+        let mut shared = self.sink.lock();
+        shared.metrics = Exposition::default();
+        let previous = std::mem::take(&mut shared.views);
+        shared.views = config.sensors.iter().enumerate().map(|(index, sensor)| {
+            let rom = format_rom(&sensor.rom);
+            let mut view = SensorView {
+                sensor: index,
+                rom: rom.clone(),
+                family: family_name(&sensor.rom),
+                label: sensor.label.clone(),
+                group: sensor.group.clone(),
+                bus: config.adapter_name(sensor).to_string(),
+                enabled: sensor.enabled,
+                offset: sensor.offset,
+                gain: sensor.gain,
+                interval: sensor.interval.map(format_duration),
+                schedule: config.schedule(sensor).map(ToString::to_string),
+                freshness: config.freshness(sensor).map(format_duration),
+                warn: sensor.warn,
+                crit: sensor.crit,
+                celsius: None,
+                raw_celsius: None,
+                timestamp: None,
+                age: None,
+                read_at: None,
+                error: None,
+                reads: 0,
+                errors: 0,
+                register: sensor.modbus_register(index),
+                level: None,
+                alarms: Vec::new(),
+            };
+            if let Some(old) = previous.iter().find(|old| old.rom == rom) {
+                view.celsius = old.celsius;
+                view.raw_celsius = old.raw_celsius;
+                view.timestamp = old.timestamp.clone();
+                view.read_at = old.read_at;
+                view.error = old.error.clone();
+                view.reads = old.reads;
+                view.errors = old.errors;
+                // Thresholds may have changed; the next reading compares against the new ones
+                view.level = old.level;
+                view.alarms = old.alarms.clone();
+            }
+            view
+        }).collect();
+    }
+
+    // Set at start and after a reload, as the output options may change
+    pub fn set_line_format(&self, line_format: LineFormat) {
+        self.sink.lock().line_format = line_format;
+    }
+
+    // The /sensors/{id} body of a sensor, after an immediate read
+    pub fn sensor_json(&self, index: usize) -> String {
+        self.sink.views().iter().find(|view| view.sensor == index).map(json::to_line).unwrap_or_default()
+    }
+
+    // Record a completed pass and the bus counters for /metrics
+    pub fn pass_done(&self, buses: &mut Buses, duration: Duration) {
+        let mut shared = self.sink.lock();
+        let daemon = &mut shared.daemon;
+        daemon.passes += 1;
+        daemon.last_pass = Some((chrono::Utc::now().timestamp(), duration));
+        daemon.buses = buses.iter_mut()
+            .map(|bus| (bus.name.clone(), bus.adapter.as_ref().map(|adapter| adapter.stats().clone())))
+            .collect();
+    }
+
+    // Wait up to `timeout` for a /read request
+    pub fn next_request(&self, timeout: Duration) -> Option<Request> {
+        self.requests.recv_timeout(timeout).ok()
+    }
+}
+
+fn handle(stream: TcpStream, sink: &ApiSink, sender: &Sender<Request>) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let request_line = read_line(&mut reader)?;
+    // Only the WebSocket key is used; the rest are read so the client sees a complete exchange
+    let mut websocket_key = None;
+    loop {
+        let line = read_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                websocket_key = Some(value.trim().to_string());
+            }
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = target.split('?').next().unwrap_or("").trim_end_matches('/');
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+
+    match (method, segments.as_slice(), websocket_key) {
+        ("GET" | "HEAD", ["metrics"], _) => return respond(stream, method, 200, METRICS_TYPE, &sink.metrics()),
+        ("GET", ["ws"], Some(key)) => {
+            let mut stream = stream;
+            let head = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                Sec-WebSocket-Accept: {}\r\n\r\n", websocket::accept_key(&key));
+            stream.write_all(head.as_bytes())?;
+            return websocket::stream(stream, reader, sink.subscribe(false));
+        }
+        ("GET", ["events"], _) => return events(stream, sink.subscribe(true)),
+        _ => {}
+    }
+    let (status, body) = match (method, segments.as_slice()) {
+        ("GET" | "HEAD", ["sensors"]) => (200, json::to_line(&sink.views())),
+        ("GET" | "HEAD", ["sensors", id]) => {
+            let id = decode(id);
+            match find(&sink.views(), &id) {
+                Some(view) => (200, json::to_line(view)),
+                None => (404, message(&format!("no sensor '{}'", id))),
+            }
+        }
+        ("GET" | "POST", ["read", id]) => Request::send(sender, Action::Read(decode(id))),
+        ("POST", ["scan"]) => Request::send(sender, Action::Scan),
+        ("POST", ["ack", id]) => Request::send(sender, Action::Ack(decode(id))),
+        (_, ["sensors"] | ["sensors", _] | ["read", _] | ["scan"] | ["ack", _] | ["metrics"]) => {
+            (405, message("method not allowed"))
+        }
+        ("GET", ["ws"]) => (426, message("a WebSocket upgrade is required")),
+        (_, ["ws"] | ["events"]) => (405, message("method not allowed")),
+        _ => (404, message("not found")),
+    };
+    respond(stream, method, status, "application/json", &format!("{}\n", body))
+}
+
+// Server-Sent Events until the client goes away, which shows when a write fails
+fn events(mut stream: TcpStream, events: Receiver<Event>) -> io::Result<()> {
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nAccess-Control-Allow-Origin: *\r\n\
+        Cache-Control: no-store\r\nConnection: close\r\n\r\n";
+    stream.write_all(head.as_bytes())?;
+    // Reconnect after a restart of the daemon within a few seconds
+    stream.write_all(b"retry: 5000\n\n")?;
+    loop {
+        match events.recv_timeout(KEEPALIVE_INTERVAL) {
+            Ok((event, data)) => stream.write_all(format!("event: {}\ndata: {}\n\n", event, data).as_bytes())?,
+            // A comment line keeps proxies from closing an idle stream
+            Err(mpsc::RecvTimeoutError::Timeout) => stream.write_all(b": keepalive\n\n")?,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+// A sensor whose reading moved to another warn/crit level
+#[derive(Serialize)]
+struct AlarmEvent<'a> {
+    timestamp: String,
+    sensor: usize,
+    rom: String,
+    label: Option<&'a str>,
+    level: &'static str,
+    // None on the first reading of a sensor that starts above a threshold
+    previous: Option<&'static str>,
+    celsius: f64,
+    warn: Option<f64>,
+    crit: Option<f64>,
+}
+
+fn alarm_event(index: usize, sensor: &SensorConfig, celsius: f32, level: Level, previous: Option<Level>) -> String {
+    json::to_line(&AlarmEvent {
+        timestamp: json::timestamp(),
+        sensor: index,
+        rom: format_rom(&sensor.rom),
+        label: sensor.label.as_deref(),
+        level: level.name(),
+        previous: previous.map(Level::name),
+        celsius: json::round(celsius, Some(4)),
+        warn: sensor.warn.map(|warn| json::round(warn, Some(4))),
+        crit: sensor.crit.map(|crit| json::round(crit, Some(4))),
+    })
+}
+
+fn respond(mut stream: TcpStream, method: &str, status: u16, content_type: &str, body: &str) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        426 => "Upgrade Required",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Error",
+    };
+    // Dashboards served from another origin may fetch the readings
+    let head = format!("HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+        Access-Control-Allow-Origin: *\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status, reason, content_type, body.len());
+    stream.write_all(head.as_bytes())?;
+    if method != "HEAD" {
+        stream.write_all(body.as_bytes())?;
+    }
+    stream.flush()
+}
+
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_LINE as u64).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "request line too long or incomplete"));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+// An index, ROM or label; a ROM may be written with separators
+pub fn find<'a>(views: &'a [SensorView], id: &str) -> Option<&'a SensorView> {
+    if let Ok(index) = id.parse::<usize>() {
+        return views.iter().find(|view| view.sensor == index);
+    }
+    let rom = crate::config::parse_rom(id).ok().map(|rom| format_rom(&rom));
+    views.iter().find(|view| Some(&view.rom) == rom.as_ref() || view.label.as_deref() == Some(id))
+}
+
+// Percent-decoding of a path segment, for labels with spaces
+fn decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+pub fn message(text: &str) -> String {
+    json::to_line(&serde_json::json!({ "error": text }))
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+use crate::config::{AdapterConfig, Config, SensorConfig};
+use crate::stats::BusStats;
+use crate::{OneWireAdapter, OneWireError};
+
+// A configured adapter and its open port, if opening succeeded
+pub struct Bus {
+    pub name: String,
+    pub tty: String,
+    pub adapter: Option<OneWireAdapter>,
+    // Counters of the adapter while its port is closed, taken up again when it is reopened
+    kept: Option<BusStats>,
+}
+
+impl Bus {
+    // The counters of this session, also while the port is closed
+    pub fn stats(&self) -> Option<&BusStats> {
+        self.adapter.as_ref().map(OneWireAdapter::stats).or(self.kept.as_ref())
+    }
+}
+
+// All configured 1-Wire buses. A bus that fails to open is kept (without a port) so its
+// sensors are reported as unavailable while the other buses keep working.
+pub struct Buses {
+    buses: Vec<Bus>,
+    // As opened, for reopen()
+    watchdog: Option<Duration>,
+    simulate: Option<u8>,
+}
+
+impl Buses {
+    // With `simulate`, every adapter gets that many virtual sensors instead of its port
+    pub fn open(adapters: &[AdapterConfig], watchdog: Option<Duration>, simulate: Option<u8>) -> Self {
+        let buses = adapters.iter().enumerate().map(|(index, adapter_config)| {
+            let adapter = match simulate {
+                Some(count) => Ok(OneWireAdapter::simulated(index, count)),
+                None => OneWireAdapter::new(&adapter_config.tty),
+            };
+            let adapter = adapter.and_then(|mut adapter| {
+                if let Some(limit) = watchdog {
+                    adapter.enable_watchdog(limit)?;
+                }
+                Ok(adapter)
+            });
+            let adapter = match adapter {
+                Ok(adapter) => Some(adapter),
+                Err(e) => {
+                    eprintln!("Cannot open {}: {}", adapter_config.tty, e);
+                    None
+                }
+            };
+            Bus { name: adapter_config.name.clone(), tty: adapter_config.tty.clone(), adapter, kept: None }
+        }).collect();
+
+        Buses { buses, watchdog, simulate }
+    }
+
+    // Let go of every port, so other programs can use it, e.g. for debugging while paused
+    pub fn close(&mut self) {
+        for bus in &mut self.buses {
+            if let Some(adapter) = bus.adapter.take() {
+                bus.kept = Some(adapter.stats().clone());
+            }
+        }
+    }
+
+    // Open the ports again after close(); the counters carry on
+    pub fn reopen(&mut self, adapters: &[AdapterConfig]) {
+        let mut reopened = Buses::open(adapters, self.watchdog, self.simulate);
+        for bus in &mut reopened.buses {
+            let kept = self.buses.iter_mut().find(|old| old.name == bus.name).and_then(|old| old.kept.take());
+            match (&mut bus.adapter, kept) {
+                (Some(adapter), Some(stats)) => adapter.carry_on(stats),
+                (None, kept) => bus.kept = kept,
+                (Some(_), None) => {}
+            }
+        }
+        *self = reopened;
+    }
+
+    pub fn any_open(&self) -> bool {
+        self.buses.iter().any(|bus| bus.adapter.is_some())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Bus> {
+        self.buses.iter_mut()
+    }
+
+    // The adapter a sensor is wired to, per its `adapter` setting
+    pub fn for_sensor(&mut self, config: &Config, sensor: &SensorConfig)
+                      -> Result<&mut OneWireAdapter, OneWireError> {
+        let name = config.adapter_name(sensor);
+        self.buses.iter_mut()
+            .find(|bus| bus.name == name)
+            .and_then(|bus| bus.adapter.as_mut())
+            .ok_or_else(|| OneWireError::AdapterUnavailable(name.to_string()))
+    }
+}
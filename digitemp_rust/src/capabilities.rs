@@ -0,0 +1,118 @@
+use serde::Serialize;
+
+// What this build supports, for `--capabilities`. Entries behind cargo features report
+// whether the feature was enabled; new backends and sinks add a row here.
+#[derive(Serialize)]
+pub struct Capability {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub available: bool,
+}
+
+#[derive(Serialize)]
+pub struct Capabilities {
+    pub version: &'static str,
+    pub backends: Vec<Capability>,
+    pub sinks: Vec<Capability>,
+    pub features: Vec<Capability>,
+}
+
+fn capability(name: &'static str, description: &'static str, available: bool) -> Capability {
+    Capability { name, description, available }
+}
+
+pub fn detect() -> Capabilities {
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        backends: vec![
+            capability("passive", "DS9097 / passive serial adapter", true),
+            capability("ds2480b", "DS2480B serial line driver", false),
+            capability("w1", "Linux w1 kernel driver (sysfs)", false),
+            capability("ds2482", "DS2482 I2C bridge", false),
+            capability("usb", "DS2490 USB adapter", false),
+            capability("simulate", "Virtual DS18B20 sensors, no hardware needed (--simulate)", true),
+        ],
+        sinks: vec![
+            capability("stdout", "Plain, LOG_TYPE and template lines on stdout", true),
+            capability("logfile", "Append to a log file (-l, LOG), reopened on rotation", true),
+            capability("json", "One JSON object per reading (--json)", true),
+            capability("csv", "CSV rows with chosen columns (--csv, [[sinks]] type = \"csv\")", true),
+            capability("prometheus", "node_exporter textfile collector file ([[sinks]] type = \"prometheus\")", true),
+            capability("openmetrics", "OpenMetrics file with quality counters ([[sinks]] type = \"openmetrics\")",
+                true),
+            capability("graphite", "Graphite plaintext over TCP or UDP ([[sinks]] type = \"graphite\")", true),
+            capability("influx", "InfluxDB 2.x write API over HTTP(S) ([[sinks]] type = \"influx\")",
+                cfg!(feature = "influx")),
+            capability("remote_write", "Prometheus remote write, e.g. to Mimir ([[sinks]] type = \"remote_write\")",
+                cfg!(feature = "remote_write")),
+            capability("zabbix", "Zabbix sender protocol to a trapper ([[sinks]] type = \"zabbix\")", true),
+            capability("rrd", "RRD files updated through rrdtool ([[sinks]] type = \"rrd\", create-rrd)", true),
+            capability("jsonl", "Fixed-schema JSON Lines for log shippers (--jsonl, [[sinks]] type = \"jsonl\")", true),
+            capability("template", "Jinja-style per-reading and per-pass templates ([[sinks]] type = \"template\")",
+                cfg!(feature = "templates")),
+            capability("history", "Built-in history store with retention ([[sinks]] type = \"history\", history)",
+                true),
+            capability("sqlite", "SQLite readings table, queried with history ([[sinks]] type = \"sqlite\")",
+                cfg!(feature = "sqlite")),
+            capability("postgres", "PostgreSQL table with batched inserts ([[sinks]] type = \"postgres\")",
+                cfg!(feature = "postgres")),
+            capability("mysql", "MySQL or MariaDB table with batched inserts ([[sinks]] type = \"mysql\")",
+                cfg!(feature = "mysql")),
+            capability("parquet", "Hourly or daily Parquet files ([[sinks]] type = \"parquet\")",
+                cfg!(feature = "parquet")),
+            capability("mqtt", "MQTT messages per reading, e.g. for Home Assistant ([[sinks]] type = \"mqtt\")",
+                cfg!(feature = "mqtt")),
+            capability("nats", "NATS messages per reading, with JetStream message ids ([[sinks]] type = \"nats\")",
+                true),
+            capability("kafka", "Kafka messages keyed by ROM, as JSON or Avro ([[sinks]] type = \"kafka\")",
+                cfg!(feature = "kafka")),
+            capability("redis", "Redis latest-value keys with a TTL and pub/sub ([[sinks]] type = \"redis\")", true),
+            capability("zeromq", "ZeroMQ PUB socket, topic and JSON per reading ([[sinks]] type = \"zeromq\")",
+                cfg!(feature = "zeromq")),
+            capability("webhook", "JSON POSTed per reading or per pass, with retries ([[sinks]] type = \"webhook\")",
+                cfg!(feature = "webhook")),
+            capability("exec", "Command run per reading or alarm, told by env and stdin ([[sinks]] type = \"exec\")",
+                true),
+            capability("chat", "Alarm messages to Slack, Discord or Telegram ([[sinks]] type = \"chat\")",
+                cfg!(feature = "chat")),
+            capability("dbus", "D-Bus service, an object with properties per sensor ([[sinks]] type = \"dbus\")",
+                cfg!(feature = "dbus")),
+        ],
+        features: vec![
+            capability("broadcast", "Broadcast conversion of all sensors (--fast)", true),
+            capability("parasite", "Parasite power detection", true),
+            capability("resolution", "9-12 bit resolution setting (set-resolution)", true),
+            capability("alarm", "TH/TL alarm registers (set-alarm, get-alarm)", true),
+            capability("watchdog", "Bus watchdog with port reopen", true),
+            capability("tui", "Live dashboard (watch)", cfg!(feature = "tui")),
+            capability("http", "JSON API, WebSocket and SSE streams, Prometheus /metrics (serve)", true),
+            capability("owserver", "owfs owserver protocol for owread and Home Assistant (serve --owserver)", true),
+            capability("modbus", "Modbus TCP registers for PLCs and building management (serve --modbus)", true),
+            capability("tcp", "Line commands over plain TCP for scripts and netcat (serve --tcp)", true),
+            capability("coap", "CoAP resources per sensor, observable, JSON or CBOR (serve --coap)", true),
+            capability("grpc", "gRPC API of proto/digitemp.proto (serve --grpc)", cfg!(feature = "grpc")),
+            capability("daemon", "Detach with a pid file, messages to syslog or a log file (monitor/serve -D)",
+                cfg!(unix)),
+            capability("systemd", "Type=notify readiness, watchdog, status and socket activation (monitor/serve)",
+                cfg!(unix)),
+            capability("service", "Windows service (install-service, uninstall-service)", cfg!(windows)),
+            capability("control", "Unix control socket for local scripts (monitor/serve --control)", cfg!(unix)),
+            capability("snmp", "snmpd pass_persist handler (snmp-pass)", true),
+            capability("completions", "Shell completion scripts (completions)", true),
+        ],
+    }
+}
+
+pub fn print(capabilities: &Capabilities) {
+    println!("digitemp_native {}", capabilities.version);
+    for (title, entries) in [
+        ("Adapter backends", &capabilities.backends),
+        ("Sinks", &capabilities.sinks),
+        ("Features", &capabilities.features),
+    ] {
+        println!("{}:", title);
+        for entry in entries {
+            println!("  {:<12} {:<4} {}", entry.name, if entry.available { "yes" } else { "no" }, entry.description);
+        }
+    }
+}
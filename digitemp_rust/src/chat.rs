@@ -0,0 +1,151 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use ureq::Agent;
+use crate::alarm::AlarmEvent;
+use crate::config::{ChatService, SensorConfig};
+
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(10);
+// Messages waiting to be posted; past this, new ones are dropped
+const QUEUE: usize = 100;
+// Attempts per message, with a pause that doubles after each failed one
+const ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+const TELEGRAM_API: &str = "https://api.telegram.org";
+
+// Chat notifier: every alarm raised or cleared is posted as a line of text to a Slack or
+// Discord incoming webhook, or sent by a Telegram bot. Messages are posted in order by a
+// thread of the sink's own, so a chat service that is slow or down never holds up a pass.
+pub struct ChatSink {
+    service: ChatService,
+    sender: Option<SyncSender<String>>,
+    thread: Option<JoinHandle<()>>,
+    // Report a full queue once, not for every message
+    dropping: bool,
+    // Set when the sink is dropped: queued messages get one attempt
+    closing: Arc<AtomicBool>,
+}
+
+impl ChatSink {
+    pub fn new(service: ChatService, url: Option<&str>, token: Option<&str>, chat_id: Option<&str>)
+               -> Result<Self, String> {
+        let endpoint = match (service, url) {
+            (ChatService::Telegram, url) => {
+                let (Some(token), Some(chat_id)) = (token, chat_id) else {
+                    return Err("Telegram chat sinks need a bot token and a chat_id".to_string());
+                };
+                let api = url.unwrap_or(TELEGRAM_API).trim_end_matches('/');
+                Endpoint { url: format!("{}/bot{}/sendMessage", api, token), chat_id: Some(chat_id.to_string()) }
+            }
+            (_, Some(url)) if url.starts_with("https://") || url.starts_with("http://") => {
+                Endpoint { url: url.to_string(), chat_id: None }
+            }
+            (_, Some(url)) => return Err(format!("Invalid {} webhook url '{}'", name(service), url)),
+            (_, None) => return Err(format!("{} chat sinks need the url of an incoming webhook", name(service))),
+        };
+        let agent = Agent::new_with_config(Agent::config_builder()
+            .timeout_global(Some(NETWORK_TIMEOUT))
+            .http_status_as_error(false)
+            .build());
+        let (sender, receiver) = mpsc::sync_channel(QUEUE);
+        let closing = Arc::new(AtomicBool::new(false));
+        let closing_ = closing.clone();
+        let thread = thread::spawn(move || post_all(&agent, service, &endpoint, receiver, &closing_));
+        Ok(ChatSink { service, sender: Some(sender), thread: Some(thread), dropping: false, closing })
+    }
+
+    pub fn alarm(&mut self, sensor: &SensorConfig, event: &AlarmEvent) {
+        let Some(sender) = &self.sender else { return };
+        match sender.try_send(message(sensor, event)) {
+            Ok(()) => self.dropping = false,
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {
+                if !self.dropping {
+                    eprintln!("{}: the chat service is not keeping up, dropping alarm messages", name(self.service));
+                }
+                self.dropping = true;
+            }
+        }
+    }
+}
+
+// Stopping or reloading posts what is queued, without retries
+impl Drop for ChatSink {
+    fn drop(&mut self) {
+        self.closing.store(true, Ordering::Relaxed);
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Endpoint {
+    url: String,
+    // Telegram's, sent with every message
+    chat_id: Option<String>,
+}
+
+// e.g. "freezer (kitchen): high alarm raised at -14.81 °C (threshold -15.00 °C)"
+fn message(sensor: &SensorConfig, event: &AlarmEvent) -> String {
+    match &sensor.group {
+        Some(group) => format!("{} ({}): {}", sensor.name(), group, event.describe()),
+        None => format!("{}: {}", sensor.name(), event.describe()),
+    }
+}
+
+fn post_all(agent: &Agent, service: ChatService, endpoint: &Endpoint, receiver: Receiver<String>,
+            closing: &AtomicBool) {
+    // Report a failing service once, not for every message
+    let mut failing = false;
+    for text in receiver {
+        let body = match service {
+            ChatService::Slack => serde_json::json!({ "text": text }),
+            ChatService::Discord => serde_json::json!({ "content": text }),
+            ChatService::Telegram => serde_json::json!({ "chat_id": endpoint.chat_id, "text": text }),
+        }.to_string();
+        let mut delay = RETRY_DELAY;
+        for attempt in 1..=ATTEMPTS {
+            match post(agent, &endpoint.url, &body) {
+                Ok(()) => {
+                    if failing {
+                        eprintln!("{}: posting again", name(service));
+                        failing = false;
+                    }
+                    break;
+                }
+                Err(e) => {
+                    if !failing {
+                        eprintln!("{}: {}", name(service), e);
+                        failing = true;
+                    }
+                    if attempt == ATTEMPTS || closing.load(Ordering::Relaxed) {
+                        eprintln!("{}: gave up on \"{}\"", name(service), text);
+                        break;
+                    }
+                    thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                }
+            }
+        }
+    }
+}
+
+fn name(service: ChatService) -> &'static str {
+    match service {
+        ChatService::Slack => "Slack",
+        ChatService::Discord => "Discord",
+        ChatService::Telegram => "Telegram",
+    }
+}
+
+fn post(agent: &Agent, url: &str, body: &str) -> Result<(), String> {
+    let response = agent.post(url).content_type("application/json").send(body).map_err(|e| e.to_string())?;
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(format!("HTTP {}", status))
+    }
+}
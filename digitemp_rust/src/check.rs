@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::path::Path;
+use crate::config::{format_rom, Config, ExecEvent, RouteConfig, SinkConfig};
+use crate::{OneWireAdapter, READ_TIME_RANGE_MS};
+
+// Validate a loaded configuration without touching the bus.
+// Returns one message per problem found; an empty list means the config is usable.
+pub fn check_config(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let mut adapter_names: HashMap<&str, usize> = HashMap::new();
+    for (i, adapter) in config.adapters.iter().enumerate() {
+        if !Path::new(&adapter.tty).exists() {
+            problems.push(format!("adapter '{}': {} does not exist", adapter.name, adapter.tty));
+        }
+        if adapter_names.insert(adapter.name.as_str(), i).is_some() {
+            problems.push(format!("adapter '{}': name is used more than once", adapter.name));
+        }
+    }
+
+    if let Some(read_time) = config.daemon.read_time_ms {
+        if !READ_TIME_RANGE_MS.contains(&read_time) {
+            problems.push(format!("daemon: read_time_ms {} is outside {}..={} and will be clamped",
+                read_time, READ_TIME_RANGE_MS.start(), READ_TIME_RANGE_MS.end()));
+        }
+    }
+
+    if config.daemon.alarm_poll.is_some() && !config.sensors.iter()
+            .filter_map(|sensor| config.alarm(sensor))
+            .any(|alarm| alarm.high.is_some() || alarm.low.is_some()) {
+        problems.push("daemon: alarm_poll is set, but no sensor has a high or low alarm".to_string());
+    }
+
+    if !(1..=3).contains(&config.output.log_type) {
+        problems.push(format!("output: log_type {} is not 1, 2 or 3", config.output.log_type));
+    }
+
+    let mut roms: HashMap<[u8; 8], usize> = HashMap::new();
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    for (i, sensor) in config.sensors.iter().enumerate() {
+        if OneWireAdapter::calculate_crc8(&sensor.rom) != 0 {
+            problems.push(format!("sensor {}: ROM {} fails CRC check", i, format_rom(&sensor.rom)));
+        }
+        if let Some(first) = roms.insert(sensor.rom, i) {
+            problems.push(format!("sensor {}: ROM {} duplicates sensor {}",
+                i, format_rom(&sensor.rom), first));
+        }
+        if let Some(label) = &sensor.label {
+            if label.trim().is_empty() {
+                problems.push(format!("sensor {}: label is empty", i));
+            } else if let Some(first) = labels.insert(label.as_str(), i) {
+                problems.push(format!("sensor {}: label '{}' duplicates sensor {}", i, label, first));
+            }
+        }
+        if let Some(adapter) = &sensor.adapter {
+            if !adapter_names.contains_key(adapter.as_str()) {
+                problems.push(format!("sensor {}: adapter '{}' is not configured", i, adapter));
+            }
+        }
+        if sensor.gain == 0.0 {
+            problems.push(format!("sensor {}: gain of 0 discards every reading", i));
+        }
+        if let (Some(warn), Some(crit)) = (sensor.warn, sensor.crit) {
+            if warn > crit {
+                problems.push(format!("sensor {}: warn {} is above crit {}", i, warn, crit));
+            }
+        }
+        if let (Some(_), Some(schedule)) = (sensor.interval, &sensor.schedule) {
+            problems.push(format!("sensor {}: interval is ignored, schedule '{}' decides when it is read",
+                i, schedule));
+        }
+    }
+
+    for group in config.groups.keys() {
+        if !config.sensors.iter().any(|sensor| sensor.group.as_ref() == Some(group)) {
+            problems.push(format!("group '{}': no sensor is in it", group));
+        }
+    }
+
+    let alarms = config.sensors.iter().enumerate().map(|(i, sensor)| (format!("sensor {}", i), &sensor.alarm))
+        .chain(config.groups.iter().map(|(name, group)| (format!("group '{}'", name), &group.alarm)));
+    for (owner, alarm) in alarms {
+        let Some(alarm) = alarm else { continue };
+        match (alarm.low, alarm.high) {
+            (None, None) if alarm.rise.is_none() && alarm.fall.is_none() && alarm.stale.is_none() && !alarm.missing => {
+                problems.push(format!("{}: alarm has no high, low, rise, fall, stale or missing", owner));
+            }
+            (Some(low), Some(high)) if low >= high => {
+                problems.push(format!("{}: alarm low {} is not below high {}", owner, low, high));
+            }
+            _ => {}
+        }
+        if alarm.hysteresis < 0.0 {
+            problems.push(format!("{}: alarm hysteresis {} is negative", owner, alarm.hysteresis));
+        }
+        for (name, rate) in [("rise", alarm.rise), ("fall", alarm.fall)] {
+            if let Some(rate) = rate.filter(|rate| *rate <= 0.0) {
+                problems.push(format!("{}: alarm {} {} °C/min is not positive", owner, name, rate));
+            }
+        }
+        if (alarm.rise.is_some() || alarm.fall.is_some()) && alarm.window.is_zero() {
+            problems.push(format!("{}: alarm window is zero", owner));
+        }
+        if alarm.stale.is_some_and(|stale| stale.is_zero()) {
+            problems.push(format!("{}: alarm stale is zero", owner));
+        }
+        if alarm.repeat.is_some_and(|repeat| repeat.is_zero()) {
+            problems.push(format!("{}: alarm repeat is zero", owner));
+        }
+        if alarm.max_repeats.is_some() && alarm.repeat.is_none() {
+            problems.push(format!("{}: alarm max_repeats is set without repeat", owner));
+        }
+        if alarm.escalate_after.is_some_and(|after| after.is_zero()) {
+            problems.push(format!("{}: alarm escalate_after is zero", owner));
+        }
+    }
+
+    if config.sensors.iter().filter_map(|sensor| config.alarm(sensor)).any(|alarm| alarm.escalate_after.is_some())
+            && !config.sinks.iter().any(|entry| entry.route.escalation) {
+        problems.push("alarm escalate_after is set, but no sink has escalation = true".to_string());
+    }
+
+    for (i, entry) in config.sinks.iter().enumerate() {
+        if matches!(entry.sink, SinkConfig::Stdout { .. }) {
+            if entry.route != RouteConfig::default() {
+                problems.push(format!("sink {}: stdout takes no sensors, groups, interval or decimals", i));
+            }
+            continue;
+        }
+        for spec in &entry.route.sensors {
+            if let Err(e) = config.resolve_sensor(spec) {
+                problems.push(format!("sink {}: {}", i, e));
+            }
+        }
+        let alarms = match &entry.sink {
+            SinkConfig::Exec { on, .. } => on.contains(&ExecEvent::Alarm),
+            SinkConfig::Chat { .. } => true,
+            _ => false,
+        };
+        if entry.route.escalation && !alarms {
+            problems.push(format!("sink {}: escalation is for chat sinks and exec sinks run on alarms", i));
+        }
+        for group in &entry.route.groups {
+            if !config.sensors.iter().any(|sensor| sensor.group.as_ref() == Some(group)) {
+                problems.push(format!("sink {}: no sensor is in group '{}'", i, group));
+            }
+        }
+    }
+
+    problems
+}
@@ -0,0 +1,547 @@
+use clap::{value_parser, Arg, ArgAction, ArgGroup, Command};
+use clap_complete::Shell;
+use crate::color::ColorChoice;
+use crate::config::{parse_duration, CsvColumn, Unit};
+
+// Name of the installed binary, used for completion scripts and the man page
+pub const BIN_NAME: &str = "digitemp_native";
+
+// Command-line interface. Each task is a subcommand with its own options; the original
+// digitemp flags stay on the top level as aliases: -a/-t for `read`, -w for `scan` and
+// -i for `init`, together with every `read` option.
+pub fn build() -> Command {
+    let command = Command::new("digitemp_rust_native")
+        .version("0.1.0")
+        .about("DS18B20 Temperature Reader - True Native Rust Implementation")
+        .args(global_args())
+        .arg(Arg::new("capabilities")
+            .long("capabilities")
+            .help("Print the adapter backends, sinks and features compiled into this build")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("all")
+            .short('a')
+            .long("all")
+            .help("Read all sensors (same as `read`)")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("sensor")
+            .short('t')
+            .long("temp")
+            .value_name("SENSOR")
+            .action(ArgAction::Append)
+            .value_delimiter(',')
+            .help("Read these sensors, in order: 0-based index, ROM or label; repeat or separate with commas (same as `read SENSOR...`)"))
+        .arg(Arg::new("init")
+            .short('i')
+            .long("init")
+            .help("Discover sensors and write the configuration file (same as `init`)")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("walk")
+            .short('w')
+            .long("walk")
+            .help("Discover and list all sensors on bus (same as `scan`)")
+            .action(ArgAction::SetTrue))
+        .args(repeat_args())
+        .args(read_args())
+        .subcommand(Command::new("read")
+            .about("Read all sensors, or the given ones, once or repeatedly")
+            .arg(Arg::new("sensor")
+                .value_name("SENSOR")
+                .num_args(0..)
+                .value_delimiter(',')
+                .help("Sensors to read, in order: 0-based index, ROM in hex or label; all sensors when omitted"))
+            .args(repeat_args())
+            .args(read_args()))
+        .subcommand(Command::new("monitor")
+            .about("Read sensors continuously, one pass every interval, until stopped with Ctrl-C")
+            .args(monitor_args())
+            .args(read_args()))
+        .subcommand(Command::new("serve")
+            .about("Monitor sensors and answer HTTP requests for their last readings or an immediate read")
+            .arg(Arg::new("http")
+                .long("http")
+                .value_name("ADDRESS")
+                .help("Address and port to listen on, e.g. 0.0.0.0:8080 for every interface, or systemd for socket activation")
+                .default_value("127.0.0.1:8080"))
+            .arg(Arg::new("owserver")
+                .long("owserver")
+                .value_name("ADDRESS")
+                .help("Also answer owfs owserver clients (owread, Home Assistant) on this address, e.g. 0.0.0.0:4304"))
+            .arg(Arg::new("modbus")
+                .long("modbus")
+                .value_name("ADDRESS")
+                .help("Also answer Modbus TCP masters (PLCs, building management) on this address, e.g. 0.0.0.0:502"))
+            .arg(Arg::new("tcp")
+                .long("tcp")
+                .value_name("ADDRESS")
+                .help("Also answer line commands (LIST, READ attic) on this address, or on a port of every interface"))
+            .arg(Arg::new("coap")
+                .long("coap")
+                .value_name("ADDRESS")
+                .help("Also answer CoAP requests, observable per sensor, on this address, or on a port, e.g. 5683"))
+            .arg(Arg::new("grpc")
+                .long("grpc")
+                .value_name("ADDRESS")
+                .help("Also serve the gRPC API of proto/digitemp.proto on this address (grpc feature)"))
+            .args(monitor_args())
+            .args(read_args()))
+        .subcommand(Command::new("scan")
+            .about("Discover and list all sensors on every bus"))
+        .subcommand(Command::new("init")
+            .about("Discover sensors and write the configuration file"))
+        .subcommand(Command::new("set-resolution")
+            .about("Set a sensor's conversion resolution: 9 bits (94 ms, 0.5 °C) to 12 bits (750 ms, 0.0625 °C)")
+            .arg(Arg::new("sensor")
+                .value_name("SENSOR")
+                .required(true)
+                .help("Sensor: 0-based index, ROM in hex or label"))
+            .arg(Arg::new("bits")
+                .value_name("BITS")
+                .required(true)
+                .help("Resolution in bits: 9, 10, 11 or 12")
+                .value_parser(value_parser!(u8).range(9..=12)))
+            .arg(persist_arg()))
+        .subcommand(Command::new("set-alarm")
+            .about("Set a sensor's alarm thresholds TH/TL, as used by the alarm search")
+            .arg(Arg::new("sensor")
+                .value_name("SENSOR")
+                .required(true)
+                .help("Sensor: 0-based index, ROM in hex or label"))
+            .arg(Arg::new("low")
+                .long("low")
+                .value_name("CELSIUS")
+                .help("Low alarm threshold TL in whole °C (-55 to 125)")
+                .allow_negative_numbers(true)
+                .value_parser(value_parser!(i8).range(-55..=125)))
+            .arg(Arg::new("high")
+                .long("high")
+                .value_name("CELSIUS")
+                .help("High alarm threshold TH in whole °C (-55 to 125)")
+                .allow_negative_numbers(true)
+                .value_parser(value_parser!(i8).range(-55..=125)))
+            .group(ArgGroup::new("thresholds").args(["low", "high"]).required(true).multiple(true))
+            .arg(persist_arg()))
+        .subcommand(Command::new("get-alarm")
+            .about("Show the alarm thresholds and resolution stored in sensors")
+            .arg(Arg::new("sensor")
+                .value_name("SENSOR")
+                .num_args(0..)
+                .value_delimiter(',')
+                .help("Sensors: 0-based index, ROM in hex or label; all sensors when omitted")))
+        .subcommand(Command::new("backup-settings")
+            .about("Save every configured sensor's alarm thresholds and resolution to a JSON file")
+            .arg(Arg::new("file")
+                .value_name("FILE")
+                .required(true)
+                .help("Backup file to write")))
+        .subcommand(Command::new("restore-settings")
+            .about("Write saved alarm thresholds and resolution back to sensors, e.g. to a replacement probe")
+            .arg(Arg::new("file")
+                .value_name("FILE")
+                .required(true)
+                .help("Backup file written by backup-settings"))
+            .arg(Arg::new("sensor")
+                .value_name("SENSOR")
+                .num_args(0..)
+                .value_delimiter(',')
+                .help("Only restore these sensors: 0-based index, ROM in hex or label; all when omitted")))
+        .subcommand(Command::new("rename")
+            .about("Set a sensor's label in the configuration file")
+            .arg(Arg::new("sensor")
+                .value_name("SENSOR")
+                .required(true)
+                .help("Sensor to rename: 0-based index, ROM in hex or current label"))
+            .arg(Arg::new("label")
+                .value_name("LABEL")
+                .required(true)
+                .help("New label")))
+        .subcommand(Command::new("bus-stats")
+            .about("Show cumulative per-bus statistics: resets, presence failures, CRC errors, retries, latency")
+            .arg(Arg::new("reset")
+                .long("reset")
+                .help("Clear the recorded statistics")
+                .action(ArgAction::SetTrue)))
+        .subcommand(Command::new("create-rrd")
+            .about("Create the RRD file of every configured sensor for the rrd sinks in the config")
+            .arg(Arg::new("force")
+                .long("force")
+                .help("Recreate files that already exist, discarding their data")
+                .action(ArgAction::SetTrue)))
+        .subcommand(Command::new("doctor")
+            .visible_alias("diagnose")
+            .about("Diagnose each adapter step by step: device, permissions, adapter, presence, ROM CRC, scratchpad"))
+        .subcommand(Command::new("check-config")
+            .about("Validate the configuration file and print it in normalized form"))
+        .subcommand(Command::new("migrate-config")
+            .about("Convert a legacy digitemp.conf/.digitemprc to digitemp.toml")
+            .arg(Arg::new("source")
+                .value_name("LEGACY")
+                .help("Legacy file to convert (default: the -c file, the legacy config found, or ~/.digitemprc)"))
+            .arg(Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("PATH")
+                .help("Where to write the TOML config (default: next to digitemp.conf, or the user config directory)"))
+            .arg(Arg::new("force")
+                .long("force")
+                .help("Overwrite an existing TOML config")
+                .action(ArgAction::SetTrue)))
+        .subcommand(Command::new("completions")
+            .about("Print a shell completion script")
+            .arg(Arg::new("shell")
+                .value_name("SHELL")
+                .required(true)
+                .help("Shell to generate completions for")
+                .value_parser(value_parser!(Shell))));
+
+    let command = command.subcommand(Command::new("history")
+        .about("Min, max and average of the readings kept by the history or sqlite sink")
+        .arg(Arg::new("sensor")
+            .value_name("SENSOR")
+            .num_args(0..)
+            .value_delimiter(',')
+            .help("Sensors to summarize: 0-based index, ROM in hex or label; all in the database when omitted"))
+        .arg(Arg::new("since")
+            .long("since")
+            .value_name("TIME")
+            .default_value("24h")
+            .help("Start of the range: a duration ago (24h, 7d), local time (2026-10-01 08:00), RFC 3339 or Unix time"))
+        .arg(Arg::new("until")
+            .long("until")
+            .value_name("TIME")
+            .default_value("now")
+            .help("End of the range, in the same forms as --since"))
+        .arg(Arg::new("every")
+            .long("every")
+            .value_name("DURATION")
+            .value_parser(parse_duration)
+            .help("One summary per period of this length, e.g. 1h or 1d, instead of one for the whole range"))
+        .arg(Arg::new("db")
+            .long("db")
+            .value_name("PATH")
+            .help("SQLite database to query instead of the first history or sqlite sink in the config")));
+
+    let command = command.subcommand(Command::new("export")
+        .about("Export the readings kept by the history or sqlite sink as min, max and average per period")
+        .arg(Arg::new("sensor")
+            .value_name("SENSOR")
+            .num_args(0..)
+            .value_delimiter(',')
+            .help("Sensors to export: 0-based index, ROM in hex or label; all in the database when omitted"))
+        .arg(Arg::new("from")
+            .long("from")
+            .value_name("TIME")
+            .default_value("24h")
+            .help("Start of the range: a duration ago (24h, 7d), local time (2026-10-01 08:00), RFC 3339 or Unix time"))
+        .arg(Arg::new("to")
+            .long("to")
+            .value_name("TIME")
+            .default_value("now")
+            .help("End of the range, in the same forms as --from"))
+        .arg(Arg::new("every")
+            .long("every")
+            .value_name("DURATION")
+            .default_value("5m")
+            .value_parser(parse_duration)
+            .help("Length of the periods readings are aggregated over, e.g. 5m, 1h or 1d"))
+        .arg(Arg::new("format")
+            .long("format")
+            .value_name("FORMAT")
+            .default_value("csv")
+            .value_parser(["csv", "json"])
+            .help("Output format: csv with a header row, or a JSON array as history --json"))
+        .arg(Arg::new("db")
+            .long("db")
+            .value_name("PATH")
+            .help("SQLite database to query instead of the first history or sqlite sink in the config")));
+
+    let command = command.subcommand(Command::new("snmp-pass")
+        .about("Answer snmpd as a pass_persist handler with the readings saved by read, monitor and serve")
+        .arg(Arg::new("oid")
+            .long("oid")
+            .value_name("OID")
+            // Under NET-SNMP-MIB::netSnmpPlaypen, which is set aside for local use
+            .default_value(".1.3.6.1.4.1.8072.9999.9999.18")
+            .help("Base OID, as given to pass_persist in snmpd.conf")));
+
+    #[cfg(windows)]
+    let command = command
+        .subcommand(Command::new("install-service")
+            .about("Register `monitor` or `serve`, with its options, as a Windows service started at boot")
+            .args(service_args()))
+        .subcommand(Command::new("uninstall-service")
+            .about("Stop and remove the Windows service")
+            .arg(service_args().remove(0)))
+        .subcommand(Command::new("run-service")
+            .about("Run as the Windows service; started by the service control manager")
+            .hide(true)
+            .args(service_args()));
+
+    #[cfg(feature = "tui")]
+    let command = command.subcommand(Command::new("watch")
+        .about("Live dashboard of sensor readings, min/max and recent history")
+        .arg(Arg::new("sensor")
+            .value_name("SENSOR")
+            .num_args(0..)
+            .value_delimiter(',')
+            .help("Sensors to show: 0-based index, ROM in hex or label; all sensors when omitted"))
+        .arg(Arg::new("interval")
+            .long("interval")
+            .value_name("DURATION")
+            .help("Time from the start of one pass to the next, e.g. 5s, 1m")
+            .value_parser(parse_duration)
+            .default_value("5s"))
+        .args(read_args().into_iter()
+            .filter(|arg| matches!(arg.get_id().as_str(), "read_time" | "unit" | "decimals" | "raw"))));
+
+    command
+}
+
+// Options accepted before or after any subcommand
+fn global_args() -> Vec<Arg> {
+    vec![
+        Arg::new("config")
+            .short('c')
+            .long("config")
+            .value_name("PATH")
+            .env("DIGITEMP_CONFIG")
+            .global(true)
+            .help("Configuration file (default: search $XDG_CONFIG_HOME/digitemp_rust, /etc/digitemp_rust, then .)"),
+        Arg::new("config_override")
+            .long("config-override")
+            .value_name("PATH")
+            .env("DIGITEMP_CONFIG_OVERRIDE")
+            .global(true)
+            .help("TOML file merged over the configuration, e.g. a root-only file with credentials"),
+        Arg::new("device")
+            .short('s')
+            .long("serial")
+            .value_name("DEVICE")
+            .env("DIGITEMP_TTY")
+            .global(true)
+            .help("Serial device path"),
+        Arg::new("watchdog")
+            .long("watchdog")
+            .value_name("SECONDS")
+            .env("DIGITEMP_WATCHDOG")
+            .global(true)
+            .help("Flush and reopen the port when a bus operation blocks this long (0 disables)")
+            .value_parser(value_parser!(u64)),
+        Arg::new("simulate")
+            .long("simulate")
+            .value_name("COUNT")
+            .global(true)
+            .help("Use COUNT virtual DS18B20 sensors per adapter instead of the serial port")
+            .value_parser(value_parser!(u8).range(1..=64)),
+        Arg::new("timeout")
+            .long("timeout")
+            .value_name("DURATION")
+            .env("DIGITEMP_TIMEOUT")
+            .global(true)
+            .help("Abort the whole invocation after this long, e.g. 10s, with exit code 9")
+            .value_parser(parse_duration),
+        Arg::new("json")
+            .long("json")
+            .global(true)
+            .help("Structured output: one JSON object per reading, or one per command for scan and init")
+            .action(ArgAction::SetTrue),
+        Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .global(true)
+            .help("Print only the temperature of each reading, one per line; messages go to stderr")
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+// Name and command of install-service and run-service
+#[cfg(windows)]
+fn service_args() -> Vec<Arg> {
+    vec![
+        Arg::new("service_name")
+            .long("name")
+            .value_name("NAME")
+            .default_value("digitemp")
+            .help("Name of the service, for sc.exe and the Services console"),
+        Arg::new("command")
+            .value_name("COMMAND")
+            .required(true)
+            .num_args(1..)
+            .trailing_var_arg(true)
+            .allow_hyphen_values(true)
+            .value_parser(value_parser!(std::ffi::OsString))
+            .help("What the service runs, e.g. monitor --config C:\\digitemp\\digitemp.toml"),
+    ]
+}
+
+// --persist of the commands that write sensor settings
+fn persist_arg() -> Arg {
+    Arg::new("persist")
+        .long("persist")
+        .help("Also copy the settings to the sensor's EEPROM so they survive a power cycle")
+        .action(ArgAction::SetTrue)
+}
+
+// digitemp's repeat options of `read`, also accepted on the top level
+fn repeat_args() -> Vec<Arg> {
+    vec![
+        Arg::new("repeat")
+            .short('n')
+            .long("repeat")
+            .value_name("COUNT")
+            .help("Number of times to repeat the reading, 0 = forever")
+            .value_parser(value_parser!(u64))
+            .default_value("1"),
+        Arg::new("delay")
+            .short('d')
+            .long("delay")
+            .value_name("SECONDS")
+            .help("Delay between repeated readings")
+            .value_parser(value_parser!(u64))
+            .default_value("0"),
+    ]
+}
+
+// Sensors and pacing of `monitor` and `serve`
+fn monitor_args() -> Vec<Arg> {
+    vec![
+        Arg::new("sensor")
+            .value_name("SENSOR")
+            .num_args(0..)
+            .value_delimiter(',')
+            .help("Sensors to read: 0-based index, ROM in hex or label; all sensors when omitted"),
+        Arg::new("interval")
+            .long("interval")
+            .value_name("DURATION")
+            .env("DIGITEMP_INTERVAL")
+            .help("Time from the start of one pass to the next, e.g. 10s, 5m (default: interval in [daemon], else 10s)")
+            .value_parser(parse_duration),
+        Arg::new("count")
+            .long("count")
+            .value_name("N")
+            .help("Number of passes, 0 = until stopped")
+            .value_parser(value_parser!(u64))
+            .default_value("0"),
+        Arg::new("max_per_pass")
+            .long("max-per-pass")
+            .value_name("N")
+            .help("Read at most N sensors per pass, in turn, those past their freshness target first")
+            .value_parser(value_parser!(u32).range(1..)),
+        Arg::new("alarm_poll")
+            .long("alarm-poll")
+            .value_name("DURATION")
+            .help("Between passes, read the sensors past their high/low alarm at once, found by alarm search this often")
+            .value_parser(parse_duration),
+        Arg::new("control")
+            .long("control")
+            .value_name("PATH")
+            .help("Take commands (sensors, read, scan, pause, resume, reload) from local clients on this Unix socket"),
+        Arg::new("daemon")
+            .short('D')
+            .long("daemon")
+            .help("Detach from the terminal once started and run in the background (Unix)")
+            .action(ArgAction::SetTrue),
+        Arg::new("pid_file")
+            .long("pid-file")
+            .value_name("PATH")
+            .help("Write the process id to this file while running (overrides pid_file in [daemon])"),
+        Arg::new("daemon_log")
+            .long("daemon-log")
+            .value_name("FILE")
+            .help("With -D or as a Windows service, append messages to this file (overrides log_file in [daemon])"),
+        Arg::new("user")
+            .long("user")
+            .value_name("USER")
+            .help("Switch to this user, with its groups, once the serial ports and listening ports are open"),
+        Arg::new("group")
+            .long("group")
+            .value_name("GROUP")
+            .help("Switch to this group once the serial ports and listening ports are open"),
+    ]
+}
+
+// Options of `read` and `monitor`, also accepted on the top level for the legacy flags
+fn read_args() -> Vec<Arg> {
+    vec![
+        Arg::new("read_time")
+            .short('r')
+            .long("read-time")
+            .value_name("MS")
+            .help("Fixed conversion wait in milliseconds instead of polling for completion (overrides READ_TIME)")
+            .value_parser(value_parser!(u64)),
+        Arg::new("fast")
+            .long("fast")
+            .help("Start all conversions with one broadcast per bus, then read each sensor without waiting again")
+            .action(ArgAction::SetTrue),
+        Arg::new("humidity")
+            .short('A')
+            .help("Treat DS2438 as humidity sensors (accepted for compatibility; DS2438 is not read)")
+            .action(ArgAction::SetTrue),
+        Arg::new("log")
+            .short('l')
+            .long("log")
+            .value_name("FILE")
+            .help("Append readings to this log file (overrides LOG from the config)"),
+        Arg::new("jsonl")
+            .long("jsonl")
+            .value_name("FILE")
+            .help("Also write one JSON object per reading to FILE (- for stdout, replacing the normal lines)"),
+        Arg::new("csv")
+            .long("csv")
+            .value_name("FILE")
+            .help("Also append one CSV row per reading to FILE, with a header if it is new; - writes to stdout"),
+        Arg::new("csv_columns")
+            .long("csv-columns")
+            .value_name("COLUMNS")
+            .help("Comma-separated CSV columns (default: timestamp,rom,label,celsius)")
+            .value_delimiter(',')
+            .requires("csv")
+            .value_parser(|text: &str| text.parse::<CsvColumn>()),
+        Arg::new("output_format")
+            .short('o')
+            .long("output-format")
+            .value_name("FORMAT")
+            .help("Output format: LOG_TYPE 1, 2 or 3, or a LOG_FORMAT template such as \"%b %d %H:%M:%S Sensor %s C: %.2C\"")
+            .allow_hyphen_values(true),
+        Arg::new("unit")
+            .long("unit")
+            .value_name("UNIT")
+            .help("Temperature unit to report: C, F, K or both")
+            .value_parser(|text: &str| text.parse::<Unit>()),
+        Arg::new("decimals")
+            .long("decimals")
+            .value_name("N")
+            .help("Decimal places of reported temperatures (default: 2)")
+            .value_parser(value_parser!(usize)),
+        Arg::new("color")
+            .long("color")
+            .value_name("WHEN")
+            .help("Color readings by the sensors' warn/crit thresholds: auto, always or never (default: auto)")
+            .value_parser(|text: &str| text.parse::<ColorChoice>()),
+        Arg::new("max_delta")
+            .long("max-delta")
+            .value_name("CELSIUS")
+            .env("DIGITEMP_MAX_DELTA")
+            .help("Mark readings that jump more than this from the previous one as suspect")
+            .value_parser(value_parser!(f32)),
+        Arg::new("fail_threshold")
+            .long("fail-threshold")
+            .value_name("N")
+            .env("DIGITEMP_FAIL_THRESHOLD")
+            .help("Consecutive failures before a sensor is marked degraded")
+            .value_parser(value_parser!(u32)),
+        Arg::new("degraded_every")
+            .long("degraded-every")
+            .value_name("N")
+            .env("DIGITEMP_DEGRADED_EVERY")
+            .help("Poll degraded sensors only once every N passes")
+            .value_parser(value_parser!(u32)),
+        Arg::new("raw")
+            .long("raw")
+            .help("Report uncalibrated values, ignoring per-sensor offset and gain")
+            .action(ArgAction::SetTrue),
+        Arg::new("quality")
+            .long("quality")
+            .help("Append read quality (raw value, retries, CRC failures, conversion time) to each reading")
+            .action(ArgAction::SetTrue),
+    ]
+}
@@ -0,0 +1,529 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use serde::Serialize;
+use serde_json::Value;
+use crate::api::{self, ApiSink, SensorView};
+use crate::json;
+use crate::systemd;
+
+const VERSION: u8 = 1;
+// Largest datagram read; CoAP keeps messages under 1152 bytes
+const MAX_DATAGRAM: usize = 2048;
+// Block size exponent of 1024-byte blocks, the largest RFC 7959 allows
+const MAX_SZX: u32 = 6;
+// Observations kept at once; past this, GETs are answered without registering
+const MAX_OBSERVERS: usize = 256;
+// Every this many notifications one is confirmable, to find observers that went away
+const CONFIRM_EVERY: u32 = 10;
+
+// Message types
+const CON: u8 = 0;
+const NON: u8 = 1;
+const ACK: u8 = 2;
+const RST: u8 = 3;
+
+// Codes, class.detail as class << 5 | detail
+const EMPTY: u8 = 0x00;
+const GET: u8 = 0x01;
+const CONTENT: u8 = 0x45;
+const BAD_REQUEST: u8 = 0x80;
+const BAD_OPTION: u8 = 0x82;
+const NOT_FOUND: u8 = 0x84;
+const METHOD_NOT_ALLOWED: u8 = 0x85;
+const NOT_ACCEPTABLE: u8 = 0x86;
+
+// Options; the odd ones are critical, and a request with one not known here is refused
+const URI_HOST: u16 = 3;
+const OBSERVE: u16 = 6;
+const URI_PORT: u16 = 7;
+const URI_PATH: u16 = 11;
+const CONTENT_FORMAT: u16 = 12;
+const URI_QUERY: u16 = 15;
+const ACCEPT: u16 = 17;
+const BLOCK2: u16 = 23;
+
+// Content formats
+const LINK_FORMAT: u16 = 40;
+const JSON: u16 = 50;
+const CBOR: u16 = 60;
+
+struct Message {
+    kind: u8,
+    code: u8,
+    id: u16,
+    token: Vec<u8>,
+    // In the order received, which is by number
+    options: Vec<(u16, Vec<u8>)>,
+}
+
+impl Message {
+    fn option(&self, number: u16) -> Option<&[u8]> {
+        self.options.iter().find(|(n, _)| *n == number).map(|(_, value)| value.as_slice())
+    }
+}
+
+struct Response {
+    code: u8,
+    options: Vec<(u16, Vec<u8>)>,
+    payload: Vec<u8>,
+}
+
+impl Response {
+    // An error with a diagnostic payload
+    fn error(code: u8, text: &str) -> Self {
+        Response { code, options: Vec::new(), payload: text.as_bytes().to_vec() }
+    }
+
+    fn content(format: u16, payload: Vec<u8>) -> Self {
+        Response { code: CONTENT, options: vec![(CONTENT_FORMAT, uint(format as u32))], payload }
+    }
+}
+
+// A client observing a sensor, identified by its address and token
+struct Observer {
+    peer: SocketAddr,
+    token: Vec<u8>,
+    rom: String,
+    format: u16,
+    notifications: u32,
+    // Message id of the last notification, which the client may reset
+    last: u16,
+    // Message id of a confirmable notification not acknowledged yet
+    unacknowledged: Option<u16>,
+}
+
+struct Server {
+    socket: UdpSocket,
+    sink: ApiSink,
+    observers: Mutex<Vec<Observer>>,
+    message_id: AtomicU16,
+    // Observe option value, increasing with every notification
+    sequence: AtomicU32,
+}
+
+// CoAP (RFC 7252) over the readings `serve` keeps, for constrained clients that prefer it to
+// HTTP. Resources, all GET only:
+//   /.well-known/core  the resources in CoRE link format, for discovery
+//   /sensors           every configured sensor with its last reading, as the HTTP /sensors
+//   /sensors/{id}      one sensor, given by index, ROM or label, as the HTTP /sensors/{id}
+// Sensors are observable (RFC 7641): a GET with Observe 0 registers the client, which is
+// then sent the sensor again after every reading or failed read. Representations are JSON,
+// or CBOR for Accept 60; long ones are sent in blocks (RFC 7959). A bare port listens on
+// every interface.
+pub fn start(address: &str, sink: ApiSink) -> Result<(), String> {
+    let socket = systemd::activated_udp(address, "coap").unwrap_or_else(|| {
+        let address = match address.parse::<u16>() {
+            Ok(port) => format!("0.0.0.0:{}", port),
+            Err(_) => address.to_string(),
+        };
+        UdpSocket::bind(&address).map_err(|e| format!("Cannot listen on {}: {}", address, e))
+    })?;
+    let events = sink.subscribe(false);
+    let server = Arc::new(Server {
+        socket,
+        sink,
+        observers: Mutex::new(Vec::new()),
+        // Message ids should not repeat those of an earlier run to the same client
+        message_id: AtomicU16::new(chrono::Utc::now().timestamp_subsec_nanos() as u16),
+        sequence: AtomicU32::new(0),
+    });
+    let notifier = server.clone();
+    thread::spawn(move || {
+        for (_, event) in events {
+            notifier.notify(&event);
+        }
+    });
+    thread::spawn(move || server.serve());
+    Ok(())
+}
+
+impl Server {
+    fn serve(&self) {
+        let mut buffer = [0u8; MAX_DATAGRAM];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((length, peer)) => self.handle(&buffer[..length], peer),
+                // Windows reports an unreachable client of an earlier send here
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+                Err(e) => eprintln!("CoAP: {}", e),
+            }
+        }
+    }
+
+    fn handle(&self, datagram: &[u8], peer: SocketAddr) {
+        let Some(message) = parse(datagram) else {
+            // A confirmable message that cannot be parsed is rejected with a reset
+            if let [first, _, high, low, ..] = datagram {
+                if first >> 6 == VERSION && (first >> 4) & 3 == CON {
+                    self.send(&encode(RST, EMPTY, u16::from_be_bytes([*high, *low]), &[], &[], &[]), peer);
+                }
+            }
+            return;
+        };
+        match (message.kind, message.code) {
+            (ACK | RST, _) => self.acknowledged(peer, message.id, message.kind == RST),
+            // A ping
+            (CON, EMPTY) => self.send(&encode(RST, EMPTY, message.id, &[], &[], &[]), peer),
+            // Responses are not expected by a server
+            (CON, code) if code >> 5 != 0 => self.send(&encode(RST, EMPTY, message.id, &[], &[], &[]), peer),
+            (_, code) if code == EMPTY || code >> 5 != 0 => {}
+            (kind, _) => {
+                let mut response = self.request(&message, peer);
+                response.options.sort_by_key(|(number, _)| *number);
+                let (kind, id) = if kind == CON { (ACK, message.id) } else { (NON, self.next_id()) };
+                self.send(&encode(kind, response.code, id, &message.token, &response.options, &response.payload),
+                    peer);
+            }
+        }
+    }
+
+    fn request(&self, message: &Message, peer: SocketAddr) -> Response {
+        if let Some((number, _)) = message.options.iter()
+                .find(|(number, _)| number % 2 == 1 && ![URI_HOST, URI_PORT, URI_PATH, URI_QUERY, ACCEPT, BLOCK2]
+                    .contains(number)) {
+            return Response::error(BAD_OPTION, &format!("unknown option {}", number));
+        }
+        if message.code != GET {
+            return Response::error(METHOD_NOT_ALLOWED, "only GET is supported");
+        }
+        let path: Vec<String> = message.options.iter().filter(|(number, _)| *number == URI_PATH)
+            .map(|(_, segment)| String::from_utf8_lossy(segment).into_owned()).collect();
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        let accept = message.option(ACCEPT).map(read_uint);
+        // JSON unless the client asks for another format
+        let format = accept.map_or(JSON, |accept| accept as u16);
+        let acceptable = |formats: &[u16]| formats.contains(&format) || accept.is_none();
+
+        let views = self.sink.views();
+        let mut response = match path.as_slice() {
+            [".well-known", "core"] if !acceptable(&[LINK_FORMAT]) => {
+                return Response::error(NOT_ACCEPTABLE, "link format (40) only");
+            }
+            [".well-known", "core"] => Response::content(LINK_FORMAT, links(&views).into_bytes()),
+            ["sensors"] | ["sensors", _] if !acceptable(&[JSON, CBOR]) => {
+                return Response::error(NOT_ACCEPTABLE, "JSON (50) or CBOR (60) only");
+            }
+            ["sensors"] => Response::content(format, representation(&views, format)),
+            ["sensors", id] => {
+                let Some(view) = api::find(&views, id) else {
+                    return Response::error(NOT_FOUND, &format!("no sensor '{}'", id));
+                };
+                let mut response = Response::content(format, representation(view, format));
+                match message.option(OBSERVE).map(read_uint) {
+                    Some(0) if self.register(peer, &message.token, &view.rom, format) => {
+                        response.options.push((OBSERVE, uint(self.next_sequence())));
+                    }
+                    Some(1) => self.observers().retain(|o| o.peer != peer || o.token != message.token),
+                    _ => {}
+                }
+                response
+            }
+            _ => return Response::error(NOT_FOUND, "not found"),
+        };
+        if let Err(text) = block(&mut response, message.option(BLOCK2).map(read_uint)) {
+            return Response::error(BAD_REQUEST, text);
+        }
+        response
+    }
+
+    // Whether the client now observes the sensor; a registration with the same address and
+    // token replaces the earlier one
+    fn register(&self, peer: SocketAddr, token: &[u8], rom: &str, format: u16) -> bool {
+        let mut observers = self.observers();
+        observers.retain(|o| o.peer != peer || o.token != token);
+        if observers.len() >= MAX_OBSERVERS {
+            return false;
+        }
+        observers.push(Observer {
+            peer,
+            token: token.to_vec(),
+            rom: rom.to_string(),
+            format,
+            notifications: 0,
+            last: 0,
+            unacknowledged: None,
+        });
+        true
+    }
+
+    // An ACK or RST from a client; a reset to a notification ends the observation
+    fn acknowledged(&self, peer: SocketAddr, id: u16, reset: bool) {
+        let mut observers = self.observers();
+        if reset {
+            observers.retain(|o| o.peer != peer || (o.last != id && o.unacknowledged != Some(id)));
+        } else if let Some(observer) = observers.iter_mut().find(|o| o.peer == peer && o.unacknowledged == Some(id)) {
+            observer.unacknowledged = None;
+        }
+    }
+
+    // Send the sensor of a reading or error event to its observers. An observer that did
+    // not acknowledge the last confirmable notification when the next one is due is dropped.
+    fn notify(&self, event: &str) {
+        let Some(rom) = serde_json::from_str::<Value>(event).ok()
+            .and_then(|event| event["rom"].as_str().map(str::to_string)) else { return };
+        let views = self.sink.views();
+        let Some(view) = views.iter().find(|view| view.rom == rom) else { return };
+        let (json, cbor) = (representation(view, JSON), representation(view, CBOR));
+        self.observers().retain_mut(|observer| {
+            if observer.rom != rom {
+                return true;
+            }
+            observer.notifications = observer.notifications.wrapping_add(1);
+            let kind = if observer.notifications % CONFIRM_EVERY == 0 { CON } else { NON };
+            if kind == CON && observer.unacknowledged.is_some() {
+                return false;
+            }
+            let id = self.next_id();
+            observer.last = id;
+            if kind == CON {
+                observer.unacknowledged = Some(id);
+            }
+            let options = [(OBSERVE, uint(self.next_sequence())), (CONTENT_FORMAT, uint(observer.format as u32))];
+            let payload = if observer.format == CBOR { &cbor } else { &json };
+            self.send(&encode(kind, CONTENT, id, &observer.token, &options, payload), observer.peer);
+            true
+        });
+    }
+
+    fn send(&self, datagram: &[u8], peer: SocketAddr) {
+        // A lost datagram is as good as a failed send: the client asks again
+        let _ = self.socket.send_to(datagram, peer);
+    }
+
+    fn next_id(&self) -> u16 {
+        self.message_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // Observe values are 24 bits, wrapping around
+    fn next_sequence(&self) -> u32 {
+        self.sequence.fetch_add(1, Ordering::Relaxed) & 0xFF_FFFF
+    }
+
+    fn observers(&self) -> MutexGuard<'_, Vec<Observer>> {
+        self.observers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Header (version, type, token length; code; message id), token, options, then the payload
+// after a 0xFF marker. None for anything malformed.
+fn parse(datagram: &[u8]) -> Option<Message> {
+    let [first, code, high, low, rest @ ..] = datagram else { return None };
+    let token_length = (first & 0x0F) as usize;
+    if first >> 6 != VERSION || token_length > 8 || rest.len() < token_length {
+        return None;
+    }
+    let (token, mut rest) = rest.split_at(token_length);
+    let mut options = Vec::new();
+    let mut number = 0u16;
+    // Requests here have no use for a payload
+    while let Some((&byte, tail)) = rest.split_first().filter(|(&byte, _)| byte != 0xFF) {
+        let (delta, tail) = extended(byte >> 4, tail)?;
+        let (length, tail) = extended(byte & 0x0F, tail)?;
+        number = number.checked_add(delta)?;
+        let value = tail.get(..length as usize)?;
+        options.push((number, value.to_vec()));
+        rest = &tail[length as usize..];
+    }
+    let id = u16::from_be_bytes([*high, *low]);
+    Some(Message { kind: (first >> 4) & 3, code: *code, id, token: token.to_vec(), options })
+}
+
+// An option delta or length: 0-12 as is, 13 and 14 followed by one or two more bytes
+fn extended(nibble: u8, data: &[u8]) -> Option<(u16, &[u8])> {
+    match (nibble, data) {
+        (0..=12, _) => Some((nibble as u16, data)),
+        (13, [byte, rest @ ..]) => Some((*byte as u16 + 13, rest)),
+        (14, [high, low, rest @ ..]) => Some((u16::from_be_bytes([*high, *low]).checked_add(269)?, rest)),
+        _ => None,
+    }
+}
+
+// Options must be in order of their numbers
+fn encode(kind: u8, code: u8, id: u16, token: &[u8], options: &[(u16, Vec<u8>)], payload: &[u8]) -> Vec<u8> {
+    let mut datagram = vec![VERSION << 6 | kind << 4 | token.len() as u8, code];
+    datagram.extend_from_slice(&id.to_be_bytes());
+    datagram.extend_from_slice(token);
+    let mut previous = 0;
+    for (number, value) in options {
+        let (delta, delta_bytes) = nibble(number - previous);
+        let (length, length_bytes) = nibble(value.len() as u16);
+        datagram.push(delta << 4 | length);
+        datagram.extend_from_slice(&delta_bytes);
+        datagram.extend_from_slice(&length_bytes);
+        datagram.extend_from_slice(value);
+        previous = *number;
+    }
+    if !payload.is_empty() {
+        datagram.push(0xFF);
+        datagram.extend_from_slice(payload);
+    }
+    datagram
+}
+
+fn nibble(value: u16) -> (u8, Vec<u8>) {
+    match value {
+        0..=12 => (value as u8, Vec::new()),
+        13..=268 => (13, vec![(value - 13) as u8]),
+        _ => (14, (value - 269).to_be_bytes().to_vec()),
+    }
+}
+
+// Unsigned option values have no leading zero bytes; 0 is empty
+fn uint(value: u32) -> Vec<u8> {
+    value.to_be_bytes().into_iter().skip_while(|byte| *byte == 0).collect()
+}
+
+fn read_uint(value: &[u8]) -> u32 {
+    value.iter().take(4).fold(0, |uint, byte| uint << 8 | *byte as u32)
+}
+
+// The block of the payload a Block2 option asks for: number, more flag and size exponent.
+// Without one, payloads up to 1024 bytes are sent whole.
+fn block(response: &mut Response, requested: Option<u32>) -> Result<(), &'static str> {
+    let (number, szx) = requested.map_or((0, MAX_SZX), |block| (block >> 4, (block & 7).min(MAX_SZX)));
+    let size = 16usize << szx;
+    if requested.is_none() && response.payload.len() <= size {
+        return Ok(());
+    }
+    let start = number as usize * size;
+    if start > 0 && start >= response.payload.len() {
+        return Err("block out of range");
+    }
+    let end = (start + size).min(response.payload.len());
+    let more = (end < response.payload.len()) as u32;
+    response.options.push((BLOCK2, uint(number << 4 | more << 3 | szx)));
+    response.payload = response.payload[start..end].to_vec();
+    Ok(())
+}
+
+// </sensors/{ROM}> for every enabled sensor, observable, with its label as the title
+fn links(views: &[SensorView]) -> String {
+    let mut links = vec!["</sensors>;ct=\"50 60\"".to_string()];
+    for view in views.iter().filter(|view| view.enabled) {
+        let mut link = format!("</sensors/{}>;rt=\"temperature\";obs;ct=\"50 60\"", view.rom);
+        if let Some(label) = &view.label {
+            link.push_str(&format!(";title=\"{}\"", label.replace('\\', "\\\\").replace('"', "\\\"")));
+        }
+        links.push(link);
+    }
+    links.join(",")
+}
+
+fn representation<T: Serialize>(value: &T, format: u16) -> Vec<u8> {
+    let value = serde_json::to_value(value).unwrap_or_default();
+    if format == CBOR {
+        let mut encoded = Vec::new();
+        cbor(&mut encoded, &value);
+        encoded
+    } else {
+        json::to_line(&value).into_bytes()
+    }
+}
+
+// CBOR (RFC 8949) of a JSON value; numbers that are not integers are 64-bit floats
+fn cbor(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => out.push(0xF6),
+        Value::Bool(false) => out.push(0xF4),
+        Value::Bool(true) => out.push(0xF5),
+        Value::Number(number) => match (number.as_u64(), number.as_i64()) {
+            (Some(unsigned), _) => cbor_head(out, 0, unsigned),
+            // -1 - n
+            (None, Some(negative)) => cbor_head(out, 1, !negative as u64),
+            (None, None) => {
+                out.push(0xFB);
+                out.extend_from_slice(&number.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        },
+        Value::String(text) => {
+            cbor_head(out, 3, text.len() as u64);
+            out.extend_from_slice(text.as_bytes());
+        }
+        Value::Array(items) => {
+            cbor_head(out, 4, items.len() as u64);
+            for item in items {
+                cbor(out, item);
+            }
+        }
+        Value::Object(fields) => {
+            cbor_head(out, 5, fields.len() as u64);
+            for (name, field) in fields {
+                cbor_head(out, 3, name.len() as u64);
+                out.extend_from_slice(name.as_bytes());
+                cbor(out, field);
+            }
+        }
+    }
+}
+
+// Major type and argument, in the fewest bytes
+fn cbor_head(out: &mut Vec<u8>, major: u8, argument: u64) {
+    let major = major << 5;
+    match argument {
+        0..=23 => out.push(major | argument as u8),
+        24..=0xFF => out.extend_from_slice(&[major | 24, argument as u8]),
+        0x100..=0xFFFF => {
+            out.push(major | 25);
+            out.extend_from_slice(&(argument as u16).to_be_bytes());
+        }
+        0x1_0000..=0xFFFF_FFFF => {
+            out.push(major | 26);
+            out.extend_from_slice(&(argument as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&argument.to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_request() {
+        // CON GET, id 0x1234, token AB CD, Observe 0, Uri-Path sensors/0, then a payload
+        let mut datagram = vec![0x42, GET, 0x12, 0x34, 0xAB, 0xCD, 0x60, 0x57];
+        datagram.extend_from_slice(b"sensors");
+        datagram.extend_from_slice(&[0x01, b'0', 0xFF, b'x']);
+        let message = parse(&datagram).unwrap();
+        assert_eq!((message.kind, message.code, message.id), (CON, GET, 0x1234));
+        assert_eq!(message.token, [0xAB, 0xCD]);
+        assert_eq!(message.options, [(OBSERVE, vec![]), (URI_PATH, b"sensors".to_vec()), (URI_PATH, b"0".to_vec())]);
+        assert_eq!(message.option(OBSERVE).map(read_uint), Some(0));
+        assert_eq!(message.option(ACCEPT), None);
+    }
+
+    #[test]
+    fn parse_extended_options() {
+        let options = [(URI_PATH, b"sensors".to_vec()), (60, vec![1]), (2000, vec![0; 300])];
+        let datagram = encode(NON, GET, 7, &[1, 2, 3], &options, &[]);
+        // Deltas of 49 and 1940 take one and two more bytes, as does the length of 300
+        assert_eq!(&datagram[15..17], [0xD1, 49 - 13]);
+        let message = parse(&datagram).unwrap();
+        assert_eq!((message.kind, message.id, message.token.as_slice()), (NON, 7, &[1, 2, 3][..]));
+        assert_eq!(message.options, options);
+    }
+
+    #[test]
+    fn parse_refuses_malformed() {
+        // Too short for a header
+        assert!(parse(&[0x40, GET, 0]).is_none());
+        // Version 2
+        assert!(parse(&[0x80, GET, 0, 1]).is_none());
+        // Token length 9, and a token longer than the datagram
+        assert!(parse(&[0x49, GET, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_none());
+        assert!(parse(&[0x44, GET, 0, 1, 0xAB]).is_none());
+        // An option value past the end, a missing extended delta byte, and the reserved nibble 15
+        assert!(parse(&[0x40, GET, 0, 1, 0xB3, b'a']).is_none());
+        assert!(parse(&[0x40, GET, 0, 1, 0xD0]).is_none());
+        assert!(parse(&[0x40, GET, 0, 1, 0xF0]).is_none());
+        // Empty, as a ping
+        let ping = parse(&[0x40, EMPTY, 0, 1]).unwrap();
+        assert_eq!((ping.kind, ping.code, ping.token.len(), ping.options.len()), (CON, EMPTY, 0, 0));
+    }
+}
@@ -0,0 +1,79 @@
+use std::io::IsTerminal;
+use crate::config::SensorConfig;
+
+// When to color readings on stdout (--color)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    // Only on a terminal, and not when NO_COLOR is set
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().as_str() {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("invalid color choice '{}' (expected auto, always or never)", text)),
+        }
+    }
+}
+
+impl ColorChoice {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && std::io::stdout().is_terminal()
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+// How a reading compares with its sensor's warn/crit thresholds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Normal,
+    Warn,
+    Crit,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Normal => "normal",
+            Level::Warn => "warn",
+            Level::Crit => "crit",
+        }
+    }
+}
+
+// None for sensors without thresholds, which are never colored
+pub fn level(sensor: &SensorConfig, celsius: f32) -> Option<Level> {
+    if sensor.warn.is_none() && sensor.crit.is_none() {
+        None
+    } else if sensor.crit.is_some_and(|crit| celsius >= crit) {
+        Some(Level::Crit)
+    } else if sensor.warn.is_some_and(|warn| celsius >= warn) {
+        Some(Level::Warn)
+    } else {
+        Some(Level::Normal)
+    }
+}
+
+// Wrap text in the ANSI color of its level: green, yellow or bold red
+pub fn paint(text: &str, level: Option<Level>) -> String {
+    let code = match level {
+        None => return text.to_string(),
+        Some(Level::Normal) => "32",
+        Some(Level::Warn) => "33",
+        Some(Level::Crit) => "1;31",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
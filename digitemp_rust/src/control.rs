@@ -0,0 +1,117 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::thread;
+use serde::Deserialize;
+use crate::api::{self, Action, ApiSink, Request};
+use crate::json;
+
+// A command sent as JSON, e.g. {"command": "read", "sensor": "attic"}
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Command {
+    command: String,
+    #[serde(default)]
+    sensor: Option<String>,
+}
+
+// Control interface of `monitor` and `serve` on a Unix socket, for local scripts and tools
+// that should not need a network port. Each line is a command, as words or as JSON, and is
+// answered with one line of JSON:
+//   sensors          every sensor with its last reading, as /sensors
+//   sensor ID        one sensor, given by index, ROM or label, as /sensors/{id}
+//   read ID          read a sensor at once, as /read/{id}
+//   scan             search every bus for devices, as POST /scan
+//   cycle            take a pass at once, as SIGUSR1 does; answered when it is over
+//   pause, resume    stop taking passes and close the serial ports, and start again
+//   toggle           pause or resume, as SIGUSR2 does
+//   reload           re-read the config file, as SIGHUP does
+//   ack ID           stop repeating and escalating a sensor's raised alarms, as POST /ack/{id}
+// Failures answer {"error": "..."}. Clients may send any number of commands on a connection.
+pub fn start(path: &Path, sink: ApiSink, sender: Sender<Request>) -> Result<(), String> {
+    let error = |e: io::Error| format!("Cannot listen on {}: {}", path.display(), e);
+    // A socket left behind by a daemon that was killed is replaced; one still answering is not
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("Cannot listen on {}: the file exists and is not a socket", path.display()));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!("Cannot listen on {}: another daemon is using it", path.display()));
+        }
+        std::fs::remove_file(path).map_err(error)?;
+    }
+    let listener = UnixListener::bind(path).map_err(error)?;
+    // The commands can pause the daemon, so other users are kept out
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660)).map_err(error)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let (sink, sender) = (sink.clone(), sender.clone());
+            thread::spawn(move || {
+                if let Err(e) = handle(stream, &sink, &sender) {
+                    if !matches!(e.kind(), io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset) {
+                        eprintln!("Control: {}", e);
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn handle(stream: UnixStream, sink: &ApiSink, sender: &Sender<Request>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        writer.write_all(format!("{}\n", answer(&line, sink, sender)).as_bytes())?;
+    }
+    Ok(())
+}
+
+fn answer(line: &str, sink: &ApiSink, sender: &Sender<Request>) -> String {
+    let Command { command, sensor } = match parse(line) {
+        Ok(command) => command,
+        Err(e) => return api::message(&e),
+    };
+    let action = match (command.as_str(), sensor) {
+        ("sensors", None) => return json::to_line(&sink.views()),
+        ("sensor", Some(id)) => {
+            return match api::find(&sink.views(), &id) {
+                Some(view) => json::to_line(view),
+                None => api::message(&format!("no sensor '{}'", id)),
+            };
+        }
+        ("read", Some(id)) => Action::Read(id),
+        ("scan", None) => Action::Scan,
+        ("cycle", None) => Action::Cycle,
+        ("pause", None) => Action::Pause,
+        ("resume", None) => Action::Resume,
+        ("toggle", None) => Action::TogglePause,
+        ("reload", None) => Action::Reload,
+        ("ack", Some(id)) => Action::Ack(id),
+        ("sensor" | "read" | "ack", None) => return api::message(&format!("{} needs a sensor", command)),
+        ("sensors" | "scan" | "cycle" | "pause" | "resume" | "toggle" | "reload", Some(_)) => {
+            return api::message(&format!("{} takes no sensor", command));
+        }
+        _ => return api::message(&format!("unknown command '{}'", command)),
+    };
+    Request::send(sender, action).1
+}
+
+// `read attic room` or {"command": "read", "sensor": "attic room"}
+fn parse(line: &str) -> Result<Command, String> {
+    let line = line.trim();
+    if line.starts_with('{') {
+        return serde_json::from_str(line).map_err(|e| format!("invalid command: {}", e));
+    }
+    let (command, sensor) = match line.split_once(char::is_whitespace) {
+        Some((command, sensor)) => (command, Some(sensor.trim().to_string())),
+        None => (line, None),
+    };
+    Ok(Command { command: command.to_ascii_lowercase(), sensor })
+}
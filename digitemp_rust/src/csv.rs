@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use crate::config::{format_rom, CsvColumn, SensorConfig};
+use crate::logfile::FileRotation;
+use crate::sink::Destination;
+use crate::{json, Reading};
+
+// CSV rows with a chosen set of columns, for spreadsheets and pandas
+pub struct CsvSink {
+    destination: Destination,
+    columns: Vec<CsvColumn>,
+    header: bool,
+    // stdout only gets its header once, before the first row
+    header_written: bool,
+}
+
+impl CsvSink {
+    pub fn open(path: Option<&Path>, columns: Vec<CsvColumn>, header: bool, append: bool,
+                rotation: Option<FileRotation>) -> Result<Self, String> {
+        let name = path.unwrap_or(Path::new("-")).display().to_string();
+        let destination = Destination::open(path, rotation)
+            .map_err(|e| format!("Cannot open CSV sink {}: {}", name, e))?;
+        // The file being written, which is the dated one when rotating
+        let path = match &destination {
+            Destination::File(file) => Some(file.path().to_path_buf()),
+            Destination::Stdout => None,
+        };
+        let name = path.as_deref().map_or(name, |path| path.display().to_string());
+        if let Some(path) = path.as_deref().filter(|_| !append) {
+            File::create(path).map_err(|e| format!("Cannot truncate CSV sink {}: {}", name, e))?;
+        }
+        let sink = CsvSink { destination, columns, header, header_written: false };
+
+        // Rows appended under another header would end up in the wrong columns
+        let existing = path.as_deref().and_then(first_line);
+        if let Some(existing) = existing.filter(|line| header && *line != sink.header_row()) {
+            eprintln!("Warning: {} has a different header ({}); rows use the columns {}",
+                name, existing, sink.header_row());
+        }
+        Ok(sink)
+    }
+
+    pub fn uses_stdout(&self) -> bool {
+        matches!(self.destination, Destination::Stdout)
+    }
+
+    fn header_row(&self) -> String {
+        self.columns.iter().map(|column| column.name()).collect::<Vec<_>>().join(",")
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading,
+                   decimals: Option<usize>) {
+        if self.header && self.needs_header() {
+            let header = self.header_row();
+            self.destination.write_line(&header);
+            self.header_written = true;
+        }
+        let decimals = decimals.unwrap_or(2);
+        let fields: Vec<String> = self.columns.iter().map(|column| match column {
+            CsvColumn::Timestamp => json::timestamp(),
+            CsvColumn::Bus => quote(bus),
+            CsvColumn::Sensor => index.to_string(),
+            CsvColumn::Rom => format_rom(&sensor.rom),
+            CsvColumn::Label => quote(sensor.label.as_deref().unwrap_or("")),
+            CsvColumn::Celsius => format!("{:.*}", decimals, reading.celsius),
+            CsvColumn::Fahrenheit => format!("{:.*}", decimals, reading.celsius * 9.0 / 5.0 + 32.0),
+            CsvColumn::Kelvin => format!("{:.*}", decimals, reading.celsius + 273.15),
+            CsvColumn::RawCelsius => reading.raw_celsius.to_string(),
+            CsvColumn::Retries => reading.retries.to_string(),
+            CsvColumn::CrcFailures => reading.crc_failures.to_string(),
+            CsvColumn::ConversionMs => reading.conversion_time.as_millis().to_string(),
+        }).collect();
+        self.destination.write_line(&fields.join(","));
+    }
+
+    // A file needs a header while it is empty, including after it was rotated away
+    fn needs_header(&self) -> bool {
+        match &self.destination {
+            Destination::Stdout => !self.header_written,
+            Destination::File(file) => std::fs::metadata(file.path()).map_or(true, |metadata| metadata.len() == 0),
+        }
+    }
+}
+
+fn first_line(path: &Path) -> Option<String> {
+    let file = File::open(path).ok()?;
+    BufReader::new(file).lines().next()?.ok()
+}
+
+// RFC 4180 quoting, only where a field needs it
+pub fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,267 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::fs::{File, OpenOptions};
+#[cfg(any(unix, windows))]
+use std::io::{BufRead, BufReader, PipeWriter};
+#[cfg(unix)]
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd};
+#[cfg(unix)]
+use std::sync::Mutex;
+use crate::logfile::LogFile;
+
+// Write end of the pipe the waiting parent of a detached daemon reads, until startup is over
+#[cfg(unix)]
+static STARTING: Mutex<Option<File>> = Mutex::new(None);
+
+// Syslog facility daemon, severity notice
+#[cfg(unix)]
+const SYSLOG_PRIORITY: u8 = 3 * 8 + 5;
+
+// The process id in a file while the daemon runs, for init scripts and `kill $(cat ...)`.
+// Removed on a clean exit; a file left by a daemon that died is taken over.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    // Refuse to start next to a daemon that is still running
+    pub fn check(path: &Path) -> Result<(), String> {
+        let pid = fs::read_to_string(path).ok().and_then(|text| text.trim().parse::<u32>().ok());
+        match pid {
+            Some(pid) if pid != std::process::id() && running(pid) => {
+                Err(format!("Already running as process {} (pid file {})", pid, path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn create(path: &Path) -> Result<Self, String> {
+        Self::check(path)?;
+        fs::write(path, format!("{}\n", std::process::id()))
+            .map_err(|e| format!("Cannot write pid file {}: {}", path.display(), e))?;
+        Ok(PidFile { path: path.to_path_buf() })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Unless another daemon has taken it over since
+        let pid = fs::read_to_string(&self.path).ok().and_then(|text| text.trim().parse::<u32>().ok());
+        if pid == Some(std::process::id()) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else { return false };
+    // Signal 0 only checks; EPERM means the process exists under another user
+    pid > 0 && (unsafe { libc::kill(pid, 0) } == 0
+        || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+}
+
+#[cfg(not(unix))]
+fn running(_: u32) -> bool {
+    false
+}
+
+// Detach from the terminal: fork, start a new session and fork again, so the daemon is
+// neither a session leader nor a child of the shell. The original process waits until the
+// daemon calls started(), and exits 0, or 1 when the daemon exits first. Until then the
+// daemon keeps the terminal, so startup errors such as a port that cannot be opened are
+// still shown. Must run before any thread is started.
+#[cfg(unix)]
+pub fn detach() -> Result<(), String> {
+    let error = |what: &str| format!("Cannot detach ({}): {}", what, std::io::Error::last_os_error());
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(error("pipe"));
+    }
+    // Owned from here, so both ends are closed where they are not needed
+    let (mut waiting, starting) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    for fd in fds {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    match unsafe { libc::fork() } {
+        -1 => return Err(error("fork")),
+        0 => {}
+        _ => {
+            drop(starting);
+            let mut started = [0u8; 1];
+            std::process::exit(if matches!(waiting.read(&mut started), Ok(1)) { 0 } else { 1 });
+        }
+    }
+    drop(waiting);
+    if unsafe { libc::setsid() } == -1 {
+        return Err(error("setsid"));
+    }
+    match unsafe { libc::fork() } {
+        -1 => return Err(error("fork")),
+        0 => {}
+        _ => unsafe { libc::_exit(0) },
+    }
+    *STARTING.lock().unwrap_or_else(|e| e.into_inner()) = Some(starting);
+    Ok(())
+}
+
+// Startup is over. A detached daemon lets go of the terminal: stdin and stdout are
+// /dev/null, and messages on stderr are appended to `log` with the time, or sent to syslog
+// without one. Then the waiting parent exits. Nothing happens in the foreground.
+#[cfg(unix)]
+pub fn started(log: Option<LogFile>) -> Result<(), String> {
+    let Some(mut starting) = STARTING.lock().unwrap_or_else(|e| e.into_inner()).take() else { return Ok(()) };
+    let null = OpenOptions::new().read(true).write(true).open("/dev/null")
+        .map_err(|e| format!("Cannot open /dev/null: {}", e))?;
+    let stderr = match log {
+        Some(log) => timestamped(log)?,
+        None => syslog()?,
+    };
+    let (null, stderr) = (null.as_raw_fd(), stderr.as_raw_fd());
+    for (from, to) in [(null, libc::STDIN_FILENO), (null, libc::STDOUT_FILENO), (stderr, libc::STDERR_FILENO)] {
+        if unsafe { libc::dup2(from, to) } == -1 {
+            return Err(format!("Cannot redirect output: {}", std::io::Error::last_os_error()));
+        }
+    }
+    let _ = starting.write_all(b"\n");
+    Ok(())
+}
+
+// A Windows service has no console either: its messages on stderr go to `log`, or nowhere
+#[cfg(windows)]
+pub fn started(log: Option<LogFile>) -> Result<(), String> {
+    use std::os::windows::io::IntoRawHandle;
+    use windows_sys::Win32::System::Console::{SetStdHandle, STD_ERROR_HANDLE};
+    let Some(log) = log else { return Ok(()) };
+    let stderr = timestamped(log)?;
+    // std looks the handle up on every write, so eprintln! follows
+    if unsafe { SetStdHandle(STD_ERROR_HANDLE, stderr.into_raw_handle()) } == 0 {
+        return Err(format!("Cannot redirect output: {}", std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn started(_: Option<LogFile>) -> Result<(), String> {
+    Ok(())
+}
+
+// Run as `user` and `group`, names or ids, for good: a root daemon opens the serial ports and
+// binds its ports first. A user brings its own groups, so membership of dialout still lets the
+// bus watchdog reopen a port; a group alone keeps the user.
+#[cfg(unix)]
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>) -> Result<(), String> {
+    let error = |what: &str| format!("Cannot {}: {}", what, std::io::Error::last_os_error());
+    let user = user.map(find_user).transpose()?;
+    let gid = match (group, &user) {
+        (Some(group), _) => Some(find_group(group)?),
+        (None, Some(user)) => Some(user.gid.ok_or_else(|| format!("User {} has no group; give --group", user.uid))?),
+        (None, None) => None,
+    };
+    if let Some(gid) = gid {
+        let groups = match user.as_ref().and_then(|user| user.name.as_ref()) {
+            Some(name) => unsafe { libc::initgroups(name.as_ptr(), gid as _) },
+            None => unsafe { libc::setgroups(1, &gid) },
+        };
+        if groups != 0 {
+            return Err(error("set the supplementary groups"));
+        }
+        if unsafe { libc::setgid(gid) } != 0 {
+            return Err(error(&format!("switch to group {}", gid)));
+        }
+    }
+    if let Some(user) = user {
+        if unsafe { libc::setuid(user.uid) } != 0 {
+            return Err(error(&format!("switch to user {}", user.uid)));
+        }
+        if user.uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err("Dropped privileges could be regained".to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+struct User {
+    // None for an id without a passwd entry
+    name: Option<std::ffi::CString>,
+    uid: libc::uid_t,
+    gid: Option<libc::gid_t>,
+}
+
+#[cfg(unix)]
+fn find_user(user: &str) -> Result<User, String> {
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    let id = user.parse::<libc::uid_t>().ok();
+    let name = std::ffi::CString::new(user).map_err(|_| format!("Invalid user name {:?}", user))?;
+    unsafe {
+        match id {
+            Some(uid) => libc::getpwuid_r(uid, &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found),
+            None => libc::getpwnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found),
+        };
+    }
+    match (found.is_null(), id) {
+        (false, _) => Ok(User {
+            name: Some(unsafe { std::ffi::CStr::from_ptr(entry.pw_name) }.to_owned()),
+            uid: entry.pw_uid,
+            gid: Some(entry.pw_gid),
+        }),
+        (true, Some(uid)) => Ok(User { name: None, uid, gid: None }),
+        (true, None) => Err(format!("No user {}", user)),
+    }
+}
+
+#[cfg(unix)]
+fn find_group(group: &str) -> Result<libc::gid_t, String> {
+    if let Ok(gid) = group.parse::<libc::gid_t>() {
+        return Ok(gid);
+    }
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    let name = std::ffi::CString::new(group).map_err(|_| format!("Invalid group name {:?}", group))?;
+    unsafe { libc::getgrnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found) };
+    if found.is_null() {
+        return Err(format!("No group {}", group));
+    }
+    Ok(entry.gr_gid)
+}
+
+// A pipe whose lines a thread sends to the local syslog daemon, as digitemp_native[pid]
+#[cfg(unix)]
+fn syslog() -> Result<PipeWriter, String> {
+    let tag = format!("<{}>{}[{}]: ", SYSLOG_PRIORITY, crate::cli::BIN_NAME, std::process::id());
+    let socket = std::os::unix::net::UnixDatagram::unbound().ok();
+    lines(move |line| {
+        // Without a syslog daemon the messages are lost, as they would be on a closed terminal
+        if let Some(socket) = &socket {
+            let _ = socket.send_to(format!("{}{}", tag, line).as_bytes(), "/dev/log");
+        }
+    })
+}
+
+// A pipe whose lines a thread appends to `log` after the time
+#[cfg(any(unix, windows))]
+fn timestamped(mut log: LogFile) -> Result<PipeWriter, String> {
+    lines(move |line| {
+        // Nowhere left to report a failed write
+        let _ = log.append(&format!("{} {}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"), line));
+    })
+}
+
+// A pipe whose lines a thread hands to `line`
+#[cfg(any(unix, windows))]
+fn lines(mut line: impl FnMut(String) + Send + 'static) -> Result<PipeWriter, String> {
+    let (reader, writer) = std::io::pipe().map_err(|e| format!("Cannot create pipe: {}", e))?;
+    std::thread::spawn(move || {
+        for text in BufReader::new(reader).lines().map_while(Result::ok) {
+            line(text);
+        }
+    });
+    Ok(writer)
+}
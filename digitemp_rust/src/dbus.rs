@@ -0,0 +1,237 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
+use zbus::blocking::connection::Builder;
+use zbus::blocking::Connection;
+use zbus::zvariant::Value;
+use crate::config::{format_rom, Config, DbusBus, SensorConfig};
+use crate::{OneWireError, Reading};
+
+const ROOT: &str = "/org/digitemp/Sensors";
+const INTERFACE: &str = "org.digitemp.Sensor1";
+
+// A connection that owns a name, with the sensor objects it serves
+struct Service {
+    connection: Connection,
+    paths: Mutex<HashSet<String>>,
+}
+
+type Services = HashMap<(DbusBus, String), Weak<Service>>;
+
+// Services by bus and name. A reload opens the new sinks before the old ones are dropped,
+// so a sink for the same name takes over the connection, and its objects, instead of
+// failing to own a name that is still taken.
+fn services() -> &'static Mutex<Services> {
+    static SERVICES: OnceLock<Mutex<Services>> = OnceLock::new();
+    SERVICES.get_or_init(Default::default)
+}
+
+// One sensor object; the properties are what the last pass found
+struct SensorObject {
+    rom: String,
+    label: String,
+    index: u32,
+    bus: String,
+    group: String,
+    celsius: f64,
+    timestamp: i64,
+    error: String,
+}
+
+#[zbus::interface(name = "org.digitemp.Sensor1")]
+impl SensorObject {
+    #[zbus(property)]
+    fn rom(&self) -> &str {
+        &self.rom
+    }
+
+    // The configured label, or empty
+    #[zbus(property)]
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    #[zbus(property)]
+    fn index(&self) -> u32 {
+        self.index
+    }
+
+    #[zbus(property)]
+    fn bus(&self) -> &str {
+        &self.bus
+    }
+
+    #[zbus(property)]
+    fn group(&self) -> &str {
+        &self.group
+    }
+
+    // NaN until the sensor has been read
+    #[zbus(property)]
+    fn celsius(&self) -> f64 {
+        self.celsius
+    }
+
+    // Unix time of the last reading or error, 0 before the first
+    #[zbus(property)]
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    // Why the last reading failed; empty after a good one
+    #[zbus(property)]
+    fn error(&self) -> &str {
+        &self.error
+    }
+}
+
+// D-Bus service: every configured sensor is an object /org/digitemp/Sensors/<ROM> with the
+// org.digitemp.Sensor1 properties, and one PropertiesChanged signal per reading or error,
+// so desktop widgets and other daemons follow the readings without polling or networking.
+// An ObjectManager on /org/digitemp/Sensors lists the sensors and signals those added and
+// removed by a scan or a reload. zbus answers calls from a thread of its own.
+pub struct DbusSink {
+    service: Arc<Service>,
+    name: String,
+    // Report a failing bus once, not for every reading
+    failing: bool,
+}
+
+impl DbusSink {
+    pub fn open(bus: DbusBus, name: &str) -> Result<Self, String> {
+        let mut services = services().lock().unwrap_or_else(|e| e.into_inner());
+        services.retain(|_, service| service.strong_count() > 0);
+        let key = (bus, name.to_string());
+        if let Some(service) = services.get(&key).and_then(Weak::upgrade) {
+            return Ok(DbusSink { service, name: name.to_string(), failing: false });
+        }
+        let (builder, which) = match bus {
+            DbusBus::System => (Builder::system(), "system"),
+            DbusBus::Session => (Builder::session(), "session"),
+        };
+        let error = |e: zbus::Error| format!("Cannot register {} on the {} bus: {}", name, which, e);
+        let builder = builder.map_err(error)?;
+        let connection = builder.name(name).map_err(error)?
+            .serve_at(ROOT, zbus::fdo::ObjectManager).map_err(error)?
+            .build().map_err(error)?;
+        let service = Arc::new(Service { connection, paths: Mutex::new(HashSet::new()) });
+        services.insert(key, Arc::downgrade(&service));
+        Ok(DbusSink { service, name: name.to_string(), failing: false })
+    }
+
+    // Objects for the enabled sensors, and none for the others
+    pub fn announce(&mut self, config: &Config) {
+        let mut paths = HashSet::new();
+        for (index, sensor) in config.sensors.iter().enumerate().filter(|(_, sensor)| sensor.enabled) {
+            let path = path(sensor);
+            let result = self.add(&path, config.adapter_name(sensor), index, sensor);
+            self.report(result);
+            paths.insert(path);
+        }
+        let removed: Vec<String> = self.paths().difference(&paths).cloned().collect();
+        for path in removed {
+            let result = self.connection().object_server().remove::<SensorObject, _>(path.as_str()).map(drop);
+            self.report(result);
+        }
+        *self.paths() = paths;
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading) {
+        let result = self.update(bus, index, sensor, reading.celsius as f64, String::new());
+        self.report(result);
+    }
+
+    pub fn error(&mut self, bus: &str, index: usize, sensor: &SensorConfig, error: Option<&OneWireError>) {
+        let error = error.map_or_else(|| "suspect reading discarded".to_string(), |e| e.to_string());
+        let result = self.update(bus, index, sensor, f64::NAN, error);
+        self.report(result);
+    }
+
+    // An object as the config describes the sensor; one already there keeps its last reading
+    fn add(&self, path: &str, bus: &str, index: usize, sensor: &SensorConfig) -> zbus::Result<()> {
+        let server = self.connection().object_server();
+        let (label, group) = (sensor.label.clone().unwrap_or_default(), sensor.group.clone().unwrap_or_default());
+        let Ok(object) = server.interface::<_, SensorObject>(path) else {
+            server.at(path, SensorObject {
+                rom: format_rom(&sensor.rom),
+                label,
+                index: index as u32,
+                bus: bus.to_string(),
+                group,
+                celsius: f64::NAN,
+                timestamp: 0,
+                error: String::new(),
+            })?;
+            return Ok(());
+        };
+        let mut object = object.get_mut();
+        if object.label == label && object.index == index as u32 && object.bus == bus && object.group == group {
+            return Ok(());
+        }
+        object.label = label.clone();
+        object.index = index as u32;
+        object.bus = bus.to_string();
+        object.group = group.clone();
+        self.changed(path, HashMap::from([
+            ("Label", Value::from(label)),
+            ("Index", Value::from(index as u32)),
+            ("Bus", Value::from(bus)),
+            ("Group", Value::from(group)),
+        ]))
+    }
+
+    fn update(&mut self, bus: &str, index: usize, sensor: &SensorConfig, celsius: f64, error: String)
+              -> zbus::Result<()> {
+        let path = path(sensor);
+        // A sensor read before it was announced, e.g. one found by a scan
+        if self.paths().insert(path.clone()) {
+            self.add(&path, bus, index, sensor)?;
+        }
+        let timestamp = chrono::Utc::now().timestamp();
+        {
+            let object = self.connection().object_server().interface::<_, SensorObject>(path.as_str())?;
+            let mut object = object.get_mut();
+            object.celsius = celsius;
+            object.timestamp = timestamp;
+            object.error = error.clone();
+        }
+        self.changed(&path, HashMap::from([
+            ("Celsius", Value::from(celsius)),
+            ("Timestamp", Value::from(timestamp)),
+            ("Error", Value::from(error)),
+        ]))
+    }
+
+    // One PropertiesChanged signal for all the properties, rather than one per property
+    fn changed(&self, path: &str, properties: HashMap<&str, Value>) -> zbus::Result<()> {
+        self.connection().emit_signal(None::<&str>, path, "org.freedesktop.DBus.Properties", "PropertiesChanged",
+            &(INTERFACE, properties, Vec::<&str>::new()))
+    }
+
+    fn connection(&self) -> &Connection {
+        &self.service.connection
+    }
+
+    fn paths(&self) -> MutexGuard<'_, HashSet<String>> {
+        self.service.paths.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn report(&mut self, result: zbus::Result<()>) {
+        match result {
+            Ok(()) if self.failing => {
+                eprintln!("D-Bus {}: delivering again", self.name);
+                self.failing = false;
+            }
+            Ok(()) => {}
+            Err(e) => {
+                if !self.failing {
+                    eprintln!("D-Bus {}: {}", self.name, e);
+                }
+                self.failing = true;
+            }
+        }
+    }
+}
+
+fn path(sensor: &SensorConfig) -> String {
+    format!("{}/{}", ROOT, format_rom(&sensor.rom))
+}
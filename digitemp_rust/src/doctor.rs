@@ -0,0 +1,141 @@
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::Path;
+use serde::Serialize;
+use crate::config::{format_rom, AdapterConfig};
+use crate::{exit_code, OneWireAdapter};
+
+// Diagnostic steps in the order they run; a failure skips the rest
+const STEPS: [&str; 6] = ["device", "permission", "adapter", "presence", "rom_crc", "scratchpad"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Fail,
+    Skip,
+}
+
+// Outcome of one step for one adapter
+#[derive(Serialize)]
+pub struct Check {
+    pub adapter: String,
+    pub step: &'static str,
+    pub status: Status,
+    pub detail: String,
+    // Exit code a failure of this step maps to
+    #[serde(skip)]
+    pub code: i32,
+}
+
+struct Report {
+    adapter: String,
+    checks: Vec<Check>,
+}
+
+impl Report {
+    fn push(&mut self, status: Status, detail: String, code: i32) {
+        let step = STEPS[self.checks.len()];
+        self.checks.push(Check { adapter: self.adapter.clone(), step, status, detail, code });
+    }
+
+    fn pass(&mut self, detail: String) {
+        self.push(Status::Pass, detail, 0);
+    }
+
+    fn fail(&mut self, detail: String, code: i32) {
+        self.push(Status::Fail, detail, code);
+    }
+
+    fn run(&mut self, tty: &str) {
+        let path = Path::new(tty);
+        if !path.exists() {
+            return self.fail(format!("{} does not exist; check the cable and `dmesg` for the USB serial device",
+                tty), exit_code::PORT_OPEN);
+        }
+        self.pass(format!("{} exists", tty));
+
+        match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(_) => self.pass(format!("{} is readable and writable", tty)),
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                return self.fail(permission_hint(path), exit_code::PORT_OPEN);
+            }
+            Err(e) => return self.fail(format!("cannot open {}: {}", tty, e), exit_code::PORT_OPEN),
+        }
+
+        let mut adapter = match OneWireAdapter::new(tty) {
+            Ok(adapter) => adapter,
+            Err(e) => return self.fail(format!("cannot configure {}: {}", tty, e), exit_code::PORT_OPEN),
+        };
+        let presence = match adapter.reset() {
+            Ok(presence) => {
+                self.pass("adapter answered a bus reset".to_string());
+                presence
+            }
+            Err(e) => {
+                return self.fail(format!("no answer to a bus reset ({}); is this a DS9097-compatible adapter?", e),
+                    exit_code::for_error(&e));
+            }
+        };
+
+        if !presence {
+            return self.fail("no presence pulse: no sensor answered the reset; check the data line, ground \
+                and the pull-up resistor".to_string(), exit_code::NO_SENSORS);
+        }
+        self.pass("presence pulse detected".to_string());
+
+        let roms = match adapter.discover_sensors() {
+            Ok(roms) if !roms.is_empty() => roms,
+            Ok(_) => {
+                return self.fail("no ROM passed the CRC check; the bus is noisy or the cable too long for \
+                    its pull-up".to_string(), exit_code::CRC);
+            }
+            Err(e) => return self.fail(format!("ROM search failed: {}", e), exit_code::for_error(&e)),
+        };
+        let listed: Vec<String> = roms.iter().map(format_rom).collect();
+        self.pass(format!("{} ROM(s) passed the CRC check: {}", roms.len(), listed.join(", ")));
+
+        match adapter.read_scratchpad_checked(&roms[0]) {
+            Ok(_) => self.pass(format!("scratchpad of {} read with a valid CRC", listed[0])),
+            Err(e) => self.fail(format!("scratchpad of {} failed: {}", listed[0], e), exit_code::for_error(&e)),
+        }
+    }
+}
+
+// Run every step against one adapter; steps after a failure are reported as skipped
+pub fn diagnose(adapter: &AdapterConfig) -> Vec<Check> {
+    let mut report = Report { adapter: adapter.name.clone(), checks: Vec::new() };
+    report.run(&adapter.tty);
+    while report.checks.len() < STEPS.len() {
+        report.push(Status::Skip, "skipped after an earlier failure".to_string(), 0);
+    }
+    report.checks
+}
+
+// Name the group that owns the device, which the user has to join
+#[cfg(unix)]
+fn permission_hint(path: &Path) -> String {
+    use std::os::unix::fs::MetadataExt;
+    let group = std::fs::metadata(path).ok().and_then(|metadata| group_name(metadata.gid()));
+    match group {
+        Some(group) => format!("permission denied on {}; add your user to the '{}' group \
+            (sudo usermod -aG {} $USER) and log in again", path.display(), group, group),
+        None => format!("permission denied on {}; run as root or fix the device permissions", path.display()),
+    }
+}
+
+#[cfg(not(unix))]
+fn permission_hint(path: &Path) -> String {
+    format!("permission denied on {}", path.display())
+}
+
+#[cfg(unix)]
+fn group_name(gid: u32) -> Option<String> {
+    let groups = std::fs::read_to_string("/etc/group").ok()?;
+    groups.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let id = fields.nth(1)?.parse::<u32>().ok()?;
+        (id == gid).then(|| name.to_string())
+    })
+}
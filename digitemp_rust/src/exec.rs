@@ -0,0 +1,173 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::alarm::{AlarmEvent, AlarmState};
+use crate::config::{format_duration, format_rom, ExecEvent, SensorConfig};
+use crate::{json, Reading};
+
+// Runs waiting for the command; past this, new ones are dropped
+const QUEUE: usize = 100;
+// How often a running command is checked for having exited
+const POLL: Duration = Duration::from_millis(50);
+
+// One run of the command: its DIGITEMP_* variables and the JSON record for its stdin
+struct Run {
+    env: Vec<(&'static str, String)>,
+    stdin: String,
+}
+
+// Exec hook: a command run for every reading and/or every alarm raised or cleared, told about
+// it by DIGITEMP_* environment variables and by the JSON record on stdin. Runs take turns on a
+// thread of the sink's own, so a slow command never holds up a pass, and one that outlives
+// `timeout` is killed. The command's output goes to stderr, or the daemon log.
+pub struct ExecSink {
+    program: String,
+    readings: bool,
+    alarms: bool,
+    sender: Option<SyncSender<Run>>,
+    thread: Option<JoinHandle<()>>,
+    // Report a full queue once, not for every run
+    dropping: bool,
+}
+
+impl ExecSink {
+    pub fn new(command: &[String], on: &[ExecEvent], timeout: Duration) -> Result<Self, String> {
+        let Some(program) = command.first().filter(|program| !program.is_empty()) else {
+            return Err("Exec sinks need a command".to_string());
+        };
+        let (sender, receiver) = mpsc::sync_channel(QUEUE);
+        let command = command.to_vec();
+        let thread = thread::spawn(move || run_all(&command, timeout, receiver));
+        Ok(ExecSink {
+            program: program.clone(),
+            readings: on.contains(&ExecEvent::Reading),
+            alarms: on.contains(&ExecEvent::Alarm),
+            sender: Some(sender),
+            thread: Some(thread),
+            dropping: false,
+        })
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading,
+                   decimals: Option<usize>) {
+        if !self.readings {
+            return;
+        }
+        let mut env = sensor_env("reading", index, sensor);
+        env.push(("DIGITEMP_BUS", bus.to_string()));
+        env.push(("DIGITEMP_CELSIUS", json::round(reading.celsius, decimals).to_string()));
+        env.push(("DIGITEMP_FAHRENHEIT", json::round(reading.celsius * 9.0 / 5.0 + 32.0, decimals).to_string()));
+        self.send(Run { env, stdin: json::jsonl_reading(bus, index, sensor, reading, decimals) });
+    }
+
+    pub fn alarm(&mut self, index: usize, sensor: &SensorConfig, event: &AlarmEvent) {
+        if !self.alarms {
+            return;
+        }
+        let mut env = sensor_env(event.state.name(), index, sensor);
+        env.push(("DIGITEMP_ALARM_KIND", event.kind.name().to_string()));
+        if let Some(celsius) = event.celsius {
+            env.push(("DIGITEMP_CELSIUS", json::round(celsius, Some(4)).to_string()));
+        }
+        if let Some(rate) = event.rate {
+            env.push(("DIGITEMP_RATE", json::round(rate, Some(4)).to_string()));
+        }
+        if let Some(threshold) = event.threshold {
+            env.push(("DIGITEMP_THRESHOLD", json::round(threshold, Some(4)).to_string()));
+        }
+        if let Some(lasted) = event.lasted {
+            env.push(("DIGITEMP_LASTED", lasted.as_secs().to_string()));
+        }
+        if let AlarmState::Repeated(repeat) = event.state {
+            env.push(("DIGITEMP_REPEAT", repeat.to_string()));
+        }
+        self.send(Run { env, stdin: json::alarm(index, sensor, event) });
+    }
+
+    fn send(&mut self, run: Run) {
+        let Some(sender) = &self.sender else { return };
+        match sender.try_send(run) {
+            Ok(()) => self.dropping = false,
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {
+                if !self.dropping {
+                    eprintln!("Exec {}: the command is not keeping up, skipping runs", self.program);
+                }
+                self.dropping = true;
+            }
+        }
+    }
+}
+
+// Stopping or reloading waits for the queued runs
+impl Drop for ExecSink {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// DIGITEMP_EVENT is reading, raised, repeated, escalated or cleared
+fn sensor_env(event: &str, index: usize, sensor: &SensorConfig) -> Vec<(&'static str, String)> {
+    vec![
+        ("DIGITEMP_EVENT", event.to_string()),
+        ("DIGITEMP_SENSOR", index.to_string()),
+        ("DIGITEMP_ROM", format_rom(&sensor.rom)),
+        ("DIGITEMP_LABEL", sensor.label.clone().unwrap_or_default()),
+        ("DIGITEMP_NAME", sensor.name()),
+        ("DIGITEMP_GROUP", sensor.group.clone().unwrap_or_default()),
+    ]
+}
+
+fn run_all(command: &[String], timeout: Duration, receiver: Receiver<Run>) {
+    // Report a failing command once, not for every run
+    let mut failing = false;
+    for run in receiver {
+        match execute(command, timeout, &run) {
+            Ok(()) => {
+                if failing {
+                    eprintln!("Exec {}: succeeding again", command[0]);
+                    failing = false;
+                }
+            }
+            Err(e) => {
+                if !failing {
+                    eprintln!("Exec {}: {}", command[0], e);
+                    failing = true;
+                }
+            }
+        }
+    }
+}
+
+fn execute(command: &[String], timeout: Duration, run: &Run) -> Result<(), String> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .envs(run.env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::piped())
+        // Kept out of stdout, which carries the readings
+        .stdout(Stdio::from(io::stderr()))
+        .spawn()
+        .map_err(|e| format!("cannot run: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that does not read its input may have closed it already
+        let _ = writeln!(stdin, "{}", run.stdin);
+    }
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => return Err(format!("exited with {}", status)),
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("killed after running for {}", format_duration(timeout)));
+            }
+            Ok(None) => thread::sleep(POLL),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
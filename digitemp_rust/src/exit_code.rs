@@ -0,0 +1,26 @@
+use crate::OneWireError;
+
+// Process exit codes, kept stable so scripts and monitoring wrappers can tell
+// failure classes apart without parsing stderr (1 remains the generic failure)
+pub const PORT_OPEN: i32 = 2;
+pub const NO_SENSORS: i32 = 3;
+pub const CRC: i32 = 4;
+pub const CONFIG: i32 = 5;
+pub const BUS_ERROR: i32 = 6;
+pub const WATCHDOG: i32 = 7;
+pub const SUSPECT_READING: i32 = 8;
+pub const TIMEOUT: i32 = 9;
+pub const USAGE: i32 = 64;
+
+pub fn for_error(error: &OneWireError) -> i32 {
+    match error {
+        OneWireError::AdapterUnavailable(_) => PORT_OPEN,
+        OneWireError::CrcMismatch => CRC,
+        OneWireError::WatchdogTimeout(_) => WATCHDOG,
+        OneWireError::InvalidTemperature(_) => SUSPECT_READING,
+        OneWireError::DeviceNotPresent
+        | OneWireError::VerifyMismatch
+        | OneWireError::SerialError(_)
+        | OneWireError::IoError(_) => BUS_ERROR,
+    }
+}
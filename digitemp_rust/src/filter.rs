@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+// Outcome of checking a reading against the previous accepted value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Accepted,
+    Suspect { previous: f32 },
+}
+
+// Rejects single-cycle jumps larger than a configured delta.
+// A jump that is confirmed by the following reading is accepted, so a
+// real step change is only delayed by one cycle instead of being lost.
+pub struct SpikeFilter {
+    max_delta: Option<f32>,
+    last: HashMap<[u8; 8], f32>,
+    pending: HashMap<[u8; 8], f32>,
+}
+
+impl SpikeFilter {
+    pub fn new(max_delta: Option<f32>) -> Self {
+        SpikeFilter {
+            max_delta,
+            last: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn check(&mut self, rom: &[u8; 8], temp_c: f32) -> Verdict {
+        let max_delta = match self.max_delta {
+            Some(delta) => delta,
+            None => return Verdict::Accepted,
+        };
+
+        let previous = match self.last.get(rom) {
+            Some(&previous) => previous,
+            None => {
+                self.last.insert(*rom, temp_c);
+                return Verdict::Accepted;
+            }
+        };
+
+        if (temp_c - previous).abs() <= max_delta {
+            self.pending.remove(rom);
+            self.last.insert(*rom, temp_c);
+            return Verdict::Accepted;
+        }
+
+        // Second consecutive reading near the suspect value confirms the jump
+        if let Some(candidate) = self.pending.remove(rom) {
+            if (temp_c - candidate).abs() <= max_delta {
+                self.last.insert(*rom, temp_c);
+                return Verdict::Accepted;
+            }
+        }
+
+        self.pending.insert(*rom, temp_c);
+        Verdict::Suspect { previous }
+    }
+}
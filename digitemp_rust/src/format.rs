@@ -0,0 +1,108 @@
+use chrono::{DateTime, Local};
+use crate::config::{format_rom, Unit};
+
+// strftime conversions passed through to chrono; anything else is copied literally
+const TIME_SPECS: &str = "aAbBdeHIjmMpSyYZzTDF";
+
+// Values available to a digitemp-style output template
+pub struct FormatContext<'a> {
+    pub sensor: &'a str,
+    pub rom: &'a [u8; 8],
+    pub celsius: f32,
+    pub time: DateTime<Local>,
+    // Precision of %C/%F/%K without an explicit one; printf's 6 when unset
+    pub decimals: Option<usize>,
+}
+
+// Line used when no LOG_FORMAT is configured. Without unit/decimals settings this is
+// the original digitemp format: "%b %d %H:%M:%S Sensor %s C: %.2C F: %.2F"
+pub fn default_template(unit: Option<Unit>, decimals: Option<usize>) -> String {
+    let d = decimals.unwrap_or(2);
+    let values = match unit.unwrap_or(Unit::Both) {
+        Unit::Celsius => format!("C: %.{}C", d),
+        Unit::Fahrenheit => format!("F: %.{}F", d),
+        Unit::Kelvin => format!("K: %.{}K", d),
+        Unit::Both => format!("C: %.{d}C F: %.{d}F", d = d),
+    };
+    format!("%b %d %H:%M:%S Sensor %s {}", values)
+}
+
+// Bare temperature value(s) in the given unit, space-separated for `Both`
+pub fn format_temperature(celsius: f32, unit: Unit, decimals: usize) -> String {
+    let fahrenheit = celsius * 9.0 / 5.0 + 32.0;
+    match unit {
+        Unit::Celsius => format!("{:.*}", decimals, celsius),
+        Unit::Fahrenheit => format!("{:.*}", decimals, fahrenheit),
+        Unit::Kelvin => format!("{:.*}", decimals, celsius + 273.15),
+        Unit::Both => format!("{:.*} {:.*}", decimals, celsius, decimals, fahrenheit),
+    }
+}
+
+// Render a digitemp LOG_FORMAT template:
+//   %s sensor (label or index)   %R ROM in hex          %N seconds since epoch
+//   %C %F %K temperature, with printf precision (%.2C); `decimals` or 6 by default
+//   %b %d %H:%M:%S and other strftime fields            %% literal percent
+pub fn render(template: &str, ctx: &FormatContext) -> String {
+    let mut out = String::with_capacity(template.len() + 16);
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        // Optional printf-style width/precision, e.g. "%.2C" or "%6.1F"
+        let mut spec = String::new();
+        while let Some(&next) = chars.peek() {
+            if next.is_ascii_digit() || next == '.' || next == '-' {
+                spec.push(next);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+
+        let Some(conversion) = chars.next() else {
+            out.push('%');
+            out.push_str(&spec);
+            break;
+        };
+
+        match conversion {
+            '%' => out.push('%'),
+            'C' => out.push_str(&format_number(ctx.celsius, &spec, ctx.decimals)),
+            'F' => out.push_str(&format_number(ctx.celsius * 9.0 / 5.0 + 32.0, &spec, ctx.decimals)),
+            'K' => out.push_str(&format_number(ctx.celsius + 273.15, &spec, ctx.decimals)),
+            's' => out.push_str(ctx.sensor),
+            'R' => out.push_str(&format_rom(ctx.rom)),
+            'N' => out.push_str(&ctx.time.timestamp().to_string()),
+            c if spec.is_empty() && TIME_SPECS.contains(c) => {
+                out.push_str(&ctx.time.format(&format!("%{}", c)).to_string());
+            }
+            other => {
+                out.push('%');
+                out.push_str(&spec);
+                out.push(other);
+            }
+        }
+    }
+
+    out
+}
+
+// Apply a printf-like "[-][width][.precision]" spec to a float
+fn format_number(value: f32, spec: &str, decimals: Option<usize>) -> String {
+    let (width, precision) = match spec.split_once('.') {
+        Some((width, precision)) => (width, precision.parse().unwrap_or(0)),
+        None => (spec, decimals.unwrap_or(6)),
+    };
+    let left_align = width.starts_with('-');
+    let width: usize = width.trim_start_matches('-').parse().unwrap_or(0);
+
+    if left_align {
+        format!("{:<width$.precision$}", value, width = width, precision = precision)
+    } else {
+        format!("{:>width$.precision$}", value, width = width, precision = precision)
+    }
+}
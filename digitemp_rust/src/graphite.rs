@@ -0,0 +1,101 @@
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+use crate::config::{format_rom, SensorConfig, Transport};
+use crate::spool::{self, Spool};
+
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Graphite/carbon plaintext protocol: `<prefix>.<sensor>.temperature <value> <unix time>`.
+// The lines of a pass are sent together; a broken TCP connection is reopened on the next
+// pass. The lines of a pass that cannot be delivered are dropped, or kept in the spool
+// and replayed when there is one.
+pub struct GraphiteSink {
+    address: String,
+    transport: Transport,
+    prefix: String,
+    lines: Vec<String>,
+    stream: Option<TcpStream>,
+    spool: Option<Spool>,
+    // Report a failing server once, not on every pass
+    failing: bool,
+}
+
+impl GraphiteSink {
+    pub fn new(address: &str, transport: Transport, prefix: &str, spool: Option<Spool>) -> Self {
+        GraphiteSink {
+            address: address.to_string(),
+            transport,
+            prefix: prefix.trim_end_matches('.').to_string(),
+            lines: Vec::new(),
+            stream: None,
+            spool,
+            failing: false,
+        }
+    }
+
+    pub fn reading(&mut self, sensor: &SensorConfig, celsius: f32) {
+        let name = sensor.label.as_deref().map_or_else(|| format_rom(&sensor.rom), metric_name);
+        let path = if self.prefix.is_empty() { name } else { format!("{}.{}", self.prefix, name) };
+        self.lines.push(format!("{}.temperature {} {}", path, celsius, chrono::Utc::now().timestamp()));
+    }
+
+    pub fn pass_done(&mut self) {
+        if self.lines.is_empty() {
+            return;
+        }
+        let lines = std::mem::take(&mut self.lines);
+        let mut spool = self.spool.take();
+        let sent = match spool.as_mut() {
+            Some(spool) => spool.deliver(lines, |lines| self.send(lines)),
+            None => self.send(&lines),
+        };
+        self.spool = spool;
+        match sent {
+            Ok(()) if self.failing => {
+                eprintln!("Graphite {}: delivering again", self.address);
+                self.failing = false;
+            }
+            Ok(()) => {}
+            Err(e) => {
+                if !self.failing {
+                    eprintln!("Graphite {}: {} ({})", self.address, e, spool::kept(self.spool.as_ref()));
+                }
+                self.failing = true;
+            }
+        }
+    }
+
+    fn send(&mut self, lines: &[String]) -> io::Result<()> {
+        let lines: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        let address = self.resolve()?;
+        if self.transport == Transport::Udp {
+            let bind = if address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+            UdpSocket::bind(bind)?.send_to(lines.as_bytes(), address)?;
+            return Ok(());
+        }
+
+        // A connection the server closed is only noticed on write: retry once on a new one
+        if let Some(stream) = self.stream.as_mut() {
+            if stream.write_all(lines.as_bytes()).is_ok() {
+                return Ok(());
+            }
+            self.stream = None;
+        }
+        let mut stream = TcpStream::connect_timeout(&address, NETWORK_TIMEOUT)?;
+        stream.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+        stream.write_all(lines.as_bytes())?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn resolve(&self) -> io::Result<SocketAddr> {
+        self.address.to_socket_addrs()?.next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address did not resolve"))
+    }
+}
+
+// Dots separate Graphite path components, so labels keep only safe characters
+fn metric_name(label: &str) -> String {
+    label.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
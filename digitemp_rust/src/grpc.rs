@@ -0,0 +1,291 @@
+use std::net::SocketAddr;
+use std::sync::mpsc::Sender;
+use std::thread;
+use serde::Deserialize;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Response, Status};
+use crate::api::{self, Action, ApiSink, Request, SensorView};
+use crate::systemd;
+
+// Server side of proto/digitemp.proto, generated by build.rs
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/digitemp.v1.Sensors.rs"));
+}
+
+use generated::sensors_server::{Sensors, SensorsServer};
+
+// Events buffered per stream before the reading side waits for a slow client
+const STREAM_BUFFER: usize = 64;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListSensorsRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListSensorsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub sensors: Vec<Sensor>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Sensor {
+    #[prost(uint32, tag = "1")]
+    pub sensor: u32,
+    #[prost(string, tag = "2")]
+    pub rom: String,
+    #[prost(string, tag = "3")]
+    pub family: String,
+    #[prost(string, optional, tag = "4")]
+    pub label: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub group: Option<String>,
+    #[prost(string, tag = "6")]
+    pub bus: String,
+    #[prost(bool, tag = "7")]
+    pub enabled: bool,
+    #[prost(double, optional, tag = "8")]
+    pub celsius: Option<f64>,
+    #[prost(double, optional, tag = "9")]
+    pub raw_celsius: Option<f64>,
+    #[prost(string, optional, tag = "10")]
+    pub timestamp: Option<String>,
+    #[prost(string, optional, tag = "11")]
+    pub error: Option<String>,
+    #[prost(uint64, tag = "12")]
+    pub reads: u64,
+    #[prost(uint64, tag = "13")]
+    pub errors: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetReadingRequest {
+    #[prost(string, tag = "1")]
+    pub sensor: String,
+    #[prost(bool, tag = "2")]
+    pub fresh: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamReadingsRequest {
+    #[prost(string, repeated, tag = "1")]
+    pub sensors: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReadingEvent {
+    #[prost(string, tag = "1")]
+    pub timestamp: String,
+    #[prost(uint32, tag = "2")]
+    pub sensor: u32,
+    #[prost(string, tag = "3")]
+    pub rom: String,
+    #[prost(string, optional, tag = "4")]
+    pub label: Option<String>,
+    #[prost(string, tag = "5")]
+    pub bus: String,
+    #[prost(double, optional, tag = "6")]
+    pub celsius: Option<f64>,
+    #[prost(double, optional, tag = "7")]
+    pub raw_celsius: Option<f64>,
+    #[prost(string, optional, tag = "8")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TriggerScanRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TriggerScanResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub buses: Vec<ScanBus>,
+}
+
+// Also the `scan --json` record the read loop answers a scan with
+#[derive(Clone, PartialEq, prost::Message, Deserialize)]
+pub struct ScanBus {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub tty: String,
+    #[prost(message, repeated, tag = "3")]
+    #[serde(rename = "sensors")]
+    pub devices: Vec<Device>,
+}
+
+#[derive(Clone, PartialEq, prost::Message, Deserialize)]
+pub struct Device {
+    #[prost(string, tag = "1")]
+    pub rom: String,
+    #[prost(string, optional, tag = "2")]
+    pub label: Option<String>,
+}
+
+// The readings and error records streamed to /ws, as far as ReadingEvent needs them
+#[derive(Deserialize)]
+struct EventRecord {
+    timestamp: String,
+    sensor: usize,
+    celsius: Option<f64>,
+    quality: Option<Quality>,
+    error: Option<ErrorRecord>,
+}
+
+#[derive(Deserialize)]
+struct Quality {
+    raw_celsius: f64,
+}
+
+#[derive(Deserialize)]
+struct ErrorRecord {
+    message: String,
+}
+
+struct Service {
+    sink: ApiSink,
+    sender: Sender<Request>,
+}
+
+// gRPC service of `serve`, answered from the same readings as the HTTP API. Immediate reads
+// and scans are carried out by the read loop, like /read/{id}, so they wait for a pass in
+// progress. The server runs on its own thread with a Tokio runtime.
+pub fn start(address: &str, sink: ApiSink, sender: Sender<Request>) -> Result<(), String> {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()
+        .map_err(|e| format!("Cannot start the gRPC runtime: {}", e))?;
+    let listener = match systemd::activated_tcp(address, "grpc") {
+        Some(listener) => {
+            let listener = listener?;
+            listener.set_nonblocking(true).map_err(|e| format!("Cannot listen on {}: {}", address, e))?;
+            let _runtime = runtime.enter();
+            tokio::net::TcpListener::from_std(listener).map_err(|e| format!("Cannot listen on {}: {}", address, e))?
+        }
+        None => {
+            let socket: SocketAddr = address.parse()
+                .map_err(|e| format!("Invalid gRPC address '{}': {}", address, e))?;
+            runtime.block_on(tokio::net::TcpListener::bind(socket))
+                .map_err(|e| format!("Cannot listen on {}: {}", address, e))?
+        }
+    };
+    thread::spawn(move || {
+        let server = tonic::transport::Server::builder()
+            .add_service(SensorsServer::new(Service { sink, sender }))
+            .serve_with_incoming(TcpListenerStream::new(listener));
+        if let Err(e) = runtime.block_on(server) {
+            eprintln!("gRPC: {}", e);
+        }
+    });
+    Ok(())
+}
+
+#[tonic::async_trait]
+impl Sensors for Service {
+    type StreamReadingsStream = ReceiverStream<Result<ReadingEvent, Status>>;
+
+    async fn list_sensors(&self, _: tonic::Request<ListSensorsRequest>)
+                          -> Result<Response<ListSensorsResponse>, Status> {
+        let sensors = self.sink.views().iter().map(sensor).collect();
+        Ok(Response::new(ListSensorsResponse { sensors }))
+    }
+
+    async fn get_reading(&self, request: tonic::Request<GetReadingRequest>) -> Result<Response<Sensor>, Status> {
+        let GetReadingRequest { sensor: id, fresh } = request.into_inner();
+        if fresh {
+            let sender = self.sender.clone();
+            let (status, body) = tokio::task::spawn_blocking(move || Request::send(&sender, Action::Read(id)))
+                .await.map_err(|e| Status::internal(e.to_string()))?;
+            if status != 200 {
+                return Err(to_status(status, &body));
+            }
+            // The body is the sensor's /sensors/{id} entry
+            let record: serde_json::Value = serde_json::from_str(&body).unwrap_or_default();
+            let views = self.sink.views();
+            let view = views.iter().find(|view| record["sensor"] == view.sensor)
+                .ok_or_else(|| Status::internal("the sensor was removed by a reload"))?;
+            return Ok(Response::new(sensor(view)));
+        }
+        match api::find(&self.sink.views(), &id) {
+            Some(view) => Ok(Response::new(sensor(view))),
+            None => Err(Status::not_found(format!("no sensor '{}'", id))),
+        }
+    }
+
+    async fn stream_readings(&self, request: tonic::Request<StreamReadingsRequest>)
+                             -> Result<Response<Self::StreamReadingsStream>, Status> {
+        let views = self.sink.views();
+        let mut selected = Vec::new();
+        for id in &request.get_ref().sensors {
+            let view = api::find(&views, id).ok_or_else(|| Status::not_found(format!("no sensor '{}'", id)))?;
+            selected.push(view.sensor);
+        }
+
+        let events = self.sink.subscribe(false);
+        let (sender, receiver) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        let sink = self.sink.clone();
+        // Events come from a blocking channel; a closed stream shows at the next one
+        tokio::task::spawn_blocking(move || {
+            for (_, data) in events {
+                let Ok(record) = serde_json::from_str::<EventRecord>(&data) else { continue };
+                if !selected.is_empty() && !selected.contains(&record.sensor) {
+                    continue;
+                }
+                let views = sink.views();
+                let Some(view) = views.iter().find(|view| view.sensor == record.sensor) else { continue };
+                let event = ReadingEvent {
+                    timestamp: record.timestamp,
+                    sensor: view.sensor as u32,
+                    rom: view.rom.clone(),
+                    label: view.label.clone(),
+                    bus: view.bus.clone(),
+                    celsius: record.celsius,
+                    raw_celsius: record.quality.map(|quality| quality.raw_celsius),
+                    error: record.error.map(|error| error.message),
+                };
+                if sender.blocking_send(Ok(event)).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn trigger_scan(&self, _: tonic::Request<TriggerScanRequest>)
+                          -> Result<Response<TriggerScanResponse>, Status> {
+        let sender = self.sender.clone();
+        let (status, body) = tokio::task::spawn_blocking(move || Request::send(&sender, Action::Scan))
+            .await.map_err(|e| Status::internal(e.to_string()))?;
+        if status != 200 {
+            return Err(to_status(status, &body));
+        }
+        let buses = serde_json::from_str(&body).map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(TriggerScanResponse { buses }))
+    }
+}
+
+fn sensor(view: &SensorView) -> Sensor {
+    Sensor {
+        sensor: view.sensor as u32,
+        rom: view.rom.clone(),
+        family: view.family.to_string(),
+        label: view.label.clone(),
+        group: view.group.clone(),
+        bus: view.bus.clone(),
+        enabled: view.enabled,
+        celsius: view.celsius,
+        raw_celsius: view.raw_celsius,
+        timestamp: view.timestamp.clone(),
+        error: view.error.clone(),
+        reads: view.reads,
+        errors: view.errors,
+    }
+}
+
+// An HTTP status and JSON error body from the read loop as a gRPC status. The body is
+// {"error": "..."} or a `--json` error record with the message inside.
+fn to_status(status: u16, body: &str) -> Status {
+    let record: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+    let message = record["error"]["message"].as_str().or(record["error"].as_str()).unwrap_or(body).to_string();
+    match status {
+        404 => Status::not_found(message),
+        502 | 503 => Status::unavailable(message),
+        504 => Status::deadline_exceeded(message),
+        _ => Status::internal(message),
+    }
+}
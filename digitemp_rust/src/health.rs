@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+// Status change reported when a sensor crosses the failure threshold
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HealthEvent {
+    Degraded { failures: u32 },
+    Recovered { failures: u32 },
+}
+
+#[derive(Debug, Default)]
+struct SensorHealth {
+    consecutive_failures: u32,
+    degraded: bool,
+    skipped: u32,
+}
+
+// Tracks consecutive read failures per sensor. Once a sensor reaches the
+// failure threshold it is only polled once every `degraded_every` passes,
+// so a dead probe stops adding serial timeouts to every cycle.
+pub struct HealthTracker {
+    threshold: u32,
+    degraded_every: u32,
+    sensors: HashMap<[u8; 8], SensorHealth>,
+}
+
+impl HealthTracker {
+    pub fn new(threshold: u32, degraded_every: u32) -> Self {
+        HealthTracker {
+            threshold: threshold.max(1),
+            degraded_every: degraded_every.max(1),
+            sensors: HashMap::new(),
+        }
+    }
+
+    pub fn degraded_every(&self) -> u32 {
+        self.degraded_every
+    }
+
+    // Backed off after failing, read only every `degraded_every` passes
+    pub fn is_degraded(&self, rom: &[u8; 8]) -> bool {
+        self.sensors.get(rom).is_some_and(|health| health.degraded)
+    }
+
+    // Decide whether the sensor should be read during this pass
+    pub fn should_poll(&mut self, rom: &[u8; 8]) -> bool {
+        let degraded_every = self.degraded_every;
+        let health = self.sensors.entry(*rom).or_default();
+        if !health.degraded {
+            return true;
+        }
+
+        health.skipped += 1;
+        if health.skipped >= degraded_every {
+            health.skipped = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn record_success(&mut self, rom: &[u8; 8]) -> Option<HealthEvent> {
+        let health = self.sensors.entry(*rom).or_default();
+        let failures = health.consecutive_failures;
+        let was_degraded = health.degraded;
+
+        *health = SensorHealth::default();
+        if was_degraded {
+            Some(HealthEvent::Recovered { failures })
+        } else {
+            None
+        }
+    }
+
+    pub fn record_failure(&mut self, rom: &[u8; 8]) -> Option<HealthEvent> {
+        let threshold = self.threshold;
+        let health = self.sensors.entry(*rom).or_default();
+        health.consecutive_failures += 1;
+
+        if !health.degraded && health.consecutive_failures >= threshold {
+            health.degraded = true;
+            health.skipped = 0;
+            Some(HealthEvent::Degraded { failures: health.consecutive_failures })
+        } else {
+            None
+        }
+    }
+}
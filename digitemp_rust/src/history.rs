@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use crate::config::{format_rom, parse_duration, SensorConfig};
+
+// Segment files start with this, so a foreign file in the directory is never parsed
+const MAGIC: &[u8; 8] = b"DTHIST01";
+// Unix time (i64), ROM (8 bytes), °C (f32), little-endian
+const RECORD_LEN: usize = 20;
+const SEGMENT_EXTENSION: &str = "seg";
+
+// Statistics of one sensor over the whole range or one period of it
+pub struct Summary {
+    pub rom: String,
+    // Start and end (exclusive) as Unix time
+    pub from: i64,
+    pub to: i64,
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+
+// Built-in history: one append-only segment file per UTC day, 20 bytes per reading. Segments
+// older than the retention are deleted, so the directory works as a ring buffer.
+pub struct HistoryStore {
+    directory: PathBuf,
+    retention: Duration,
+    records: Vec<u8>,
+    // Day of the last segment written; retention is applied when it changes
+    day: Option<NaiveDate>,
+    // Report a failing write once, not on every pass
+    failing: bool,
+}
+
+impl HistoryStore {
+    pub fn open(directory: &Path, retention: Duration) -> Result<Self, String> {
+        std::fs::create_dir_all(directory)
+            .map_err(|e| format!("Cannot create history directory {}: {}", directory.display(), e))?;
+        Ok(HistoryStore { directory: directory.to_path_buf(), retention, records: Vec::new(), day: None,
+                          failing: false })
+    }
+
+    pub fn reading(&mut self, sensor: &SensorConfig, celsius: f32) {
+        self.records.extend_from_slice(&Utc::now().timestamp().to_le_bytes());
+        self.records.extend_from_slice(&sensor.rom);
+        self.records.extend_from_slice(&celsius.to_le_bytes());
+    }
+
+    pub fn pass_done(&mut self) {
+        if self.records.is_empty() {
+            return;
+        }
+        let today = Utc::now().date_naive();
+        if self.day != Some(today) {
+            self.day = Some(today);
+            if let Err(e) = self.expire() {
+                eprintln!("Cannot remove old history in {}: {}", self.directory.display(), e);
+            }
+        }
+        let path = segment_path(&self.directory, today);
+        match append(&path, &self.records) {
+            Ok(()) => {
+                self.records.clear();
+                if self.failing {
+                    eprintln!("History {}: writing again", self.directory.display());
+                    self.failing = false;
+                }
+            }
+            Err(e) => {
+                // Kept for the next pass, which stores them under its own day
+                if !self.failing {
+                    eprintln!("Cannot write {}: {} (readings are kept and retried)", path.display(), e);
+                }
+                self.failing = true;
+            }
+        }
+    }
+
+    // Delete the segments whose whole day is older than the retention
+    fn expire(&self) -> io::Result<()> {
+        let oldest = (Utc::now() - self.retention).date_naive();
+        for (day, path) in segments(&self.directory)? {
+            if day < oldest {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn segment_path(directory: &Path, day: NaiveDate) -> PathBuf {
+    directory.join(format!("{}.{}", day.format("%Y-%m-%d"), SEGMENT_EXTENSION))
+}
+
+// Segment files of the directory by day
+fn segments(directory: &Path) -> io::Result<BTreeMap<NaiveDate, PathBuf>> {
+    let mut segments = BTreeMap::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == SEGMENT_EXTENSION) {
+            let day = path.file_stem().and_then(|stem| stem.to_str())
+                .and_then(|stem| NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok());
+            if let Some(day) = day {
+                segments.insert(day, path);
+            }
+        }
+    }
+    Ok(segments)
+}
+
+fn append(path: &Path, records: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let len = file.metadata()?.len();
+    if len == 0 {
+        file.write_all(MAGIC)?;
+    } else {
+        // Cut a record left half-written by a crash, which would shift every later one
+        let tail = (len - MAGIC.len() as u64) % RECORD_LEN as u64;
+        if tail != 0 {
+            file.set_len(len - tail)?;
+        }
+    }
+    file.write_all(records)
+}
+
+// Min/max/avg of the readings in [since, until) per sensor, and per period of `every` seconds
+// (aligned to local midnight) when given. An empty `roms` means every sensor in the store.
+pub fn summarize(directory: &Path, roms: &[String], since: i64, until: i64, every: Option<i64>)
+                 -> Result<Vec<Summary>, String> {
+    let read_error = |e: io::Error| format!("Cannot read history in {}: {}", directory.display(), e);
+    let first_day = DateTime::from_timestamp(since, 0).unwrap_or_default().date_naive();
+    let last_day = DateTime::from_timestamp(until, 0).unwrap_or_default().date_naive();
+    let offset = i64::from(Local::now().offset().fix().local_minus_utc());
+
+    // (count, min, max, sum) by sensor and period start
+    let mut periods: BTreeMap<(String, i64), (u64, f64, f64, f64)> = BTreeMap::new();
+    for (day, path) in segments(directory).map_err(read_error)? {
+        if day < first_day || day > last_day {
+            continue;
+        }
+        let mut data = Vec::new();
+        File::open(&path).and_then(|mut file| file.read_to_end(&mut data)).map_err(read_error)?;
+        if !data.starts_with(MAGIC) {
+            eprintln!("Skipping {}: not a history segment", path.display());
+            continue;
+        }
+        for record in data[MAGIC.len()..].chunks_exact(RECORD_LEN) {
+            let time = i64::from_le_bytes(record[..8].try_into().expect("8 time bytes"));
+            if time < since || time >= until {
+                continue;
+            }
+            let rom = format_rom(record[8..16].try_into().expect("8 ROM bytes"));
+            if !roms.is_empty() && !roms.contains(&rom) {
+                continue;
+            }
+            // Four decimals keep the sensor's 1/16 °C steps without float noise
+            let celsius = f64::from(f32::from_le_bytes(record[16..].try_into().expect("4 value bytes")));
+            let celsius = (celsius * 1e4).round() / 1e4;
+            let period = every.map_or(since, |every| (time + offset).div_euclid(every) * every - offset);
+            let entry = periods.entry((rom, period)).or_insert((0, f64::MAX, f64::MIN, 0.0));
+            entry.0 += 1;
+            entry.1 = entry.1.min(celsius);
+            entry.2 = entry.2.max(celsius);
+            entry.3 += celsius;
+        }
+    }
+
+    Ok(periods.into_iter().map(|((rom, period), (count, min, max, sum))| Summary {
+        rom,
+        // The first and last period are cut to the range
+        from: period.max(since),
+        to: every.map_or(until, |every| (period + every).min(until)),
+        count,
+        min,
+        max,
+        avg: (sum / count as f64 * 1e4).round() / 1e4,
+    }).collect())
+}
+
+// A point in time for --since/--until: a duration back from now (24h, 7d), "now", Unix time,
+// an RFC 3339 time, or a local date or date and time (2026-10-01, 2026-10-01 08:00)
+pub fn parse_time(text: &str) -> Result<i64, String> {
+    let text = text.trim();
+    let now = Local::now();
+    if text == "now" {
+        return Ok(now.timestamp());
+    }
+    // Checked before durations, which take a bare number as seconds
+    if let Ok(unix) = text.parse::<i64>() {
+        return Ok(unix);
+    }
+    if let Ok(ago) = parse_duration(text) {
+        return Ok(now.timestamp() - ago.as_secs() as i64);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.timestamp());
+    }
+    let local = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0)));
+    local.and_then(|time| Local.from_local_datetime(&time).earliest())
+        .map(|time| time.timestamp())
+        .ok_or_else(|| format!("invalid time '{}' (expected e.g. 24h, 7d, 2026-10-01 or 2026-10-01 08:00)", text))
+}
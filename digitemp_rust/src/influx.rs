@@ -0,0 +1,160 @@
+use std::io::{self, Write};
+use std::time::Duration;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use ureq::Agent;
+use crate::config::{format_rom, SensorConfig};
+use crate::spool::{self, Spool};
+use crate::{json, Reading};
+
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(10);
+const MEASUREMENT: &str = "temperature";
+
+// InfluxDB 2.x write API: line protocol POSTed to `/api/v2/write` with the bucket's org and
+// an API token, gzip-compressed unless disabled. The lines of `batch` passes go out in one
+// request. Lines that cannot be delivered are dropped, or kept in the spool and replayed
+// when there is one; lines the server rejects as invalid are not retried.
+pub struct InfluxSink {
+    url: String,
+    org: String,
+    bucket: String,
+    token: String,
+    gzip: bool,
+    batch: u32,
+    agent: Agent,
+    lines: Vec<String>,
+    passes: u32,
+    spool: Option<Spool>,
+    // Report a failing server once, not on every pass
+    failing: bool,
+}
+
+impl InfluxSink {
+    pub fn new(url: &str, org: &str, bucket: &str, token: &str, gzip: bool, batch: u32, spool: Option<Spool>)
+               -> Result<Self, String> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(format!("Invalid influx url '{}' (expected e.g. http://influx.lan:8086)", url));
+        }
+        let agent = Agent::new_with_config(Agent::config_builder()
+            .timeout_global(Some(NETWORK_TIMEOUT))
+            // Status codes are sorted into retried and rejected below
+            .http_status_as_error(false)
+            .build());
+        Ok(InfluxSink {
+            url: format!("{}/api/v2/write", url.trim_end_matches('/')),
+            org: org.to_string(),
+            bucket: bucket.to_string(),
+            token: token.to_string(),
+            gzip,
+            batch: batch.max(1),
+            agent,
+            lines: Vec::new(),
+            passes: 0,
+            spool,
+            failing: false,
+        })
+    }
+
+    // `temperature,rom=28...,bus=default,label=attic celsius=21.3125,raw_celsius=21.25 1792224942`
+    pub fn reading(&mut self, bus: &str, sensor: &SensorConfig, reading: &Reading) {
+        let mut line = format!("{},rom={},bus={}", MEASUREMENT, format_rom(&sensor.rom), escape(bus));
+        if let Some(label) = &sensor.label {
+            line.push_str(&format!(",label={}", escape(label)));
+        }
+        if let Some(group) = &sensor.group {
+            line.push_str(&format!(",group={}", escape(group)));
+        }
+        line.push_str(&format!(" celsius={},raw_celsius={} {}", json::round(reading.celsius, Some(4)),
+            json::round(reading.raw_celsius, Some(4)), chrono::Utc::now().timestamp()));
+        self.lines.push(line);
+    }
+
+    pub fn pass_done(&mut self) {
+        self.passes += 1;
+        if self.passes >= self.batch {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.passes = 0;
+        if self.lines.is_empty() {
+            return;
+        }
+        let lines = std::mem::take(&mut self.lines);
+        let mut spool = self.spool.take();
+        let sent = match spool.as_mut() {
+            Some(spool) => spool.deliver(lines, |lines| self.send(lines)),
+            None => self.send(&lines),
+        };
+        self.spool = spool;
+        match sent {
+            Ok(()) if self.failing => {
+                eprintln!("InfluxDB {}: delivering again", self.url);
+                self.failing = false;
+            }
+            Ok(()) => {}
+            Err(e) => {
+                if !self.failing {
+                    eprintln!("InfluxDB {}: {} ({})", self.url, e, spool::kept(self.spool.as_ref()));
+                }
+                self.failing = true;
+            }
+        }
+    }
+
+    fn send(&self, lines: &[String]) -> io::Result<()> {
+        let body: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        let mut request = self.agent.post(&self.url)
+            .query("org", &self.org)
+            .query("bucket", &self.bucket)
+            .query("precision", "s")
+            .header("Authorization", format!("Token {}", self.token))
+            .content_type("text/plain; charset=utf-8");
+        let response = if self.gzip {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body.as_bytes())?;
+            request = request.header("Content-Encoding", "gzip");
+            request.send(encoder.finish()?)
+        } else {
+            request.send(&body)
+        };
+        let mut response = response.map_err(io::Error::other)?;
+        let status = response.status().as_u16();
+        if (200..300).contains(&status) {
+            return Ok(());
+        }
+        // {"code":"invalid","message":"..."}
+        let text = response.body_mut().read_to_string().unwrap_or_default();
+        let message = serde_json::from_str::<serde_json::Value>(&text).ok()
+            .and_then(|error| error["message"].as_str().map(str::to_string))
+            .unwrap_or(text);
+        match status {
+            // Malformed or oversized lines would be rejected again
+            400 | 413 | 422 => {
+                eprintln!("InfluxDB {}: {} lines rejected: {} {}", self.url, lines.len(), status, message);
+                Ok(())
+            }
+            _ => Err(io::Error::other(format!("HTTP {} {}", status, message))),
+        }
+    }
+}
+
+// Stopping or reloading writes what a batch has collected so far
+impl Drop for InfluxSink {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+// Tag values escape commas, equal signs and spaces with a backslash
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
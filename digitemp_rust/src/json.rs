@@ -0,0 +1,292 @@
+use serde::{Deserialize, Serialize};
+use crate::alarm::{AlarmEvent, AlarmState};
+use crate::config::{format_rom, SensorConfig, Unit};
+use crate::{ConversionWait, OneWireError, Reading};
+
+// JSON records printed with --json, one object per line, so output composes with jq
+
+#[derive(Serialize)]
+struct SensorRecord<'a> {
+    sensor: usize,
+    rom: String,
+    label: Option<&'a str>,
+    timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    celsius: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fahrenheit: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kelvin: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quality: Option<Quality>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorRecord>,
+    // A reading from the state file of an earlier run, taken at `timestamp`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stale: bool,
+}
+
+#[derive(Serialize)]
+struct Quality {
+    raw_celsius: f32,
+    retries: u32,
+    crc_failures: u32,
+    conversion_ms: u128,
+    wait: &'static str,
+}
+
+#[derive(Serialize)]
+struct ErrorRecord {
+    kind: &'static str,
+    message: String,
+}
+
+#[derive(Serialize)]
+pub struct ScanBus<'a> {
+    pub name: &'a str,
+    pub tty: &'a str,
+    pub sensors: Vec<ScanSensor<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct ScanSensor<'a> {
+    pub rom: String,
+    pub label: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+pub struct InitSensor<'a> {
+    pub sensor: usize,
+    pub rom: String,
+    pub label: Option<&'a str>,
+    pub status: &'static str,
+}
+
+// A sensor's TH/TL/resolution settings; also the entry format of settings backups
+#[derive(Serialize, Deserialize)]
+pub struct SettingsRecord {
+    pub sensor: usize,
+    pub rom: String,
+    pub label: Option<String>,
+    pub high: i8,
+    pub low: i8,
+    pub resolution: u8,
+    // Whether the settings were copied to EEPROM, for commands that write them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persisted: Option<bool>,
+}
+
+// File written by backup-settings
+#[derive(Serialize, Deserialize)]
+pub struct SettingsBackup {
+    pub created: String,
+    pub sensors: Vec<SettingsRecord>,
+}
+
+#[derive(Serialize)]
+pub struct RenameResult<'a> {
+    pub sensor: usize,
+    pub rom: String,
+    pub previous: Option<&'a str>,
+    pub label: &'a str,
+    pub path: String,
+    pub backup: Option<String>,
+}
+
+// One sensor's statistics from `history`, over the range or one period of it
+#[derive(Serialize)]
+pub struct HistoryRecord<'a> {
+    pub sensor: String,
+    pub rom: &'a str,
+    pub from: String,
+    pub to: String,
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+
+// Outcome of create-rrd for one sensor
+#[derive(Serialize)]
+pub struct RrdFile {
+    pub sensor: usize,
+    pub path: String,
+    // "created" or "exists"
+    pub status: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct InitResult<'a> {
+    pub found: usize,
+    pub sensors: Vec<InitSensor<'a>>,
+    pub path: String,
+    pub backup: Option<String>,
+}
+
+// Fixed-schema record of the jsonl sink, for log shippers
+#[derive(Serialize)]
+struct JsonlRecord<'a> {
+    timestamp: String,
+    bus: &'a str,
+    sensor: usize,
+    rom: String,
+    label: Option<&'a str>,
+    celsius: f64,
+    fahrenheit: f64,
+    quality: Quality,
+}
+
+// A successful reading, in the configured unit(s) and precision
+pub fn reading(index: usize, sensor: &SensorConfig, reading: &Reading, unit: Option<Unit>,
+               decimals: Option<usize>, show_quality: bool) -> String {
+    let mut record = record(index, sensor);
+    set_temperature(&mut record, reading.celsius, unit, decimals);
+    record.quality = show_quality.then(|| quality(reading));
+    to_line(&record)
+}
+
+// The last reading an earlier run stored for the sensor, marked stale
+pub fn stale(index: usize, sensor: &SensorConfig, celsius: f32, timestamp: &str, unit: Option<Unit>,
+             decimals: Option<usize>) -> String {
+    let mut record = record(index, sensor);
+    set_temperature(&mut record, celsius, unit, decimals);
+    record.timestamp = timestamp.to_string();
+    record.stale = true;
+    to_line(&record)
+}
+
+fn set_temperature(record: &mut SensorRecord, celsius: f32, unit: Option<Unit>, decimals: Option<usize>) {
+    let round = |value: f32| round(value, decimals);
+    let (c, f, k) = match unit.unwrap_or(Unit::Both) {
+        Unit::Celsius => (true, false, false),
+        Unit::Fahrenheit => (false, true, false),
+        Unit::Kelvin => (false, false, true),
+        Unit::Both => (true, true, false),
+    };
+    record.celsius = c.then(|| round(celsius));
+    record.fahrenheit = f.then(|| round(celsius * 9.0 / 5.0 + 32.0));
+    record.kelvin = k.then(|| round(celsius + 273.15));
+}
+
+// A successful reading for the jsonl sink: always both units and the read quality
+pub fn jsonl_reading(bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading,
+                     decimals: Option<usize>) -> String {
+    to_line(&JsonlRecord {
+        timestamp: timestamp(),
+        bus,
+        sensor: index,
+        rom: format_rom(&sensor.rom),
+        label: sensor.label.as_deref(),
+        celsius: round(reading.celsius, decimals),
+        fahrenheit: round(reading.celsius * 9.0 / 5.0 + 32.0, decimals),
+        quality: quality(reading),
+    })
+}
+
+pub fn round(value: f32, decimals: Option<usize>) -> f64 {
+    let scale = 10f64.powi(decimals.unwrap_or(2) as i32);
+    (value as f64 * scale).round() / scale
+}
+
+fn quality(reading: &Reading) -> Quality {
+    Quality {
+        raw_celsius: reading.raw_celsius,
+        retries: reading.retries,
+        crc_failures: reading.crc_failures,
+        conversion_ms: reading.conversion_time.as_millis(),
+        wait: match reading.wait {
+            ConversionWait::Polled => "polled",
+            ConversionWait::Fixed => "fixed",
+        },
+    }
+}
+
+pub fn timestamp() -> String {
+    chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+}
+
+// A software alarm raised, notified again or cleared, apart from the readings
+#[derive(Serialize)]
+struct AlarmRecord<'a> {
+    sensor: usize,
+    rom: String,
+    label: Option<&'a str>,
+    timestamp: String,
+    // "raised", "repeated", "escalated" or "cleared"
+    alarm: &'static str,
+    // "high", "low", "rise", "fall", "stale" or "missing"
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    celsius: Option<f64>,
+    // °C per minute, for rise and fall alarms
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate: Option<f64>,
+    // °C, °C per minute for rise and fall alarms, seconds for stale ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    threshold: Option<f64>,
+    // Seconds the alarm has been raised, once repeated, escalated or cleared
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lasted: Option<u64>,
+    // How many times it was notified again, when repeated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repeat: Option<u32>,
+}
+
+pub fn alarm(index: usize, sensor: &SensorConfig, event: &AlarmEvent) -> String {
+    to_line(&AlarmRecord {
+        sensor: index,
+        rom: format_rom(&sensor.rom),
+        label: sensor.label.as_deref(),
+        timestamp: timestamp(),
+        alarm: event.state.name(),
+        kind: event.kind.name(),
+        celsius: event.celsius.map(|celsius| round(celsius, Some(4))),
+        rate: event.rate.map(|rate| round(rate, Some(4))),
+        threshold: event.threshold.map(|threshold| round(threshold, Some(4))),
+        lasted: event.lasted.map(|lasted| lasted.as_secs()),
+        repeat: match event.state {
+            AlarmState::Repeated(repeat) => Some(repeat),
+            _ => None,
+        },
+    })
+}
+
+// A failed or discarded reading
+pub fn error(index: usize, sensor: &SensorConfig, kind: &'static str, message: String) -> String {
+    let mut record = record(index, sensor);
+    record.error = Some(ErrorRecord { kind, message });
+    to_line(&record)
+}
+
+// Machine-readable class of a bus error
+pub fn error_kind(error: &OneWireError) -> &'static str {
+    match error {
+        OneWireError::SerialError(_) => "serial",
+        OneWireError::IoError(_) => "io",
+        OneWireError::DeviceNotPresent => "not_present",
+        OneWireError::AdapterUnavailable(_) => "adapter_unavailable",
+        OneWireError::CrcMismatch => "crc",
+        OneWireError::WatchdogTimeout(_) => "watchdog",
+        OneWireError::InvalidTemperature(_) => "out_of_range",
+        OneWireError::VerifyMismatch => "verify",
+    }
+}
+
+pub fn to_line<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("JSON records always serialize")
+}
+
+fn record(index: usize, sensor: &SensorConfig) -> SensorRecord<'_> {
+    SensorRecord {
+        sensor: index,
+        rom: format_rom(&sensor.rom),
+        label: sensor.label.as_deref(),
+        timestamp: timestamp(),
+        celsius: None,
+        fahrenheit: None,
+        kelvin: None,
+        quality: None,
+        error: None,
+        stale: false,
+    }
+}
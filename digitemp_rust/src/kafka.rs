@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use rdkafka::config::{ClientConfig, RDKafkaLogLevel};
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::producer::{BaseRecord, DeliveryResult, Producer, ProducerContext, ThreadedProducer};
+use rdkafka::ClientContext;
+use crate::config::{format_rom, KafkaFormat, SensorConfig};
+use crate::{json, Reading};
+
+// How long dropping the sink waits for messages still queued, e.g. at the end of `read`
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+// Prefix of the Confluent schema registry wire format, before the schema id
+const MAGIC_BYTE: u8 = 0;
+
+// Reports broker and delivery failures once, until a message is delivered again
+struct Reporter {
+    brokers: String,
+    failing: Arc<AtomicBool>,
+}
+
+impl Reporter {
+    fn failed(&self, error: impl std::fmt::Display) {
+        if !self.failing.swap(true, Ordering::Relaxed) {
+            eprintln!("Kafka {}: {}", self.brokers, error);
+        }
+    }
+}
+
+impl ClientContext for Reporter {
+    fn error(&self, error: KafkaError, reason: &str) {
+        self.failed(format_args!("{} ({})", error, reason));
+    }
+}
+
+impl ProducerContext for Reporter {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        match result {
+            Ok(_) => {
+                if self.failing.swap(false, Ordering::Relaxed) {
+                    eprintln!("Kafka {}: delivering again", self.brokers);
+                }
+            }
+            Err((error, _)) => self.failed(format_args!("message dropped: {}", error)),
+        }
+    }
+}
+
+// Kafka producer through librdkafka: every reading is a message keyed by the sensor's ROM,
+// so a sensor's readings stay in order on one partition. librdkafka queues the messages,
+// batches them and retries failed sends for up to `timeout`; the producer is idempotent,
+// so retries do not duplicate messages.
+pub struct KafkaSink {
+    producer: ThreadedProducer<Reporter>,
+    brokers: String,
+    topic: String,
+    format: KafkaFormat,
+    schema_id: Option<u32>,
+    // Report a full queue once, not for every reading
+    dropping: bool,
+}
+
+impl KafkaSink {
+    pub fn new(brokers: &str, topic: &str, format: KafkaFormat, schema_id: Option<u32>, timeout: Duration,
+               properties: &BTreeMap<String, String>) -> Result<Self, String> {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", brokers)
+            .set("client.id", "digitemp_rust")
+            .set("enable.idempotence", "true")
+            .set("message.timeout.ms", timeout.as_millis().to_string())
+            // librdkafka's own stderr lines repeat what error() reports, on every reconnect
+            .set_log_level(RDKafkaLogLevel::Critical);
+        // Set last, so they can change the defaults above
+        for (key, value) in properties {
+            config.set(key, value);
+        }
+        let reporter = Reporter { brokers: brokers.to_string(), failing: Arc::new(AtomicBool::new(false)) };
+        let producer = config.create_with_context(reporter)
+            .map_err(|e| format!("Cannot create Kafka producer for {}: {}", brokers, e))?;
+        Ok(KafkaSink {
+            producer,
+            brokers: brokers.to_string(),
+            topic: topic.to_string(),
+            format,
+            schema_id,
+            dropping: false,
+        })
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading,
+                   decimals: Option<usize>) {
+        let now = chrono::Utc::now();
+        let rom = format_rom(&sensor.rom);
+        let payload = match self.format {
+            KafkaFormat::Json => json::jsonl_reading(bus, index, sensor, reading, decimals).into_bytes(),
+            KafkaFormat::Avro => {
+                let mut payload = Vec::new();
+                if let Some(schema_id) = self.schema_id {
+                    payload.push(MAGIC_BYTE);
+                    payload.extend_from_slice(&schema_id.to_be_bytes());
+                }
+                avro_reading(&mut payload, now.timestamp_millis(), bus, index, &rom, sensor.label.as_deref(),
+                    json::round(reading.celsius, decimals));
+                payload
+            }
+        };
+        let record = BaseRecord::to(&self.topic).key(&rom).payload(&payload).timestamp(now.timestamp_millis());
+        match self.producer.send(record) {
+            Ok(()) => self.dropping = false,
+            Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) => {
+                if !self.dropping {
+                    eprintln!("Kafka {}: the brokers are not keeping up, dropping readings", self.brokers);
+                }
+                self.dropping = true;
+            }
+            Err((e, _)) => eprintln!("Kafka {}: {}", self.brokers, e),
+        }
+    }
+}
+
+impl Drop for KafkaSink {
+    fn drop(&mut self) {
+        if self.producer.flush(FLUSH_TIMEOUT).is_err() {
+            eprintln!("Kafka {}: messages still queued were dropped", self.brokers);
+        }
+    }
+}
+
+// Avro binary encoding of the record
+//   {"type": "record", "name": "Reading", "namespace": "digitemp", "fields": [
+//     {"name": "timestamp", "type": {"type": "long", "logicalType": "timestamp-millis"}},
+//     {"name": "bus", "type": "string"}, {"name": "sensor", "type": "long"},
+//     {"name": "rom", "type": "string"}, {"name": "label", "type": ["null", "string"]},
+//     {"name": "celsius", "type": "double"}]}
+fn avro_reading(out: &mut Vec<u8>, timestamp: i64, bus: &str, index: usize, rom: &str, label: Option<&str>,
+                celsius: f64) {
+    avro_long(out, timestamp);
+    avro_string(out, bus);
+    avro_long(out, index as i64);
+    avro_string(out, rom);
+    match label {
+        None => avro_long(out, 0),
+        Some(label) => {
+            avro_long(out, 1);
+            avro_string(out, label);
+        }
+    }
+    out.extend_from_slice(&celsius.to_le_bytes());
+}
+
+// Zigzag varint, as Avro encodes int and long
+fn avro_long(out: &mut Vec<u8>, value: i64) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn avro_string(out: &mut Vec<u8>, value: &str) {
+    avro_long(out, value.len() as i64);
+    out.extend_from_slice(value.as_bytes());
+}
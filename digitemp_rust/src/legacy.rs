@@ -0,0 +1,148 @@
+use crate::config::{AdapterConfig, Config, DaemonConfig, SensorConfig, DEFAULT_ADAPTER};
+
+// Reader and writer for the original digitemp rc format (digitemp.conf / .digitemprc):
+//   TTY /dev/ttyUSB0
+//   READ_TIME 1000
+//   LOG_TYPE 1
+//   LOG_FORMAT "%b %d %H:%M:%S Sensor %s C: %.2C F: %.2F"
+//   CNT_FORMAT "%b %d %H:%M:%S Sensor %s #%n %C"
+//   HUM_FORMAT "%b %d %H:%M:%S Sensor %s C: %.2C F: %.2F H: %h%%"
+//   FAIL_TIME 3
+//   LOG /var/log/temperature
+//   SENSORS 2
+//   ROM 0 0x28 0x52 0xC0 0x80 0x00 0x00 0x00 0xA5
+// Unknown keys are ignored, as the original does.
+
+pub fn parse(content: &str) -> Result<Config, String> {
+    let mut config = Config::default();
+    let mut declared_sensors = None;
+
+    for (line_no, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let value = value.trim();
+        let parse_number = |what: &str| -> Result<u64, String> {
+            value.parse().map_err(|_| format!("line {}: invalid {} '{}'", line_no + 1, what, value))
+        };
+
+        match key {
+            "TTY" => {
+                config.adapters = vec![AdapterConfig {
+                    name: DEFAULT_ADAPTER.to_string(),
+                    tty: value.to_string(),
+                }];
+            }
+            "READ_TIME" => config.daemon.read_time_ms = Some(parse_number("READ_TIME")?),
+            "LOG_TYPE" => {
+                config.output.log_type = match parse_number("LOG_TYPE")? {
+                    log_type @ 1..=3 => log_type as u8,
+                    _ => return Err(format!("line {}: LOG_TYPE must be 1, 2 or 3", line_no + 1)),
+                };
+            }
+            "LOG_FORMAT" => config.output.log_format = Some(unquote(value)),
+            "CNT_FORMAT" => config.output.cnt_format = Some(unquote(value)),
+            "HUM_FORMAT" => config.output.hum_format = Some(unquote(value)),
+            "FAIL_TIME" => config.daemon.fail_threshold = parse_number("FAIL_TIME")? as u32,
+            "LOG" => config.output.log = Some(unquote(value).into()),
+            "SENSORS" => declared_sensors = Some(parse_number("SENSORS")? as usize),
+            "ROM" => {
+                let parts: Vec<&str> = value.split_whitespace().collect();
+                if parts.len() < 9 {
+                    return Err(format!("line {}: ROM needs 8 address bytes", line_no + 1));
+                }
+                let mut rom = [0u8; 8];
+                for (i, byte) in rom.iter_mut().enumerate() {
+                    *byte = u8::from_str_radix(parts[i + 1].trim_start_matches("0x"), 16)
+                        .map_err(|_| format!("line {}: invalid ROM byte '{}'", line_no + 1, parts[i + 1]))?;
+                }
+                config.sensors.push(SensorConfig::new(rom));
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(declared) = declared_sensors {
+        if declared != config.sensors.len() {
+            return Err(format!("SENSORS {} does not match the {} ROM line(s)", declared, config.sensors.len()));
+        }
+    }
+
+    Ok(config)
+}
+
+pub fn render(config: &Config) -> String {
+    let mut content = format!("TTY {}\n", config.tty());
+    content.push_str(&format!("READ_TIME {}\n", config.daemon.read_time_ms.unwrap_or(1000)));
+    if config.output.log_type != 1 {
+        content.push_str(&format!("LOG_TYPE {}\n", config.output.log_type));
+    }
+    let formats = [
+        ("LOG_FORMAT", &config.output.log_format),
+        ("CNT_FORMAT", &config.output.cnt_format),
+        ("HUM_FORMAT", &config.output.hum_format),
+    ];
+    for (key, format) in formats {
+        if let Some(format) = format {
+            content.push_str(&format!("{} \"{}\"\n", key, format));
+        }
+    }
+    if config.daemon.fail_threshold != DaemonConfig::default().fail_threshold {
+        content.push_str(&format!("FAIL_TIME {}\n", config.daemon.fail_threshold));
+    }
+    if let Some(log) = &config.output.log {
+        content.push_str(&format!("LOG {}\n", log.display()));
+    }
+    content.push_str(&format!("SENSORS {}\n", config.sensors.len()));
+
+    for (i, sensor) in config.sensors.iter().enumerate() {
+        let rom = &sensor.rom;
+        content.push_str(&format!("ROM {} 0x{:02X} 0x{:02X} 0x{:02X} 0x{:02X} 0x{:02X} 0x{:02X} 0x{:02X} 0x{:02X}\n",
+            i, rom[0], rom[1], rom[2], rom[3], rom[4], rom[5], rom[6], rom[7]));
+    }
+    content
+}
+
+// Where each line of a legacy file ends up in the TOML format, for migrate-config.
+// Lines without a TOML equivalent map to None.
+pub fn mapping(content: &str) -> Vec<(String, Option<String>)> {
+    let mut sensor = 0;
+    content.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim();
+            let target = match key {
+                "TTY" => Some(format!("[[adapters]] name = \"{}\", tty = \"{}\"", DEFAULT_ADAPTER, value)),
+                "READ_TIME" => Some(format!("[daemon] read_time_ms = {}", value)),
+                "FAIL_TIME" => Some(format!("[daemon] fail_threshold = {}", value)),
+                "LOG_TYPE" => Some(format!("[output] log_type = {}", value)),
+                "LOG_FORMAT" => Some(format!("[output] log_format = \"{}\"", unquote(value))),
+                "CNT_FORMAT" => Some(format!("[output] cnt_format = \"{}\"", unquote(value))),
+                "HUM_FORMAT" => Some(format!("[output] hum_format = \"{}\"", unquote(value))),
+                "LOG" => Some(format!("[output] log = \"{}\"", unquote(value))),
+                "SENSORS" => Some("(implied by the number of [[sensors]])".to_string()),
+                "ROM" => {
+                    let digits: String = value.split_whitespace().skip(1)
+                        .filter_map(|byte| u8::from_str_radix(byte.trim_start_matches("0x"), 16).ok())
+                        .map(|byte| format!("{:02X}", byte))
+                        .collect();
+                    sensor += 1;
+                    Some(format!("[[sensors]] #{} rom = \"{}\"", sensor - 1, digits))
+                }
+                _ => None,
+            };
+            (line.to_string(), target)
+        })
+        .collect()
+}
+
+fn unquote(value: &str) -> String {
+    value.strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
@@ -0,0 +1,229 @@
+use std::fs::{File, Metadata, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use chrono::Local;
+use flate2::write::GzEncoder;
+use crate::config::Rotation;
+
+// Dated files instead of one growing file: readings.csv is written as readings-2026-10-17.csv
+// (local time), and a new file is started when the day or hour changes
+#[derive(Debug, Clone, Copy)]
+pub struct FileRotation {
+    pub every: Rotation,
+    // Gzip each file once a newer one is started
+    pub compress: bool,
+    // Dated files kept, the current one included; older ones are deleted
+    pub keep: Option<usize>,
+}
+
+// Numbered files instead of one growing file, as logrotate names them: once daemon.log has
+// reached `max_bytes`, it becomes daemon.log.1, the former daemon.log.1 becomes .2, and so on
+#[derive(Debug, Clone, Copy)]
+pub struct SizeRotation {
+    pub max_bytes: u64,
+    // Gzip each file as it is rotated away
+    pub compress: bool,
+    // Files kept, the current one included; older ones are deleted
+    pub keep: Option<usize>,
+}
+
+// Log file that readings are appended to, as digitemp's -l / LOG does.
+// The file is reopened when logrotate (or anything else) moves or deletes it,
+// so no restart or copytruncate is needed.
+pub struct LogFile {
+    // The configured path, which dated names are derived from
+    base: PathBuf,
+    rotation: Option<FileRotation>,
+    size_rotation: Option<SizeRotation>,
+    // The file being written: `base`, or its dated name when rotating
+    path: PathBuf,
+    file: File,
+}
+
+impl LogFile {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(LogFile { base: path.to_path_buf(), rotation: None, size_rotation: None, path: path.to_path_buf(),
+                     file: Self::open_append(path)? })
+    }
+
+    pub fn open_sized(path: &Path, rotation: SizeRotation) -> io::Result<Self> {
+        Ok(LogFile { size_rotation: Some(rotation), ..Self::open(path)? })
+    }
+
+    pub fn open_rotating(path: &Path, rotation: FileRotation) -> io::Result<Self> {
+        let dated = dated_path(path, rotation.every);
+        let log = LogFile { base: path.to_path_buf(), rotation: Some(rotation), size_rotation: None,
+                            file: Self::open_append(&dated)?, path: dated };
+        // Files left by an earlier run that stopped before its day was over
+        log.finish_rotated();
+        Ok(log)
+    }
+
+    fn open_append(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&mut self, line: &str) -> io::Result<()> {
+        if let Some(rotation) = self.rotation {
+            let dated = dated_path(&self.base, rotation.every);
+            if dated != self.path {
+                self.file = Self::open_append(&dated)?;
+                self.path = dated;
+                self.finish_rotated();
+            }
+        }
+        if self.rotated() {
+            self.file = Self::open_append(&self.path)?;
+        }
+        writeln!(self.file, "{}", line)?;
+        match self.size_rotation {
+            Some(rotation) if self.file.metadata()?.len() >= rotation.max_bytes => self.rotate_by_size(rotation),
+            _ => Ok(()),
+        }
+    }
+
+    // Shift the numbered files up by one, dropping those beyond `keep`, and start a new file
+    fn rotate_by_size(&mut self, rotation: SizeRotation) -> io::Result<()> {
+        let numbered = |number: usize, gzip: bool| {
+            let mut name = self.path.as_os_str().to_os_string();
+            name.push(format!(".{}{}", number, if gzip { ".gz" } else { "" }));
+            PathBuf::from(name)
+        };
+        let mut last = 0;
+        while numbered(last + 1, false).exists() || numbered(last + 1, true).exists() {
+            last += 1;
+        }
+        // Numbered files besides the current one
+        let kept = rotation.keep.map(|keep| keep.max(1) - 1);
+        for number in (1..=last).rev() {
+            for file in [numbered(number, false), numbered(number, true)].into_iter().filter(|file| file.exists()) {
+                if kept.is_some_and(|kept| number >= kept) {
+                    std::fs::remove_file(&file)?;
+                } else {
+                    let gzip = is_gzip(&file);
+                    std::fs::rename(&file, numbered(number + 1, gzip))?;
+                }
+            }
+        }
+        if kept == Some(0) {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            std::fs::rename(&self.path, numbered(1, false))?;
+        }
+        self.file = Self::open_append(&self.path)?;
+        if rotation.compress && kept != Some(0) {
+            compress(&numbered(1, false))?;
+        }
+        Ok(())
+    }
+
+    // The path no longer refers to the file we hold open
+    fn rotated(&self) -> bool {
+        match (std::fs::metadata(&self.path), self.file.metadata()) {
+            (Ok(on_disk), Ok(open)) => !same_file(&on_disk, &open),
+            _ => true,
+        }
+    }
+
+    // Compress the dated files before the current one and delete those beyond `keep`.
+    // Failures are reported and only cost disk space.
+    fn finish_rotated(&self) {
+        let Some(rotation) = self.rotation else {
+            return;
+        };
+        let mut files = match dated_files(&self.base) {
+            Ok(files) => files,
+            Err(e) => {
+                eprintln!("Cannot list rotated files of {}: {}", self.base.display(), e);
+                return;
+            }
+        };
+        if rotation.compress {
+            for file in files.iter_mut().filter(|file| **file != self.path && !is_gzip(file)) {
+                match compress(file) {
+                    Ok(compressed) => *file = compressed,
+                    Err(e) => eprintln!("Cannot compress {}: {}", file.display(), e),
+                }
+            }
+        }
+        if let Some(keep) = rotation.keep {
+            let excess = files.len().saturating_sub(keep.max(1));
+            for file in files.iter().take(excess).filter(|file| **file != self.path) {
+                if let Err(e) = std::fs::remove_file(file) {
+                    eprintln!("Cannot remove {}: {}", file.display(), e);
+                }
+            }
+        }
+    }
+}
+
+// readings.csv -> readings-2026-10-17.csv, or readings-2026-10-17T08.csv when hourly
+fn dated_path(path: &Path, every: Rotation) -> PathBuf {
+    let date = match every {
+        Rotation::Hourly => Local::now().format("%Y-%m-%dT%H"),
+        Rotation::Daily => Local::now().format("%Y-%m-%d"),
+    };
+    let (stem, extension) = split_name(path);
+    path.with_file_name(format!("{}-{}{}", stem, date, extension))
+}
+
+// File name stem and extension (with its dot, empty when there is none)
+fn split_name(path: &Path) -> (String, String) {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (stem, extension)
+}
+
+// The dated files of `path`, compressed or not, oldest first
+fn dated_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let (stem, extension) = split_name(path);
+    let prefix = format!("{}-", stem);
+    let directory = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let file = entry?.path();
+        let name = file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let date = name.strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(".gz").unwrap_or(rest).strip_suffix(extension.as_str()));
+        // Names sort by date, which is all digits and separators
+        let dated = date.is_some_and(|date| matches!(date.len(), 10 | 13)
+            && date.chars().all(|c| c.is_ascii_digit() || c == '-' || c == 'T'));
+        if dated {
+            files.push(file);
+        }
+    }
+    files.sort_by_key(|file| file.file_name().map(|name| name.to_os_string()));
+    Ok(files)
+}
+
+fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "gz")
+}
+
+// Replace the file by a gzipped copy; the original is only removed once the copy is complete
+fn compress(path: &Path) -> io::Result<PathBuf> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".gz");
+    let compressed = path.with_file_name(name);
+    let mut encoder = GzEncoder::new(File::create(&compressed)?, flate2::Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    std::fs::remove_file(path)?;
+    Ok(compressed)
+}
+
+#[cfg(unix)]
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(_: &Metadata, _: &Metadata) -> bool {
+    true
+}
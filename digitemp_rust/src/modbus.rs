@@ -0,0 +1,172 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use crate::api::{ApiSink, SensorView};
+use crate::systemd;
+
+// Masters usually keep their connection and poll; one silent this long is closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+// Most registers one read may ask for, as the Modbus specification allows
+const MAX_QUANTITY: u16 = 125;
+// Register value of a sensor without an accepted reading, and of unmapped registers
+const NO_VALUE: i16 = i16::MIN;
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+
+// Exception codes, returned with the function code's high bit set
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+
+// Modbus TCP slave over the readings `serve` keeps, so PLCs and building-management systems
+// can poll the sensors directly. Each sensor's last accepted reading is a signed register
+// of hundredths of a degree Celsius (21.5 °C is 2150) at its `modbus_register`, or at its
+// index when unset; holding and input registers are the same. Registers without a reading
+// hold -32768, and writes are refused. Every unit id is answered.
+pub fn start(address: &str, sink: ApiSink) -> Result<(), String> {
+    let listener = systemd::activated_tcp(address, "modbus")
+        .unwrap_or_else(|| TcpListener::bind(address).map_err(|e| format!("Cannot listen on {}: {}", address, e)))?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let sink = sink.clone();
+            thread::spawn(move || {
+                if let Err(e) = handle(stream, &sink) {
+                    if !matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            | io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset) {
+                        eprintln!("Modbus: {}", e);
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+// Requests are answered in turn until the master disconnects. Each is a 7-byte MBAP header
+// (transaction id, protocol id 0, length of the rest, unit id) followed by the PDU.
+fn handle(mut stream: TcpStream, sink: &ApiSink) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    stream.set_write_timeout(Some(IDLE_TIMEOUT))?;
+    loop {
+        let mut header = [0u8; 7];
+        match stream.read_exact(&mut header) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        let protocol = u16::from_be_bytes([header[2], header[3]]);
+        let length = u16::from_be_bytes([header[4], header[5]]);
+        // The length counts the unit id, and a PDU is at most 253 bytes
+        if protocol != 0 || !(2..=254).contains(&length) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad MBAP header {:02X?}", header)));
+        }
+        let mut pdu = vec![0u8; length as usize - 1];
+        stream.read_exact(&mut pdu)?;
+
+        let response = answer(&pdu, &sink.views());
+        let mut message = Vec::with_capacity(7 + response.len());
+        message.extend_from_slice(&header[..4]);
+        message.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+        message.push(header[6]);
+        message.extend_from_slice(&response);
+        stream.write_all(&message)?;
+    }
+}
+
+// The response PDU to a request PDU: register values, or an exception
+fn answer(pdu: &[u8], views: &[SensorView]) -> Vec<u8> {
+    let function = pdu[0];
+    let exception = |code: u8| vec![function | 0x80, code];
+    if !matches!(function, READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS) {
+        return exception(ILLEGAL_FUNCTION);
+    }
+    let [start_high, start_low, quantity_high, quantity_low] = pdu[1..] else {
+        return exception(ILLEGAL_DATA_VALUE);
+    };
+    let start = u16::from_be_bytes([start_high, start_low]);
+    let quantity = u16::from_be_bytes([quantity_high, quantity_low]);
+    if !(1..=MAX_QUANTITY).contains(&quantity) {
+        return exception(ILLEGAL_DATA_VALUE);
+    }
+    // Gaps between mapped registers can be read, past the last one cannot
+    let last = views.iter().filter_map(|view| view.register).max();
+    if last.is_none_or(|last| start as u32 + quantity as u32 - 1 > last as u32) {
+        return exception(ILLEGAL_DATA_ADDRESS);
+    }
+
+    let mut response = vec![function, (quantity * 2) as u8];
+    for register in start..=start + (quantity - 1) {
+        response.extend_from_slice(&value(views, register).to_be_bytes());
+    }
+    response
+}
+
+fn value(views: &[SensorView], register: u16) -> i16 {
+    views.iter().find(|view| view.register == Some(register))
+        .and_then(|view| view.celsius)
+        .map(|celsius| (celsius * 100.0).round().clamp(i16::MIN as f64 + 1.0, i16::MAX as f64) as i16)
+        .unwrap_or(NO_VALUE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Server;
+    use crate::config::Config;
+    use crate::{ConversionWait, Reading};
+
+    // Sensor 0 at register 0 reads 21.5 °C, sensor 1 at register 10 reads -3.25 °C, and
+    // sensor 2 at register 2 has not been read
+    fn views() -> Vec<SensorView> {
+        let config = Config::from_toml("[[sensors]]\nrom = \"2853494D0000000E\"\n\n\
+            [[sensors]]\nrom = \"2853494D000100CA\"\nmodbus_register = 10\n\n\
+            [[sensors]]\nrom = \"2853494D0002009F\"\n").unwrap();
+        let server = Server::start(None, &config).unwrap();
+        let sink = server.sink();
+        for (index, celsius) in [(0, 21.5), (1, -3.25)] {
+            let reading = Reading {
+                celsius,
+                raw_celsius: celsius,
+                retries: 0,
+                crc_failures: 0,
+                conversion_time: Duration::ZERO,
+                wait: ConversionWait::Polled,
+                resolution: 12,
+            };
+            sink.reading("bus0", index, &config.sensors[index], &reading);
+        }
+        sink.views()
+    }
+
+    #[test]
+    fn registers() {
+        let views = views();
+        let no_value = NO_VALUE.to_be_bytes();
+        assert_eq!(answer(&[READ_INPUT_REGISTERS, 0, 0, 0, 3], &views),
+            [&[READ_INPUT_REGISTERS, 6][..], &2150i16.to_be_bytes(), &no_value, &no_value].concat());
+        assert_eq!(answer(&[READ_HOLDING_REGISTERS, 0, 10, 0, 1], &views),
+            [&[READ_HOLDING_REGISTERS, 2][..], &(-325i16).to_be_bytes()].concat());
+        // Gaps between mapped registers read as no value
+        assert_eq!(answer(&[READ_HOLDING_REGISTERS, 0, 5, 0, 2], &views),
+            [&[READ_HOLDING_REGISTERS, 4][..], &no_value, &no_value].concat());
+    }
+
+    #[test]
+    fn exceptions() {
+        let views = views();
+        // Writes, and other functions
+        assert_eq!(answer(&[0x06, 0, 0, 0, 1], &views), [0x86, ILLEGAL_FUNCTION]);
+        assert_eq!(answer(&[0x10], &views), [0x90, ILLEGAL_FUNCTION]);
+        // Past the last mapped register
+        assert_eq!(answer(&[READ_HOLDING_REGISTERS, 0, 10, 0, 2], &views), [0x83, ILLEGAL_DATA_ADDRESS]);
+        assert_eq!(answer(&[READ_HOLDING_REGISTERS, 0xFF, 0xFF, 0, 1], &views), [0x83, ILLEGAL_DATA_ADDRESS]);
+        assert_eq!(answer(&[READ_INPUT_REGISTERS, 0, 0, 0, 1], &[]), [0x84, ILLEGAL_DATA_ADDRESS]);
+        // No registers, too many, or a request of the wrong length
+        assert_eq!(answer(&[READ_HOLDING_REGISTERS, 0, 0, 0, 0], &views), [0x83, ILLEGAL_DATA_VALUE]);
+        assert_eq!(answer(&[READ_HOLDING_REGISTERS, 0, 0, 0, 126], &views), [0x83, ILLEGAL_DATA_VALUE]);
+        assert_eq!(answer(&[READ_HOLDING_REGISTERS, 0, 0, 0], &views), [0x83, ILLEGAL_DATA_VALUE]);
+        assert_eq!(answer(&[READ_HOLDING_REGISTERS, 0, 0, 0, 1, 0], &views), [0x83, ILLEGAL_DATA_VALUE]);
+    }
+}
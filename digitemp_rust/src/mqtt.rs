@@ -0,0 +1,220 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use rumqttc::{Client, ClientError, Event, LastWill, MqttOptions, Outgoing, Packet, QoS, Transport};
+use crate::config::{family_name, format_rom, Config, MqttConfig, SensorConfig};
+use crate::{json, sink};
+
+// Messages waiting for the broker; while it is away, newer readings are dropped once it is full
+const QUEUE: usize = 100;
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+// Pause before connecting again after a failure
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// MQTT publisher: every reading is a message with the bare temperature, e.g. "21.44", on a
+// per-sensor topic. The connection is kept by a thread of its own, which reconnects after
+// failures, so a broker that is down never holds up a pass.
+pub struct MqttSink {
+    client: Client,
+    broker: String,
+    topic: String,
+    qos: QoS,
+    retain: bool,
+    // Home Assistant discovery prefix, when announcing
+    discovery: Option<String>,
+    // Report a full queue once, not for every reading
+    dropping: bool,
+    // Topic and payloads of the availability messages, for the discovery configs
+    availability: Option<(String, String, String)>,
+    // Set when the sink is dropped, to stop the connection thread
+    closed: Arc<AtomicBool>,
+}
+
+impl MqttSink {
+    pub fn new(config: &MqttConfig) -> Result<Self, String> {
+        let qos = match config.qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            qos => return Err(format!("Invalid MQTT qos {} (expected 0, 1 or 2)", qos)),
+        };
+        if config.topic.contains(['#', '+']) {
+            return Err(format!("Invalid MQTT topic '{}': wildcards cannot be published to", config.topic));
+        }
+        let (tls, host, port) = parse_broker(&config.broker)?;
+        let client_id = config.client_id.clone().unwrap_or_else(|| format!("digitemp_rust-{}", std::process::id()));
+        let mut options = MqttOptions::new(client_id, host, port);
+        options.set_keep_alive(KEEP_ALIVE);
+        if tls {
+            options.set_transport(transport(config)?);
+        } else if config.ca_file.is_some() || config.client_cert.is_some() {
+            return Err(format!("MQTT broker '{}': ca_file and client_cert need mqtts://", config.broker));
+        }
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.clone().unwrap_or_default());
+        }
+        if let Some(topic) = &config.availability_topic {
+            options.set_last_will(LastWill::new(topic, config.payload_offline.as_str(), qos, true));
+        }
+
+        let (client, mut connection) = Client::new(options, QUEUE);
+        let broker = config.broker.clone();
+        let birth = config.availability_topic.clone().map(|topic| (topic, config.payload_online.clone()));
+        let thread_client = client.clone();
+        let closed = Arc::new(AtomicBool::new(false));
+        let thread_closed = closed.clone();
+        thread::spawn(move || {
+            let mut failing = false;
+            // Ends once the sink is dropped: after its disconnect, or at the next failure
+            for event in connection.iter() {
+                match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        if failing {
+                            eprintln!("MQTT {}: connected again", broker);
+                        }
+                        failing = false;
+                        // After every connect: the broker published the last will when it lost the last one
+                        if let Some((topic, payload)) = &birth {
+                            let _ = thread_client.try_publish(topic, qos, true, payload.as_str());
+                        }
+                    }
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                    Ok(_) => {}
+                    Err(_) if thread_closed.load(Ordering::Relaxed) => break,
+                    Err(e) => {
+                        if !failing {
+                            eprintln!("MQTT {}: {}", broker, e);
+                        }
+                        failing = true;
+                        thread::sleep(RETRY_DELAY);
+                    }
+                }
+            }
+        });
+
+        Ok(MqttSink {
+            client,
+            broker: config.broker.clone(),
+            topic: config.topic.clone(),
+            qos,
+            retain: config.retain,
+            discovery: config.discovery.then(|| config.discovery_prefix.trim_end_matches('/').to_string()),
+            dropping: false,
+            availability: config.availability_topic.clone()
+                .map(|topic| (topic, config.payload_online.clone(), config.payload_offline.clone())),
+            closed,
+        })
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, celsius: f32, decimals: Option<usize>) {
+        let topic = sink::expand(&self.topic, bus, index, sensor);
+        let payload = json::round(celsius, decimals).to_string();
+        match self.client.try_publish(topic, self.qos, self.retain, payload) {
+            Ok(()) => self.dropping = false,
+            Err(ClientError::TryRequest(_)) => {
+                if !self.dropping {
+                    eprintln!("MQTT {}: the broker is not keeping up, dropping readings", self.broker);
+                }
+                self.dropping = true;
+            }
+            // A label that makes the topic invalid, such as one with a wildcard
+            Err(e) => eprintln!("MQTT {}: {}", self.broker, e),
+        }
+    }
+
+    // Home Assistant MQTT discovery: a retained config message per sensor, so an entity is
+    // created for it with the sensor's state topic. Disabled sensors get an empty message,
+    // which removes theirs. The ROM is the unique id, and the group the suggested area.
+    pub fn announce(&mut self, config: &Config) {
+        let Some(prefix) = &self.discovery else { return };
+        for (index, sensor) in config.sensors.iter().enumerate() {
+            let id = format!("ds18b20_{}", format_rom(&sensor.rom).to_ascii_lowercase());
+            let topic = format!("{}/sensor/{}/config", prefix, id);
+            let payload = if sensor.enabled {
+                let mut device = serde_json::json!({
+                    "identifiers": [id],
+                    "name": sensor.name(),
+                    "model": family_name(&sensor.rom),
+                    "manufacturer": "Maxim Integrated",
+                });
+                if let Some(group) = &sensor.group {
+                    device["suggested_area"] = group.clone().into();
+                }
+                let mut entity = serde_json::json!({
+                    // The entity takes the device's name
+                    "name": null,
+                    "unique_id": id,
+                    "state_topic": sink::expand(&self.topic, config.adapter_name(sensor), index, sensor),
+                    "device_class": "temperature",
+                    "state_class": "measurement",
+                    "unit_of_measurement": "°C",
+                    "device": device,
+                });
+                if let Some((topic, online, offline)) = &self.availability {
+                    entity["availability_topic"] = topic.clone().into();
+                    entity["payload_available"] = online.clone().into();
+                    entity["payload_not_available"] = offline.clone().into();
+                }
+                entity.to_string()
+            } else {
+                String::new()
+            };
+            if let Err(e) = self.client.try_publish(topic, self.qos, true, payload) {
+                eprintln!("MQTT {}: cannot announce {}: {}", self.broker, sensor.name(), e);
+            }
+        }
+    }
+}
+
+impl Drop for MqttSink {
+    // On exit and reload: a clean disconnect does not make the broker publish the last will,
+    // so the sink says it is offline itself
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        if let Some((topic, _, offline)) = &self.availability {
+            let _ = self.client.try_publish(topic, self.qos, true, offline.as_str());
+        }
+        let _ = self.client.try_disconnect();
+    }
+}
+
+// TLS with the system's CA certificates, or with `ca_file` and a client certificate
+fn transport(config: &MqttConfig) -> Result<Transport, String> {
+    let read = |path: &Path| std::fs::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e));
+    let client_auth = match (&config.client_cert, &config.client_key) {
+        (Some(cert), Some(key)) => Some((read(cert)?, read(key)?)),
+        (None, None) => None,
+        _ => return Err("MQTT client_cert and client_key go together".to_string()),
+    };
+    match (&config.ca_file, client_auth) {
+        (Some(ca), client_auth) => Ok(Transport::tls(read(ca)?, client_auth, None)),
+        (None, None) => Ok(Transport::tls_with_default_config()),
+        (None, Some(_)) => Err("MQTT client_cert needs ca_file, the CA of the broker's certificate".to_string()),
+    }
+}
+
+// mqtt://host:port, mqtts://host:port or host:port; the port defaults to 1883, or 8883 for TLS
+fn parse_broker(broker: &str) -> Result<(bool, String, u16), String> {
+    let (tls, address) = match broker.split_once("://") {
+        Some(("mqtt" | "tcp", address)) => (false, address),
+        Some(("mqtts" | "ssl", address)) => (true, address),
+        Some((scheme, _)) => return Err(format!("Invalid MQTT broker '{}': unknown scheme {}", broker, scheme)),
+        None => (false, broker),
+    };
+    let address = address.trim_end_matches('/');
+    let default_port = if tls { 8883 } else { 1883 };
+    // An IPv6 address is in brackets, as in URLs
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => {
+            (host, port.parse().map_err(|_| format!("Invalid MQTT broker '{}': bad port", broker))?)
+        }
+        _ => (address, default_port),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(format!("Invalid MQTT broker '{}': no host", broker));
+    }
+    Ok((tls, host.to_string(), port))
+}
@@ -0,0 +1,153 @@
+use std::time::Duration;
+use mysql::prelude::Queryable;
+use mysql::{Conn, Opts, OptsBuilder, TxOpts, Value};
+use crate::config::{format_rom, SensorConfig};
+use crate::{json, Reading};
+
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(5);
+// Rows per INSERT statement, well below MySQL's limit of 65535 placeholders
+const ROWS_PER_STATEMENT: usize = 500;
+// Rows kept while the server is unreachable; the oldest are dropped beyond this
+const MAX_PENDING: usize = 100_000;
+
+struct Row {
+    unix: f64,
+    rom: String,
+    bus: String,
+    label: Option<String>,
+    celsius: f64,
+    raw_celsius: f64,
+}
+
+// Readings inserted into a MySQL or MariaDB table, created on first connect. As with the
+// postgres sink, rows are collected for `batch` passes and inserted in one transaction, and
+// a lost connection is reopened on the next flush with the rows kept until then.
+pub struct MysqlSink {
+    // Boxed, as it is much larger than any other sink
+    opts: Box<Opts>,
+    table: String,
+    batch: u32,
+    conn: Option<Conn>,
+    rows: Vec<Row>,
+    passes: u32,
+    // Report a failing server once, not on every pass
+    failing: bool,
+}
+
+impl MysqlSink {
+    pub fn new(url: &str, table: &str, batch: u32) -> Result<Self, String> {
+        let opts = Opts::from_url(url).map_err(|e| format!("Invalid mysql url: {}", e))?;
+        let opts = OptsBuilder::from_opts(opts)
+            .tcp_connect_timeout(Some(NETWORK_TIMEOUT))
+            .read_timeout(Some(NETWORK_TIMEOUT))
+            .write_timeout(Some(NETWORK_TIMEOUT))
+            // Times are stored as UTC, whatever the server's zone
+            .init(vec!["SET time_zone = '+00:00'"]);
+        // The name is part of the SQL text, so it cannot be a parameter
+        let valid = |part: &str| part.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !table.split('.').all(valid) || table.split('.').count() > 2 {
+            return Err(format!("Invalid mysql table name '{}' (expected e.g. readings or sensors.readings)", table));
+        }
+        Ok(MysqlSink {
+            opts: Box::new(opts.into()),
+            table: table.to_string(),
+            batch: batch.max(1),
+            conn: None,
+            rows: Vec::new(),
+            passes: 0,
+            failing: false,
+        })
+    }
+
+    pub fn reading(&mut self, bus: &str, sensor: &SensorConfig, reading: &Reading) {
+        self.rows.push(Row {
+            unix: chrono::Utc::now().timestamp_millis() as f64 / 1000.0,
+            rom: format_rom(&sensor.rom),
+            bus: bus.to_string(),
+            label: sensor.label.clone(),
+            celsius: json::round(reading.celsius, Some(4)),
+            raw_celsius: json::round(reading.raw_celsius, Some(4)),
+        });
+    }
+
+    pub fn pass_done(&mut self) {
+        self.passes += 1;
+        if self.passes >= self.batch {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.rows.is_empty() {
+            return;
+        }
+        match self.insert() {
+            Ok(()) => {
+                self.rows.clear();
+                self.passes = 0;
+                if self.failing {
+                    eprintln!("MySQL: delivering again");
+                    self.failing = false;
+                }
+            }
+            Err(e) => {
+                // Reconnect on the next flush
+                self.conn = None;
+                if self.rows.len() > MAX_PENDING {
+                    self.rows.drain(..self.rows.len() - MAX_PENDING);
+                }
+                if !self.failing {
+                    eprintln!("MySQL: {} (readings are kept, up to {}, and retried)", e, MAX_PENDING);
+                }
+                self.failing = true;
+            }
+        }
+    }
+
+    fn insert(&mut self) -> mysql::Result<()> {
+        if self.conn.is_none() {
+            let mut conn = Conn::new((*self.opts).clone())?;
+            conn.query_drop(format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    time DATETIME(3) NOT NULL,
+                    rom CHAR(16) NOT NULL,
+                    bus VARCHAR(64) NOT NULL,
+                    label VARCHAR(255),
+                    celsius DOUBLE NOT NULL,
+                    raw_celsius DOUBLE NOT NULL,
+                    PRIMARY KEY (rom, time)
+                )", self.table))?;
+            self.conn = Some(conn);
+        }
+        let conn = self.conn.as_mut().expect("connected above");
+
+        let mut transaction = conn.start_transaction(TxOpts::default())?;
+        for chunk in self.rows.chunks(ROWS_PER_STATEMENT) {
+            // Rows already stored before a lost connection are not duplicated on retry
+            let mut sql = format!("INSERT IGNORE INTO {} (time, rom, bus, label, celsius, raw_celsius) VALUES ",
+                self.table);
+            let mut values: Vec<Value> = Vec::with_capacity(chunk.len() * 6);
+            for (i, row) in chunk.iter().enumerate() {
+                if i > 0 {
+                    sql.push_str(", ");
+                }
+                sql.push_str("(FROM_UNIXTIME(?), ?, ?, ?, ?, ?)");
+                values.extend([row.unix.into(), row.rom.as_str().into(), row.bus.as_str().into(),
+                    row.label.as_deref().into(), row.celsius.into(), row.raw_celsius.into()]);
+            }
+            transaction.exec_drop(sql, values)?;
+        }
+        transaction.commit()
+    }
+}
+
+// Stopping or reloading inserts what a batch has collected so far
+impl Drop for MysqlSink {
+    fn drop(&mut self) {
+        self.flush();
+        if !self.rows.is_empty() {
+            eprintln!("MySQL: {} readings were not delivered", self.rows.len());
+        }
+    }
+}
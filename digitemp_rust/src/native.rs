@@ -1,15 +1,130 @@
+mod alarm;
+mod api;
+mod bus;
+mod capabilities;
+#[cfg(feature = "chat")]
+mod chat;
+mod check;
+mod cli;
+mod coap;
+mod color;
+mod config;
+#[cfg(unix)]
+mod control;
+mod csv;
+mod daemon;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod doctor;
+mod exec;
+mod exit_code;
+mod filter;
+mod format;
+mod graphite;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod health;
+mod history;
+#[cfg(feature = "influx")]
+mod influx;
+mod json;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod legacy;
+mod logfile;
+mod modbus;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "mysql")]
+mod mysql_sink;
+mod nats;
+mod openmetrics;
+mod owserver;
+#[cfg(feature = "parquet")]
+mod parquet_sink;
+#[cfg(feature = "postgres")]
+mod postgres_sink;
+mod prometheus;
+mod redis;
+#[cfg(feature = "remote_write")]
+mod remote_write;
+mod rrd;
+mod signals;
+mod schedule;
+#[cfg(windows)]
+mod service;
+mod simulate;
+mod sink;
+mod snmp;
+mod spool;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod state;
+mod stats;
+mod systemd;
+mod tcp;
+#[cfg(feature = "templates")]
+mod template;
+#[cfg(feature = "tui")]
+mod watch;
+mod watchdog;
+#[cfg(feature = "webhook")]
+mod webhook;
+mod websocket;
+mod zabbix;
+#[cfg(feature = "zeromq")]
+mod zeromq;
+
+use std::collections::{BTreeMap, HashMap};
 use std::io::Read;
 use std::io::Write;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::thread;
-use clap::{Arg, Command};
+use alarm::{AlarmEvent, AlarmKind, AlarmTracker};
+use clap::ArgMatches;
 use serialport::{SerialPort, DataBits, Parity, StopBits};
+use bus::Buses;
+use color::ColorChoice;
+use config::{Config, ConfigSource, CsvColumn, SensorConfig, Unit};
+use csv::CsvSink;
+use filter::{SpikeFilter, Verdict};
+use format::FormatContext;
+use health::{HealthEvent, HealthTracker};
+use logfile::{FileRotation, LogFile};
+use signals::{ReloadSignal, StopSignal, UserSignals};
+use sink::{Route, Sink};
+use state::SensorStates;
+use stats::BusStats;
+use schedule::Scheduler;
+use watchdog::Watchdog;
 
 // DS18B20 commands
-const DS18B20_SKIP_ROM: u8 = 0xCC;
 const DS18B20_CONVERT_T: u8 = 0x44;
 const DS18B20_READ_SCRATCHPAD: u8 = 0xBE;
 const DS18B20_MATCH_ROM: u8 = 0x55;
+const DS18B20_SKIP_ROM: u8 = 0xCC;
+const DS18B20_WRITE_SCRATCHPAD: u8 = 0x4E;
+const DS18B20_COPY_SCRATCHPAD: u8 = 0x48;
+const DS18B20_SEARCH_ROM: u8 = 0xF0;
+const DS18B20_ALARM_SEARCH: u8 = 0xEC;
+
+// EEPROM write time after Copy Scratchpad (datasheet: 10 ms max)
+const DS18B20_EEPROM_WRITE_MS: u64 = 10;
+const DS18B20_READ_POWER_SUPPLY: u8 = 0xB4;
+
+// Worst-case 12-bit conversion time and polling granularity
+const DS18B20_CONVERSION_MS: u64 = 750;
+const CONVERSION_POLL_MS: u64 = 10;
+
+// Accepted READ_TIME / -r range: the 9-bit conversion time up to a generous ceiling
+const READ_TIME_RANGE_MS: std::ops::RangeInclusive<u64> = 94..=10_000;
+
+// Scratchpad re-reads attempted after a CRC mismatch
+const MAX_CRC_RETRIES: u32 = 2;
+
+// DS18B20 measurement range (datasheet)
+const DS18B20_MIN_TEMP: f32 = -55.0;
+const DS18B20_MAX_TEMP: f32 = 125.0;
 
 // UART FIFO size for buffered communication
 const UART_FIFO_SIZE: usize = 16; // Start with smaller chunks for reliability
@@ -20,7 +135,12 @@ pub enum OneWireError {
     SerialError(serialport::Error),
     IoError(std::io::Error),
     DeviceNotPresent,
+    AdapterUnavailable(String),
+    CrcMismatch,
+    WatchdogTimeout(&'static str),
     InvalidTemperature(f64),
+    // Scratchpad read back after a write does not hold the written values
+    VerifyMismatch,
 }
 
 impl std::fmt::Display for OneWireError {
@@ -29,7 +149,11 @@ impl std::fmt::Display for OneWireError {
             OneWireError::SerialError(e) => write!(f, "Serial error: {}", e),
             OneWireError::IoError(e) => write!(f, "IO error: {}", e),
             OneWireError::DeviceNotPresent => write!(f, "No device present on bus"),
+            OneWireError::AdapterUnavailable(name) => write!(f, "Adapter '{}' is not available", name),
+            OneWireError::CrcMismatch => write!(f, "CRC validation failed"),
+            OneWireError::WatchdogTimeout(op) => write!(f, "Bus operation '{}' exceeded watchdog limit, port reopened", op),
             OneWireError::InvalidTemperature(temp) => write!(f, "Temperature out of range: {:.2}°C", temp),
+            OneWireError::VerifyMismatch => write!(f, "Scratchpad read back does not match the written settings"),
         }
     }
 }
@@ -56,13 +180,89 @@ impl From<std::io::Error> for OneWireError {
     }
 }
 
+// How the adapter waited for a temperature conversion to finish
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConversionWait {
+    Polled,
+    Fixed,
+}
+
+// Temperature reading with metadata about how it was obtained
+#[derive(Debug, Clone, Copy)]
+pub struct Reading {
+    pub celsius: f32,
+    // Value as reported by the sensor, before calibration
+    pub raw_celsius: f32,
+    pub retries: u32,
+    pub crc_failures: u32,
+    pub conversion_time: Duration,
+    pub wait: ConversionWait,
+    // Conversion resolution in bits, from the configuration register
+    pub resolution: u8,
+}
+
+// A finished temperature conversion: how long it took and how its end was detected
+#[derive(Debug, Clone, Copy)]
+pub struct Conversion {
+    pub time: Duration,
+    pub wait: ConversionWait,
+}
+
+// A sensor's user settings: alarm thresholds TH/TL (°C) and conversion resolution (9-12 bits),
+// bytes 2-4 of the scratchpad, kept in EEPROM across power cycles once copied
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub high: i8,
+    pub low: i8,
+    pub resolution: u8,
+}
+
+impl Settings {
+    fn from_scratchpad(scratchpad: &[u8; 9]) -> Self {
+        Settings {
+            high: scratchpad[2] as i8,
+            low: scratchpad[3] as i8,
+            // Configuration register bits R1 R0 (6, 5) select 9 + n bits
+            resolution: 9 + ((scratchpad[4] >> 5) & 0b11),
+        }
+    }
+
+    fn config_register(&self) -> u8 {
+        ((self.resolution.clamp(9, 12) - 9) << 5) | 0x1F
+    }
+}
+
 // Native DS9097 1-Wire adapter implementation
 pub struct OneWireAdapter {
     port: Box<dyn SerialPort>,
+    path: String,
+    // Parasite-power state per ROM, queried once (parasite sensors cannot be polled)
+    parasite: HashMap<[u8; 8], bool>,
+    watchdog: Option<Watchdog>,
+    // Fixed conversion wait replacing completion polling (digitemp's read delay, -r)
+    read_time: Option<Duration>,
+    // Counters of this session, saved to the state file for bus-stats
+    stats: BusStats,
 }
 
 impl OneWireAdapter {
     pub fn new(path: &str) -> Result<Self, OneWireError> {
+        let port = Self::open_port(path)?;
+        Ok(Self::with_port(path, port))
+    }
+
+    // Virtual sensors instead of a serial port (--simulate)
+    pub fn simulated(bus: usize, count: u8) -> Self {
+        let port = simulate::SimulatedPort::new(bus, count);
+        Self::with_port(&port.name().unwrap_or_default(), Box::new(port))
+    }
+
+    fn with_port(path: &str, port: Box<dyn SerialPort>) -> Self {
+        OneWireAdapter { port, path: path.to_string(), parasite: HashMap::new(), watchdog: None,
+                         read_time: None, stats: BusStats::default() }
+    }
+
+    fn open_port(path: &str) -> Result<Box<dyn SerialPort>, OneWireError> {
         // Open port at 115200 baud (data transmission speed)
         let port = serialport::new(path, 115200)
             .data_bits(DataBits::Eight)
@@ -70,8 +270,38 @@ impl OneWireAdapter {
             .stop_bits(StopBits::One)
             .timeout(Duration::from_secs(5))
             .open()?;
+        Ok(port)
+    }
+
+    pub fn set_read_time(&mut self, read_time: Duration) {
+        self.read_time = Some(read_time);
+    }
+
+    // Supervise every bus operation with a watchdog thread
+    pub fn enable_watchdog(&mut self, limit: Duration) -> Result<(), OneWireError> {
+        self.watchdog = Some(Watchdog::spawn(limit, self.port.try_clone()?));
+        Ok(())
+    }
+
+    // Run a bus operation under the watchdog; reopen the port if it overran
+    fn guarded<T>(&mut self, operation: &'static str,
+                  f: impl FnOnce(&mut Self) -> Result<T, OneWireError>) -> Result<T, OneWireError> {
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.arm(operation);
+        }
+        let result = f(self);
 
-        Ok(OneWireAdapter { port })
+        let tripped = self.watchdog.as_ref().is_some_and(|w| w.disarm());
+        if !tripped {
+            return result;
+        }
+
+        eprintln!("Watchdog: reopening {}", self.path);
+        self.port = Self::open_port(&self.path)?;
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.replace_port(self.port.try_clone()?);
+        }
+        Err(OneWireError::WatchdogTimeout(operation))
     }
 
     fn set_baud(&mut self, baud: u32) -> Result<(), OneWireError> {
@@ -80,6 +310,21 @@ impl OneWireAdapter {
     }
 
     pub fn reset(&mut self) -> Result<bool, OneWireError> {
+        let presence = self.guarded("reset", Self::bus_reset)?;
+        self.stats.record_reset(presence);
+        Ok(presence)
+    }
+
+    pub fn stats(&self) -> &BusStats {
+        &self.stats
+    }
+
+    // Count on from the counters of the same port before it was closed
+    pub fn carry_on(&mut self, stats: BusStats) {
+        self.stats = stats;
+    }
+
+    fn bus_reset(&mut self) -> Result<bool, OneWireError> {
         // Flush buffers
         self.port.clear(serialport::ClearBuffer::All)?;
         
@@ -100,13 +345,16 @@ impl OneWireAdapter {
     }
 
     fn touch_bits(&mut self, bits: &[u8]) -> Result<Vec<u8>, OneWireError> {
+        self.guarded("touch_bits", |adapter| adapter.bus_touch_bits(bits))
+    }
+
+    fn bus_touch_bits(&mut self, bits: &[u8]) -> Result<Vec<u8>, OneWireError> {
         let nbits = bits.len();
-        let mut send_buf = vec![0u8; nbits];
         
         // Convert bits to bytes for transmission
-        for i in 0..nbits {
-            send_buf[i] = if bits[i] != 0 { 0xFF } else { 0x00 };
-        }
+        let send_buf: Vec<u8> = bits.iter()
+            .map(|&bit| if bit != 0 { 0xFF } else { 0x00 })
+            .collect();
         
         // Send bits in chunks of UART_FIFO_SIZE
         let mut result_bits = Vec::with_capacity(nbits);
@@ -135,8 +383,8 @@ impl OneWireAdapter {
 
     pub fn write_byte(&mut self, byte: u8) -> Result<(), OneWireError> {
         let mut bits = [0u8; 8];
-        for i in 0..8 {
-            bits[i] = (byte >> i) & 1;
+        for (i, bit) in bits.iter_mut().enumerate() {
+            *bit = (byte >> i) & 1;
         }
         self.touch_bits(&bits)?;
         Ok(())
@@ -149,8 +397,8 @@ impl OneWireAdapter {
         
         // Reconstruct byte from bits (LSB first)
         let mut byte = 0u8;
-        for i in 0..8 {
-            if result_bits[i] != 0 {
+        for (i, &bit) in result_bits.iter().enumerate() {
+            if bit != 0 {
                 byte |= 1 << i;
             }
         }
@@ -173,7 +421,7 @@ impl OneWireAdapter {
 
     // CRC-8 calculation for DS18B20 scratchpad validation
     // Uses Dallas/Maxim CRC-8 lookup table
-    fn calculate_crc8(data: &[u8]) -> u8 {
+    pub fn calculate_crc8(data: &[u8]) -> u8 {
         const CRC_TABLE: [u8; 256] = [
             0, 94,188,226, 97, 63,221,131,194,156,126, 32,163,253, 31, 65,
             157,195, 33,127,252,162, 64, 30, 95,  1,227,189, 62, 96,130,220,
@@ -202,6 +450,16 @@ impl OneWireAdapter {
 
     // Discover all DS18B20 sensors on the bus using search ROM algorithm
     pub fn discover_sensors(&mut self) -> Result<Vec<[u8; 8]>, OneWireError> {
+        self.search(DS18B20_SEARCH_ROM)
+    }
+
+    // The sensors whose last conversion was at or above TH, or at or below TL; only whole
+    // degrees are compared
+    pub fn alarm_search(&mut self) -> Result<Vec<[u8; 8]>, OneWireError> {
+        self.search(DS18B20_ALARM_SEARCH)
+    }
+
+    fn search(&mut self, command: u8) -> Result<Vec<[u8; 8]>, OneWireError> {
         let mut sensors = Vec::new();
         let mut last_discrepancy = 0;
         let mut last_device = false;
@@ -213,11 +471,11 @@ impl OneWireAdapter {
                 break;
             }
             
-            // Issue search ROM command
-            self.write_byte(0xF0)?; // SEARCH_ROM command
+            self.write_byte(command)?;
             
             let mut rom = [0u8; 8];
             let mut discrepancy_marker = 0;
+            let mut answered = true;
             
             // Search through all 64 bits of ROM
             for bit_position in 0..64 {
@@ -225,13 +483,14 @@ impl OneWireAdapter {
                 let bit_mask = 1u8 << (bit_position % 8);
                 
                 // Read two bits: actual bit and its complement
-                let mut bits = [1u8; 2];
+                let bits = [1u8; 2];
                 let result = self.touch_bits(&bits)?;
                 let id_bit = result[0];
                 let cmp_id_bit = result[1];
                 
                 let search_direction = if id_bit == 1 && cmp_id_bit == 1 {
-                    // No devices responded
+                    // No devices responded, as when none is in alarm
+                    answered = false;
                     break;
                 } else if id_bit != cmp_id_bit {
                     // All devices have same bit value
@@ -239,8 +498,13 @@ impl OneWireAdapter {
                 } else {
                     // Discrepancy: choose path
                     if bit_position < last_discrepancy {
-                        // Take same path as before
-                        if last_rom[byte_idx] & bit_mask != 0 { 1 } else { 0 }
+                        // Take same path as before; a 0 taken here still has a 1 branch to visit
+                        if last_rom[byte_idx] & bit_mask != 0 {
+                            1
+                        } else {
+                            discrepancy_marker = bit_position;
+                            0
+                        }
                     } else if bit_position == last_discrepancy {
                         1 // Take 1 path at discrepancy point
                     } else {
@@ -258,6 +522,10 @@ impl OneWireAdapter {
                 }
             }
             
+            if !answered {
+                break;
+            }
+
             // Validate ROM with CRC
             if Self::calculate_crc8(&rom) == 0 {
                 sensors.push(rom);
@@ -271,26 +539,47 @@ impl OneWireAdapter {
         Ok(sensors)
     }
 
-    // Read temperature from a specific DS18B20 sensor
-    pub fn read_temperature(&mut self, rom: &[u8; 8]) -> Result<f32, OneWireError> {
-        // Reset and check presence
-        if !self.reset()? {
-            return Err(OneWireError::IoError(std::io::Error::new(
-                std::io::ErrorKind::NotConnected,
-                "No device presence detected"
-            )));
+    // Ask a sensor whether it runs on parasite power (it pulls the bus low if so)
+    fn is_parasite(&mut self, rom: &[u8; 8]) -> Result<bool, OneWireError> {
+        if let Some(&parasite) = self.parasite.get(rom) {
+            return Ok(parasite);
         }
 
-        // Select the specific device
+        if !self.reset()? {
+            return Err(OneWireError::DeviceNotPresent);
+        }
         self.select_device(rom)?;
+        self.write_byte(DS18B20_READ_POWER_SUPPLY)?;
+        let parasite = self.touch_bits(&[1])?[0] == 0;
 
-        // Issue temperature conversion command
-        self.write_byte(0x44)?;
+        self.parasite.insert(*rom, parasite);
+        Ok(parasite)
+    }
 
-        // Wait for conversion to complete (750ms max for 12-bit)
-        thread::sleep(Duration::from_millis(750));
+    // Wait for a conversion to finish, polling read slots when the sensor is externally powered
+    fn wait_for_conversion(&mut self, parasite: bool) -> Result<ConversionWait, OneWireError> {
+        if let Some(read_time) = self.read_time {
+            thread::sleep(read_time);
+            return Ok(ConversionWait::Fixed);
+        }
+        if parasite {
+            thread::sleep(Duration::from_millis(DS18B20_CONVERSION_MS));
+            return Ok(ConversionWait::Fixed);
+        }
+
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(DS18B20_CONVERSION_MS) {
+            if self.touch_bits(&[1])?[0] == 1 {
+                return Ok(ConversionWait::Polled);
+            }
+            thread::sleep(Duration::from_millis(CONVERSION_POLL_MS));
+        }
+
+        // Never signalled completion; the full datasheet time has elapsed anyway
+        Ok(ConversionWait::Fixed)
+    }
 
-        // Reset again
+    fn read_scratchpad(&mut self, rom: &[u8; 8]) -> Result<[u8; 9], OneWireError> {
         if !self.reset()? {
             return Err(OneWireError::IoError(std::io::Error::new(
                 std::io::ErrorKind::NotConnected,
@@ -302,57 +591,163 @@ impl OneWireAdapter {
         self.select_device(rom)?;
 
         // Read scratchpad
-        self.write_byte(0xBE)?;
+        self.write_byte(DS18B20_READ_SCRATCHPAD)?;
 
         // Read 9 bytes of scratchpad data
         let mut scratchpad = [0u8; 9];
-        for i in 0..9 {
-            scratchpad[i] = self.read_byte()?;
+        for byte in scratchpad.iter_mut() {
+            *byte = self.read_byte()?;
         }
+        Ok(scratchpad)
+    }
 
-        // Validate CRC
+    // Read a sensor's scratchpad without starting a conversion, verifying its CRC
+    pub fn read_scratchpad_checked(&mut self, rom: &[u8; 8]) -> Result<[u8; 9], OneWireError> {
+        let scratchpad = self.read_scratchpad(rom)?;
         if !Self::validate_crc(&scratchpad) {
+            return Err(OneWireError::CrcMismatch);
+        }
+        Ok(scratchpad)
+    }
+
+    pub fn read_settings(&mut self, rom: &[u8; 8]) -> Result<Settings, OneWireError> {
+        Ok(Settings::from_scratchpad(&self.read_scratchpad_checked(rom)?))
+    }
+
+    // Write TH, TL and the configuration register, then read them back to verify.
+    // With `persist` the scratchpad is also copied to EEPROM so it survives a power cycle.
+    pub fn write_settings(&mut self, rom: &[u8; 8], settings: &Settings, persist: bool) -> Result<(), OneWireError> {
+        if !self.reset()? {
+            return Err(OneWireError::DeviceNotPresent);
+        }
+        self.select_device(rom)?;
+        self.write_byte(DS18B20_WRITE_SCRATCHPAD)?;
+        self.write_byte(settings.high as u8)?;
+        self.write_byte(settings.low as u8)?;
+        self.write_byte(settings.config_register())?;
+
+        if self.read_settings(rom)? != *settings {
+            return Err(OneWireError::VerifyMismatch);
+        }
+        if !persist {
+            return Ok(());
+        }
+
+        if !self.reset()? {
+            return Err(OneWireError::DeviceNotPresent);
+        }
+        self.select_device(rom)?;
+        self.write_byte(DS18B20_COPY_SCRATCHPAD)?;
+        thread::sleep(Duration::from_millis(DS18B20_EEPROM_WRITE_MS));
+        Ok(())
+    }
+
+    // Read temperature from a specific DS18B20 sensor
+    pub fn read_temperature(&mut self, rom: &[u8; 8]) -> Result<Reading, OneWireError> {
+        let started = Instant::now();
+        let result = self.convert_and_read(rom);
+        self.stats.record_read(started.elapsed(), &result);
+        result
+    }
+
+    fn convert_and_read(&mut self, rom: &[u8; 8]) -> Result<Reading, OneWireError> {
+        let parasite = self.is_parasite(rom)?;
+
+        // Reset and check presence
+        if !self.reset()? {
             return Err(OneWireError::IoError(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "CRC validation failed"
+                std::io::ErrorKind::NotConnected,
+                "No device presence detected"
             )));
         }
 
-        // Extract temperature (bytes 0 and 1, little-endian)
-        let temp_raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
-        let temp_c = temp_raw as f32 * 0.0625;
+        // Select the specific device
+        self.select_device(rom)?;
+
+        // Issue temperature conversion command
+        self.write_byte(DS18B20_CONVERT_T)?;
 
-        Ok(temp_c)
+        // Wait for conversion to complete (750ms max for 12-bit)
+        let conversion_started = Instant::now();
+        let wait = self.wait_for_conversion(parasite)?;
+        self.scratchpad_reading(rom, Conversion { time: conversion_started.elapsed(), wait })
     }
-}
 
-fn read_config() -> (String, Vec<[u8; 8]>) {
-    let mut device_path = "/dev/ttyUSB0".to_string();
-    let mut sensors = Vec::new();
-    
-    if let Ok(content) = std::fs::read_to_string("digitemp.conf") {
-        for line in content.lines() {
-            if line.starts_with("TTY") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    device_path = parts[1].to_string();
-                }
-            } else if line.starts_with("ROM") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 10 {
-                    let mut rom = [0u8; 8];
-                    for i in 0..8 {
-                        if let Ok(byte) = u8::from_str_radix(&parts[i + 2].trim_start_matches("0x"), 16) {
-                            rom[i] = byte;
-                        }
-                    }
-                    sensors.push(rom);
+    // Start a conversion on every sensor of the bus at once (Skip ROM + Convert T) and wait
+    // for all of them; each result is then collected with `read_converted`
+    pub fn convert_all(&mut self) -> Result<Conversion, OneWireError> {
+        let parasite = self.any_parasite()?;
+        if !self.reset()? {
+            return Err(OneWireError::DeviceNotPresent);
+        }
+        self.write_byte(DS18B20_SKIP_ROM)?;
+        self.write_byte(DS18B20_CONVERT_T)?;
+
+        let conversion_started = Instant::now();
+        let wait = self.wait_for_conversion(parasite)?;
+        Ok(Conversion { time: conversion_started.elapsed(), wait })
+    }
+
+    // Whether any sensor on the bus runs on parasite power
+    fn any_parasite(&mut self) -> Result<bool, OneWireError> {
+        if !self.reset()? {
+            return Err(OneWireError::DeviceNotPresent);
+        }
+        self.write_byte(DS18B20_SKIP_ROM)?;
+        self.write_byte(DS18B20_READ_POWER_SUPPLY)?;
+        Ok(self.touch_bits(&[1])?[0] == 0)
+    }
+
+    // Collect the result of a finished conversion from one sensor
+    pub fn read_converted(&mut self, rom: &[u8; 8], conversion: Conversion) -> Result<Reading, OneWireError> {
+        let started = Instant::now();
+        let result = self.scratchpad_reading(rom, conversion);
+        self.stats.record_read(started.elapsed(), &result);
+        result
+    }
+
+    fn scratchpad_reading(&mut self, rom: &[u8; 8], conversion: Conversion) -> Result<Reading, OneWireError> {
+        // Re-read the scratchpad on CRC mismatch; the conversion result stays valid
+        let mut retries = 0;
+        let mut crc_failures = 0;
+        let scratchpad = loop {
+            let scratchpad = self.read_scratchpad(rom)?;
+            if Self::validate_crc(&scratchpad) {
+                break scratchpad;
+            }
+
+            crc_failures += 1;
+            if retries >= MAX_CRC_RETRIES {
+                // Nothing pulled the bus low: no sensor answered to its ROM
+                if scratchpad == [0xFF; 9] {
+                    return Err(OneWireError::DeviceNotPresent);
                 }
+                return Err(OneWireError::CrcMismatch);
             }
+            retries += 1;
+        };
+
+        // Extract temperature (bytes 0 and 1, little-endian)
+        // Bits below the configured resolution are undefined
+        let resolution = Settings::from_scratchpad(&scratchpad).resolution;
+        let temp_raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]) & !((1 << (12 - resolution)) - 1);
+        let temp_c = temp_raw as f32 * 0.0625;
+
+        // Reject values the DS18B20 cannot physically report
+        if !(DS18B20_MIN_TEMP..=DS18B20_MAX_TEMP).contains(&temp_c) {
+            return Err(OneWireError::InvalidTemperature(temp_c as f64));
         }
+
+        Ok(Reading {
+            celsius: temp_c,
+            raw_celsius: temp_c,
+            retries,
+            crc_failures,
+            conversion_time: conversion.time,
+            wait: conversion.wait,
+            resolution,
+        })
     }
-    
-    (device_path, sensors)
 }
 
 fn celsius_to_fahrenheit(celsius: f32) -> f32 {
@@ -360,139 +755,2217 @@ fn celsius_to_fahrenheit(celsius: f32) -> f32 {
 }
 
 fn format_timestamp() -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-    let h = (now / 3600) % 24;
-    let m = (now / 60) % 60;
-    let s = now % 60;
-    format!("Oct 23 {:02}:{:02}:{:02}", h, m, s)
+    chrono::Local::now().format("%b %d %H:%M:%S").to_string()
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let matches = Command::new("digitemp_rust_native")
-        .version("0.1.0")
-        .about("DS18B20 Temperature Reader - True Native Rust Implementation")
-        .arg(Arg::new("all")
-            .short('a')
-            .long("all")
-            .help("Read all sensors with header")
-            .action(clap::ArgAction::SetTrue))
-        .arg(Arg::new("temp")
-            .short('t')
-            .long("temp")
-            .value_name("SENSOR")
-            .help("Read temperature from sensor N (0-based index)"))
-        .arg(Arg::new("device")
-            .short('s')
-            .long("serial")
-            .value_name("DEVICE")
-            .help("Serial device path"))
-        .arg(Arg::new("init")
-            .short('i')
-            .long("init")
-            .help("Discover sensors and write digitemp.conf")
-            .action(clap::ArgAction::SetTrue))
-        .arg(Arg::new("walk")
-            .short('w')
-            .long("walk")
-            .help("Discover and list all sensors on bus")
-            .action(clap::ArgAction::SetTrue))
-        .get_matches();
-
-    let (config_device_path, sensors) = read_config();
-    
-    let device_path = matches.get_one::<String>("device")
-        .map(|s| s.as_str())
-        .unwrap_or(&config_device_path);
-
-    let mut adapter = OneWireAdapter::new(device_path)?;
-
-    // Handle discovery/initialization modes
-    if matches.get_flag("init") {
-        println!("Discovering sensors on {}...", device_path);
-        let discovered = adapter.discover_sensors()?;
-        
-        if discovered.is_empty() {
-            eprintln!("No sensors found!");
-            std::process::exit(1);
+// Report a bus error and exit with the code for its failure class
+fn exit_with(error: &OneWireError) -> ! {
+    eprintln!("Error: {}", error);
+    std::process::exit(exit_code::for_error(error));
+}
+
+fn format_quality(reading: &Reading) -> String {
+    let wait = match reading.wait {
+        ConversionWait::Polled => "polled",
+        ConversionWait::Fixed => "fixed",
+    };
+    format!("raw={:.2} retries={} crc_failures={} conversion={}ms wait={}",
+        reading.raw_celsius, reading.retries, reading.crc_failures,
+        reading.conversion_time.as_millis(), wait)
+}
+
+// Replace the reading's temperature with the sensor's calibrated value, keeping the raw one
+fn calibrate(sensor: &SensorConfig, mut reading: Reading, raw: bool) -> Reading {
+    if !raw {
+        reading.celsius = sensor.calibrate(reading.raw_celsius);
+    }
+    reading
+}
+
+fn report_health(name: &str, event: HealthEvent, degraded_every: u32) {
+    match event {
+        HealthEvent::Degraded { failures } => eprintln!(
+            "Sensor {} degraded after {} consecutive failures, polling every {} passes",
+            name, failures, degraded_every),
+        HealthEvent::Recovered { failures } => eprintln!(
+            "Sensor {} recovered after {} consecutive failures", name, failures),
+    }
+}
+
+// A software alarm goes to stderr, or stdout with --json, apart from the readings, and to the
+// sinks that pass alarms on. The state file keeps the raised ones for the next run.
+fn report_alarm(index: usize, sensor: &SensorConfig, event: &AlarmEvent, states: Option<&mut SensorStates>,
+                output: &mut OutputOptions) {
+    if let Some(states) = states {
+        states.alarm(sensor, event);
+    }
+    if output.json {
+        output.emit(&json::alarm(index, sensor, event));
+    } else {
+        eprintln!("Sensor {} {}", sensor.name(), event.describe());
+    }
+    for sink in &mut output.sinks {
+        sink.alarm(index, sensor, event);
+    }
+}
+
+// Sensor column of the plain output line: the label if configured, else the
+// index, which keeps unlabeled output identical to the original digitemp
+fn plain_tag(index: usize, sensor: &SensorConfig) -> String {
+    match &sensor.label {
+        Some(label) => label.clone(),
+        None => index.to_string(),
+    }
+}
+
+// How readings are presented
+struct OutputOptions {
+    show_quality: bool,
+    raw: bool,
+    log_type: u8,
+    log_format: String,
+    unit: Option<Unit>,
+    decimals: Option<usize>,
+    quiet: bool,
+    json: bool,
+    // Color readings on stdout by their sensor's warn/crit thresholds
+    color: bool,
+    log: Option<LogFile>,
+    sinks: Vec<Route>,
+    // A sink writes to stdout, so the reading lines stay out of it
+    stdout_sink: bool,
+}
+
+impl OutputOptions {
+    // Write a reading line to the log file, if any, and to stdout unless quiet
+    fn emit(&mut self, line: &str) {
+        self.emit_painted(line, line);
+    }
+
+    // As `emit`, with `painted` shown on stdout in place of the line when colors are on
+    fn emit_painted(&mut self, line: &str, painted: &str) {
+        if let Some(log) = self.log.as_mut() {
+            if let Err(e) = log.append(line) {
+                eprintln!("Cannot write {}: {}", log.path().display(), e);
+            }
         }
-        
-        println!("Found {} sensor(s)", discovered.len());
-        
-        // Write config file
-        let mut config_content = format!("TTY {}\n", device_path);
-        config_content.push_str("READ_TIME 1000\n");
-        config_content.push_str(&format!("SENSORS {}\n", discovered.len()));
-        
-        for (i, rom) in discovered.iter().enumerate() {
-            config_content.push_str(&format!("ROM {} 0x{:02X} 0x{:02X} 0x{:02X} 0x{:02X} 0x{:02X} 0x{:02X} 0x{:02X} 0x{:02X}\n",
-                i, rom[0], rom[1], rom[2], rom[3], rom[4], rom[5], rom[6], rom[7]));
-            println!("  Sensor {}: {:02X?}", i, rom);
+        if (!self.quiet || self.json) && !self.stdout_sink {
+            println!("{}", if self.color { painted } else { line });
         }
-        
-        std::fs::write("digitemp.conf", config_content)?;
-        println!("Configuration written to digitemp.conf");
-        return Ok(());
     }
-    
-    if matches.get_flag("walk") {
-        println!("Scanning bus {}...", device_path);
-        let discovered = adapter.discover_sensors()?;
-        
-        if discovered.is_empty() {
-            println!("No sensors found.");
+
+    fn paint(&self, text: &str, level: Option<color::Level>) -> String {
+        if self.color {
+            color::paint(text, level)
         } else {
-            println!("Found {} sensor(s):", discovered.len());
-            for (i, rom) in discovered.iter().enumerate() {
-                println!("  Sensor {}: {:02X}{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
-                    i, rom[0], rom[1], rom[2], rom[3], rom[4], rom[5], rom[6], rom[7]);
+            text.to_string()
+        }
+    }
+
+    fn line_format(&self) -> api::LineFormat {
+        api::LineFormat { template: self.log_format.clone(), decimals: self.decimals }
+    }
+
+    // With -q, stdout gets only the bare temperature, one reading per line
+    fn emit_value(&self, sensor: &SensorConfig, celsius: f32) {
+        if self.quiet && !self.json && !self.stdout_sink {
+            let value = format::format_temperature(celsius,
+                self.unit.unwrap_or(Unit::Celsius), self.decimals.unwrap_or(2));
+            println!("{}", self.paint(&value, color::level(sensor, celsius)));
+        }
+    }
+}
+
+// Per-sensor state carried from one read pass to the next
+struct PassState {
+    spike_filter: SpikeFilter,
+    health: HealthTracker,
+    // Software alarms, for monitor and serve
+    alarms: Option<AlarmTracker>,
+    scheduler: Scheduler,
+    // --fast: one broadcast conversion per bus, then only scratchpad reads
+    broadcast: bool,
+    // Last readings and last-seen times, kept across runs
+    sensor_states: Option<SensorStates>,
+    missing_after: Duration,
+    // Sensors due and sensors read well in the last pass
+    last_pass: (usize, usize),
+    // Sensors read by one pass at most, in turn (monitor --max-per-pass)
+    max_per_pass: Option<usize>,
+    // TH/TL written to each sensor for --alarm-poll
+    thresholds: HashMap<[u8; 8], (i8, i8)>,
+}
+
+impl PassState {
+    // Report the sensors among `indices` that have gone missing, then save the state file
+    fn save_states(&mut self, config: &Config, indices: &[usize], explicit: bool, output: &mut OutputOptions) {
+        let Some(states) = self.sensor_states.as_mut() else {
+            return;
+        };
+        for &i in indices {
+            let sensor = &config.sensors[i];
+            if !sensor.enabled && !explicit {
+                continue;
+            }
+            if let Some(last_seen) = states.check_missing(sensor, self.missing_after, config.read_gap(sensor)) {
+                let message = format!("missing, no reading since {}", last_seen.format("%Y-%m-%d %H:%M:%S"));
+                if output.json {
+                    output.emit(&json::error(i, sensor, "missing", message));
+                } else {
+                    eprintln!("Sensor {} {}", sensor.name(), message);
+                }
             }
         }
-        return Ok(());
+        states.save();
     }
+}
 
-    // Temperature reading modes
-    if let Some(sensor_arg) = matches.get_one::<String>("temp") {
-        // Read specific sensor by index
-        let sensor_idx: usize = sensor_arg.parse()
-            .map_err(|_| "Invalid sensor index")?;
-        
-        if sensors.is_empty() {
-            eprintln!("No sensors found in config. Run with -i to initialize.");
-            std::process::exit(1);
+// Carry on with the software alarms an earlier run left raised, without raising them again.
+// Returns them, for the HTTP API to show.
+fn restore_alarms(config: &Config, indices: &[usize], state: &mut PassState) -> Vec<(usize, AlarmKind)> {
+    let (Some(alarms), Some(states)) = (state.alarms.as_mut(), &state.sensor_states) else {
+        return Vec::new();
+    };
+    let mut restored = Vec::new();
+    for &i in indices {
+        let sensor = &config.sensors[i];
+        for raised in states.get(sensor).map_or(&[][..], |state| &state.alarms) {
+            let (Some(since), Some(age)) = (raised.since(), raised.age()) else { continue };
+            alarms.restore(&sensor.rom, raised, age);
+            eprintln!("Sensor {} {} alarm still raised, since {}", sensor.name(), raised.kind.name(),
+                since.format("%Y-%m-%d %H:%M:%S"));
+            restored.push((i, raised.kind));
         }
-        
-        if sensor_idx >= sensors.len() {
-            eprintln!("Sensor {} not found (have {} sensors)", sensor_idx, sensors.len());
-            std::process::exit(1);
+    }
+    restored
+}
+
+// Show the readings an earlier run left in the state file before the first pass, marked stale
+fn show_stale(config: &Config, indices: &[usize], states: &SensorStates, output: &OutputOptions) {
+    if (output.quiet && !output.json) || output.stdout_sink {
+        return;
+    }
+    for &i in indices {
+        let sensor = &config.sensors[i];
+        let Some((celsius, last_seen)) = states.get(sensor)
+            .and_then(|state| state.celsius.zip(state.last_seen.as_deref())) else {
+            continue;
+        };
+        if output.json {
+            println!("{}", json::stale(i, sensor, celsius, last_seen, output.unit, output.decimals));
+        } else {
+            let time = chrono::DateTime::parse_from_rfc3339(last_seen)
+                .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|_| last_seen.to_string());
+            let value = format::format_temperature(celsius, output.unit.unwrap_or(Unit::Celsius),
+                output.decimals.unwrap_or(2));
+            println!("{}: {} at {} (stale)", plain_tag(i, sensor), value, time);
         }
-        
-        match adapter.read_temperature(&sensors[sensor_idx]) {
-            Ok(temp) => println!("{:.2}", temp),
-            Err(e) => {
-                eprintln!("Error: {}", e);
-                std::process::exit(1);
+    }
+}
+
+// Read every due sensor once, skipping disabled sensors, sensors whose interval has not
+// elapsed, and sensors the health tracker has backed off.
+// Returns the exit code of the first failure, if any sensor failed.
+// With LOG_TYPE 2/3 one tab-separated line is printed for the whole pass instead.
+// Sensors named on the command line (`explicit`) are read even when disabled.
+fn read_pass(buses: &mut Buses, config: &Config, selected: &[usize], explicit: bool, state: &mut PassState,
+             output: &mut OutputOptions) -> Option<i32> {
+    let PassState { health, alarms, scheduler, broadcast, sensor_states, max_per_pass, .. } = state;
+    let mut due = Vec::new();
+    for (column, &i) in selected.iter().enumerate() {
+        let sensor = &config.sensors[i];
+        let now = Instant::now();
+        let elapsed = if explicit {
+            scheduler.interval_elapsed(sensor, config.schedule(sensor), now)
+        } else {
+            scheduler.is_due(sensor, config.schedule(sensor), now)
+        };
+        if elapsed && health.should_poll(&sensor.rom) {
+            due.push((column, i));
+        } else if elapsed {
+            let event = alarms.as_mut().and_then(|alarms| alarms.backed_off(&sensor.rom, config.alarm(sensor), now));
+            if let Some(event) = event {
+                report_alarm(i, sensor, &event, sensor_states.as_mut(), output);
             }
         }
-    } else {
-        // Default or -a flag: read all sensors
-        if sensors.is_empty() {
-            eprintln!("No sensors found in config. Run with -i to initialize.");
-            std::process::exit(1);
+    }
+    let now = Instant::now();
+    if let Some(limit) = *max_per_pass {
+        scheduler.take_turns(&mut due, limit, now, |&(_, i)| {
+            let sensor = &config.sensors[i];
+            (sensor, config.freshness(sensor))
+        });
+    }
+    for &(_, i) in &due {
+        scheduler.mark_read(&config.sensors[i], now);
+    }
+
+    // Convert every bus with a due sensor at once; a bus whose broadcast fails falls back
+    // to converting its sensors one by one
+    let count = due.len();
+    let mut conversions: HashMap<&str, Option<Conversion>> = HashMap::new();
+    if *broadcast {
+        for &(_, i) in &due {
+            let sensor = &config.sensors[i];
+            let bus = config.adapter_name(sensor);
+            if conversions.contains_key(bus) {
+                continue;
+            }
+            let conversion = buses.for_sensor(config, sensor).and_then(OneWireAdapter::convert_all);
+            if let Err(e) = &conversion {
+                eprintln!("Bus {}: broadcast conversion failed ({}), reading sensors one by one", bus, e);
+            }
+            conversions.insert(bus, conversion.ok());
         }
-        
-        for (i, rom) in sensors.iter().enumerate() {
-            match adapter.read_temperature(rom) {
-                Ok(temp_c) => {
-                    let temp_f = celsius_to_fahrenheit(temp_c);
-                    println!("{} Sensor {} C: {:.2} F: {:.2}", 
-                        format_timestamp(), i, temp_c, temp_f);
+    }
+
+    let (failure, good) = read_sensors(buses, config, selected.len(), due, &conversions, state, output);
+    state.last_pass = (count, good);
+    // Raised alarms nobody acknowledged are repeated and escalated whether or not their sensor
+    // was read
+    if let Some(alarms) = state.alarms.as_mut() {
+        let now = Instant::now();
+        for &i in selected {
+            let sensor = &config.sensors[i];
+            for event in alarms.reminders(&sensor.rom, config.alarm(sensor), now) {
+                report_alarm(i, sensor, &event, state.sensor_states.as_mut(), output);
+            }
+        }
+    }
+    state.save_states(config, selected, explicit, output);
+    failure
+}
+
+// Read the `due` sensors, (column, index) pairs among `columns`, collecting the readings of
+// the buses in `conversions`. Returns the exit code of the first failure, if any, and how
+// many were read well.
+fn read_sensors(buses: &mut Buses, config: &Config, columns: usize, due: Vec<(usize, usize)>,
+                conversions: &HashMap<&str, Option<Conversion>>, state: &mut PassState,
+                output: &mut OutputOptions) -> (Option<i32>, usize) {
+    let PassState { spike_filter, health, alarms, sensor_states, .. } = state;
+    let (mut failure, mut good) = (None, 0);
+    let mut pass_values: Vec<Option<(f32, Option<color::Level>)>> = vec![None; columns];
+    for (column, i) in due {
+        let sensor = &config.sensors[i];
+        let rom = &sensor.rom;
+        let conversion = conversions.get(config.adapter_name(sensor)).copied().flatten();
+        let reading = buses.for_sensor(config, sensor).and_then(|adapter| match conversion {
+            Some(conversion) => adapter.read_converted(rom, conversion),
+            None => adapter.read_temperature(rom),
+        });
+
+        match reading {
+            Ok(reading) => {
+                if let Some(event) = health.record_success(rom) {
+                    report_health(&sensor.name(), event, health.degraded_every());
+                }
+                let reading = calibrate(sensor, reading, output.raw);
+                let temp_c = reading.celsius;
+                let verdict = spike_filter.check(rom, temp_c);
+                if let Some(states) = sensor_states.as_mut() {
+                    match verdict {
+                        Verdict::Accepted => states.reading(sensor, &reading),
+                        Verdict::Suspect { .. } => states.error(sensor, None),
+                    }
+                }
+                let bus = config.adapter_name(sensor);
+                for sink in &mut output.sinks {
+                    if verdict == Verdict::Accepted {
+                        sink.reading(bus, i, sensor, &reading, output.decimals);
+                    } else {
+                        sink.error(bus, i, sensor, None);
+                    }
+                }
+                if verdict == Verdict::Accepted {
+                    good += 1;
+                }
+                match verdict {
+                    Verdict::Accepted if output.json => {
+                        output.emit(&json::reading(i, sensor, &reading, output.unit, output.decimals,
+                            output.show_quality));
+                    }
+                    Verdict::Accepted if output.log_type != 1 => {
+                        output.emit_value(sensor, temp_c);
+                        pass_values[column] = Some((temp_c, color::level(sensor, temp_c)));
+                    }
+                    Verdict::Accepted => {
+                        output.emit_value(sensor, temp_c);
+                        let mut line = format::render(&output.log_format, &FormatContext {
+                            sensor: &plain_tag(i, sensor),
+                            rom,
+                            celsius: temp_c,
+                            time: chrono::Local::now(),
+                            decimals: output.decimals,
+                        });
+                        if output.show_quality {
+                            line.push(' ');
+                            line.push_str(&format_quality(&reading));
+                        }
+                        let painted = output.paint(&line, color::level(sensor, temp_c));
+                        output.emit_painted(&line, &painted);
+                    }
+                    Verdict::Suspect { previous } => {
+                        let message = format!("suspect reading {:.2} (previous {:.2}), discarded", temp_c, previous);
+                        if output.json {
+                            output.emit(&json::error(i, sensor, "suspect", message));
+                        } else {
+                            eprintln!("Sensor {} {}", sensor.name(), message);
+                        }
+                        failure.get_or_insert(exit_code::SUSPECT_READING);
+                    }
+                }
+                let events = alarms.as_mut().map(|alarms| match verdict {
+                    Verdict::Accepted => alarms.reading(rom, config.alarm(sensor), temp_c, Instant::now()),
+                    Verdict::Suspect { .. } => alarms.failed(rom, config.alarm(sensor), false, Instant::now()),
+                });
+                for event in events.into_iter().flatten() {
+                    report_alarm(i, sensor, &event, sensor_states.as_mut(), output);
+                }
+            }
+            Err(e) => {
+                for sink in &mut output.sinks {
+                    sink.error(config.adapter_name(sensor), i, sensor, Some(&e));
+                }
+                if let Some(states) = sensor_states.as_mut() {
+                    states.error(sensor, Some(&e));
+                }
+                if output.json {
+                    output.emit(&json::error(i, sensor, json::error_kind(&e), e.to_string()));
+                } else {
+                    eprintln!("Sensor {} error: {}", sensor.name(), e);
+                }
+                failure.get_or_insert(exit_code::for_error(&e));
+                if let Some(event) = health.record_failure(rom) {
+                    report_health(&sensor.name(), event, health.degraded_every());
+                }
+                let absent = matches!(e, OneWireError::DeviceNotPresent);
+                let events = alarms.as_mut()
+                    .map(|alarms| alarms.failed(rom, config.alarm(sensor), absent, Instant::now()));
+                for event in events.into_iter().flatten() {
+                    report_alarm(i, sensor, &event, sensor_states.as_mut(), output);
                 }
-                Err(e) => eprintln!("Sensor {} error: {}", i, e),
             }
+        }
+        if conversion.is_none() {
             thread::sleep(Duration::from_millis(500));
         }
     }
 
-    Ok(())
+    if output.log_type != 1 && !output.json {
+        let mut line = format_timestamp();
+        let mut painted = line.clone();
+        let decimals = output.decimals.unwrap_or(2);
+        for value in pass_values {
+            let (column, level) = match value {
+                Some((c, level)) if output.log_type == 3 => {
+                    (format!("{:.*}", decimals, celsius_to_fahrenheit(c)), level)
+                }
+                Some((c, level)) => (format!("{:.*}", decimals, c), level),
+                None => ("NaN".to_string(), None),
+            };
+            line.push('\t');
+            line.push_str(&column);
+            painted.push('\t');
+            painted.push_str(&output.paint(&column, level));
+        }
+        output.emit_painted(&line, &painted);
+    }
+    for sink in &mut output.sinks {
+        sink.pass_done();
+    }
+    (failure, good)
+}
+
+// Between passes with --alarm-poll: write TH/TL from the high/low alarms where they are not
+// yet, convert every bus with such sensors at once, and read the sensors the alarm search finds
+// past them out of turn. Other sensors of those buses get thresholds they cannot reach, so they
+// keep out of the search.
+fn poll_alarms(buses: &mut Buses, config: &Config, selected: &[usize], explicit: bool, state: &mut PassState,
+               output: &mut OutputOptions) -> Option<i32> {
+    // Software alarms, and so polling, are for monitor and serve
+    state.alarms.as_ref()?;
+    let thresholds = |i: usize| hardware_thresholds(config.alarm(&config.sensors[i]));
+    let polled: Vec<&str> = selected.iter().filter(|&&i| thresholds(i).is_some())
+        .map(|&i| config.adapter_name(&config.sensors[i])).collect();
+    let mut buses_polled: BTreeMap<&str, Vec<(usize, usize)>> = BTreeMap::new();
+    for (column, &i) in selected.iter().enumerate() {
+        let sensor = &config.sensors[i];
+        let bus = config.adapter_name(sensor);
+        if polled.contains(&bus) && (sensor.enabled || explicit) && !state.health.is_degraded(&sensor.rom) {
+            buses_polled.entry(bus).or_default().push((column, i));
+        }
+    }
+
+    let mut due = Vec::new();
+    let mut conversions: HashMap<&str, Option<Conversion>> = HashMap::new();
+    for (bus, sensors) in buses_polled {
+        let Ok(adapter) = buses.for_sensor(config, &config.sensors[sensors[0].1]) else { continue };
+        for &(_, i) in &sensors {
+            let sensor = &config.sensors[i];
+            let (high, low) = thresholds(i).unwrap_or((DS18B20_MAX_TEMP as i8, DS18B20_MIN_TEMP as i8));
+            if state.thresholds.get(&sensor.rom) == Some(&(high, low)) {
+                continue;
+            }
+            let written = adapter.read_settings(&sensor.rom).and_then(|settings| {
+                let wanted = Settings { high, low, ..settings };
+                if settings == wanted { Ok(()) } else { adapter.write_settings(&sensor.rom, &wanted, false) }
+            });
+            match written {
+                Ok(()) => {
+                    state.thresholds.insert(sensor.rom, (high, low));
+                }
+                Err(e) => eprintln!("Sensor {}: cannot write TH/TL for --alarm-poll: {}", sensor.name(), e),
+            }
+        }
+        let flagged = adapter.convert_all().and_then(|conversion| {
+            conversions.insert(bus, Some(conversion));
+            adapter.alarm_search()
+        });
+        let flagged = match flagged {
+            Ok(flagged) => flagged,
+            Err(e) => {
+                eprintln!("Bus {}: alarm poll failed ({})", bus, e);
+                continue;
+            }
+        };
+        for (column, i) in sensors {
+            let sensor = &config.sensors[i];
+            if flagged.contains(&sensor.rom) && thresholds(i).is_some() {
+                due.push((column, i));
+                // Checked again on the next poll, in case a power cycle put back the EEPROM's
+                state.thresholds.remove(&sensor.rom);
+            }
+        }
+    }
+    if due.is_empty() {
+        return None;
+    }
+    let now = Instant::now();
+    for &(_, i) in &due {
+        state.scheduler.mark_read(&config.sensors[i], now);
+    }
+    let (failure, _) = read_sensors(buses, config, selected.len(), due, &conversions, state, output);
+    state.save_states(config, selected, explicit, output);
+    failure
+}
+
+// TH/TL that flag a sensor whenever a high or low alarm may be due: the whole degrees at or
+// below `high` and `low`. None for sensors with neither.
+fn hardware_thresholds(alarm: Option<&config::AlarmConfig>) -> Option<(i8, i8)> {
+    let alarm = alarm.filter(|alarm| alarm.high.is_some() || alarm.low.is_some())?;
+    let whole = |celsius: f32| celsius.floor().clamp(DS18B20_MIN_TEMP, DS18B20_MAX_TEMP) as i8;
+    Some((alarm.high.map_or(DS18B20_MAX_TEMP as i8, whole), alarm.low.map_or(DS18B20_MIN_TEMP as i8, whole)))
+}
+
+// The config file with --config-override merged over it
+fn load_config(matches: &ArgMatches) -> Result<(Config, ConfigSource), String> {
+    let config_path = matches.get_one::<String>("config").map(std::path::Path::new);
+    let (config, source) = Config::load(config_path)?;
+    match matches.get_one::<String>("config_override") {
+        Some(path) => Ok((config.merge_file(std::path::Path::new(path))?, source)),
+        None => Ok((config, source)),
+    }
+}
+
+fn pid_file(config: &Config, args: &ArgMatches) -> Option<std::path::PathBuf> {
+    args.get_one::<String>("pid_file").map(std::path::PathBuf::from).or_else(|| config.daemon.pid_file.clone())
+}
+
+// Before the buses are opened: refuse to run twice, and detach with -D
+fn start_daemon(config: &Config, args: &ArgMatches) {
+    if let Some(Err(e)) = pid_file(config, args).map(|path| daemon::PidFile::check(&path)) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    if !args.get_flag("daemon") {
+        return;
+    }
+    #[cfg(unix)]
+    let result = daemon::detach();
+    #[cfg(not(unix))]
+    let result = Err("--daemon needs a Unix system; run under a service manager instead".to_string());
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn started_daemon(config: &Config, args: &ArgMatches) -> Option<daemon::PidFile> {
+    let pid_file = pid_file(config, args).map(|path| daemon::PidFile::create(&path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }));
+    // After the pid file, which may be in a directory only root can write, such as /run
+    let user = args.get_one::<String>("user").or(config.daemon.user.as_ref());
+    let group = args.get_one::<String>("group").or(config.daemon.group.as_ref());
+    if user.is_some() || group.is_some() {
+        #[cfg(unix)]
+        let result = daemon::drop_privileges(user.map(String::as_str), group.map(String::as_str));
+        #[cfg(not(unix))]
+        let result = Err("--user and --group need a Unix system".to_string());
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    // Only a daemon or a Windows service has no terminal for its messages
+    #[cfg(windows)]
+    let detached = args.get_flag("daemon") || service::running();
+    #[cfg(not(windows))]
+    let detached = args.get_flag("daemon");
+    let log = args.get_one::<String>("daemon_log").map(std::path::PathBuf::from)
+        .or_else(|| config.daemon.log_file.clone())
+        .filter(|_| detached)
+        .map(|path| daemon_log(&path, &config.daemon));
+    if let Err(e) = log.transpose().and_then(daemon::started) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    pid_file
+}
+
+// The -D message log, rotated by time or size as [daemon] says
+fn daemon_log(path: &std::path::Path, daemon: &config::DaemonConfig) -> Result<LogFile, String> {
+    let log = match (daemon.log_rotate, daemon.log_max_mb) {
+        (Some(_), Some(_)) => return Err("daemon: log_rotate and log_max_mb cannot be combined".to_string()),
+        (Some(every), None) => LogFile::open_rotating(path, FileRotation {
+            every,
+            compress: daemon.log_compress,
+            keep: daemon.log_keep,
+        }),
+        (None, Some(max_mb)) => LogFile::open_sized(path, logfile::SizeRotation {
+            max_bytes: max_mb.max(1) * 1024 * 1024,
+            compress: daemon.log_compress,
+            keep: daemon.log_keep,
+        }),
+        (None, None) => LogFile::open(path),
+    };
+    log.map_err(|e| format!("Cannot open daemon log {}: {}", path.display(), e))
+}
+
+// Command-line settings that take precedence over the config file
+fn apply_overrides(args: &ArgMatches, config: &mut Config) {
+    if let Some(device_path) = args.get_one::<String>("device") {
+        config.set_primary_tty(device_path);
+    } else if config.adapters.is_empty() {
+        config.set_primary_tty(config::DEFAULT_TTY);
+    }
+}
+
+fn output_options(args: &ArgMatches, config: &Config) -> Result<OutputOptions, String> {
+    // -o takes a LOG_TYPE number or a LOG_FORMAT template, like the original
+    let (log_type, log_format) = match args.get_one::<String>("output_format").map(String::as_str) {
+        Some(log_type @ ("1" | "2" | "3")) => (log_type.parse().unwrap(), config.output.log_format.clone()),
+        Some(template) => (1, Some(template.to_string())),
+        None => (config.output.log_type, config.output.log_format.clone()),
+    };
+    let log_path = args.get_one::<String>("log").map(std::path::PathBuf::from)
+        .or_else(|| config.output.log.clone());
+    let log_rotation = config.output.log_rotate.map(|every| FileRotation {
+        every,
+        compress: config.output.log_compress,
+        keep: config.output.log_keep,
+    });
+    let log = match log_path {
+        Some(path) => {
+            let log = match log_rotation {
+                Some(rotation) => LogFile::open_rotating(&path, rotation),
+                None => LogFile::open(&path),
+            };
+            Some(log.map_err(|e| format!("Cannot open log file {}: {}", path.display(), e))?)
+        }
+        None => None,
+    };
+    let mut sinks = Route::open_configured(config)?;
+    for sink in &mut sinks {
+        sink.announce(config);
+    }
+    if let Some(path) = args.get_one::<String>("jsonl") {
+        sinks.push(Route::all(Sink::jsonl(Some(std::path::Path::new(path)))?));
+    }
+    if let Some(path) = args.get_one::<String>("csv") {
+        let columns = args.get_many::<CsvColumn>("csv_columns")
+            .map_or_else(config::default_csv_columns, |columns| columns.copied().collect());
+        let csv = CsvSink::open(Some(std::path::Path::new(path)), columns, true, true, None).map(Sink::Csv)?;
+        sinks.push(Route::all(csv));
+    }
+    let unit = args.get_one::<Unit>("unit").copied().or(config.output.unit);
+    let decimals = args.get_one::<usize>("decimals").copied().or(config.output.decimals);
+    Ok(OutputOptions {
+        show_quality: args.get_flag("quality") || config.stdout_quality(),
+        raw: args.get_flag("raw"),
+        log_type,
+        log_format: log_format.unwrap_or_else(|| format::default_template(unit, decimals)),
+        unit,
+        decimals,
+        quiet: args.get_flag("quiet"),
+        json: args.get_flag("json"),
+        color: args.get_one::<ColorChoice>("color").copied().unwrap_or(ColorChoice::Auto).enabled(),
+        log,
+        stdout_sink: sinks.iter().any(Route::uses_stdout),
+        sinks,
+    })
+}
+
+// Progress messages on stdout, suppressed by -q and --json so stdout stays machine-readable
+fn progress(args: &ArgMatches, message: &str) {
+    if !args.get_flag("quiet") && !args.get_flag("json") {
+        println!("{}", message);
+    }
+}
+
+// Re-read the config on SIGHUP or a control `reload`. Sensors, labels, intervals and output
+// settings take effect from the next pass; the open serial ports are kept, so adapter changes
+// need a restart. A config that fails to load leaves the running one in place, and the reason
+// is returned. The server, if any, lists the sensors of the new config.
+fn reload_config(args: &ArgMatches, config: &mut Config, output: &mut OutputOptions, server: Option<&api::Server>,
+                 notifier: Option<&systemd::Notifier>) -> Result<(), String> {
+    if let Some(notifier) = notifier {
+        notifier.reloading();
+    }
+    let result = reload(args, config, output, server);
+    if let Some(notifier) = notifier {
+        notifier.ready(&format!("Reading {} sensor(s)", config.sensors.len()));
+    }
+    result
+}
+
+fn reload(args: &ArgMatches, config: &mut Config, output: &mut OutputOptions, server: Option<&api::Server>)
+          -> Result<(), String> {
+    let mut reloaded = match load_config(args)? {
+        (_, ConfigSource::New(path)) => return Err(format!("{} not found", path.display())),
+        (reloaded, _) => reloaded,
+    };
+    apply_overrides(args, &mut reloaded);
+    if reloaded.adapters != config.adapters {
+        eprintln!("Reload: adapter changes take effect after a restart");
+        reloaded.adapters = config.adapters.clone();
+    }
+    reloaded.check_modbus_registers()?;
+    *output = output_options(args, &reloaded)?;
+
+    let added = reloaded.sensors.iter().filter(|s| config.find_sensor(&s.rom).is_none()).count();
+    let removed = config.sensors.iter().filter(|s| reloaded.find_sensor(&s.rom).is_none()).count();
+    eprintln!("Reloaded config: {} sensor(s), {} added, {} removed",
+        reloaded.sensors.len(), added, removed);
+    *config = reloaded;
+    if let Some(server) = server {
+        server.configure(config);
+        server.set_line_format(output.line_format());
+        output.sinks.push(Route::all(Sink::Api(server.sink())));
+    }
+    Ok(())
+}
+
+// How repeated passes are spaced
+enum Pace {
+    // Fixed pause after each pass, as digitemp's -d
+    Delay(Duration),
+    // From the start of one pass to the start of the next (monitor --interval)
+    Interval(Duration),
+}
+
+// What `repeat` hands its closure
+enum Turn {
+    // A pass, with the cycle request that asked for it
+    Pass(Option<api::Request>),
+    // A request to answer between passes
+    Request(api::Request),
+    // Time for --alarm-poll
+    AlarmPoll,
+}
+
+// Run `pass` `count` times (0 = forever), spaced by `pace`, until `stop` is signalled.
+// With a `server`, requests from its clients while waiting, such as immediate reads, are
+// handed to `pass` as they arrive, instead of a pass; a cycle request is handed over as the
+// next pass, taken at once. `wake` signals end the wait, too. Every `alarm_poll` while
+// waiting, `pass` is asked to poll the alarms. Returns the exit code of the first failure.
+fn repeat(count: u64, pace: Pace, stop: Option<&StopSignal>, server: Option<&api::Server>,
+          wake: Option<&UserSignals>, alarm_poll: Option<Duration>, mut pass: impl FnMut(Turn) -> Option<i32>)
+          -> Option<i32> {
+    let mut failure = None;
+    let mut done = 0;
+    let mut cycle = None;
+    loop {
+        let started = Instant::now();
+        if let Some(code) = pass(Turn::Pass(cycle.take())) {
+            failure.get_or_insert(code);
+        }
+        done += 1;
+        if count != 0 && done >= count {
+            return failure;
+        }
+
+        let wait = match pace {
+            Pace::Delay(delay) => delay,
+            Pace::Interval(interval) => interval.saturating_sub(started.elapsed()),
+        };
+        let until = Instant::now() + wait;
+        let mut poll_at = alarm_poll.map(|poll| Instant::now() + poll);
+        while !stop.is_some_and(StopSignal::requested) && !wake.is_some_and(UserSignals::pending) {
+            if let (Some(poll), Some(at)) = (alarm_poll, poll_at) {
+                if Instant::now() >= at {
+                    if let Some(code) = pass(Turn::AlarmPoll) {
+                        failure.get_or_insert(code);
+                    }
+                    poll_at = Some(Instant::now() + poll);
+                }
+            }
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            let nap = poll_at.map_or(left, |at| left.min(at.saturating_duration_since(Instant::now())))
+                .min(Duration::from_millis(100));
+            let Some(server) = server else {
+                thread::sleep(nap);
+                continue;
+            };
+            match server.next_request(nap) {
+                Some(request) if matches!(request.action, api::Action::Cycle) => {
+                    cycle = Some(request);
+                    break;
+                }
+                Some(request) => {
+                    pass(Turn::Request(request));
+                }
+                None => {}
+            }
+        }
+        if stop.is_some_and(StopSignal::requested) {
+            return failure;
+        }
+    }
+}
+
+fn run_check_config(config: &Config, source: &ConfigSource) -> ! {
+    match source {
+        ConfigSource::Toml(path) | ConfigSource::Legacy(path) => println!("Config: {}", path.display()),
+        ConfigSource::New(_) => {
+            eprintln!("No configuration file found. Run with -i to initialize.");
+            std::process::exit(exit_code::CONFIG);
+        }
+    }
+
+    match config.to_toml() {
+        Ok(normalized) => print!("{}", normalized),
+        Err(e) => eprintln!("Cannot render config: {}", e),
+    }
+
+    let problems = check::check_config(config);
+    if problems.is_empty() {
+        eprintln!("Configuration OK ({} sensor(s))", config.sensors.len());
+        std::process::exit(0);
+    }
+    for problem in &problems {
+        eprintln!("Problem: {}", problem);
+    }
+    eprintln!("{} problem(s) found", problems.len());
+    std::process::exit(exit_code::CONFIG);
+}
+
+// Convert a legacy digitemp rc file to digitemp.toml, showing where each line went
+fn run_migrate_config(source: &std::path::Path, output: Option<&std::path::Path>, force: bool) -> ! {
+    let content = std::fs::read_to_string(source).unwrap_or_else(|e| {
+        eprintln!("Cannot read {}: {}", source.display(), e);
+        std::process::exit(exit_code::CONFIG);
+    });
+    let migrated = legacy::parse(&content)
+        .and_then(|config| config.to_toml())
+        .unwrap_or_else(|e| {
+            eprintln!("Config error: {} {}", source.display(), e);
+            std::process::exit(exit_code::CONFIG);
+        });
+
+    // digitemp.conf gets a digitemp.toml next to it, which then takes precedence;
+    // other rc files (~/.digitemprc) move to the user config directory
+    let target = match output {
+        Some(path) => path.to_path_buf(),
+        None if source.file_name().is_some_and(|name| name == config::LEGACY_CONFIG) => {
+            source.with_file_name(config::TOML_CONFIG)
+        }
+        None => config::search_dirs()[0].join(config::TOML_CONFIG),
+    };
+    if target.exists() && !force {
+        eprintln!("{} already exists; use --force to overwrite it", target.display());
+        std::process::exit(exit_code::CONFIG);
+    }
+
+    println!("--- {}", source.display());
+    println!("+++ {}", target.display());
+    for (line, mapped) in legacy::mapping(&content) {
+        println!("- {}", line);
+        match mapped {
+            Some(mapped) => println!("+ {}", mapped),
+            None => println!("  (no equivalent, dropped)"),
+        }
+    }
+
+    let written = target.parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| config::write_atomic(&target, &migrated));
+    match written {
+        Ok(backup) => {
+            if let Some(backup) = backup {
+                println!("Previous version saved as {}", backup.display());
+            }
+        }
+        Err(e) => {
+            eprintln!("Cannot write {}: {}", target.display(), e);
+            std::process::exit(exit_code::CONFIG);
+        }
+    }
+    println!("Configuration written to {}", target.display());
+    std::process::exit(0);
+}
+
+// Step-by-step diagnosis of every configured adapter; exits with the first failure's code
+fn run_doctor(config: &Config, args: &ArgMatches) -> ! {
+    let checks: Vec<doctor::Check> = config.adapters.iter().flat_map(doctor::diagnose).collect();
+    if args.get_flag("json") {
+        println!("{}", json::to_line(&checks));
+    } else {
+        let mut adapter = "";
+        for check in &checks {
+            if check.adapter != adapter {
+                adapter = &check.adapter;
+                let tty = config.adapters.iter().find(|a| a.name == adapter).map_or("", |a| a.tty.as_str());
+                println!("Adapter '{}' ({}):", adapter, tty);
+            }
+            match check.status {
+                doctor::Status::Pass => println!("  [PASS] {}: {}", check.step, check.detail),
+                doctor::Status::Fail => println!("  [FAIL] {}: {}", check.step, check.detail),
+                doctor::Status::Skip => println!("  [SKIP] {}", check.step),
+            }
+        }
+    }
+
+    let failed: Vec<&doctor::Check> = checks.iter().filter(|c| c.status == doctor::Status::Fail).collect();
+    match failed.first() {
+        None => {
+            progress(args, "All checks passed");
+            std::process::exit(0);
+        }
+        Some(first) => {
+            eprintln!("{} check(s) failed", failed.len());
+            std::process::exit(first.code);
+        }
+    }
+}
+
+// Write the config back to the file it came from, atomically; exits on failure.
+// Returns the path written and the backup of the previous version, if there was one.
+fn save_config(config: &Config, source: &ConfigSource, args: &ArgMatches)
+               -> (std::path::PathBuf, Option<std::path::PathBuf>) {
+    // Legacy installations keep their digitemp.conf; everything else gets TOML
+    let overlaid = !config.include.is_empty() || args.get_one::<String>("config_override").is_some();
+    let (path, content) = match source {
+        ConfigSource::Legacy(path) => (path.clone(), Ok(legacy::render(config))),
+        ConfigSource::Toml(path) | ConfigSource::New(path) if overlaid => {
+            (path.clone(), config.rebase(path).and_then(|base| base.to_toml()))
+        }
+        ConfigSource::Toml(path) | ConfigSource::New(path) => (path.clone(), config.to_toml()),
+    };
+    let written = content.and_then(|content| {
+        config::write_atomic(&path, &content).map_err(|e| e.to_string())
+    });
+    let backup = match written {
+        Ok(backup) => backup,
+        Err(e) => {
+            eprintln!("Cannot write {}: {}", path.display(), e);
+            std::process::exit(exit_code::CONFIG);
+        }
+    };
+    if let Some(backup) = &backup {
+        progress(args, &format!("Previous version saved as {}", backup.display()));
+    }
+    progress(args, &format!("Configuration written to {}", path.display()));
+    (path, backup)
+}
+
+// Print the bus statistics accumulated in the state file, or clear them with --reset
+fn run_bus_stats(config: &Config, args: &ArgMatches) -> ! {
+    let Some(path) = config.daemon.state_file.clone().or_else(stats::default_path) else {
+        eprintln!("No state file: set state_file in [daemon] or HOME");
+        std::process::exit(exit_code::CONFIG);
+    };
+    if args.get_flag("reset") {
+        match std::fs::remove_file(&path) {
+            Ok(()) => progress(args, &format!("Bus statistics in {} cleared", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                eprintln!("Cannot remove {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+
+    let file = stats::StatsFile::load(&path).unwrap_or_else(|e| {
+        eprintln!("Cannot read bus statistics: {}", e);
+        std::process::exit(1);
+    });
+    if args.get_flag("json") {
+        println!("{}", json::to_line(&file));
+        std::process::exit(0);
+    }
+    if file.buses.is_empty() {
+        println!("No bus statistics recorded yet in {}", path.display());
+        std::process::exit(0);
+    }
+    println!("Since {}:", file.since);
+    for (name, entry) in &file.buses {
+        let stats = &entry.stats;
+        let percent = |count: u64, total: u64| {
+            if total > 0 { count as f64 * 100.0 / total as f64 } else { 0.0 }
+        };
+        println!("Bus '{}' ({}):", name, entry.tty);
+        println!("  resets             {}", stats.resets);
+        println!("  presence failures  {} ({:.1}%)", stats.presence_failures,
+            percent(stats.presence_failures, stats.resets));
+        println!("  reads              {} ({} failed, {:.1}%)", stats.reads, stats.failed_reads,
+            percent(stats.failed_reads, stats.reads));
+        println!("  CRC errors         {}", stats.crc_errors);
+        println!("  retries            {}", stats.retries);
+        match stats.average_latency_ms() {
+            Some(latency) => println!("  average latency    {:.0} ms", latency),
+            None => println!("  average latency    -"),
+        }
+    }
+    std::process::exit(0);
+}
+
+// Create the files of the rrd sinks for the configured sensors
+fn run_create_rrd(config: &Config, args: &ArgMatches) -> ! {
+    let sensors: Vec<(usize, &str, &SensorConfig)> = config.sensors.iter().enumerate()
+        .map(|(i, sensor)| (i, config.adapter_name(sensor), sensor))
+        .collect();
+    let mut files = Vec::new();
+    for entry in &config.sinks {
+        if let config::SinkConfig::Rrd { path, rrdtool, step } = &entry.sink {
+            match rrd::create(path, rrdtool, *step, &sensors, args.get_flag("force")) {
+                Ok(created) => files.extend(created),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    if files.is_empty() {
+        eprintln!("No rrd sink with configured sensors; add a [[sinks]] entry with type = \"rrd\"");
+        std::process::exit(exit_code::CONFIG);
+    }
+
+    if args.get_flag("json") {
+        println!("{}", json::to_line(&files));
+    } else if !args.get_flag("quiet") {
+        for file in &files {
+            let status = if file.status == "created" { "created" } else { "exists, kept" };
+            println!("Sensor {}: {} {}", file.sensor, file.path, status);
+        }
+    }
+    std::process::exit(0);
+}
+
+// Range and store of a `history` or `export` query
+struct HistoryQuery {
+    path: std::path::PathBuf,
+    since: i64,
+    until: i64,
+    every: Option<i64>,
+}
+
+// Summaries of the stored readings in the range given by the `start` and `end` arguments, from
+// --db or the first history or sqlite sink in the config; exits on errors
+fn query_history(config: &Config, args: &ArgMatches, start: &str, end: &str)
+                 -> (HistoryQuery, Vec<history::Summary>) {
+    // (path, whether it is an SQLite database)
+    let configured = config.sinks.iter().find_map(|entry| match &entry.sink {
+        config::SinkConfig::History { directory, .. } => Some((directory.clone(), false)),
+        config::SinkConfig::Sqlite { path } => Some((path.clone(), true)),
+        _ => None,
+    });
+    let Some((path, is_sqlite)) = args.get_one::<String>("db").map(|db| (std::path::PathBuf::from(db), true))
+        .or(configured) else {
+        eprintln!("No history or sqlite sink in the config; add a [[sinks]] entry with type = \"history\"");
+        std::process::exit(exit_code::CONFIG);
+    };
+    let time = |name: &str| history::parse_time(args.get_one::<String>(name).unwrap()).unwrap_or_else(|e| {
+        eprintln!("--{}: {}", name, e);
+        std::process::exit(exit_code::USAGE);
+    });
+    let (since, mut until) = (time(start), time(end));
+    // The range excludes its end, but "now" should include the readings of this second
+    if args.get_one::<String>(end).is_some_and(|until| until.trim() == "now") {
+        until += 1;
+    }
+    let every = args.get_one::<Duration>("every").map(|every| every.as_secs().max(1) as i64);
+
+    // Sensors no longer in the config can still be named by ROM
+    let roms: Vec<String> = args.get_many::<String>("sensor").unwrap_or_default()
+        .map(|spec| match config.resolve_sensor(spec) {
+            Ok(index) => Ok(config::format_rom(&config.sensors[index].rom)),
+            Err(e) => config::parse_rom(spec).map(|rom| config::format_rom(&rom)).map_err(|_| e),
+        })
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(exit_code::NO_SENSORS);
+        });
+
+    #[cfg(feature = "sqlite")]
+    let summaries = if is_sqlite {
+        sqlite::summarize(&path, &roms, since, until, every)
+    } else {
+        history::summarize(&path, &roms, since, until, every)
+    };
+    #[cfg(not(feature = "sqlite"))]
+    let summaries = if is_sqlite {
+        Err("SQLite databases need a build with the sqlite feature".to_string())
+    } else {
+        history::summarize(&path, &roms, since, until, every)
+    };
+    let summaries = summaries.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    (HistoryQuery { path, since, until, every }, summaries)
+}
+
+fn history_record<'a>(config: &Config, summary: &'a history::Summary) -> json::HistoryRecord<'a> {
+    let local = |unix: i64| chrono::DateTime::from_timestamp(unix, 0).unwrap_or_default().with_timezone(&chrono::Local);
+    json::HistoryRecord {
+        sensor: stored_sensor_name(config, &summary.rom),
+        rom: &summary.rom,
+        from: local(summary.from).to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        to: local(summary.to).to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        count: summary.count,
+        min: summary.min,
+        max: summary.max,
+        avg: summary.avg,
+    }
+}
+
+// Label of a stored sensor, or its ROM when it is no longer configured
+fn stored_sensor_name(config: &Config, rom: &str) -> String {
+    config::parse_rom(rom).ok().and_then(|rom| config.find_sensor(&rom))
+        .map_or_else(|| rom.to_string(), SensorConfig::name)
+}
+
+// Summarize the readings kept by the history store or the sqlite sink, per sensor and
+// optionally per period
+fn run_history(config: &Config, args: &ArgMatches) -> ! {
+    let (HistoryQuery { path, since, until, every }, summaries) = query_history(config, args, "since", "until");
+    let name = |rom: &str| stored_sensor_name(config, rom);
+    let local = |unix: i64| chrono::DateTime::from_timestamp(unix, 0).unwrap_or_default().with_timezone(&chrono::Local);
+
+    if args.get_flag("json") {
+        let records: Vec<json::HistoryRecord> = summaries.iter()
+            .map(|summary| history_record(config, summary)).collect();
+        println!("{}", json::to_line(&records));
+        std::process::exit(0);
+    }
+    if summaries.is_empty() {
+        println!("No readings from {} to {} in {}", local(since).format("%Y-%m-%d %H:%M"),
+            local(until).format("%Y-%m-%d %H:%M"), path.display());
+        std::process::exit(0);
+    }
+    let decimals = config.output.decimals.unwrap_or(2);
+    let mut current = None;
+    for summary in &summaries {
+        let values = format!("min {:.*}, max {:.*}, avg {:.*}", decimals, summary.min, decimals, summary.max,
+            decimals, summary.avg);
+        if every.is_none() {
+            println!("{} ({}): {} readings, {}", name(&summary.rom), summary.rom, summary.count, values);
+            continue;
+        }
+        if current != Some(&summary.rom) {
+            println!("{} ({}):", name(&summary.rom), summary.rom);
+            current = Some(&summary.rom);
+        }
+        println!("  {}  {} ({} readings)", local(summary.from).format("%Y-%m-%d %H:%M"), values, summary.count);
+    }
+    std::process::exit(0);
+}
+
+// Serve snmpd until it closes our stdin; the sensor state file is all this reads
+fn run_snmp_pass(config: &Config, args: &ArgMatches) -> ! {
+    let Some(path) = config.daemon.sensor_state.clone().or_else(state::default_path) else {
+        eprintln!("No sensor state file: set sensor_state in [daemon] or HOME");
+        std::process::exit(exit_code::CONFIG);
+    };
+    let oid = args.get_one::<String>("oid").unwrap();
+    let Some(base) = snmp::parse_oid(oid) else {
+        eprintln!("Invalid OID '{}'", oid);
+        std::process::exit(exit_code::CONFIG);
+    };
+    if let Err(e) = snmp::pass_persist(config, &path, &base) {
+        eprintln!("snmp-pass: {}", e);
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+// Downsampled readings for reports: one row per sensor and period, in time order
+fn run_export(config: &Config, args: &ArgMatches) -> ! {
+    let (_, mut summaries) = query_history(config, args, "from", "to");
+    summaries.sort_by(|a, b| (a.from, &a.rom).cmp(&(b.from, &b.rom)));
+    let records: Vec<json::HistoryRecord> = summaries.iter().map(|summary| history_record(config, summary)).collect();
+
+    if args.get_flag("json") || args.get_one::<String>("format").is_some_and(|format| format == "json") {
+        println!("{}", json::to_line(&records));
+        std::process::exit(0);
+    }
+    println!("from,to,sensor,rom,count,min,max,avg");
+    for record in &records {
+        println!("{},{},{},{},{},{},{},{}", record.from, record.to, csv::quote(&record.sensor), record.rom,
+            record.count, record.min, record.max, record.avg);
+    }
+    std::process::exit(0);
+}
+
+// Give a sensor a new label in the config file, without touching the bus
+fn run_rename(mut config: Config, source: &ConfigSource, args: &ArgMatches) -> ! {
+    if let ConfigSource::Legacy(path) = source {
+        eprintln!("{} is a legacy config, which has no labels; convert it with migrate-config first",
+            path.display());
+        std::process::exit(exit_code::CONFIG);
+    }
+    if let ConfigSource::New(_) = source {
+        eprintln!("No configuration file found. Run with -i to initialize.");
+        std::process::exit(exit_code::CONFIG);
+    }
+
+    let index = config.resolve_sensor(args.get_one::<String>("sensor").unwrap()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(exit_code::NO_SENSORS);
+    });
+    let label = args.get_one::<String>("label").unwrap().trim().to_string();
+    if let Err(e) = config.check_label(index, &label) {
+        eprintln!("{}", e);
+        std::process::exit(exit_code::USAGE);
+    }
+
+    let sensor = &mut config.sensors[index];
+    let previous = sensor.label.replace(label.clone());
+    let rom = config::format_rom(&sensor.rom);
+    let (path, backup) = save_config(&config, source, args);
+
+    if args.get_flag("json") {
+        println!("{}", json::to_line(&json::RenameResult {
+            sensor: index,
+            rom,
+            previous: previous.as_deref(),
+            label: &label,
+            path: path.display().to_string(),
+            backup: backup.map(|backup| backup.display().to_string()),
+        }));
+    } else if !args.get_flag("quiet") {
+        match previous {
+            Some(previous) => println!("Sensor {} ({}): '{}' -> '{}'", index, rom, previous, label),
+            None => println!("Sensor {} ({}): labelled '{}'", index, rom, label),
+        }
+    }
+    std::process::exit(0);
+}
+
+// The sensor named by the SENSOR argument and the adapter it is wired to; exits when either is missing
+fn sensor_adapter<'a>(buses: &'a mut Buses, config: &'a Config, args: &ArgMatches)
+                      -> (usize, &'a SensorConfig, &'a mut OneWireAdapter) {
+    let index = config.resolve_sensor(args.get_one::<String>("sensor").unwrap()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(exit_code::NO_SENSORS);
+    });
+    let sensor = &config.sensors[index];
+    let adapter = buses.for_sensor(config, sensor).unwrap_or_else(|e| exit_with(&e));
+    (index, sensor, adapter)
+}
+
+fn settings_record(index: usize, sensor: &SensorConfig, settings: &Settings, persisted: Option<bool>)
+                   -> json::SettingsRecord {
+    json::SettingsRecord {
+        sensor: index,
+        rom: config::format_rom(&sensor.rom),
+        label: sensor.label.clone(),
+        high: settings.high,
+        low: settings.low,
+        resolution: settings.resolution,
+        persisted,
+    }
+}
+
+// Change a sensor's conversion resolution, verified by reading the scratchpad back
+fn run_set_resolution(buses: &mut Buses, config: &Config, args: &ArgMatches) -> Option<i32> {
+    let (index, sensor, adapter) = sensor_adapter(buses, config, args);
+    let persist = args.get_flag("persist");
+    let current = adapter.read_settings(&sensor.rom).unwrap_or_else(|e| exit_with(&e));
+    let settings = Settings { resolution: *args.get_one::<u8>("bits").unwrap(), ..current };
+    adapter.write_settings(&sensor.rom, &settings, persist).unwrap_or_else(|e| exit_with(&e));
+
+    if args.get_flag("json") {
+        println!("{}", json::to_line(&settings_record(index, sensor, &settings, Some(persist))));
+    } else if !args.get_flag("quiet") {
+        println!("Sensor {}: resolution {} -> {} bits{}", sensor.name(), current.resolution, settings.resolution,
+            if persist { ", saved to EEPROM" } else { " (until power-off; use --persist to keep it)" });
+    }
+    None
+}
+
+// Write a sensor's alarm thresholds TH/TL, keeping the ones not given
+fn run_set_alarm(buses: &mut Buses, config: &Config, args: &ArgMatches) -> Option<i32> {
+    let (index, sensor, adapter) = sensor_adapter(buses, config, args);
+    let persist = args.get_flag("persist");
+    let current = adapter.read_settings(&sensor.rom).unwrap_or_else(|e| exit_with(&e));
+    let settings = Settings {
+        low: args.get_one::<i8>("low").copied().unwrap_or(current.low),
+        high: args.get_one::<i8>("high").copied().unwrap_or(current.high),
+        ..current
+    };
+    if settings.low > settings.high {
+        eprintln!("Low alarm {} °C is above high alarm {} °C", settings.low, settings.high);
+        std::process::exit(exit_code::USAGE);
+    }
+    adapter.write_settings(&sensor.rom, &settings, persist).unwrap_or_else(|e| exit_with(&e));
+
+    if args.get_flag("json") {
+        println!("{}", json::to_line(&settings_record(index, sensor, &settings, Some(persist))));
+    } else if !args.get_flag("quiet") {
+        println!("Sensor {}: alarm low {} -> {} °C, high {} -> {} °C{}", sensor.name(),
+            current.low, settings.low, current.high, settings.high,
+            if persist { ", saved to EEPROM" } else { " (until power-off; use --persist to keep it)" });
+    }
+    None
+}
+
+// Print the alarm thresholds and resolution of the given sensors, or of all of them
+fn run_get_alarm(buses: &mut Buses, config: &Config, args: &ArgMatches) -> Option<i32> {
+    let specs: Vec<String> = args.get_many::<String>("sensor").into_iter().flatten().cloned().collect();
+    let selected = config.select_sensors(&specs).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(exit_code::NO_SENSORS);
+    });
+
+    let mut failure = None;
+    for i in selected {
+        let sensor = &config.sensors[i];
+        let settings = buses.for_sensor(config, sensor).and_then(|adapter| adapter.read_settings(&sensor.rom));
+        match settings {
+            Ok(settings) if args.get_flag("json") => {
+                println!("{}", json::to_line(&settings_record(i, sensor, &settings, None)));
+            }
+            Ok(settings) => println!("Sensor {}: alarm low {} °C, high {} °C, resolution {} bits",
+                plain_tag(i, sensor), settings.low, settings.high, settings.resolution),
+            Err(e) => {
+                eprintln!("Sensor {} error: {}", sensor.name(), e);
+                failure.get_or_insert(exit_code::for_error(&e));
+            }
+        }
+    }
+    failure
+}
+
+// Save the TH/TL/resolution settings of every configured sensor to a JSON file
+fn run_backup_settings(buses: &mut Buses, config: &Config, args: &ArgMatches) -> Option<i32> {
+    let path = std::path::Path::new(args.get_one::<String>("file").unwrap());
+    let mut sensors = Vec::new();
+    let mut failure = None;
+    for (i, sensor) in config.sensors.iter().enumerate() {
+        match buses.for_sensor(config, sensor).and_then(|adapter| adapter.read_settings(&sensor.rom)) {
+            Ok(settings) => sensors.push(settings_record(i, sensor, &settings, None)),
+            Err(e) => {
+                eprintln!("Sensor {} error: {}, not saved", sensor.name(), e);
+                failure.get_or_insert(exit_code::for_error(&e));
+            }
+        }
+    }
+    if sensors.is_empty() {
+        eprintln!("No sensor settings could be read; {} not written", path.display());
+        std::process::exit(failure.unwrap_or(exit_code::NO_SENSORS));
+    }
+
+    let backup = json::SettingsBackup { created: chrono::Local::now().to_rfc3339(), sensors };
+    let content = serde_json::to_string_pretty(&backup).expect("JSON records always serialize");
+    if let Err(e) = config::write_atomic(path, &(content + "\n")) {
+        eprintln!("Cannot write {}: {}", path.display(), e);
+        std::process::exit(1);
+    }
+    progress(args, &format!("Saved the settings of {} sensor(s) to {}", backup.sensors.len(), path.display()));
+    failure
+}
+
+// Write saved settings back to the sensors, matching entries by label, then by ROM, so a
+// replacement probe that took over a label gets its predecessor's settings
+fn run_restore_settings(buses: &mut Buses, config: &Config, args: &ArgMatches) -> Option<i32> {
+    let path = std::path::Path::new(args.get_one::<String>("file").unwrap());
+    let backup: json::SettingsBackup = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("Cannot read {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+    let specs: Vec<String> = args.get_many::<String>("sensor").into_iter().flatten().cloned().collect();
+    let selected = config.select_sensors(&specs).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(exit_code::NO_SENSORS);
+    });
+
+    let mut failure = None;
+    let mut restored = Vec::new();
+    for record in &backup.sensors {
+        let target = config.sensors.iter().position(|s| record.label.is_some() && s.label == record.label)
+            .or_else(|| config::parse_rom(&record.rom).ok()
+                .and_then(|rom| config.sensors.iter().position(|s| s.rom == rom)));
+        let Some(i) = target else {
+            eprintln!("No configured sensor matches {} ({}), skipped",
+                record.label.as_deref().unwrap_or("unlabelled"), record.rom);
+            continue;
+        };
+        if !selected.contains(&i) {
+            continue;
+        }
+
+        let sensor = &config.sensors[i];
+        let settings = Settings { high: record.high, low: record.low, resolution: record.resolution };
+        let written = buses.for_sensor(config, sensor)
+            .and_then(|adapter| adapter.write_settings(&sensor.rom, &settings, true));
+        match written {
+            Ok(()) => {
+                let origin = if config::format_rom(&sensor.rom) == record.rom {
+                    String::new()
+                } else {
+                    format!(" from {}", record.rom)
+                };
+                progress(args, &format!("Sensor {}: alarm low {} °C, high {} °C, resolution {} bits restored{}",
+                    sensor.name(), settings.low, settings.high, settings.resolution, origin));
+                restored.push(settings_record(i, sensor, &settings, Some(true)));
+            }
+            Err(e) => {
+                eprintln!("Sensor {} error: {}", sensor.name(), e);
+                failure.get_or_insert(exit_code::for_error(&e));
+            }
+        }
+    }
+    if args.get_flag("json") {
+        println!("{}", json::to_line(&restored));
+    }
+    failure
+}
+
+// Discover the sensors on every open bus and merge them into the config file
+fn run_init(buses: &mut Buses, mut config: Config, source: &ConfigSource, args: &ArgMatches) -> Option<i32> {
+    let mut added = Vec::new();
+    let mut missing = Vec::new();
+    let mut found = 0;
+    for bus in buses.iter_mut() {
+        let Some(adapter) = bus.adapter.as_mut() else {
+            eprintln!("Skipping bus '{}' ({}): not open, its sensors are left unchanged", bus.name, bus.tty);
+            continue;
+        };
+        progress(args, &format!("Discovering sensors on {}...", bus.tty));
+        let discovered = adapter.discover_sensors().unwrap_or_else(|e| exit_with(&e));
+        found += discovered.len();
+
+        // Keep the rest of an existing config; only the bus contents change
+        let (bus_added, bus_missing) = config.merge_discovered(&bus.name, &discovered);
+        added.extend(bus_added);
+        missing.extend(bus_missing);
+    }
+
+    if found == 0 {
+        eprintln!("No sensors found!");
+        std::process::exit(exit_code::NO_SENSORS);
+    }
+
+    progress(args, &format!("Found {} sensor(s)", found));
+
+    let json = args.get_flag("json");
+    let listed = !json && !args.get_flag("quiet");
+    for (i, sensor) in config.sensors.iter().enumerate().filter(|_| listed) {
+        let status = if added.contains(&i) {
+            "new"
+        } else if missing.contains(&i) {
+            "MISSING from bus, kept"
+        } else {
+            "present"
+        };
+        match &sensor.label {
+            Some(label) => println!("  Sensor {}: {:02X?} ({}) {}", i, sensor.rom, label, status),
+            None => println!("  Sensor {}: {:02X?} {}", i, sensor.rom, status),
+        }
+    }
+    if !missing.is_empty() {
+        eprintln!("{} configured sensor(s) not found on the bus; remove them from the config if retired",
+            missing.len());
+    }
+
+    let (path, backup) = save_config(&config, source, args);
+
+    if json {
+        let sensors = config.sensors.iter().enumerate().map(|(i, sensor)| json::InitSensor {
+            sensor: i,
+            rom: config::format_rom(&sensor.rom),
+            label: sensor.label.as_deref(),
+            status: if added.contains(&i) {
+                "new"
+            } else if missing.contains(&i) {
+                "missing"
+            } else {
+                "present"
+            },
+        }).collect();
+        println!("{}", json::to_line(&json::InitResult {
+            found,
+            sensors,
+            path: path.display().to_string(),
+            backup: backup.map(|backup| backup.display().to_string()),
+        }));
+    }
+    None
+}
+
+// List the sensors found on every open bus, with their configured labels
+fn run_scan(buses: &mut Buses, config: &Config, args: &ArgMatches) -> Option<i32> {
+    let json = args.get_flag("json");
+    let quiet = args.get_flag("quiet");
+    let mut scanned = Vec::new();
+    for bus in buses.iter_mut() {
+        let Some(adapter) = bus.adapter.as_mut() else {
+            continue;
+        };
+        progress(args, &format!("Scanning bus {}...", bus.tty));
+        let discovered = adapter.discover_sensors().unwrap_or_else(|e| exit_with(&e));
+
+        if json {
+            let sensors = discovered.iter().map(|rom| json::ScanSensor {
+                rom: config::format_rom(rom),
+                label: config.find_sensor(rom).and_then(|s| s.label.as_deref()),
+            }).collect();
+            scanned.push(json::ScanBus { name: &bus.name, tty: &bus.tty, sensors });
+        } else if quiet {
+            // Bare ROM codes, one per line, for scripts
+            for rom in &discovered {
+                println!("{}", config::format_rom(rom));
+            }
+        } else if discovered.is_empty() {
+            println!("No sensors found.");
+        } else {
+            println!("Found {} sensor(s):", discovered.len());
+            for (i, rom) in discovered.iter().enumerate() {
+                match config.find_sensor(rom).and_then(|s| s.label.as_ref()) {
+                    Some(label) => println!("  Sensor {}: {} ({})", i, config::format_rom(rom), label),
+                    None => println!("  Sensor {}: {}", i, config::format_rom(rom)),
+                }
+            }
+        }
+    }
+    if json {
+        println!("{}", json::to_line(&scanned));
+    }
+    None
+}
+
+// A scan asked for by a client: every open bus is searched, and the request answered with
+// the `scan --json` record
+fn scan_now(buses: &mut Buses, config: &Config, request: api::Request) {
+    let mut scanned = Vec::new();
+    for bus in buses.iter_mut() {
+        let Some(adapter) = bus.adapter.as_mut() else {
+            continue;
+        };
+        match adapter.discover_sensors() {
+            Ok(discovered) => {
+                let sensors = discovered.iter().map(|rom| json::ScanSensor {
+                    rom: config::format_rom(rom),
+                    label: config.find_sensor(rom).and_then(|s| s.label.as_deref()),
+                }).collect();
+                scanned.push(json::ScanBus { name: &bus.name, tty: &bus.tty, sensors });
+            }
+            Err(e) => return request.reply(502, api::message(&format!("Scanning bus {}: {}", bus.name, e))),
+        }
+    }
+    request.reply(200, json::to_line(&scanned));
+}
+
+// READ_TIME replaces completion polling with a fixed wait, as in digitemp; -r overrides it
+fn apply_read_time(buses: &mut Buses, args: &ArgMatches, config: &Config) {
+    if let Some(read_time) = args.get_one::<u64>("read_time").copied().or(config.daemon.read_time_ms) {
+        let clamped = read_time.clamp(*READ_TIME_RANGE_MS.start(), *READ_TIME_RANGE_MS.end());
+        if clamped != read_time {
+            eprintln!("Read time {} ms is out of range, using {} ms", read_time, clamped);
+        }
+        for adapter in buses.iter_mut().filter_map(|bus| bus.adapter.as_mut()) {
+            adapter.set_read_time(Duration::from_millis(clamped));
+        }
+    }
+}
+
+// Live dashboard of the selected sensors until the user quits
+#[cfg(feature = "tui")]
+fn run_watch(buses: &mut Buses, config: Config, args: &ArgMatches) -> Option<i32> {
+    let specs: Vec<String> = args.get_many::<String>("sensor").into_iter().flatten().cloned().collect();
+    let selected = match config.select_sensors(&specs) {
+        Ok(selected) if !selected.is_empty() => selected,
+        Ok(_) => {
+            eprintln!("No sensors found in config. Run with -i to initialize.");
+            std::process::exit(exit_code::NO_SENSORS);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(exit_code::NO_SENSORS);
+        }
+    };
+    apply_read_time(buses, args, &config);
+
+    let unit = args.get_one::<Unit>("unit").copied().or(config.output.unit).unwrap_or(Unit::Celsius);
+    let decimals = args.get_one::<usize>("decimals").copied().or(config.output.decimals).unwrap_or(2);
+    let raw = args.get_flag("raw");
+    let rows = selected.iter().map(|&i| {
+        let sensor = &config.sensors[i];
+        (plain_tag(i, sensor), config::format_rom(&sensor.rom))
+    }).collect();
+    let dashboard = watch::Dashboard::new(rows, unit, decimals, *args.get_one::<Duration>("interval").unwrap());
+
+    let result = watch::run(dashboard, |row| {
+        let sensor = &config.sensors[selected[row]];
+        buses.for_sensor(&config, sensor)
+            .and_then(|adapter| adapter.read_temperature(&sensor.rom))
+            .map(|reading| calibrate(sensor, reading, raw).celsius)
+            .map_err(|e| e.to_string())
+    });
+    if let Err(e) = result {
+        eprintln!("Cannot run the dashboard: {}", e);
+        std::process::exit(1);
+    }
+    None
+}
+
+// Acknowledge a sensor's raised alarms, answered with the kinds acknowledged
+fn acknowledge(config: &Config, state: &mut PassState, sensor: &str, request: api::Request) {
+    let index = match config.resolve_sensor(sensor) {
+        Ok(index) => index,
+        Err(e) => return request.reply(404, api::message(&e)),
+    };
+    let sensor = &config.sensors[index];
+    let kinds = state.alarms.as_mut().map_or_else(Vec::new, |alarms| alarms.acknowledge(&sensor.rom));
+    let names: Vec<&str> = kinds.iter().map(|kind| kind.name()).collect();
+    if !names.is_empty() {
+        if let Some(states) = state.sensor_states.as_mut() {
+            states.acknowledge(sensor);
+        }
+        eprintln!("Sensor {} {} alarm acknowledged", sensor.name(), names.join(", "));
+    }
+    request.reply(200, json::to_line(&serde_json::json!({ "acknowledged": names })));
+}
+
+// A read asked for over HTTP: the sensor is read at once, outside its schedule, and the
+// request answered with its /sensors entry or the error
+fn read_now(buses: &mut Buses, config: &Config, state: &mut PassState, server: &api::Server, raw: bool,
+            sensor: &str, request: api::Request) {
+    let index = match config.resolve_sensor(sensor) {
+        Ok(index) => index,
+        Err(e) => return request.reply(404, api::message(&e)),
+    };
+    let sensor = &config.sensors[index];
+    let reading = buses.for_sensor(config, sensor)
+        .and_then(|adapter| adapter.read_temperature(&sensor.rom))
+        .map(|reading| calibrate(sensor, reading, raw));
+    let (sink, bus) = (server.sink(), config.adapter_name(sensor));
+    match reading {
+        Ok(reading) => match state.spike_filter.check(&sensor.rom, reading.celsius) {
+            Verdict::Accepted => {
+                if let Some(states) = state.sensor_states.as_mut() {
+                    states.reading(sensor, &reading);
+                }
+                sink.reading(bus, index, sensor, &reading);
+                request.reply(200, server.sensor_json(index));
+            }
+            Verdict::Suspect { previous } => {
+                sink.error(bus, index, sensor, None);
+                let message = format!("suspect reading {:.2} (previous {:.2}), discarded", reading.celsius, previous);
+                request.reply(502, json::error(index, sensor, "suspect", message));
+            }
+        },
+        Err(e) => {
+            if let Some(states) = state.sensor_states.as_mut() {
+                states.error(sensor, Some(&e));
+            }
+            sink.error(bus, index, sensor, Some(&e));
+            request.reply(502, json::error(index, sensor, json::error_kind(&e), e.to_string()));
+        }
+    }
+}
+
+// Addresses the servers of `serve` listen on; none for the other commands
+#[derive(Default)]
+struct Listen<'a> {
+    http: Option<&'a str>,
+    owserver: Option<&'a str>,
+    modbus: Option<&'a str>,
+    tcp: Option<&'a str>,
+    coap: Option<&'a str>,
+    grpc: Option<&'a str>,
+    // Unix socket path of the control interface, for monitor too
+    control: Option<&'a str>,
+}
+
+impl<'a> Listen<'a> {
+    fn serve(args: &'a ArgMatches) -> Self {
+        let address = |id: &str| args.get_one::<String>(id).map(String::as_str);
+        Listen {
+            http: address("http"),
+            owserver: address("owserver"),
+            modbus: address("modbus"),
+            tcp: address("tcp"),
+            coap: address("coap"),
+            grpc: address("grpc"),
+            control: address("control"),
+        }
+    }
+
+    fn monitor(args: &'a ArgMatches) -> Self {
+        Listen { control: args.get_one::<String>("control").map(String::as_str), ..Listen::default() }
+    }
+
+    // The servers besides HTTP, answering from its readings
+    fn start_others(&self, server: &api::Server, config: &Config) -> Result<(), String> {
+        if let Some(path) = self.control {
+            #[cfg(unix)]
+            control::start(std::path::Path::new(path), server.sink(), server.sender())?;
+            #[cfg(not(unix))]
+            return Err(format!("--control {} needs Unix domain sockets", path));
+        }
+        if let Some(address) = self.owserver {
+            owserver::start(address, server.sink(), server.sender())?;
+        }
+        if let Some(address) = self.modbus {
+            config.check_modbus_registers()?;
+            modbus::start(address, server.sink())?;
+        }
+        if let Some(address) = self.tcp {
+            tcp::start(address, server.sink())?;
+        }
+        if let Some(address) = self.coap {
+            coap::start(address, server.sink())?;
+        }
+        if let Some(address) = self.grpc {
+            #[cfg(feature = "grpc")]
+            grpc::start(address, server.sink(), server.sender())?;
+            #[cfg(not(feature = "grpc"))]
+            return Err(format!("--grpc {} needs a build with the grpc feature", address));
+        }
+        Ok(())
+    }
+}
+
+// Read one sensor or all of them, `-n` times, or continuously for `monitor` and `serve`,
+// which also answers clients on the `listen` addresses; returns the first failure's code
+fn run_read(buses: &mut Buses, mut config: Config, args: &ArgMatches, monitor: bool, listen: Listen,
+            sensor_state: Option<std::path::PathBuf>) -> Option<i32> {
+    if config.sensors.is_empty() {
+        eprintln!("No sensors found in config. Run with -i to initialize.");
+        std::process::exit(exit_code::NO_SENSORS);
+    }
+
+    let daemon = &config.daemon;
+    let mut state = PassState {
+        spike_filter: SpikeFilter::new(
+            args.get_one::<f32>("max_delta").copied().or(daemon.max_delta)),
+        health: HealthTracker::new(
+            args.get_one::<u32>("fail_threshold").copied().unwrap_or(daemon.fail_threshold),
+            args.get_one::<u32>("degraded_every").copied().unwrap_or(daemon.degraded_every)),
+        alarms: monitor.then(AlarmTracker::default),
+        scheduler: Scheduler::default(),
+        broadcast: args.get_flag("fast"),
+        sensor_states: sensor_state.as_deref().map(SensorStates::load),
+        missing_after: daemon.missing_after,
+        last_pass: (0, 0),
+        max_per_pass: monitor
+            .then(|| args.get_one::<u32>("max_per_pass").map(|&n| n as usize).or(daemon.max_per_pass))
+            .flatten().filter(|&limit| limit > 0),
+        thresholds: HashMap::new(),
+    };
+    let alarm_poll = monitor.then(|| args.get_one::<Duration>("alarm_poll").copied().or(daemon.alarm_poll))
+        .flatten().filter(|poll| !poll.is_zero());
+    apply_read_time(buses, args, &config);
+    let mut output = output_options(args, &config).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let (passes, pace) = if monitor {
+        let interval = args.get_one::<Duration>("interval").copied().or(daemon.interval)
+            .unwrap_or(Duration::from_secs(10));
+        (*args.get_one::<u64>("count").unwrap(), Pace::Interval(interval))
+    } else {
+        (*args.get_one::<u64>("repeat").unwrap(),
+            Pace::Delay(Duration::from_secs(*args.get_one::<u64>("delay").unwrap())))
+    };
+    // The spike filter compares a reading with the one before in this run; one pass has none. The
+    // [daemon] value is shared with monitor and stays quiet
+    if passes == 1 && args.get_one::<f32>("max_delta").is_some() {
+        eprintln!("Warning: --max-delta has no effect on a single pass, use monitor, serve or read -n");
+    }
+    // Repeated reads stay connected until stopped; Ctrl-C ends them after the current pass
+    let stop = (passes != 1).then(|| StopSignal::install().unwrap_or_else(|e| {
+        eprintln!("Cannot install signal handlers: {}", e);
+        std::process::exit(1);
+    }));
+
+    // `-t 0,2,5`, `-t attic -t boiler` or `read attic boiler`; a single sensor prints just its value
+    let specs: Vec<String> = args.get_many::<String>("sensor").into_iter().flatten().cloned().collect();
+    let failure = if let ([sensor_arg], false) = (specs.as_slice(), monitor) {
+        let sensor_idx = config.resolve_sensor(sensor_arg).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(exit_code::NO_SENSORS);
+        });
+
+        let sensor = &config.sensors[sensor_idx];
+        repeat(passes, pace, stop.as_ref(), None, None, None, |_| {
+            let reading = buses.for_sensor(&config, sensor)
+                .and_then(|adapter| adapter.read_temperature(&sensor.rom));
+            let bus = config.adapter_name(sensor);
+            let failure = match reading.map(|r| calibrate(sensor, r, output.raw)) {
+                Ok(reading) => {
+                    let verdict = state.spike_filter.check(&sensor.rom, reading.celsius);
+                    if let Some(states) = state.sensor_states.as_mut() {
+                        match verdict {
+                            Verdict::Accepted => states.reading(sensor, &reading),
+                            Verdict::Suspect { .. } => states.error(sensor, None),
+                        }
+                    }
+                    for sink in &mut output.sinks {
+                        if verdict == Verdict::Accepted {
+                            sink.reading(bus, sensor_idx, sensor, &reading, output.decimals);
+                        } else {
+                            sink.error(bus, sensor_idx, sensor, None);
+                        }
+                    }
+                    match verdict {
+                        Verdict::Accepted if output.json => {
+                            output.emit(&json::reading(sensor_idx, sensor, &reading, output.unit,
+                                output.decimals, output.show_quality));
+                            None
+                        }
+                        Verdict::Accepted => {
+                            output.emit_value(sensor, reading.celsius);
+                            let mut line = format::format_temperature(reading.celsius,
+                                output.unit.unwrap_or(Unit::Celsius), output.decimals.unwrap_or(2));
+                            if output.show_quality {
+                                line.push(' ');
+                                line.push_str(&format_quality(&reading));
+                            }
+                            let painted = output.paint(&line, color::level(sensor, reading.celsius));
+                            output.emit_painted(&line, &painted);
+                            None
+                        }
+                        Verdict::Suspect { previous } => {
+                            let message = format!("suspect reading {:.2} (previous {:.2}), discarded",
+                                reading.celsius, previous);
+                            if output.json {
+                                output.emit(&json::error(sensor_idx, sensor, "suspect", message));
+                            } else {
+                                eprintln!("Sensor {} {}", sensor.name(), message);
+                            }
+                            Some(exit_code::SUSPECT_READING)
+                        }
+                    }
+                }
+                Err(e) => {
+                    for sink in &mut output.sinks {
+                        sink.error(bus, sensor_idx, sensor, Some(&e));
+                    }
+                    if let Some(states) = state.sensor_states.as_mut() {
+                        states.error(sensor, Some(&e));
+                    }
+                    if output.json {
+                        output.emit(&json::error(sensor_idx, sensor, json::error_kind(&e), e.to_string()));
+                    } else {
+                        eprintln!("Error: {}", e);
+                    }
+                    Some(exit_code::for_error(&e))
+                }
+            };
+            for sink in &mut output.sinks {
+                sink.pass_done();
+            }
+            state.save_states(&config, &[sensor_idx], true, &mut output);
+            failure
+        })
+    } else {
+        let selected = config.select_sensors(&specs).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(exit_code::NO_SENSORS);
+        });
+        // Until the first pass completes, a monitor shows what the last run saw
+        if let (true, Some(states)) = (monitor, &state.sensor_states) {
+            show_stale(&config, &selected, states, &output);
+        }
+        let restored = restore_alarms(&config, &selected, &mut state);
+
+        // SIGHUP reloads the config between repeated passes
+        let reload = (passes != 1).then(|| ReloadSignal::install().unwrap_or_else(|e| {
+            eprintln!("Cannot install SIGHUP handler: {}", e);
+            std::process::exit(1);
+        }));
+        // SIGUSR1 takes a pass at once, SIGUSR2 pauses or resumes
+        let user = (passes != 1).then(|| UserSignals::install().unwrap_or_else(|e| {
+            eprintln!("Cannot install SIGUSR1/SIGUSR2 handlers: {}", e);
+            std::process::exit(1);
+        }));
+        let server = (listen.http.is_some() || listen.control.is_some()).then(|| {
+            api::Server::start(listen.http, &config).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            })
+        });
+        if let Some(server) = &server {
+            server.set_line_format(output.line_format());
+            let api = server.sink();
+            for &(i, kind) in &restored {
+                api.restore_alarm(i, &config.sensors[i], kind);
+            }
+            output.sinks.push(Route::all(Sink::Api(api)));
+            if let Err(e) = listen.start_others(server, &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        // Startup is over once the ports are open: the pid file is written and a detached
+        // daemon lets go of the terminal. The file is removed when the loop ends.
+        let _pid_file = monitor.then(|| started_daemon(&config, args));
+        let notifier = monitor.then(systemd::Notifier::from_env).flatten();
+        if let Some(notifier) = &notifier {
+            if let (Some(watchdog), Pace::Interval(interval)) = (notifier.watchdog(), &pace) {
+                if watchdog <= *interval {
+                    eprintln!("WatchdogSec={} is not longer than the interval {}; systemd will restart the daemon \
+                        between passes", watchdog.as_secs(), config::format_duration(*interval));
+                }
+            }
+            notifier.ready(&format!("Reading {} sensor(s)", config.sensors.len()));
+        }
+        // Passes are skipped while paused by SIGUSR2 or the control socket; requests are still answered
+        let mut paused = false;
+        let failure = repeat(passes, pace, stop.as_ref(), server.as_ref(), user.as_ref(), alarm_poll, |turn| {
+            // A cycle request is this pass, and is answered once it is over
+            let (cycle, request) = match turn {
+                Turn::Pass(cycle) => (cycle, None),
+                Turn::Request(request) => (None, Some(request)),
+                Turn::AlarmPoll => {
+                    return match (paused, config.select_sensors(&specs)) {
+                        (false, Ok(selected)) => {
+                            poll_alarms(buses, &config, &selected, !specs.is_empty(), &mut state, &mut output)
+                        }
+                        _ => None,
+                    };
+                }
+            };
+            if let (Some(request), Some(server)) = (request, &server) {
+                match request.action.clone() {
+                    api::Action::Read(sensor) => read_now(buses, &config, &mut state, server, output.raw, &sensor,
+                        request),
+                    api::Action::Scan => {
+                        scan_now(buses, &config, request);
+                        for sink in &mut output.sinks {
+                            sink.announce(&config);
+                        }
+                    }
+                    api::Action::Pause | api::Action::Resume | api::Action::TogglePause => {
+                        let pause = match request.action {
+                            api::Action::Pause => true,
+                            api::Action::Resume => false,
+                            _ => !paused,
+                        };
+                        match set_paused(buses, &config, args, &mut paused, pause) {
+                            Ok(()) => request.reply(200, json::to_line(&serde_json::json!({ "paused": paused }))),
+                            Err(e) => request.reply(503, api::message(&e)),
+                        }
+                    }
+                    api::Action::Ack(sensor) => acknowledge(&config, &mut state, &sensor, request),
+                    // Handed over as a pass instead
+                    api::Action::Cycle => {}
+                    api::Action::Reload => match reload_config(args, &mut config, &mut output, Some(server),
+                        notifier.as_ref()) {
+                        Ok(()) => request.reply(200, json::to_line(&serde_json::json!({
+                            "sensors": config.sensors.len(),
+                        }))),
+                        Err(e) => {
+                            eprintln!("Reload failed, keeping the current config: {}", e);
+                            request.reply(422, api::message(&e));
+                        }
+                    },
+                }
+                return None;
+            }
+            if reload.as_ref().is_some_and(ReloadSignal::take) {
+                if let Err(e) = reload_config(args, &mut config, &mut output, server.as_ref(), notifier.as_ref()) {
+                    eprintln!("Reload failed, keeping the current config: {}", e);
+                }
+            }
+            if let Some(user) = &user {
+                if user.take_pause() {
+                    let pause = !paused;
+                    if let Err(e) = set_paused(buses, &config, args, &mut paused, pause) {
+                        eprintln!("{}", e);
+                    }
+                }
+                if user.take_cycle() && paused {
+                    eprintln!("SIGUSR1: paused, so no pass is taken");
+                }
+            }
+            if paused {
+                // Paused on purpose, so not wedged
+                if let Some(notifier) = &notifier {
+                    notifier.alive("Paused");
+                }
+                if let Some(cycle) = cycle {
+                    cycle.reply(409, api::message("paused; resume first"));
+                }
+                return None;
+            }
+            // Selected sensors are looked up again each pass, as a reload may reorder them
+            match config.select_sensors(&specs) {
+                Ok(selected) => {
+                    let started = Instant::now();
+                    let failure = read_pass(buses, &config, &selected, !specs.is_empty(), &mut state, &mut output);
+                    if let Some(server) = &server {
+                        server.pass_done(buses, started.elapsed());
+                    }
+                    if let Some(notifier) = &notifier {
+                        notify_pass(notifier, state.last_pass, started.elapsed());
+                    }
+                    if let Some(cycle) = cycle {
+                        let (due, read) = state.last_pass;
+                        cycle.reply(200, json::to_line(&serde_json::json!({ "due": due, "read": read })));
+                    }
+                    failure
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    if let Some(cycle) = cycle {
+                        cycle.reply(422, api::message(&e));
+                    }
+                    Some(exit_code::NO_SENSORS)
+                }
+            }
+        });
+        if let Some(notifier) = &notifier {
+            notifier.stopping();
+        }
+        failure
+    };
+    failure
+}
+
+// Pause or resume the passes. Paused, the serial ports are closed, so they are free for
+// other programs, such as a terminal to debug the adapter with; resumed, they are opened
+// again. Stays paused when none of them can be.
+fn set_paused(buses: &mut Buses, config: &Config, args: &ArgMatches, paused: &mut bool, pause: bool)
+              -> Result<(), String> {
+    if pause == *paused {
+        return Ok(());
+    }
+    if pause {
+        buses.close();
+        *paused = true;
+        eprintln!("Paused; the serial ports are closed until resumed");
+        return Ok(());
+    }
+    buses.reopen(&config.adapters);
+    if !buses.any_open() {
+        buses.close();
+        return Err("Cannot resume: no serial port could be opened".to_string());
+    }
+    apply_read_time(buses, args, config);
+    *paused = false;
+    eprintln!("Resumed");
+    Ok(())
+}
+
+// The last pass as `systemctl status` shows it. The watchdog is kept alive by passes that
+// read a sensor, or had none due, but not by those where every read failed.
+fn notify_pass(notifier: &systemd::Notifier, (due, good): (usize, usize), took: Duration) {
+    let status = format!("Last pass {}: {} of {} sensor(s) read in {:.1}s", chrono::Local::now().format("%H:%M:%S"),
+        good, due, took.as_secs_f32());
+    if good > 0 || due == 0 {
+        notifier.alive(&status);
+    } else {
+        notifier.status(&status);
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = cli::build()
+        .try_get_matches()
+        .unwrap_or_else(|e| {
+            // Keep clap's usage errors out of the range used for runtime failures
+            let _ = e.print();
+            std::process::exit(if e.use_stderr() { exit_code::USAGE } else { 0 });
+        });
+
+    #[cfg(windows)]
+    if let Some((command @ ("install-service" | "uninstall-service" | "run-service"), args)) = matches.subcommand() {
+        let result = match command {
+            "install-service" => service::install(args).map(|message| println!("{}", message)),
+            "uninstall-service" => service::uninstall(args).map(|message| println!("{}", message)),
+            _ => service::run(args, run),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(code) = run(&matches) {
+        std::process::exit(code);
+    }
+    Ok(())
+}
+
+// Everything but the Windows service commands; the exit status of a failure
+fn run(matches: &ArgMatches) -> Option<i32> {
+    if matches.get_flag("capabilities") {
+        let capabilities = capabilities::detect();
+        if matches.get_flag("json") {
+            println!("{}", json::to_line(&capabilities));
+        } else {
+            capabilities::print(&capabilities);
+        }
+        return None;
+    }
+
+    // The legacy top-level flags select the equivalent subcommand
+    let (command, args) = match matches.subcommand() {
+        Some((command, args)) => (command, args),
+        None if matches.get_flag("init") => ("init", matches),
+        None if matches.get_flag("walk") => ("scan", matches),
+        None => ("read", matches),
+    };
+    // -A is kept for digitemp command lines and may come before or after the subcommand
+    if matches.get_flag("humidity") || matches!(args.try_get_one::<bool>("humidity"), Ok(Some(true))) {
+        eprintln!("Warning: DS2438 not supported, -A ignored");
+    }
+
+    // --timeout bounds everything that follows: opening, discovery and reads
+    if let Some(&limit) = args.get_one::<Duration>("timeout") {
+        thread::spawn(move || {
+            thread::sleep(limit);
+            eprintln!("Timed out after {:?}", limit);
+            std::process::exit(exit_code::TIMEOUT);
+        });
+    }
+
+    if command == "completions" {
+        let shell = *args.get_one::<clap_complete::Shell>("shell").unwrap();
+        clap_complete::generate(shell, &mut cli::build(), cli::BIN_NAME, &mut std::io::stdout());
+        return None;
+    }
+
+    let config_path = args.get_one::<String>("config").map(std::path::Path::new);
+    let (mut config, config_source) = load_config(args).unwrap_or_else(|e| {
+        eprintln!("Config error: {}", e);
+        std::process::exit(exit_code::CONFIG);
+    });
+    if let (Some(path), ConfigSource::New(_)) = (config_path, &config_source) {
+        if command != "init" {
+            eprintln!("Config error: {} not found", path.display());
+            std::process::exit(exit_code::CONFIG);
+        }
+    }
+
+    match command {
+        "check-config" => run_check_config(&config, &config_source),
+        "migrate-config" => {
+            let home_rc = std::env::var_os("HOME").map(|home| std::path::PathBuf::from(home).join(".digitemprc"));
+            let source = match (args.get_one::<String>("source"), &config_source) {
+                (Some(path), _) => std::path::PathBuf::from(path),
+                (None, ConfigSource::Legacy(path)) => path.clone(),
+                (None, _) => match home_rc.filter(|path| path.exists()) {
+                    Some(path) => path,
+                    None => {
+                        eprintln!("No legacy configuration found; give the file to convert");
+                        std::process::exit(exit_code::CONFIG);
+                    }
+                },
+            };
+            run_migrate_config(&source, args.get_one::<String>("output").map(std::path::Path::new),
+                args.get_flag("force"));
+        }
+        _ => {}
+    }
+
+    if command == "rename" {
+        run_rename(config, &config_source, args);
+    }
+    if command == "bus-stats" {
+        run_bus_stats(&config, args);
+    }
+    if command == "create-rrd" {
+        run_create_rrd(&config, args);
+    }
+    if command == "history" {
+        run_history(&config, args);
+    }
+    if command == "export" {
+        run_export(&config, args);
+    }
+    if command == "snmp-pass" {
+        run_snmp_pass(&config, args);
+    }
+
+    apply_overrides(args, &mut config);
+    if command == "doctor" {
+        run_doctor(&config, args);
+    }
+
+    if matches!(command, "monitor" | "serve") {
+        start_daemon(&config, args);
+    }
+
+    let watchdog_secs = args.get_one::<u64>("watchdog").copied().unwrap_or(config.daemon.watchdog_secs);
+    let watchdog = (watchdog_secs > 0).then(|| Duration::from_secs(watchdog_secs));
+    let simulate = args.get_one::<u8>("simulate").copied();
+    let mut buses = Buses::open(&config.adapters, watchdog, simulate);
+    if !buses.any_open() {
+        std::process::exit(exit_code::PORT_OPEN);
+    }
+
+    // Without a config, simulated sensors are read as if `init` had found them
+    if simulate.is_some() && config.sensors.is_empty() && command != "init" {
+        for bus in buses.iter_mut() {
+            if let Some(adapter) = bus.adapter.as_mut() {
+                let discovered = adapter.discover_sensors().unwrap_or_else(|e| exit_with(&e));
+                config.merge_discovered(&bus.name, &discovered);
+            }
+        }
+    }
+
+    // Simulated buses must not skew the statistics of the real ones
+    let state_file = config.daemon.state_file.clone().or_else(stats::default_path).filter(|_| simulate.is_none());
+    let sensor_state = config.daemon.sensor_state.clone().or_else(state::default_path).filter(|_| simulate.is_none());
+    let failure = match command {
+        "init" => run_init(&mut buses, config, &config_source, args),
+        "scan" => run_scan(&mut buses, &config, args),
+        "set-resolution" => run_set_resolution(&mut buses, &config, args),
+        "set-alarm" => run_set_alarm(&mut buses, &config, args),
+        "get-alarm" => run_get_alarm(&mut buses, &config, args),
+        "backup-settings" => run_backup_settings(&mut buses, &config, args),
+        "restore-settings" => run_restore_settings(&mut buses, &config, args),
+        "monitor" => run_read(&mut buses, config, args, true, Listen::monitor(args), sensor_state),
+        "serve" => run_read(&mut buses, config, args, true, Listen::serve(args), sensor_state),
+        #[cfg(feature = "tui")]
+        "watch" => run_watch(&mut buses, config, args),
+        _ => run_read(&mut buses, config, args, false, Listen::default(), sensor_state),
+    };
+
+    if let Some(path) = state_file {
+        let session: Vec<(&str, &str, &BusStats)> = buses.iter_mut()
+            .filter_map(|bus| bus.stats().map(|stats| (bus.name.as_str(), bus.tty.as_str(), stats)))
+            .collect();
+        stats::accumulate(&path, &session);
+    }
+    failure
 }
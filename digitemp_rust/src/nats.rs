@@ -0,0 +1,240 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::config::{format_rom, SensorConfig};
+use crate::spool::{self, Spool};
+use crate::{json, sink, Reading};
+
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_PORT: u16 = 4222;
+
+// One message, spooled as a JSON line
+#[derive(Serialize, Deserialize)]
+struct Message {
+    subject: String,
+    // Nats-Msg-Id header, so a JetStream stream drops messages sent twice
+    id: String,
+    payload: String,
+}
+
+// The part of the server's INFO that matters here
+#[derive(Deserialize)]
+struct Info {
+    #[serde(default)]
+    headers: bool,
+    #[serde(default)]
+    tls_required: bool,
+}
+
+#[derive(Serialize)]
+struct Connect<'a> {
+    verbose: bool,
+    pedantic: bool,
+    headers: bool,
+    name: &'static str,
+    lang: &'static str,
+    version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pass: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth_token: Option<&'a str>,
+}
+
+// Credentials, a token or a user and password
+pub struct NatsAuth {
+    pub token: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    // Whether the server takes HPUB; servers before 2.2 do not
+    headers: bool,
+}
+
+// NATS client protocol, spoken directly: every reading is a message with the jsonl record
+// on a per-sensor subject. The messages of a pass are published together and confirmed
+// with a PING, on a connection kept between passes and reopened when it breaks. With
+// headers, each message has a Nats-Msg-Id, so JetStream streams drop the duplicates a
+// resend can cause. Messages that cannot be delivered are dropped, or kept in the spool.
+pub struct NatsSink {
+    server: String,
+    subject: String,
+    auth: NatsAuth,
+    messages: Vec<Message>,
+    connection: Option<Connection>,
+    spool: Option<Spool>,
+    // Report a failing server once, not on every pass
+    failing: bool,
+}
+
+impl NatsSink {
+    pub fn new(server: &str, subject: &str, auth: NatsAuth, spool: Option<Spool>) -> Result<Self, String> {
+        if subject.contains(['*', '>']) || subject.contains(char::is_whitespace) {
+            return Err(format!("Invalid NATS subject '{}': no wildcards or spaces", subject));
+        }
+        address(server)?;
+        Ok(NatsSink {
+            server: server.to_string(),
+            subject: subject.to_string(),
+            auth,
+            messages: Vec::new(),
+            connection: None,
+            spool,
+            failing: false,
+        })
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading,
+                   decimals: Option<usize>) {
+        // A label can hold characters that are not allowed in a subject token
+        let subject = sink::expand(&self.subject, bus, index, sensor)
+            .replace(|c: char| c.is_whitespace() || c == '*' || c == '>', "_");
+        self.messages.push(Message {
+            subject,
+            id: format!("{}-{}", format_rom(&sensor.rom), chrono::Utc::now().timestamp_millis()),
+            payload: json::jsonl_reading(bus, index, sensor, reading, decimals),
+        });
+    }
+
+    pub fn pass_done(&mut self) {
+        if self.messages.is_empty() {
+            return;
+        }
+        let messages = std::mem::take(&mut self.messages);
+        let mut spool = self.spool.take();
+        let sent = match spool.as_mut() {
+            Some(spool) => {
+                let records = messages.iter().filter_map(|message| serde_json::to_string(message).ok()).collect();
+                spool.deliver(records, |records| {
+                    let messages: Vec<Message> = records.iter()
+                        .filter_map(|record| serde_json::from_str(record).ok()).collect();
+                    self.send(&messages)
+                })
+            }
+            None => self.send(&messages),
+        };
+        self.spool = spool;
+        match sent {
+            Ok(()) if self.failing => {
+                eprintln!("NATS {}: delivering again", self.server);
+                self.failing = false;
+            }
+            Ok(()) => {}
+            Err(e) => {
+                if !self.failing {
+                    eprintln!("NATS {}: {} ({})", self.server, e, spool::kept(self.spool.as_ref()));
+                }
+                self.failing = true;
+            }
+        }
+    }
+
+    fn send(&mut self, messages: &[Message]) -> io::Result<()> {
+        // A connection the server closed, e.g. for missed PINGs between passes, is only
+        // noticed now: retry once on a new one
+        if let Some(connection) = self.connection.as_mut() {
+            if publish(connection, messages).is_ok() {
+                return Ok(());
+            }
+            self.connection = None;
+        }
+        let mut connection = connect(&self.server, &self.auth)?;
+        publish(&mut connection, messages)?;
+        self.connection = Some(connection);
+        Ok(())
+    }
+}
+
+// nats://host:port or host:port, the port defaulting to 4222
+fn address(server: &str) -> Result<String, String> {
+    let address = match server.split_once("://") {
+        Some(("nats", address)) => address,
+        Some((scheme, _)) => return Err(format!("Invalid NATS server '{}': unsupported scheme {}", server, scheme)),
+        None => server,
+    };
+    let address = address.trim_end_matches('/');
+    if address.is_empty() {
+        return Err(format!("Invalid NATS server '{}': no host", server));
+    }
+    match address.rsplit_once(':') {
+        Some((_, port)) if !port.contains(']') => Ok(address.to_string()),
+        _ => Ok(format!("{}:{}", address, DEFAULT_PORT)),
+    }
+}
+
+fn connect(server: &str, auth: &NatsAuth) -> io::Result<Connection> {
+    let address = address(server).map_err(io::Error::other)?.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address did not resolve"))?;
+    let stream = TcpStream::connect_timeout(&address, NETWORK_TIMEOUT)?;
+    stream.set_read_timeout(Some(NETWORK_TIMEOUT))?;
+    stream.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+
+    let line = read_line(&mut reader)?;
+    let info: Info = line.strip_prefix("INFO ").and_then(|info| serde_json::from_str(info).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a NATS server"))?;
+    if info.tls_required {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "the server requires TLS"));
+    }
+    let connect = Connect {
+        verbose: false,
+        pedantic: false,
+        headers: info.headers,
+        name: "digitemp_rust",
+        lang: "rust",
+        version: env!("CARGO_PKG_VERSION"),
+        user: auth.username.as_deref(),
+        pass: auth.password.as_deref(),
+        auth_token: auth.token.as_deref(),
+    };
+    let connect = serde_json::to_string(&connect).map_err(io::Error::other)?;
+    reader.get_mut().write_all(format!("CONNECT {}\r\n", connect).as_bytes())?;
+    Ok(Connection { reader, headers: info.headers })
+}
+
+// Publish the messages, then wait for the PONG to a PING: the server has taken them all, or
+// answered -ERR, e.g. for a failed authorization or a subject it does not allow
+fn publish(connection: &mut Connection, messages: &[Message]) -> io::Result<()> {
+    let mut data = Vec::new();
+    for message in messages {
+        if connection.headers {
+            let headers = format!("NATS/1.0\r\nNats-Msg-Id: {}\r\n\r\n", message.id);
+            let total = headers.len() + message.payload.len();
+            data.extend_from_slice(format!("HPUB {} {} {}\r\n{}", message.subject, headers.len(), total, headers)
+                .as_bytes());
+        } else {
+            data.extend_from_slice(format!("PUB {} {}\r\n", message.subject, message.payload.len()).as_bytes());
+        }
+        data.extend_from_slice(message.payload.as_bytes());
+        data.extend_from_slice(b"\r\n");
+    }
+    data.extend_from_slice(b"PING\r\n");
+    connection.reader.get_mut().write_all(&data)?;
+
+    loop {
+        let line = read_line(&mut connection.reader)?;
+        match line.as_str() {
+            "PONG" => return Ok(()),
+            "PING" => connection.reader.get_mut().write_all(b"PONG\r\n")?,
+            line if line.starts_with("-ERR") => {
+                let error = line.trim_start_matches("-ERR").trim().trim_matches('\'');
+                return Err(io::Error::other(error.to_string()));
+            }
+            // +OK, and INFO about changes to the cluster
+            _ => {}
+        }
+    }
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the server closed the connection"));
+    }
+    Ok(line.trim_end().to_string())
+}
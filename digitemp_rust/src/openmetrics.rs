@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use crate::config::{family_name, format_rom, SensorConfig};
+use crate::prometheus::{escape, replace_file};
+use crate::{OneWireError, Reading, MAX_CRC_RETRIES};
+
+// OpenMetrics text exposition, rewritten after every pass like the textfile collector output.
+// Besides the Prometheus metrics, it labels sensors with family, resolution and group, and
+// counts CRC failures and scratchpad retries.
+pub struct OpenMetricsSink {
+    path: PathBuf,
+    // Unix time the counters started from, exported as their _created samples
+    created: i64,
+    // Keyed by sensor index, so the file lists sensors in config order
+    sensors: BTreeMap<usize, SensorMetrics>,
+}
+
+struct SensorMetrics {
+    bus: String,
+    name: String,
+    rom: [u8; 8],
+    group: Option<String>,
+    // Known once the sensor has been read
+    resolution: Option<u8>,
+    celsius: Option<f32>,
+    last_success: Option<i64>,
+    up: bool,
+    reads: u64,
+    errors: u64,
+    crc_errors: u64,
+    retries: u64,
+}
+
+impl SensorMetrics {
+    fn labels(&self) -> String {
+        let mut labels = format!("sensor=\"{}\",rom=\"{}\",bus=\"{}\",family=\"{}\"",
+            escape(&self.name), format_rom(&self.rom), escape(&self.bus), family_name(&self.rom));
+        if let Some(resolution) = self.resolution {
+            let _ = write!(labels, ",resolution=\"{}\"", resolution);
+        }
+        if let Some(group) = &self.group {
+            let _ = write!(labels, ",group=\"{}\"", escape(group));
+        }
+        labels
+    }
+}
+
+impl OpenMetricsSink {
+    pub fn new(path: &Path) -> Self {
+        OpenMetricsSink { path: path.to_path_buf(), created: chrono::Utc::now().timestamp(), sensors: BTreeMap::new() }
+    }
+
+    fn metrics(&mut self, bus: &str, index: usize, sensor: &SensorConfig) -> &mut SensorMetrics {
+        self.sensors.entry(index).or_insert_with(|| SensorMetrics {
+            bus: bus.to_string(),
+            name: sensor.name(),
+            rom: sensor.rom,
+            group: sensor.group.clone(),
+            resolution: None,
+            celsius: None,
+            last_success: None,
+            up: false,
+            reads: 0,
+            errors: 0,
+            crc_errors: 0,
+            retries: 0,
+        })
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading) {
+        let metrics = self.metrics(bus, index, sensor);
+        metrics.resolution = Some(reading.resolution);
+        metrics.celsius = Some(reading.celsius);
+        metrics.last_success = Some(chrono::Utc::now().timestamp());
+        metrics.up = true;
+        metrics.reads += 1;
+        metrics.crc_errors += u64::from(reading.crc_failures);
+        metrics.retries += u64::from(reading.retries);
+    }
+
+    // A failed or discarded reading; the last good value stays exported
+    pub fn error(&mut self, bus: &str, index: usize, sensor: &SensorConfig, error: Option<&OneWireError>) {
+        let metrics = self.metrics(bus, index, sensor);
+        metrics.up = false;
+        metrics.reads += 1;
+        metrics.errors += 1;
+        // Every attempt of a read that gave up on CRC mismatches failed the check
+        if let Some(OneWireError::CrcMismatch) = error {
+            metrics.crc_errors += u64::from(MAX_CRC_RETRIES) + 1;
+            metrics.retries += u64::from(MAX_CRC_RETRIES);
+        }
+    }
+
+    pub fn pass_done(&mut self) {
+        if self.sensors.is_empty() {
+            return;
+        }
+        if let Err(e) = replace_file(&self.path, &self.render()) {
+            eprintln!("Cannot write {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut content = String::new();
+        let mut gauge = |name: &str, unit: Option<&str>, help: &str, value: &dyn Fn(&SensorMetrics) -> Option<String>| {
+            let _ = writeln!(content, "# TYPE {} gauge", name);
+            if let Some(unit) = unit {
+                let _ = writeln!(content, "# UNIT {} {}", name, unit);
+            }
+            let _ = writeln!(content, "# HELP {} {}", name, help);
+            for metrics in self.sensors.values() {
+                if let Some(value) = value(metrics) {
+                    let _ = writeln!(content, "{}{{{}}} {}", name, metrics.labels(), value);
+                }
+            }
+        };
+        gauge("ds18b20_temperature_celsius", Some("celsius"), "Last accepted temperature reading.",
+            &|m| m.celsius.map(|c| c.to_string()));
+        gauge("ds18b20_last_success_timestamp_seconds", Some("seconds"), "Unix time of the last accepted reading.",
+            &|m| m.last_success.map(|t| t.to_string()));
+        gauge("ds18b20_up", None, "Whether the last read of the sensor succeeded.",
+            &|m| Some(u8::from(m.up).to_string()));
+
+        // Counter families are named without _total, which only their samples carry
+        let mut counter = |name: &str, help: &str, value: &dyn Fn(&SensorMetrics) -> u64| {
+            let _ = writeln!(content, "# TYPE {} counter", name);
+            let _ = writeln!(content, "# HELP {} {}", name, help);
+            for metrics in self.sensors.values() {
+                let labels = metrics.labels();
+                let _ = writeln!(content, "{}_total{{{}}} {}", name, labels, value(metrics));
+                let _ = writeln!(content, "{}_created{{{}}} {}", name, labels, self.created);
+            }
+        };
+        counter("ds18b20_reads", "Readings attempted since start or the last reload.", &|m| m.reads);
+        counter("ds18b20_read_errors", "Failed or discarded readings since start or the last reload.",
+            &|m| m.errors);
+        counter("ds18b20_crc_errors", "Scratchpad reads that failed the CRC check.",
+            &|m| m.crc_errors);
+        counter("ds18b20_retries", "Scratchpad reads repeated after a CRC failure.", &|m| m.retries);
+        content.push_str("# EOF\n");
+        content
+    }
+}
@@ -0,0 +1,257 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+use crate::api::{Action, ApiSink, Request, SensorView};
+use crate::config::{family_name, parse_rom};
+use crate::systemd;
+
+// A persistent connection is closed after this long without a request
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+// Longest request payload: a path, or a path and the data to write
+const MAX_PAYLOAD: i32 = 65536;
+
+const MSG_NOP: i32 = 1;
+const MSG_READ: i32 = 2;
+const MSG_WRITE: i32 = 3;
+const MSG_DIR: i32 = 4;
+const MSG_SIZE: i32 = 5;
+const MSG_PRESENCE: i32 = 6;
+const MSG_DIRALL: i32 = 7;
+const MSG_GET: i32 = 8;
+const MSG_DIRALLSLASH: i32 = 9;
+const MSG_GETSLASH: i32 = 10;
+
+// Control flag asking to keep the connection open for further requests
+const FLG_PERSISTENCE: i32 = 0x04;
+
+// Returned negated, as owserver does
+const ENOENT: i32 = 2;
+const EIO: i32 = 5;
+const EACCES: i32 = 13;
+const ENOTSUP: i32 = 95;
+
+// Read-only properties of a sensor directory; `alias` only for sensors with a label
+const PROPERTIES: [&str; 9] = ["address", "alias", "crc8", "family", "id", "r_address", "r_id", "temperature", "type"];
+
+// A client message after its header: the path, and how much of the value to return from where
+struct Message {
+    kind: i32,
+    path: String,
+    flags: i32,
+    size: i32,
+    offset: i32,
+}
+
+// What a path names, after an optional /uncached prefix
+enum Node {
+    Root,
+    Sensor(SensorView),
+    Property(SensorView, &'static str),
+}
+
+// owfs owserver protocol over the readings `serve` keeps, so owread, owdir, Home Assistant's
+// onewire integration and Munin plugins can use this daemon in place of owfs. Each message
+// is six big-endian i32s followed by a payload:
+//   request   version, payload length, message type, control flags, size, offset
+//   response  version, payload length, return value, control flags, size, offset
+// Every enabled sensor is a directory such as /28.53494D000000 with read-only properties.
+// Its temperature is the last accepted reading; under /uncached it is read at once.
+pub fn start(address: &str, sink: ApiSink, sender: Sender<Request>) -> Result<(), String> {
+    let listener = systemd::activated_tcp(address, "owserver")
+        .unwrap_or_else(|| TcpListener::bind(address).map_err(|e| format!("Cannot listen on {}: {}", address, e)))?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let (sink, sender) = (sink.clone(), sender.clone());
+            thread::spawn(move || {
+                if let Err(e) = handle(stream, &sink, &sender) {
+                    if !matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            | io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset) {
+                        eprintln!("owserver: {}", e);
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream, sink: &ApiSink, sender: &Sender<Request>) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    stream.set_write_timeout(Some(IDLE_TIMEOUT))?;
+    loop {
+        let mut header = [0u8; 24];
+        match stream.read_exact(&mut header) {
+            // The client is done with a persistent connection
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        let field = |i: usize| i32::from_be_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+        let (length, kind, flags, size, offset) = (field(1), field(2), field(3), field(4), field(5));
+        if !(0..=MAX_PAYLOAD).contains(&length) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad payload length {}", length)));
+        }
+        let mut payload = vec![0u8; length as usize];
+        stream.read_exact(&mut payload)?;
+        // The path is NUL-terminated; a write carries its data after it
+        let end = payload.iter().position(|&byte| byte == 0).unwrap_or(payload.len());
+        let path = String::from_utf8_lossy(&payload[..end]).into_owned();
+
+        answer(&mut stream, &Message { kind, path, flags, size, offset }, sink, sender)?;
+        if flags & FLG_PERSISTENCE == 0 {
+            return Ok(());
+        }
+    }
+}
+
+fn answer(stream: &mut TcpStream, message: &Message, sink: &ApiSink, sender: &Sender<Request>)
+          -> io::Result<()> {
+    let Message { kind, flags, size, offset, .. } = *message;
+    let resolved = resolve(&sink.views(), &message.path);
+    match (kind, resolved) {
+        (MSG_NOP, _) => reply(stream, flags, 0, b""),
+        (_, None) => reply(stream, flags, -ENOENT, b""),
+        (MSG_PRESENCE, Some(_)) => reply(stream, flags, 0, b""),
+        (MSG_WRITE, Some(_)) => reply(stream, flags, -EACCES, b""),
+        (MSG_DIR, Some((uncached, node))) => {
+            for entry in entries(&sink.views(), uncached, &node, flags, false).unwrap_or_default() {
+                reply(stream, flags, 0, entry.as_bytes())?;
+            }
+            // An empty message ends the listing
+            reply(stream, flags, 0, b"")
+        }
+        (MSG_DIRALL | MSG_DIRALLSLASH | MSG_GET | MSG_GETSLASH, Some((uncached, node)))
+                if !matches!(node, Node::Property(..)) => {
+            let slash = matches!(kind, MSG_DIRALLSLASH | MSG_GETSLASH);
+            let listing = entries(&sink.views(), uncached, &node, flags, slash).unwrap_or_default().join(",");
+            reply(stream, flags, 0, listing.as_bytes())
+        }
+        (MSG_READ | MSG_SIZE | MSG_GET | MSG_GETSLASH, Some((uncached, Node::Property(view, property)))) => {
+            match value(sink, sender, uncached, view, property, flags) {
+                Ok(value) if kind == MSG_SIZE => reply(stream, flags, value.len() as i32, b""),
+                Ok(value) => {
+                    // Clients ask for up to `size` bytes from `offset`
+                    let start = (offset.max(0) as usize).min(value.len());
+                    let end = start.saturating_add(size.max(0) as usize).min(value.len());
+                    let data = &value.as_bytes()[start..end];
+                    reply(stream, flags, data.len() as i32, data)
+                }
+                Err(errno) => reply(stream, flags, -errno, b""),
+            }
+        }
+        // Reading a directory, or listing a property
+        (MSG_READ | MSG_SIZE | MSG_DIRALL | MSG_DIRALLSLASH, Some(_)) => reply(stream, flags, -ENOENT, b""),
+        _ => reply(stream, flags, -ENOTSUP, b""),
+    }
+}
+
+fn reply(stream: &mut TcpStream, flags: i32, ret: i32, data: &[u8]) -> io::Result<()> {
+    let mut message = Vec::with_capacity(24 + data.len());
+    for field in [0, data.len() as i32, ret, flags, data.len() as i32, 0] {
+        message.extend_from_slice(&field.to_be_bytes());
+    }
+    message.extend_from_slice(data);
+    stream.write_all(&message)
+}
+
+fn resolve(views: &[SensorView], path: &str) -> Option<(bool, Node)> {
+    let mut segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let uncached = segments.first() == Some(&"uncached");
+    if uncached {
+        segments.remove(0);
+    }
+    let node = match segments.as_slice() {
+        [] => Node::Root,
+        [name] => Node::Sensor(find(views, name)?.clone()),
+        [name, property] => {
+            let view = find(views, name)?;
+            let property = PROPERTIES.iter().find(|candidate| *candidate == property)?;
+            if *property == "alias" && view.label.is_none() {
+                return None;
+            }
+            Node::Property(view.clone(), property)
+        }
+        _ => return None,
+    };
+    Some((uncached, node))
+}
+
+// A device name in any of owfs' formats: family and id, with or without the CRC and dots
+fn find<'a>(views: &'a [SensorView], name: &str) -> Option<&'a SensorView> {
+    let digits = name.replace('.', "").to_ascii_uppercase();
+    if !matches!(digits.len(), 14 | 16) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    views.iter().find(|view| view.enabled && view.rom.starts_with(&digits))
+}
+
+// Device names follow the format bits of the control flags, as with owfs (default 28.53494D000000)
+fn device_name(rom: &str, flags: i32) -> String {
+    let (family, id, crc) = (&rom[..2], &rom[2..14], &rom[14..]);
+    match (flags >> 24) & 0xFF {
+        1 => format!("{}{}", family, id),
+        2 => format!("{}.{}.{}", family, id, crc),
+        3 => format!("{}.{}{}", family, id, crc),
+        4 => format!("{}{}.{}", family, id, crc),
+        5 => rom.to_string(),
+        _ => format!("{}.{}", family, id),
+    }
+}
+
+// Full paths of a directory's entries; with `slash`, directories end in "/"
+fn entries(views: &[SensorView], uncached: bool, node: &Node, flags: i32, slash: bool) -> Option<Vec<String>> {
+    let base = if uncached { "/uncached" } else { "" };
+    match node {
+        Node::Root => Some(views.iter().filter(|view| view.enabled)
+            .map(|view| format!("{}/{}{}", base, device_name(&view.rom, flags), if slash { "/" } else { "" }))
+            .collect()),
+        Node::Sensor(view) => Some(PROPERTIES.iter()
+            .filter(|property| **property != "alias" || view.label.is_some())
+            .map(|property| format!("{}/{}/{}", base, device_name(&view.rom, flags), property))
+            .collect()),
+        Node::Property(..) => None,
+    }
+}
+
+fn value(sink: &ApiSink, sender: &Sender<Request>, uncached: bool, view: SensorView, property: &str, flags: i32)
+         -> Result<String, i32> {
+    let rom = &view.rom;
+    // Byte-reversed hex, as owfs' r_ properties
+    let reversed = |hex: &str| hex.as_bytes().chunks(2).rev().map(|pair| String::from_utf8_lossy(pair).into_owned())
+        .collect::<String>();
+    Ok(match property {
+        "address" => rom.clone(),
+        "alias" => view.label.clone().unwrap_or_default(),
+        "crc8" => rom[14..].to_string(),
+        "family" => rom[..2].to_string(),
+        "id" => rom[2..14].to_string(),
+        "r_address" => reversed(rom),
+        "r_id" => reversed(&rom[2..14]),
+        "type" => family_name(&parse_rom(rom).map_err(|_| EIO)?).to_string(),
+        _ => {
+            let celsius = if uncached {
+                match Request::send(sender, Action::Read(view.sensor.to_string())) {
+                    (200, _) => sink.views().into_iter().find(|fresh| fresh.sensor == view.sensor)
+                        .and_then(|fresh| fresh.celsius),
+                    _ => None,
+                }
+            } else {
+                view.celsius
+            };
+            format!("{:>12}", scale(celsius.ok_or(EIO)?, flags))
+        }
+    })
+}
+
+// The temperature scale bits of the control flags: Celsius, Fahrenheit, Kelvin or Rankine
+fn scale(celsius: f64, flags: i32) -> f64 {
+    let value = match (flags >> 16) & 0x03 {
+        1 => celsius * 1.8 + 32.0,
+        2 => celsius + 273.15,
+        3 => (celsius + 273.15) * 1.8,
+        _ => celsius,
+    };
+    (value * 10000.0).round() / 10000.0
+}
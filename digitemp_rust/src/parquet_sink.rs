@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use crate::config::{format_rom, Rotation, SensorConfig};
+use crate::{json, Reading};
+
+// Fixed file schema; columns are written in this order
+const SCHEMA: &str = "
+    message reading {
+        REQUIRED INT64 timestamp (TIMESTAMP(MILLIS,true));
+        REQUIRED BYTE_ARRAY bus (UTF8);
+        REQUIRED INT32 sensor;
+        REQUIRED BYTE_ARRAY rom (UTF8);
+        OPTIONAL BYTE_ARRAY label (UTF8);
+        REQUIRED DOUBLE celsius;
+        REQUIRED DOUBLE raw_celsius;
+        REQUIRED INT32 retries;
+        REQUIRED INT32 crc_failures;
+        REQUIRED INT32 conversion_ms;
+    }
+";
+
+struct Row {
+    timestamp: i64,
+    bus: String,
+    sensor: i32,
+    rom: String,
+    label: Option<String>,
+    celsius: f64,
+    raw_celsius: f64,
+    retries: i32,
+    crc_failures: i32,
+    conversion_ms: i32,
+}
+
+// Readings buffered in memory and written as one Parquet file per hour or day (UTC) once
+// the period is over, or when the tool stops. Files are never appended to: a period that
+// already has a file, e.g. after a restart, gets a numbered second file.
+pub struct ParquetSink {
+    directory: PathBuf,
+    rotate: Rotation,
+    // Rows by file name stem of their period
+    periods: BTreeMap<String, Vec<Row>>,
+    // Report a failing write once, not on every pass
+    failing: bool,
+}
+
+impl ParquetSink {
+    pub fn open(directory: &Path, rotate: Rotation) -> Result<Self, String> {
+        std::fs::create_dir_all(directory)
+            .map_err(|e| format!("Cannot create parquet directory {}: {}", directory.display(), e))?;
+        Ok(ParquetSink { directory: directory.to_path_buf(), rotate, periods: BTreeMap::new(), failing: false })
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading,
+                   decimals: Option<usize>) {
+        let now = Utc::now();
+        self.periods.entry(self.stem(now)).or_default().push(Row {
+            timestamp: now.timestamp_millis(),
+            bus: bus.to_string(),
+            sensor: index as i32,
+            rom: format_rom(&sensor.rom),
+            label: sensor.label.clone(),
+            celsius: json::round(reading.celsius, decimals),
+            raw_celsius: json::round(reading.raw_celsius, decimals),
+            retries: reading.retries as i32,
+            crc_failures: reading.crc_failures as i32,
+            conversion_ms: reading.conversion_time.as_millis() as i32,
+        });
+    }
+
+    // Write the periods that are over; a failed write is retried after the next pass
+    pub fn pass_done(&mut self) {
+        let current = self.stem(Utc::now());
+        self.flush(|stem| *stem < current);
+    }
+
+    fn flush(&mut self, done: impl Fn(&String) -> bool) {
+        let stems: Vec<String> = self.periods.keys().filter(|stem| done(stem)).cloned().collect();
+        for stem in stems {
+            match self.write(&stem, &self.periods[&stem]) {
+                Ok(()) => {
+                    self.periods.remove(&stem);
+                    if self.failing {
+                        eprintln!("Parquet {}: writing again", self.directory.display());
+                        self.failing = false;
+                    }
+                }
+                Err(e) => {
+                    if !self.failing {
+                        eprintln!("Cannot write parquet file {}: {} (readings are kept and retried)",
+                            self.directory.join(&stem).display(), e);
+                    }
+                    self.failing = true;
+                }
+            }
+        }
+    }
+
+    fn stem(&self, time: DateTime<Utc>) -> String {
+        match self.rotate {
+            Rotation::Hourly => time.format("readings-%Y-%m-%dT%H").to_string(),
+            Rotation::Daily => time.format("readings-%Y-%m-%d").to_string(),
+        }
+    }
+
+    fn write(&self, stem: &str, rows: &[Row]) -> Result<(), String> {
+        let mut path = self.directory.join(format!("{}.parquet", stem));
+        let mut part = 1;
+        while path.exists() {
+            path = self.directory.join(format!("{}-{}.parquet", stem, part));
+            part += 1;
+        }
+
+        // Write a hidden file and rename, so readers globbing *.parquet never see a partial file
+        let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let temp = path.with_file_name(format!(".{}.tmp", file_name));
+        let written = File::create(&temp).map_err(|e| e.to_string())
+            .and_then(|file| write_rows(file, rows).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&temp);
+            return Err(e);
+        }
+        std::fs::rename(&temp, &path).map_err(|e| e.to_string())
+    }
+}
+
+// Stopping or reloading writes what is buffered, including the current period
+impl Drop for ParquetSink {
+    fn drop(&mut self) {
+        self.flush(|_| true);
+    }
+}
+
+fn write_rows(file: File, rows: &[Row]) -> parquet::errors::Result<()> {
+    let schema = Arc::new(parse_message_type(SCHEMA)?);
+    let properties = Arc::new(WriterProperties::builder().set_compression(Compression::SNAPPY).build());
+    let mut writer = SerializedFileWriter::new(file, schema, properties)?;
+    let mut row_group = writer.next_row_group()?;
+
+    let timestamps: Vec<i64> = rows.iter().map(|row| row.timestamp).collect();
+    let strings = |value: fn(&Row) -> &str| -> Vec<ByteArray> {
+        rows.iter().map(|row| ByteArray::from(value(row))).collect()
+    };
+    let integers = |value: fn(&Row) -> i32| -> Vec<i32> { rows.iter().map(value).collect() };
+    let doubles = |value: fn(&Row) -> f64| -> Vec<f64> { rows.iter().map(value).collect() };
+    // The optional label column takes a definition level per row and values only where set
+    let labels: Vec<ByteArray> = rows.iter().filter_map(|row| row.label.as_deref().map(ByteArray::from)).collect();
+    let label_levels: Vec<i16> = rows.iter().map(|row| i16::from(row.label.is_some())).collect();
+
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column()? {
+        match index {
+            0 => column.typed::<Int64Type>().write_batch(&timestamps, None, None),
+            1 => column.typed::<ByteArrayType>().write_batch(&strings(|row| &row.bus), None, None),
+            2 => column.typed::<Int32Type>().write_batch(&integers(|row| row.sensor), None, None),
+            3 => column.typed::<ByteArrayType>().write_batch(&strings(|row| &row.rom), None, None),
+            4 => column.typed::<ByteArrayType>().write_batch(&labels, Some(&label_levels), None),
+            5 => column.typed::<DoubleType>().write_batch(&doubles(|row| row.celsius), None, None),
+            6 => column.typed::<DoubleType>().write_batch(&doubles(|row| row.raw_celsius), None, None),
+            7 => column.typed::<Int32Type>().write_batch(&integers(|row| row.retries), None, None),
+            8 => column.typed::<Int32Type>().write_batch(&integers(|row| row.crc_failures), None, None),
+            _ => column.typed::<Int32Type>().write_batch(&integers(|row| row.conversion_ms), None, None),
+        }?;
+        column.close()?;
+        index += 1;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use std::error::Error as _;
+use std::time::Duration;
+use postgres::types::ToSql;
+use postgres::{Client, NoTls};
+use crate::config::{format_rom, SensorConfig};
+use crate::{json, Reading};
+
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(5);
+// Rows per INSERT statement, well below PostgreSQL's limit of 65535 parameters
+const ROWS_PER_STATEMENT: usize = 500;
+// Rows kept while the server is unreachable; the oldest are dropped beyond this
+const MAX_PENDING: usize = 100_000;
+
+struct Row {
+    unix: f64,
+    rom: String,
+    bus: String,
+    label: Option<String>,
+    celsius: f64,
+    raw_celsius: f64,
+}
+
+// Readings inserted into a PostgreSQL table, created on first connect. Rows are collected
+// for `batch` passes and inserted in one transaction. A lost connection is reopened on the
+// next flush, and rows are kept until then.
+pub struct PostgresSink {
+    // Boxed, as it is much larger than any other sink
+    config: Box<postgres::Config>,
+    table: String,
+    batch: u32,
+    client: Option<Client>,
+    rows: Vec<Row>,
+    passes: u32,
+    // Report a failing server once, not on every pass
+    failing: bool,
+}
+
+impl PostgresSink {
+    pub fn new(url: &str, table: &str, batch: u32) -> Result<Self, String> {
+        let mut config: postgres::Config = url.parse()
+            .map_err(|e| format!("Invalid postgres url: {}", e))?;
+        if config.get_connect_timeout().is_none() {
+            config.connect_timeout(NETWORK_TIMEOUT);
+        }
+        // The name is part of the SQL text, so it cannot be a parameter
+        let valid = |part: &str| part.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !table.split('.').all(valid) || table.split('.').count() > 2 {
+            return Err(format!("Invalid postgres table name '{}' (expected e.g. readings or sensors.readings)",
+                table));
+        }
+        Ok(PostgresSink {
+            config: Box::new(config),
+            table: table.to_string(),
+            batch: batch.max(1),
+            client: None,
+            rows: Vec::new(),
+            passes: 0,
+            failing: false,
+        })
+    }
+
+    pub fn reading(&mut self, bus: &str, sensor: &SensorConfig, reading: &Reading) {
+        self.rows.push(Row {
+            unix: chrono::Utc::now().timestamp_millis() as f64 / 1000.0,
+            rom: format_rom(&sensor.rom),
+            bus: bus.to_string(),
+            label: sensor.label.clone(),
+            celsius: json::round(reading.celsius, Some(4)),
+            raw_celsius: json::round(reading.raw_celsius, Some(4)),
+        });
+    }
+
+    pub fn pass_done(&mut self) {
+        self.passes += 1;
+        if self.passes >= self.batch {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.rows.is_empty() {
+            return;
+        }
+        match self.insert() {
+            Ok(()) => {
+                self.rows.clear();
+                self.passes = 0;
+                if self.failing {
+                    eprintln!("PostgreSQL: delivering again");
+                    self.failing = false;
+                }
+            }
+            Err(e) => {
+                // Reconnect on the next flush
+                self.client = None;
+                if self.rows.len() > MAX_PENDING {
+                    self.rows.drain(..self.rows.len() - MAX_PENDING);
+                }
+                if !self.failing {
+                    // The error itself is terse, such as "error connecting to server"
+                    let cause = e.source().map(|source| format!(": {}", source)).unwrap_or_default();
+                    eprintln!("PostgreSQL: {}{} (readings are kept, up to {}, and retried)", e, cause, MAX_PENDING);
+                }
+                self.failing = true;
+            }
+        }
+    }
+
+    fn insert(&mut self) -> Result<(), postgres::Error> {
+        if self.client.is_none() {
+            let mut client = self.config.connect(NoTls)?;
+            client.batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    time timestamptz NOT NULL,
+                    rom text NOT NULL,
+                    bus text NOT NULL,
+                    label text,
+                    celsius double precision NOT NULL,
+                    raw_celsius double precision NOT NULL,
+                    PRIMARY KEY (rom, time)
+                )", self.table))?;
+            self.client = Some(client);
+        }
+        let client = self.client.as_mut().expect("connected above");
+
+        let mut transaction = client.transaction()?;
+        for chunk in self.rows.chunks(ROWS_PER_STATEMENT) {
+            let mut sql = format!("INSERT INTO {} (time, rom, bus, label, celsius, raw_celsius) VALUES ", self.table);
+            let mut values: Vec<&(dyn ToSql + Sync)> = Vec::with_capacity(chunk.len() * 6);
+            for (i, row) in chunk.iter().enumerate() {
+                let n = i * 6;
+                if i > 0 {
+                    sql.push_str(", ");
+                }
+                sql.push_str(&format!("(to_timestamp(${}), ${}, ${}, ${}, ${}, ${})", n + 1, n + 2, n + 3, n + 4, n + 5,
+                    n + 6));
+                values.extend([&row.unix as &(dyn ToSql + Sync), &row.rom, &row.bus, &row.label, &row.celsius,
+                    &row.raw_celsius]);
+            }
+            // Rows already stored before a lost connection are not duplicated on retry
+            sql.push_str(" ON CONFLICT DO NOTHING");
+            transaction.execute(sql.as_str(), &values)?;
+        }
+        transaction.commit()
+    }
+}
+
+// Stopping or reloading inserts what a batch has collected so far
+impl Drop for PostgresSink {
+    fn drop(&mut self) {
+        self.flush();
+        if !self.rows.is_empty() {
+            eprintln!("PostgreSQL: {} readings were not delivered", self.rows.len());
+        }
+    }
+}
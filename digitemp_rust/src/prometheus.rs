@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::config::{format_rom, SensorConfig};
+
+// node_exporter textfile collector output: a .prom file rewritten after every pass.
+// The file is replaced atomically, as the collector may read it at any moment.
+pub struct TextfileSink {
+    path: PathBuf,
+    exposition: Exposition,
+}
+
+impl TextfileSink {
+    pub fn new(path: &Path) -> Self {
+        TextfileSink { path: path.to_path_buf(), exposition: Exposition::default() }
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, celsius: f32) {
+        self.exposition.reading(bus, index, sensor, celsius);
+    }
+
+    pub fn error(&mut self, bus: &str, index: usize, sensor: &SensorConfig) {
+        self.exposition.error(bus, index, sensor);
+    }
+
+    pub fn pass_done(&mut self) {
+        if self.exposition.sensors.is_empty() {
+            return;
+        }
+        if let Err(e) = replace_file(&self.path, &self.exposition.render()) {
+            eprintln!("Cannot write {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+// The sensor metrics since start or the last reload, in the text exposition format; written
+// to the textfile and served on /metrics by `serve`
+#[derive(Default)]
+pub struct Exposition {
+    // Keyed by sensor index, so sensors are listed in config order
+    sensors: BTreeMap<usize, SensorMetrics>,
+}
+
+struct SensorMetrics {
+    labels: String,
+    celsius: Option<f32>,
+    // Unix time of the last accepted reading
+    last_success: Option<i64>,
+    up: bool,
+    reads: u64,
+    errors: u64,
+}
+
+impl Exposition {
+    fn metrics(&mut self, bus: &str, index: usize, sensor: &SensorConfig) -> &mut SensorMetrics {
+        self.sensors.entry(index).or_insert_with(|| SensorMetrics {
+            labels: format!("sensor=\"{}\",rom=\"{}\",bus=\"{}\"",
+                escape(&sensor.name()), format_rom(&sensor.rom), escape(bus)),
+            celsius: None,
+            last_success: None,
+            up: false,
+            reads: 0,
+            errors: 0,
+        })
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, celsius: f32) {
+        let metrics = self.metrics(bus, index, sensor);
+        metrics.celsius = Some(celsius);
+        metrics.last_success = Some(chrono::Utc::now().timestamp());
+        metrics.up = true;
+        metrics.reads += 1;
+    }
+
+    // A failed or discarded reading; the last good value stays exported
+    pub fn error(&mut self, bus: &str, index: usize, sensor: &SensorConfig) {
+        let metrics = self.metrics(bus, index, sensor);
+        metrics.up = false;
+        metrics.reads += 1;
+        metrics.errors += 1;
+    }
+
+    pub fn render(&self) -> String {
+        let mut content = String::new();
+        let mut family = |name: &str, kind: &str, help: &str, value: &dyn Fn(&SensorMetrics) -> Option<String>| {
+            let samples: Vec<(String, String)> = self.sensors.values()
+                .filter_map(|metrics| value(metrics).map(|value| (metrics.labels.clone(), value)))
+                .collect();
+            write_family(&mut content, name, kind, help, &samples);
+        };
+        family("ds18b20_temperature_celsius", "gauge", "Last accepted temperature reading.",
+            &|m| m.celsius.map(|c| c.to_string()));
+        family("ds18b20_last_success_timestamp_seconds", "gauge", "Unix time of the last accepted reading.",
+            &|m| m.last_success.map(|t| t.to_string()));
+        family("ds18b20_up", "gauge", "Whether the last read of the sensor succeeded.",
+            &|m| Some(u8::from(m.up).to_string()));
+        family("ds18b20_reads_total", "counter", "Readings attempted since start or the last reload.",
+            &|m| Some(m.reads.to_string()));
+        family("ds18b20_read_errors_total", "counter",
+            "Failed or discarded readings since start or the last reload.", &|m| Some(m.errors.to_string()));
+        content
+    }
+}
+
+// One metric family: HELP and TYPE, then a sample per (labels, value)
+pub fn write_family(content: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, String)]) {
+    let _ = writeln!(content, "# HELP {} {}", name, help);
+    let _ = writeln!(content, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(content, "{} {}", name, value);
+        } else {
+            let _ = writeln!(content, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+// Write next to the target and rename, so a scraper never sees a partial file
+pub fn replace_file(path: &Path, content: &str) -> io::Result<()> {
+    let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let temp = path.with_file_name(format!(".{}.tmp", file_name));
+    let written = std::fs::File::create(&temp).and_then(|mut file| file.write_all(content.as_bytes()));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+    std::fs::rename(&temp, path)
+}
+
+// Label values escape backslash, double quote and newline
+pub fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,173 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use crate::config::SensorConfig;
+use crate::{json, sink, Reading};
+
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_PORT: u16 = 6379;
+
+// Where and how to connect
+pub struct RedisServer {
+    pub address: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub database: u32,
+}
+
+// Redis latest values and pub/sub: every reading is stored with SET under a per-sensor key
+// that expires after `ttl`, so a key of a sensor that stopped answering disappears, and
+// published on `channel`. The value and the message are the jsonl record. The commands of
+// a pass are pipelined on a connection kept between passes and reopened when it breaks;
+// those of a pass that cannot be delivered are dropped, as a later pass replaces them.
+pub struct RedisSink {
+    server: RedisServer,
+    key: String,
+    channel: Option<String>,
+    ttl: Option<Duration>,
+    commands: Vec<Vec<String>>,
+    stream: Option<BufReader<TcpStream>>,
+    // Report a failing server once, not on every pass
+    failing: bool,
+}
+
+impl RedisSink {
+    pub fn new(server: RedisServer, key: &str, channel: &str, ttl: Option<Duration>) -> Result<Self, String> {
+        if ttl.is_some_and(|ttl| ttl.as_millis() == 0) {
+            return Err("Invalid Redis ttl: must be at least 1ms".to_string());
+        }
+        let server = RedisServer { address: address(&server.address)?, ..server };
+        Ok(RedisSink {
+            server,
+            key: key.to_string(),
+            // An empty channel publishes nothing
+            channel: (!channel.is_empty()).then(|| channel.to_string()),
+            ttl,
+            commands: Vec::new(),
+            stream: None,
+            failing: false,
+        })
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading,
+                   decimals: Option<usize>) {
+        let record = json::jsonl_reading(bus, index, sensor, reading, decimals);
+        let mut set = vec!["SET".to_string(), sink::expand(&self.key, bus, index, sensor), record.clone()];
+        if let Some(ttl) = self.ttl {
+            set.extend(["PX".to_string(), ttl.as_millis().to_string()]);
+        }
+        self.commands.push(set);
+        if let Some(channel) = &self.channel {
+            self.commands.push(vec!["PUBLISH".to_string(), channel.clone(), record]);
+        }
+    }
+
+    pub fn pass_done(&mut self) {
+        if self.commands.is_empty() {
+            return;
+        }
+        let commands = std::mem::take(&mut self.commands);
+        match self.send(&commands) {
+            Ok(()) if self.failing => {
+                eprintln!("Redis {}: delivering again", self.server.address);
+                self.failing = false;
+            }
+            Ok(()) => {}
+            Err(e) => {
+                if !self.failing {
+                    eprintln!("Redis {}: {} (readings are dropped until it is reachable)", self.server.address, e);
+                }
+                self.failing = true;
+            }
+        }
+    }
+
+    fn send(&mut self, commands: &[Vec<String>]) -> io::Result<()> {
+        // A connection the server closed, e.g. for its idle timeout, is only noticed now:
+        // retry once on a new one
+        if let Some(stream) = self.stream.as_mut() {
+            match pipeline(stream, commands) {
+                Ok(()) => return Ok(()),
+                // The server answered, so it has the commands: sending them again would not help
+                Err(e) if e.kind() == io::ErrorKind::Other => return Err(e),
+                Err(_) => self.stream = None,
+            }
+        }
+        let mut stream = connect(&self.server)?;
+        pipeline(&mut stream, commands)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+}
+
+// redis://host:port or host:port, the port defaulting to 6379
+fn address(server: &str) -> Result<String, String> {
+    let address = match server.split_once("://") {
+        Some(("redis", address)) => address,
+        Some((scheme, _)) => return Err(format!("Invalid Redis server '{}': unsupported scheme {}", server, scheme)),
+        None => server,
+    };
+    let address = address.trim_end_matches('/');
+    if address.is_empty() {
+        return Err(format!("Invalid Redis server '{}': no host", server));
+    }
+    match address.rsplit_once(':') {
+        Some((_, port)) if !port.contains(']') => Ok(address.to_string()),
+        _ => Ok(format!("{}:{}", address, DEFAULT_PORT)),
+    }
+}
+
+fn connect(server: &RedisServer) -> io::Result<BufReader<TcpStream>> {
+    let address = server.address.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address did not resolve"))?;
+    let stream = TcpStream::connect_timeout(&address, NETWORK_TIMEOUT)?;
+    stream.set_read_timeout(Some(NETWORK_TIMEOUT))?;
+    stream.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+    let mut stream = BufReader::new(stream);
+
+    let mut commands = Vec::new();
+    match (&server.username, &server.password) {
+        (Some(username), Some(password)) => commands.push(vec!["AUTH".to_string(), username.clone(), password.clone()]),
+        (None, Some(password)) => commands.push(vec!["AUTH".to_string(), password.clone()]),
+        _ => {}
+    }
+    if server.database != 0 {
+        commands.push(vec!["SELECT".to_string(), server.database.to_string()]);
+    }
+    if !commands.is_empty() {
+        pipeline(&mut stream, &commands)?;
+    }
+    Ok(stream)
+}
+
+// Send the commands at once, then read a reply for each. Error replies, such as WRONGPASS
+// or an out-of-memory refusal, are io::ErrorKind::Other; the first one is returned.
+fn pipeline(stream: &mut BufReader<TcpStream>, commands: &[Vec<String>]) -> io::Result<()> {
+    let mut data = Vec::new();
+    for command in commands {
+        data.extend_from_slice(format!("*{}\r\n", command.len()).as_bytes());
+        for argument in command {
+            data.extend_from_slice(format!("${}\r\n", argument.len()).as_bytes());
+            data.extend_from_slice(argument.as_bytes());
+            data.extend_from_slice(b"\r\n");
+        }
+    }
+    stream.get_mut().write_all(&data)?;
+
+    let mut error = None;
+    for _ in commands {
+        let mut line = String::new();
+        if stream.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the server closed the connection"));
+        }
+        match line.trim_end().split_at_checked(1) {
+            // +OK from SET, the number of subscribers from PUBLISH
+            Some(("+" | ":", _)) => {}
+            Some(("-", message)) => {
+                error.get_or_insert_with(|| io::Error::other(message.to_string()));
+            }
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a Redis reply")),
+        }
+    }
+    error.map_or(Ok(()), Err)
+}
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::time::Duration;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use ureq::Agent;
+use crate::config::{format_rom, SensorConfig};
+use crate::spool::{self, Spool};
+use crate::Reading;
+
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(10);
+const METRIC: &str = "ds18b20_temperature_celsius";
+
+// One sample of a series, spooled as a JSON line
+#[derive(Serialize, Deserialize)]
+struct Sample {
+    // Sorted by name, as remote_write requires; __name__ sorts first
+    labels: Vec<(String, String)>,
+    value: f64,
+    // Unix time in milliseconds
+    timestamp: i64,
+}
+
+// Prometheus remote_write (1.0): snappy-compressed protobuf WriteRequests POSTed to e.g.
+// VictoriaMetrics, Mimir or a Prometheus receiver, with the same series as the textfile
+// sink's temperature. The samples of `batch` passes go out in one request. Samples that
+// cannot be delivered are dropped, or kept in the spool and replayed when there is one;
+// samples the server rejects (4xx, such as out of order) are not retried.
+pub struct RemoteWriteSink {
+    url: String,
+    authorization: Option<String>,
+    batch: u32,
+    agent: Agent,
+    samples: Vec<Sample>,
+    passes: u32,
+    spool: Option<Spool>,
+    // Report a failing server once, not on every pass
+    failing: bool,
+}
+
+impl RemoteWriteSink {
+    pub fn new(url: &str, username: Option<&str>, password: Option<&str>, bearer_token: Option<&str>, batch: u32,
+               spool: Option<Spool>) -> Result<Self, String> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(format!("Invalid remote_write url '{}' (expected e.g. http://vm.lan:8428/api/v1/write)",
+                url));
+        }
+        let authorization = match (username, bearer_token) {
+            (Some(_), Some(_)) => return Err("remote_write takes username/password or bearer_token, not both"
+                .to_string()),
+            (Some(username), None) => {
+                let credentials = format!("{}:{}", username, password.unwrap_or(""));
+                Some(format!("Basic {}", STANDARD.encode(credentials)))
+            }
+            (None, Some(token)) => Some(format!("Bearer {}", token)),
+            (None, None) => None,
+        };
+        let agent = Agent::new_with_config(Agent::config_builder()
+            .timeout_global(Some(NETWORK_TIMEOUT))
+            // Status codes are sorted into retried and rejected below
+            .http_status_as_error(false)
+            .build());
+        Ok(RemoteWriteSink {
+            url: url.to_string(),
+            authorization,
+            batch: batch.max(1),
+            agent,
+            samples: Vec::new(),
+            passes: 0,
+            spool,
+            failing: false,
+        })
+    }
+
+    pub fn reading(&mut self, bus: &str, sensor: &SensorConfig, reading: &Reading) {
+        let mut labels = vec![
+            ("__name__".to_string(), METRIC.to_string()),
+            ("bus".to_string(), bus.to_string()),
+            ("rom".to_string(), format_rom(&sensor.rom)),
+            ("sensor".to_string(), sensor.name()),
+        ];
+        if let Some(group) = &sensor.group {
+            labels.push(("group".to_string(), group.clone()));
+        }
+        labels.sort();
+        self.samples.push(Sample {
+            labels,
+            value: reading.celsius as f64,
+            timestamp: chrono::Utc::now().timestamp_millis(),
+        });
+    }
+
+    pub fn pass_done(&mut self) {
+        self.passes += 1;
+        if self.passes >= self.batch {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.passes = 0;
+        if self.samples.is_empty() {
+            return;
+        }
+        let samples = std::mem::take(&mut self.samples);
+        let sent = match self.spool.as_mut() {
+            Some(spool) => {
+                let records = samples.iter().filter_map(|sample| serde_json::to_string(sample).ok()).collect();
+                let (agent, url, authorization) = (&self.agent, &self.url, self.authorization.as_deref());
+                spool.deliver(records, |records| {
+                    let samples: Vec<Sample> = records.iter()
+                        .filter_map(|record| serde_json::from_str(record).ok()).collect();
+                    send(agent, url, authorization, &samples)
+                })
+            }
+            None => send(&self.agent, &self.url, self.authorization.as_deref(), &samples),
+        };
+        match sent {
+            Ok(()) if self.failing => {
+                eprintln!("remote_write {}: delivering again", self.url);
+                self.failing = false;
+            }
+            Ok(()) => {}
+            Err(e) => {
+                if !self.failing {
+                    eprintln!("remote_write {}: {} ({})", self.url, e, spool::kept(self.spool.as_ref()));
+                }
+                self.failing = true;
+            }
+        }
+    }
+}
+
+// Stopping or reloading writes what a batch has collected so far
+impl Drop for RemoteWriteSink {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+fn send(agent: &Agent, url: &str, authorization: Option<&str>, samples: &[Sample]) -> io::Result<()> {
+    let body = snap::raw::Encoder::new().compress_vec(&write_request(samples)).map_err(io::Error::other)?;
+    let mut request = agent.post(url)
+        .content_type("application/x-protobuf")
+        .header("Content-Encoding", "snappy")
+        .header("X-Prometheus-Remote-Write-Version", "0.1.0");
+    if let Some(authorization) = authorization {
+        request = request.header("Authorization", authorization);
+    }
+    let mut response = request.send(body).map_err(io::Error::other)?;
+    let status = response.status().as_u16();
+    if (200..300).contains(&status) {
+        return Ok(());
+    }
+    let message = response.body_mut().read_to_string().unwrap_or_default();
+    let message = message.trim();
+    // As Prometheus does: client errors would recur, server errors and throttling may not
+    if (400..500).contains(&status) && status != 429 {
+        eprintln!("remote_write {}: {} samples rejected: {} {}", url, samples.len(), status, message);
+        return Ok(());
+    }
+    Err(io::Error::other(format!("HTTP {} {}", status, message)))
+}
+
+// prometheus.WriteRequest with one TimeSeries per label set, its samples in time order:
+//   WriteRequest { repeated TimeSeries timeseries = 1; }
+//   TimeSeries { repeated Label labels = 1; repeated Sample samples = 2; }
+//   Label { string name = 1; string value = 2; }
+//   Sample { double value = 1; int64 timestamp = 2; }
+fn write_request(samples: &[Sample]) -> Vec<u8> {
+    let mut series: BTreeMap<&[(String, String)], Vec<&Sample>> = BTreeMap::new();
+    for sample in samples {
+        series.entry(&sample.labels).or_default().push(sample);
+    }
+
+    let mut request = Vec::new();
+    for (labels, samples) in series {
+        let mut timeseries = Vec::new();
+        for (name, value) in labels {
+            let mut label = Vec::new();
+            field_bytes(&mut label, 1, name.as_bytes());
+            field_bytes(&mut label, 2, value.as_bytes());
+            field_bytes(&mut timeseries, 1, &label);
+        }
+        for sample in samples {
+            let mut encoded = Vec::new();
+            // Fixed 64-bit double, then a varint
+            varint(&mut encoded, (1 << 3) | 1);
+            encoded.extend_from_slice(&sample.value.to_le_bytes());
+            varint(&mut encoded, 2 << 3);
+            varint(&mut encoded, sample.timestamp as u64);
+            field_bytes(&mut timeseries, 2, &encoded);
+        }
+        field_bytes(&mut request, 1, &timeseries);
+    }
+    request
+}
+
+// A length-delimited field (wire type 2)
+fn field_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    varint(out, (field << 3) | 2);
+    varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use crate::config::SensorConfig;
+use crate::json::RrdFile;
+use crate::sink;
+
+// Data source name inside every RRD file
+const DS_NAME: &str = "temperature";
+// DS18B20 range; values outside are stored as unknown
+const DS_MIN: i32 = -55;
+const DS_MAX: i32 = 125;
+
+// Round-robin archives as (consolidated steps, rows), with MIN and MAX next to AVERAGE:
+// every step for two days, 5 steps for two weeks, an hour for 62 days, a day for 5 years
+// (with the default 60 s step)
+const ARCHIVES: [(u64, u64); 4] = [(1, 2880), (5, 4032), (60, 1488), (1440, 1830)];
+
+// One RRD file per sensor, updated through the rrdtool command after every pass.
+// `path` is a template such as /var/lib/digitemp/{label}.rrd.
+pub struct RrdSink {
+    path: String,
+    rrdtool: String,
+    // Values of the current pass by file
+    updates: BTreeMap<PathBuf, String>,
+    // Report a missing rrdtool once, not on every pass
+    failing: bool,
+}
+
+impl RrdSink {
+    pub fn new(path: &str, rrdtool: &str) -> Self {
+        RrdSink { path: path.to_string(), rrdtool: rrdtool.to_string(), updates: BTreeMap::new(), failing: false }
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, celsius: f32) {
+        let path = PathBuf::from(sink::expand(&self.path, bus, index, sensor));
+        self.updates.insert(path, format!("{}:{}", chrono::Utc::now().timestamp(), celsius));
+    }
+
+    pub fn pass_done(&mut self) {
+        for (path, update) in std::mem::take(&mut self.updates) {
+            let result = Command::new(&self.rrdtool).arg("update").arg(&path).arg(&update).output();
+            match result {
+                Ok(output) if output.status.success() => self.failing = false,
+                // rrdtool's own message names the file and the reason
+                Ok(output) => eprintln!("rrdtool update {}: {}", path.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()),
+                Err(e) => {
+                    if !self.failing {
+                        eprintln!("Cannot run {}: {} (RRD files are not updated)", self.rrdtool, e);
+                    }
+                    self.failing = true;
+                }
+            }
+        }
+    }
+}
+
+// Create the RRD file of every sensor for one rrd sink; existing files are kept unless `force`
+pub fn create(path: &str, rrdtool: &str, step: u64, sensors: &[(usize, &str, &SensorConfig)], force: bool)
+              -> Result<Vec<RrdFile>, String> {
+    let mut created = Vec::new();
+    for &(index, bus, sensor) in sensors {
+        let file = sink::expand(path, bus, index, sensor);
+        if Path::new(&file).exists() && !force {
+            created.push(RrdFile { sensor: index, path: file, status: "exists" });
+            continue;
+        }
+
+        let mut command = Command::new(rrdtool);
+        command.arg("create").arg(&file).arg("--step").arg(step.to_string())
+            .arg(format!("DS:{}:GAUGE:{}:{}:{}", DS_NAME, step * 2, DS_MIN, DS_MAX));
+        for (steps, rows) in ARCHIVES {
+            // Minimum and maximum of a single step are the average
+            let consolidations: &[&str] = if steps == 1 { &["AVERAGE"] } else { &["AVERAGE", "MIN", "MAX"] };
+            for consolidation in consolidations {
+                command.arg(format!("RRA:{}:0.5:{}:{}", consolidation, steps, rows));
+            }
+        }
+        let output = command.output().map_err(|e| format!("Cannot run {}: {}", rrdtool, e))?;
+        if !output.status.success() {
+            return Err(format!("rrdtool create {}: {}", file, String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        created.push(RrdFile { sensor: index, path: file, status: "created" });
+    }
+    Ok(created)
+}
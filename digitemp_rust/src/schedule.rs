@@ -0,0 +1,369 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use crate::config::{format_duration, parse_duration, SensorConfig};
+
+// Decides which sensors are due in a pass, honoring per-sensor `enabled`, `interval` and
+// `schedule`, or the schedule of the sensor's group
+#[derive(Default)]
+pub struct Scheduler {
+    last_read: HashMap<[u8; 8], (Instant, NaiveDateTime)>,
+    // Sensors reported past their freshness target, until they are read
+    late: HashSet<[u8; 8]>,
+}
+
+impl Scheduler {
+    pub fn is_due(&self, sensor: &SensorConfig, schedule: Option<&Schedule>, now: Instant) -> bool {
+        sensor.enabled && self.interval_elapsed(sensor, schedule, now)
+    }
+
+    // As `is_due`, for sensors named on the command line, which are read even when disabled
+    pub fn interval_elapsed(&self, sensor: &SensorConfig, schedule: Option<&Schedule>, now: Instant) -> bool {
+        let last = self.last_read.get(&sensor.rom);
+        match (schedule, sensor.interval, last) {
+            (Some(schedule), _, _) => schedule.is_due(last.copied(), now, Local::now().naive_local()),
+            (None, Some(interval), Some(&(last, _))) => now.duration_since(last) >= interval,
+            _ => true,
+        }
+    }
+
+    pub fn mark_read(&mut self, sensor: &SensorConfig, at: Instant) {
+        self.last_read.insert(sensor.rom, (at, Local::now().naive_local()));
+        self.late.remove(&sensor.rom);
+    }
+
+    // With more than `limit` sensors due, keep the `limit` that waited longest, those whose
+    // data is older than their `freshness` target first, so every sensor gets its turn; the
+    // others stay due for the next pass. A sensor left behind past its target is reported.
+    pub fn take_turns<'c, T>(&mut self, due: &mut Vec<T>, limit: usize, now: Instant,
+                             sensor: impl Fn(&T) -> (&'c SensorConfig, Option<Duration>)) {
+        if due.len() <= limit {
+            return;
+        }
+        let age = |rom: &[u8; 8]| self.last_read.get(rom).map_or(Duration::MAX, |(at, _)| now.duration_since(*at));
+        let late = |(sensor, freshness): (&SensorConfig, Option<Duration>)| {
+            freshness.is_some_and(|freshness| age(&sensor.rom) > freshness)
+        };
+        // Never read counts as the oldest
+        due.sort_by_key(|item| (!late(sensor(item)), std::cmp::Reverse(age(&sensor(item).0.rom))));
+        for item in due.drain(limit..) {
+            let (sensor, freshness) = sensor(&item);
+            let (Some(freshness), Some(&(at, _))) = (freshness, self.last_read.get(&sensor.rom)) else { continue };
+            let age = now.duration_since(at);
+            if age > freshness && self.late.insert(sensor.rom) {
+                eprintln!("Sensor {}: not read for {:.1}s, past its freshness target of {}; more sensors are \
+                    due than max_per_pass allows", sensor.name(), age.as_secs_f32(), format_duration(freshness));
+            }
+        }
+    }
+}
+
+// When a sensor is read, instead of every pass: a crontab line of local time such as
+// `*/5 * * * *`, one of @hourly, @daily, @weekly and @monthly, or `every 30s`, optionally
+// `between 06:00-22:00`. Kept as written, so the config is saved back unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    text: String,
+    kind: Kind,
+    // Longest time between two reads, so a sensor read rarely is not reported missing
+    max_gap: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    Cron(Cron),
+    // Every pass, or at most every `every`, inside the daily window [from, to)
+    Every { every: Option<Duration>, window: Option<(NaiveTime, NaiveTime)> },
+}
+
+impl Schedule {
+    pub fn max_gap(&self) -> Duration {
+        self.max_gap
+    }
+
+    // Whether a pass at `now` (`wall` in local time) reads a sensor last read at `last`.
+    // As with `interval`, the first pass reads it whatever the schedule, so a single `read`
+    // shows every sensor. A cron schedule is then due once a minute it names has begun since
+    // the last read, so a pass interval longer than a minute delays a read rather than missing it.
+    fn is_due(&self, last: Option<(Instant, NaiveDateTime)>, now: Instant, wall: NaiveDateTime) -> bool {
+        let Some((last, last_wall)) = last else { return true };
+        match &self.kind {
+            Kind::Cron(cron) => cron.next_after(minute(last_wall)).is_some_and(|next| next <= wall),
+            Kind::Every { every, window } => {
+                let inside = window.is_none_or(|(from, to)| {
+                    let time = wall.time();
+                    if from < to { from <= time && time < to } else { time >= from || time < to }
+                });
+                inside && every.is_none_or(|every| now.duration_since(last) >= every)
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        let spec = text.trim().to_ascii_lowercase();
+        let kind = match spec.as_str() {
+            "@hourly" => Kind::Cron(Cron::parse("0 * * * *")?),
+            "@daily" | "@midnight" => Kind::Cron(Cron::parse("0 0 * * *")?),
+            "@weekly" => Kind::Cron(Cron::parse("0 0 * * 0")?),
+            "@monthly" => Kind::Cron(Cron::parse("0 0 1 * *")?),
+            _ if spec.starts_with("every") || spec.starts_with("between") => parse_every(&spec)?,
+            _ => Kind::Cron(Cron::parse(&spec)?),
+        };
+        let max_gap = match &kind {
+            Kind::Cron(cron) => cron.max_gap()
+                .ok_or_else(|| format!("schedule '{}' never fires", text))?,
+            Kind::Every { every, window } => {
+                let closed = window.map_or(Duration::ZERO, |(from, to)| {
+                    let open = (to - from).num_seconds().rem_euclid(86_400) as u64;
+                    Duration::from_secs(86_400 - open)
+                });
+                closed + every.unwrap_or_default()
+            }
+        };
+        Ok(Schedule { text, kind, max_gap })
+    }
+}
+
+impl serde::Serialize for Schedule {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Schedule {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Schedule::try_from(String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+// `every 30s`, `between 06:00-22:00`, or both; the window may run past midnight
+fn parse_every(spec: &str) -> Result<Kind, String> {
+    let (every, window) = match spec.split_once("between") {
+        Some((every, window)) => (every.trim(), Some(window.trim())),
+        None => (spec, None),
+    };
+    let every = match every.strip_prefix("every") {
+        Some(every) => Some(parse_duration(every.trim())?).filter(|every| !every.is_zero()),
+        None if every.is_empty() => None,
+        None => return Err(format!("expected 'every DURATION' in schedule '{}'", spec)),
+    };
+    let window = window.map(|window| {
+        let (from, to) = window.split_once(['-', '–'])
+            .or_else(|| window.split_once(" to "))
+            .or_else(|| window.split_once(" and "))
+            .ok_or_else(|| format!("expected 'between HH:MM-HH:MM' in schedule '{}'", spec))?;
+        let time = |text: &str| NaiveTime::parse_from_str(text.trim(), "%H:%M")
+            .map_err(|_| format!("'{}' is not a time of day such as 06:00", text.trim()));
+        match (time(from)?, time(to)?) {
+            (from, to) if from == to => Err(format!("the window of schedule '{}' is empty", spec)),
+            window => Ok(window),
+        }
+    }).transpose()?;
+    Ok(Kind::Every { every, window })
+}
+
+// The five fields of a crontab line, as bit sets
+#[derive(Debug, Clone, PartialEq)]
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // As cron does, a day matches either field when both are restricted
+    any_day: bool,
+}
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// No schedule needs longer to tell how far apart its reads are
+const HORIZON_DAYS: i64 = 400;
+
+impl Cron {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("schedule '{}' is not five cron fields, @daily or 'every ...'", spec));
+        };
+        // Sunday is 0 or 7
+        let field = |name: &str, text: &str, min: u32, max: u32, names: &[&str]| {
+            field(text, min, max, names).map_err(|e| format!("{} of schedule '{}': {}", name, spec, e))
+        };
+        let weekday_bits = field("day of week", weekdays, 0, 7, &WEEKDAYS)?;
+        Ok(Cron {
+            minutes: field("minute", minutes, 0, 59, &[])?,
+            hours: field("hour", hours, 0, 23, &[])?,
+            days: field("day of month", days, 1, 31, &[])?,
+            months: field("month", months, 1, 12, &MONTHS)?,
+            weekdays: (weekday_bits | weekday_bits >> 7) & 0x7f,
+            any_day: !days.starts_with('*') && !weekdays.starts_with('*'),
+        })
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days & 1 << date.day() != 0;
+        let weekday = self.weekdays & 1 << date.weekday().num_days_from_sunday() != 0;
+        if self.any_day { day || weekday } else { day && weekday }
+    }
+
+    // The first minute it fires after `after`, within the horizon
+    fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let end = after + chrono::Duration::days(HORIZON_DAYS * 4);
+        let mut time = minute(after) + chrono::Duration::minutes(1);
+        while time <= end {
+            if self.months & 1 << time.month() == 0 {
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1),
+                };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(time.date()) {
+                time = time.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & 1 << time.hour() == 0 {
+                time = minute(time) + chrono::Duration::minutes(60 - i64::from(time.minute()));
+            } else if self.minutes & 1 << time.minute() == 0 {
+                time += chrono::Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    // Longest time between two firings over about a year, for a schedule that fires at all
+    fn max_gap(&self) -> Option<Duration> {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1)?.and_hms_opt(0, 0, 0)?;
+        let end = start + chrono::Duration::days(HORIZON_DAYS);
+        let mut previous = self.next_after(start)?;
+        let mut gap = chrono::Duration::minutes(1);
+        for _ in 0..10_000 {
+            let next = self.next_after(previous)?;
+            gap = gap.max(next - previous);
+            if next > end {
+                break;
+            }
+            previous = next;
+        }
+        gap.to_std().ok()
+    }
+}
+
+// One cron field: `*`, values, ranges and steps such as `1-5`, `*/15` or `mon,wed`
+fn field(text: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |text: &str| -> Result<u32, String> {
+        let named = names.iter().position(|name| *name == text).map(|i| i as u32 + min);
+        let value = named.or_else(|| text.parse().ok())
+            .ok_or_else(|| format!("'{}' is not a number", text))?;
+        if (min..=max).contains(&value) { Ok(value) } else { Err(format!("{} is outside {}-{}", value, min, max)) }
+    };
+    let mut bits = 0;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|&step| step > 0)
+                .ok_or_else(|| format!("'{}' is not a step", step))?),
+            None => (part, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (value(first)?, value(last)?),
+            // `5/10` runs from 5 to the end
+            None if step > 1 => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if first > last {
+            return Err(format!("range '{}' runs backwards", range));
+        }
+        for v in (first..=last).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+fn minute(time: NaiveDateTime) -> NaiveDateTime {
+    time.with_second(0).and_then(|time| time.with_nanosecond(0)).unwrap_or(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn sunday_is_0_or_7() {
+        let sunday = Cron::parse("0 0 * * 0").unwrap();
+        assert_eq!(sunday.weekdays, 1);
+        assert_eq!(Cron::parse("0 0 * * 7").unwrap(), sunday);
+        assert_eq!(Cron::parse("0 0 * * sun").unwrap(), sunday);
+        assert_eq!(Cron::parse("0 0 * * 5-7").unwrap().weekdays, 1 | 1 << 5 | 1 << 6);
+        // 2024-01-07 is a Sunday
+        assert_eq!(sunday.next_after(at("2024-01-01 12:00")), Some(at("2024-01-07 00:00")));
+    }
+
+    #[test]
+    fn restricted_day_fields_are_or_ed() {
+        // The 13th, or any Friday
+        let cron = Cron::parse("0 0 13 * fri").unwrap();
+        assert_eq!(cron.next_after(at("2024-01-01 00:00")), Some(at("2024-01-05 00:00")));
+        assert_eq!(cron.next_after(at("2024-01-12 00:00")), Some(at("2024-01-13 00:00")));
+        // With one of them `*`, only the other counts
+        let fridays = Cron::parse("0 0 * * fri").unwrap();
+        assert_eq!(fridays.next_after(at("2024-01-12 00:00")), Some(at("2024-01-19 00:00")));
+    }
+
+    #[test]
+    fn steps_and_ranges() {
+        assert_eq!(field("5/10", 0, 59, &[]).unwrap(), [5, 15, 25, 35, 45, 55].iter().map(|v| 1u64 << v).sum::<u64>());
+        assert_eq!(field("*/6", 0, 23, &[]).unwrap(), 1 | 1 << 6 | 1 << 12 | 1 << 18);
+        assert_eq!(field("1-3,mar", 1, 12, &MONTHS).unwrap(), 1 << 1 | 1 << 2 | 1 << 3);
+        assert!(field("5-1", 0, 59, &[]).is_err());
+        assert!(field("*/0", 0, 59, &[]).is_err());
+        assert!(field("60", 0, 59, &[]).is_err());
+
+        let cron = Cron::parse("5/10 * * * *").unwrap();
+        assert_eq!(cron.next_after(at("2024-01-01 10:55")), Some(at("2024-01-01 11:05")));
+        assert!(Cron::parse("* * * *").is_err());
+    }
+
+    #[test]
+    fn window_past_midnight() {
+        let schedule = Schedule::try_from("between 22:00-06:00".to_string()).unwrap();
+        let start = Instant::now();
+        let last = Some((start, at("2024-01-01 21:00")));
+        let due = |wall| schedule.is_due(last, start + Duration::from_secs(60), at(wall));
+        assert!(due("2024-01-01 23:30"));
+        assert!(due("2024-01-02 05:59"));
+        assert!(!due("2024-01-02 06:00"));
+        assert!(!due("2024-01-02 12:00"));
+        // The first pass reads the sensor, outside the window too
+        assert!(schedule.is_due(None, start, at("2024-01-02 12:00")));
+        assert!(Schedule::try_from("between 06:00-06:00".to_string()).is_err());
+    }
+
+    #[test]
+    fn max_gap() {
+        let gap = |text: &str| Schedule::try_from(text.to_string()).unwrap().max_gap();
+        assert_eq!(gap("*/5 * * * *"), Duration::from_secs(5 * 60));
+        assert_eq!(gap("0 */6 * * *"), Duration::from_secs(6 * 3600));
+        assert_eq!(gap("@weekly"), Duration::from_secs(7 * 86_400));
+        // Closed from 22:00 to 06:00, plus the interval
+        assert_eq!(gap("every 30s between 06:00-22:00"), Duration::from_secs(8 * 3600 + 30));
+        assert_eq!(gap("every 10m"), Duration::from_secs(600));
+        // February 30th never comes
+        assert!(Schedule::try_from("0 0 30 2 *".to_string()).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo,
+    ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+use crate::cli;
+use crate::signals;
+
+// What run-service runs once the service control manager calls back
+struct Service {
+    name: String,
+    matches: ArgMatches,
+    run: fn(&ArgMatches) -> Option<i32>,
+}
+
+static SERVICE: OnceLock<Service> = OnceLock::new();
+
+// True in the process the service control manager started
+pub fn running() -> bool {
+    SERVICE.get().is_some()
+}
+
+// Register this executable to run `monitor` or `serve` at boot, as LocalSystem
+pub fn install(args: &ArgMatches) -> Result<String, String> {
+    let name = service_name(args);
+    let mut command: Vec<OsString> = args.get_many::<OsString>("command").into_iter().flatten().cloned().collect();
+    let matches = parse(&command)?;
+    let options = match matches.subcommand() {
+        Some(("monitor" | "serve", options)) => options,
+        _ => return Err("A service runs `monitor` or `serve`".to_string()),
+    };
+    if options.get_flag("daemon") {
+        return Err("A service is started by the service control manager; leave out --daemon".to_string());
+    }
+    // Services start in the system directory, where a relative path finds nothing. A -c given
+    // before install-service, or in DIGITEMP_CONFIG, is passed on to the service.
+    match (options.value_source("config"), args.get_one::<String>("config")) {
+        (Some(ValueSource::CommandLine), _) => {
+            let config = options.get_one::<String>("config").map(String::as_str).unwrap_or_default();
+            if !Path::new(config).is_absolute() {
+                return Err(format!("Give the configuration of a service as an absolute path, not {}", config));
+            }
+        }
+        (_, Some(config)) => {
+            let path = std::path::absolute(config).map_err(|e| format!("Cannot resolve {}: {}", config, e))?;
+            command.splice(1..1, [OsString::from("--config"), path.into_os_string()]);
+        }
+        _ => {}
+    }
+
+    let manager = ServiceManager::local_computer(None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)
+        .map_err(|e| error("connect to the service control manager", e))?;
+    let executable_path = std::env::current_exe().map_err(|e| format!("Cannot find this program: {}", e))?;
+    let mut launch_arguments: Vec<OsString> = ["run-service", "--name", name, "--"].map(OsString::from).into();
+    launch_arguments.extend(command);
+    let info = ServiceInfo {
+        name: OsString::from(name),
+        display_name: OsString::from(format!("DigiTemp ({})", name)),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path,
+        launch_arguments,
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .map_err(|e| error(&format!("install service {}", name), e))?;
+    service.set_description("Reads DS18B20 temperature sensors on a DS9097 1-Wire adapter")
+        .map_err(|e| error("describe the service", e))?;
+    Ok(format!("Installed service {}; start it with `sc start {}` or reboot", name, name))
+}
+
+// Stop the service if it runs, then remove it
+pub fn uninstall(args: &ArgMatches) -> Result<String, String> {
+    let name = service_name(args);
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .map_err(|e| error("connect to the service control manager", e))?;
+    let service = manager.open_service(name, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)
+        .map_err(|e| error(&format!("open service {}", name), e))?;
+    let status = service.query_status().map_err(|e| error("query the service", e))?;
+    if status.current_state != ServiceState::Stopped {
+        service.stop().map_err(|e| error("stop the service", e))?;
+    }
+    // Removed for good once the last handle to it is closed
+    service.delete().map_err(|e| error(&format!("remove service {}", name), e))?;
+    Ok(format!("Removed service {}", name))
+}
+
+// Hand the main thread to the service control manager, which calls back service_main on
+// another thread; returns once the service has stopped
+pub fn run(args: &ArgMatches, run: fn(&ArgMatches) -> Option<i32>) -> Result<(), String> {
+    let name = service_name(args).to_string();
+    let command: Vec<OsString> = args.get_many::<OsString>("command").into_iter().flatten().cloned().collect();
+    let matches = parse(&command)?;
+    let _ = SERVICE.set(Service { name: name.clone(), matches, run });
+    service_dispatcher::start(&name, ffi_service_main)
+        .map_err(|e| error("connect to the service control manager (run-service is started by it)", e))
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_: Vec<OsString>) {
+    let Some(service) = SERVICE.get() else { return };
+    // Stop and shutdown end the read loop after the current pass, as SIGTERM does on Unix
+    let handler = |control: ServiceControl| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            signals::request_stop();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let status = match service_control_handler::register(&service.name, handler) {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("{}", error("register with the service control manager", e));
+            return;
+        }
+    };
+    let report = |state, controls_accepted, exit_code| {
+        let result = status.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        });
+        if let Err(e) = result {
+            eprintln!("{}", error("report the service status", e));
+        }
+    };
+    report(ServiceState::Running, ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        ServiceExitCode::Win32(0));
+    // A startup error exits the process, which the service control manager reports as a
+    // service that terminated unexpectedly, and restarts if recovery is configured
+    let exit_code = match (service.run)(&service.matches) {
+        Some(code) => ServiceExitCode::ServiceSpecific(code as u32),
+        None => ServiceExitCode::Win32(0),
+    };
+    report(ServiceState::Stopped, ServiceControlAccept::empty(), exit_code);
+}
+
+fn service_name(args: &ArgMatches) -> &str {
+    args.get_one::<String>("service_name").map(String::as_str).unwrap_or("digitemp")
+}
+
+// The service's command line, as if given to digitemp_native itself
+fn parse(command: &[OsString]) -> Result<ArgMatches, String> {
+    cli::build()
+        .try_get_matches_from(std::iter::once(OsString::from(cli::BIN_NAME)).chain(command.iter().cloned()))
+        .map_err(|e| e.to_string())
+}
+
+fn error(what: &str, e: windows_service::Error) -> String {
+    match e {
+        // Its own message leaves out the system error
+        windows_service::Error::Winapi(e) => format!("Cannot {}: {}", what, e),
+        e => format!("Cannot {}: {}", what, e),
+    }
+}
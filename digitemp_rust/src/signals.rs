@@ -0,0 +1,110 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+#[cfg(not(unix))]
+use std::sync::OnceLock;
+
+// Exit status after a second Ctrl-C, as shells report for SIGINT
+#[cfg(unix)]
+const INTERRUPTED: i32 = 130;
+
+// Without signals, the one flag every StopSignal shares, set by request_stop()
+#[cfg(not(unix))]
+static STOP: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+// Set by SIGHUP; the read loop re-reads the configuration between passes
+pub struct ReloadSignal {
+    requested: Arc<AtomicBool>,
+}
+
+impl ReloadSignal {
+    #[cfg(unix)]
+    pub fn install() -> io::Result<Self> {
+        let requested = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&requested))?;
+        Ok(ReloadSignal { requested })
+    }
+
+    // No SIGHUP outside Unix; the flag is simply never set
+    #[cfg(not(unix))]
+    pub fn install() -> io::Result<Self> {
+        Ok(ReloadSignal { requested: Arc::new(AtomicBool::new(false)) })
+    }
+
+    // True once per received signal
+    pub fn take(&self) -> bool {
+        self.requested.swap(false, Ordering::Relaxed)
+    }
+}
+
+// Set by SIGUSR1, a pass at once, and SIGUSR2, pause or resume the passes; the read loop
+// stops waiting for the next pass when either arrives
+pub struct UserSignals {
+    cycle: Arc<AtomicBool>,
+    pause: Arc<AtomicBool>,
+}
+
+impl UserSignals {
+    #[cfg(unix)]
+    pub fn install() -> io::Result<Self> {
+        use signal_hook::consts::{SIGUSR1, SIGUSR2};
+        let (cycle, pause) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicBool::new(false)));
+        signal_hook::flag::register(SIGUSR1, Arc::clone(&cycle))?;
+        signal_hook::flag::register(SIGUSR2, Arc::clone(&pause))?;
+        Ok(UserSignals { cycle, pause })
+    }
+
+    // No SIGUSR1 or SIGUSR2 outside Unix; the control socket is not there either
+    #[cfg(not(unix))]
+    pub fn install() -> io::Result<Self> {
+        Ok(UserSignals { cycle: Arc::new(AtomicBool::new(false)), pause: Arc::new(AtomicBool::new(false)) })
+    }
+
+    // Either signal arrived and is not taken yet
+    pub fn pending(&self) -> bool {
+        self.cycle.load(Ordering::Relaxed) || self.pause.load(Ordering::Relaxed)
+    }
+
+    pub fn take_cycle(&self) -> bool {
+        self.cycle.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn take_pause(&self) -> bool {
+        self.pause.swap(false, Ordering::Relaxed)
+    }
+}
+
+// Set by Ctrl-C (SIGINT) or SIGTERM; the read loop finishes the current pass and returns.
+// A second signal while stopping terminates at once.
+pub struct StopSignal {
+    requested: Arc<AtomicBool>,
+}
+
+impl StopSignal {
+    #[cfg(unix)]
+    pub fn install() -> io::Result<Self> {
+        use signal_hook::consts::{SIGINT, SIGTERM};
+        let requested = Arc::new(AtomicBool::new(false));
+        for signal in [SIGINT, SIGTERM] {
+            signal_hook::flag::register_conditional_shutdown(signal, INTERRUPTED, Arc::clone(&requested))?;
+            signal_hook::flag::register(signal, Arc::clone(&requested))?;
+        }
+        Ok(StopSignal { requested })
+    }
+
+    // Ctrl-C keeps its default behaviour outside Unix; a Windows service is stopped by request_stop()
+    #[cfg(not(unix))]
+    pub fn install() -> io::Result<Self> {
+        Ok(StopSignal { requested: Arc::clone(STOP.get_or_init(Default::default)) })
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::Relaxed)
+    }
+}
+
+// Stop as SIGTERM would, from the Windows service control handler
+#[cfg(windows)]
+pub fn request_stop() {
+    STOP.get_or_init(Default::default).store(true, Ordering::Relaxed);
+}
@@ -0,0 +1,378 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+use crate::OneWireAdapter;
+
+// A fake serial port with virtual DS18B20s behind it (--simulate). It speaks the same
+// DS9097 byte protocol as the real adapter, so discovery, CRCs, settings and broadcast
+// conversion all run through the normal code paths.
+
+// Power-on scratchpad: 85 °C, TH 75, TL 70, 12-bit resolution
+const POWER_ON_SCRATCHPAD: [u8; 8] = [0x50, 0x05, 0x4B, 0x46, 0x7F, 0xFF, 0x0C, 0x10];
+const FAMILY_DS18B20: u8 = 0x28;
+
+#[derive(Clone)]
+pub struct SimulatedPort {
+    path: String,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    baud: u32,
+    // Answers the adapter has not read yet, one byte per written byte
+    pending: VecDeque<u8>,
+    devices: Vec<Device>,
+    // xorshift state for measurement noise
+    noise: u32,
+}
+
+// What a device does with the next time slot
+enum Phase {
+    // Not addressed until the next reset
+    Idle,
+    RomCommand,
+    // Each ROM bit takes three slots: the bit, its complement, the master's choice
+    Search { bit: usize, step: u8 },
+    Match { bit: usize },
+    Function,
+    Transmit { data: Vec<u8>, bit: usize },
+    WriteScratchpad { data: Vec<u8>, bit: usize },
+    // Read slots answer 1: conversion finished, externally powered
+    Ready,
+}
+
+struct Device {
+    rom: [u8; 8],
+    scratchpad: [u8; 9],
+    eeprom: [u8; 3],
+    // Set by a conversion at or past TH or TL; the device then answers the alarm search
+    alarm: bool,
+    // Temperature the reading drifts around
+    base: f32,
+    phase: Phase,
+    command: u8,
+    command_bits: u8,
+}
+
+impl SimulatedPort {
+    // `count` sensors on the bus with index `bus`; the ROMs only depend on both numbers,
+    // so a config written by `init` matches later runs
+    pub fn new(bus: usize, count: u8) -> Self {
+        let devices = (0..count).map(|i| Device::new(bus as u8, i)).collect();
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        let state = State { baud: 115200, pending: VecDeque::new(), devices, noise: seed | 1 };
+        SimulatedPort { path: format!("simulated:{}", bus), state: Arc::new(Mutex::new(state)) }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl State {
+    fn write(&mut self, byte: u8) {
+        // At 9600 baud a 0xF0 is a reset pulse; devices pull the echo low as presence
+        if self.baud == 9600 {
+            for device in &mut self.devices {
+                device.phase = Phase::RomCommand;
+                device.command_bits = 0;
+            }
+            self.pending.push_back(if self.devices.is_empty() { 0xF0 } else { 0xE0 });
+            return;
+        }
+
+        // At 115200 baud each byte is one time slot; the bus is wired-AND
+        let written = byte & 1;
+        let temperature = self.temperature_source();
+        let mut bus = written;
+        for device in &mut self.devices {
+            bus &= device.slot(written, &temperature);
+        }
+        self.pending.push_back(if bus == 1 { 0xFF } else { 0x00 });
+    }
+
+    // Plausible readings: a slow drift per sensor plus a little noise
+    fn temperature_source(&mut self) -> impl Fn(&Device) -> f32 {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        let noise = (self.noise % 200) as f32 / 1000.0 - 0.1;
+        let minutes = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f32() / 60.0).unwrap_or(0.0);
+        move |device: &Device| device.base + 1.5 * (minutes / 10.0 + device.base).sin() + noise
+    }
+}
+
+impl Device {
+    fn new(bus: u8, index: u8) -> Self {
+        let mut rom = [FAMILY_DS18B20, b'S', b'I', b'M', bus, index, 0x00, 0x00];
+        rom[7] = OneWireAdapter::calculate_crc8(&rom[..7]);
+        let mut scratchpad = [0u8; 9];
+        scratchpad[..8].copy_from_slice(&POWER_ON_SCRATCHPAD);
+        scratchpad[8] = OneWireAdapter::calculate_crc8(&scratchpad[..8]);
+        Device {
+            rom,
+            scratchpad,
+            eeprom: [scratchpad[2], scratchpad[3], scratchpad[4]],
+            alarm: false,
+            base: 18.0 + 1.5 * index as f32 + 4.0 * bus as f32,
+            phase: Phase::Idle,
+            command: 0,
+            command_bits: 0,
+        }
+    }
+
+    // Take part in one time slot; returns 0 to pull the bus low, 1 to leave it released
+    fn slot(&mut self, written: u8, temperature: &impl Fn(&Device) -> f32) -> u8 {
+        let (driven, next) = match &mut self.phase {
+            Phase::Idle | Phase::Ready => (1, None),
+            Phase::RomCommand | Phase::Function => {
+                self.command |= written << self.command_bits;
+                self.command_bits += 1;
+                if self.command_bits < 8 {
+                    (1, None)
+                } else {
+                    let command = std::mem::take(&mut self.command);
+                    self.command_bits = 0;
+                    let next = if matches!(self.phase, Phase::RomCommand) {
+                        self.rom_command(command)
+                    } else {
+                        self.function_command(command, temperature)
+                    };
+                    (1, Some(next))
+                }
+            }
+            Phase::Search { bit, step } => {
+                let rom_bit = (self.rom[*bit / 8] >> (*bit % 8)) & 1;
+                match step {
+                    0 => {
+                        *step = 1;
+                        (rom_bit, None)
+                    }
+                    1 => {
+                        *step = 2;
+                        (rom_bit ^ 1, None)
+                    }
+                    _ if written != rom_bit => (1, Some(Phase::Idle)),
+                    _ if *bit == 63 => (1, Some(Phase::Function)),
+                    _ => {
+                        *bit += 1;
+                        *step = 0;
+                        (1, None)
+                    }
+                }
+            }
+            Phase::Match { bit } => {
+                let rom_bit = (self.rom[*bit / 8] >> (*bit % 8)) & 1;
+                *bit += 1;
+                if written != rom_bit {
+                    (1, Some(Phase::Idle))
+                } else {
+                    (1, (*bit == 64).then_some(Phase::Function))
+                }
+            }
+            Phase::Transmit { data, bit } => {
+                let driven = data.get(*bit / 8).map_or(1, |byte| (byte >> (*bit % 8)) & 1);
+                *bit += 1;
+                (driven, None)
+            }
+            Phase::WriteScratchpad { data, bit } => {
+                data[*bit / 8] |= written << (*bit % 8);
+                *bit += 1;
+                if *bit < 24 {
+                    (1, None)
+                } else {
+                    // Only the resolution bits of the configuration register are writable
+                    let written = [data[0], data[1], (data[2] & 0x60) | 0x1F];
+                    self.scratchpad[2..5].copy_from_slice(&written);
+                    self.update_crc();
+                    (1, Some(Phase::Idle))
+                }
+            }
+        };
+        if let Some(next) = next {
+            self.phase = next;
+        }
+        driven
+    }
+
+    fn rom_command(&self, command: u8) -> Phase {
+        match command {
+            0xF0 => Phase::Search { bit: 0, step: 0 },
+            0xEC if self.alarm => Phase::Search { bit: 0, step: 0 },
+            0x55 => Phase::Match { bit: 0 },
+            0xCC => Phase::Function,
+            _ => Phase::Idle,
+        }
+    }
+
+    fn function_command(&mut self, command: u8, temperature: &impl Fn(&Device) -> f32) -> Phase {
+        match command {
+            // Convert T
+            0x44 => {
+                let resolution = ((self.scratchpad[4] >> 5) & 0x03) + 9;
+                let raw = (temperature(self) * 16.0).round() as i16 & !((1 << (12 - resolution)) - 1);
+                self.scratchpad[..2].copy_from_slice(&raw.to_le_bytes());
+                self.update_crc();
+                // Only the whole degrees are compared
+                let whole = (raw >> 4) as i8;
+                self.alarm = whole >= self.scratchpad[2] as i8 || whole <= self.scratchpad[3] as i8;
+                Phase::Ready
+            }
+            // Read Scratchpad
+            0xBE => Phase::Transmit { data: self.scratchpad.to_vec(), bit: 0 },
+            0x4E => Phase::WriteScratchpad { data: vec![0; 3], bit: 0 },
+            // Copy Scratchpad
+            0x48 => {
+                self.eeprom.copy_from_slice(&self.scratchpad[2..5]);
+                Phase::Ready
+            }
+            // Recall E2
+            0xB8 => {
+                self.scratchpad[2..5].copy_from_slice(&self.eeprom);
+                self.update_crc();
+                Phase::Ready
+            }
+            // Read Power Supply
+            0xB4 => Phase::Ready,
+            _ => Phase::Idle,
+        }
+    }
+
+    fn update_crc(&mut self) {
+        self.scratchpad[8] = OneWireAdapter::calculate_crc8(&self.scratchpad[..8]);
+    }
+}
+
+impl io::Read for SimulatedPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state();
+        if state.pending.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "no answer from the simulated bus"));
+        }
+        let count = buf.len().min(state.pending.len());
+        for (slot, byte) in buf.iter_mut().zip(state.pending.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+}
+
+impl io::Write for SimulatedPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state();
+        for &byte in buf {
+            state.write(byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for SimulatedPort {
+    fn name(&self) -> Option<String> {
+        Some(self.path.clone())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.state().baud)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(Parity::None)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(StopBits::One)
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.state().baud = baud_rate;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, _: DataBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, _: FlowControl) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_parity(&mut self, _: Parity) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, _: StopBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, _: Duration) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.state().pending.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, _: ClearBuffer) -> serialport::Result<()> {
+        self.state().pending.clear();
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,532 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use crate::alarm::AlarmEvent;
+use crate::api::ApiSink;
+use crate::config::{format_rom, Config, SensorConfig, SinkConfig, SinkEntry};
+#[cfg(feature = "chat")]
+use crate::chat::ChatSink;
+use crate::csv::CsvSink;
+#[cfg(feature = "dbus")]
+use crate::dbus::DbusSink;
+use crate::exec::ExecSink;
+use crate::graphite::GraphiteSink;
+#[cfg(feature = "kafka")]
+use crate::kafka::KafkaSink;
+use crate::history::HistoryStore;
+#[cfg(feature = "influx")]
+use crate::influx::InfluxSink;
+use crate::logfile::{FileRotation, LogFile};
+#[cfg(feature = "mqtt")]
+use crate::mqtt::MqttSink;
+use crate::nats::{NatsAuth, NatsSink};
+use crate::redis::{RedisServer, RedisSink};
+use crate::openmetrics::OpenMetricsSink;
+#[cfg(feature = "parquet")]
+use crate::parquet_sink::ParquetSink;
+#[cfg(feature = "postgres")]
+use crate::postgres_sink::PostgresSink;
+#[cfg(feature = "mysql")]
+use crate::mysql_sink::MysqlSink;
+use crate::prometheus::TextfileSink;
+#[cfg(feature = "remote_write")]
+use crate::remote_write::RemoteWriteSink;
+use crate::rrd::RrdSink;
+use crate::spool::Spool;
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteSink;
+#[cfg(feature = "templates")]
+use crate::template::TemplateSink;
+#[cfg(feature = "webhook")]
+use crate::webhook::{RetryPolicy, WebhookSink};
+use crate::zabbix::ZabbixSink;
+#[cfg(feature = "zeromq")]
+use crate::zeromq::ZeromqSink;
+use crate::{json, OneWireError, Reading};
+
+// Where a sink writes its lines: stdout, or a file appended to like the -l log
+pub enum Destination {
+    Stdout,
+    File(LogFile),
+}
+
+impl Destination {
+    // `-` or no path means stdout, which is never rotated
+    pub fn open(path: Option<&Path>, rotation: Option<FileRotation>) -> io::Result<Self> {
+        match (path.filter(|path| *path != Path::new("-")), rotation) {
+            (Some(path), Some(rotation)) => Ok(Destination::File(LogFile::open_rotating(path, rotation)?)),
+            (Some(path), None) => Ok(Destination::File(LogFile::open(path)?)),
+            (None, _) => Ok(Destination::Stdout),
+        }
+    }
+
+    pub fn write_line(&mut self, line: &str) {
+        match self {
+            Destination::Stdout => println!("{}", line),
+            Destination::File(file) => {
+                if let Err(e) = file.append(line) {
+                    eprintln!("Cannot write {}: {}", file.path().display(), e);
+                }
+            }
+        }
+    }
+}
+
+// Machine-readable outputs fed with every accepted reading, next to the stdout line
+pub enum Sink {
+    Jsonl(Destination),
+    Csv(CsvSink),
+    Prometheus(TextfileSink),
+    OpenMetrics(OpenMetricsSink),
+    Graphite(GraphiteSink),
+    #[cfg(feature = "influx")]
+    Influx(InfluxSink),
+    Zabbix(ZabbixSink),
+    #[cfg(feature = "remote_write")]
+    RemoteWrite(RemoteWriteSink),
+    Rrd(RrdSink),
+    History(HistoryStore),
+    #[cfg(feature = "templates")]
+    Template(TemplateSink),
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqliteSink),
+    #[cfg(feature = "postgres")]
+    Postgres(PostgresSink),
+    #[cfg(feature = "mysql")]
+    Mysql(MysqlSink),
+    #[cfg(feature = "parquet")]
+    Parquet(ParquetSink),
+    #[cfg(feature = "mqtt")]
+    Mqtt(MqttSink),
+    Nats(NatsSink),
+    #[cfg(feature = "kafka")]
+    Kafka(KafkaSink),
+    Redis(RedisSink),
+    #[cfg(feature = "zeromq")]
+    Zeromq(ZeromqSink),
+    #[cfg(feature = "webhook")]
+    Webhook(WebhookSink),
+    Exec(ExecSink),
+    #[cfg(feature = "chat")]
+    Chat(ChatSink),
+    #[cfg(feature = "dbus")]
+    Dbus(DbusSink),
+    // Last readings for the HTTP API of `serve`; not configured as a sink
+    Api(ApiSink),
+}
+
+impl Sink {
+    // A configured sink; none for stdout, which is the plain output
+    pub fn open(sink: &SinkConfig) -> Option<Result<Sink, String>> {
+        match sink {
+            SinkConfig::Stdout { .. } => None,
+            SinkConfig::Jsonl { path } => Some(Self::jsonl(path.as_deref())),
+            SinkConfig::Csv { path, columns, header, append, rotate, compress, keep } => {
+                let rotation = rotate.map(|every| FileRotation { every, compress: *compress, keep: *keep });
+                Some(CsvSink::open(path.as_deref(), columns.clone(), *header, *append, rotation).map(Sink::Csv))
+            }
+            SinkConfig::Prometheus { path } => Some(Ok(Sink::Prometheus(TextfileSink::new(path)))),
+            SinkConfig::OpenMetrics { path } => Some(Ok(Sink::OpenMetrics(OpenMetricsSink::new(path)))),
+            SinkConfig::Graphite { address, transport, prefix, spool, spool_max_mb } => {
+                Some(open_spool(spool.as_deref(), *spool_max_mb)
+                    .map(|spool| Sink::Graphite(GraphiteSink::new(address, *transport, prefix, spool))))
+            }
+            #[cfg(feature = "influx")]
+            SinkConfig::Influx { url, org, bucket, token, gzip, batch, spool, spool_max_mb } => {
+                Some(open_spool(spool.as_deref(), *spool_max_mb).and_then(|spool| {
+                    InfluxSink::new(url, org, bucket, token, *gzip, *batch, spool).map(Sink::Influx)
+                }))
+            }
+            #[cfg(not(feature = "influx"))]
+            SinkConfig::Influx { .. } => Some(Err("InfluxDB sinks need a build with the influx feature".to_string())),
+            #[cfg(feature = "remote_write")]
+            SinkConfig::RemoteWrite { url, username, password, bearer_token, batch, spool, spool_max_mb } => {
+                Some(open_spool(spool.as_deref(), *spool_max_mb).and_then(|spool| {
+                    RemoteWriteSink::new(url, username.as_deref(), password.as_deref(), bearer_token.as_deref(),
+                        *batch, spool).map(Sink::RemoteWrite)
+                }))
+            }
+            #[cfg(not(feature = "remote_write"))]
+            SinkConfig::RemoteWrite { .. } => {
+                Some(Err("remote_write sinks need a build with the remote_write feature".to_string()))
+            }
+            SinkConfig::Zabbix { server, host, key, spool, spool_max_mb } => {
+                Some(open_spool(spool.as_deref(), *spool_max_mb)
+                    .map(|spool| Sink::Zabbix(ZabbixSink::new(server, host, key, spool))))
+            }
+            SinkConfig::Rrd { path, rrdtool, .. } => Some(Ok(Sink::Rrd(RrdSink::new(path, rrdtool)))),
+            SinkConfig::History { directory, retention } => {
+                Some(HistoryStore::open(directory, *retention).map(Sink::History))
+            }
+            #[cfg(feature = "templates")]
+            SinkConfig::Template { path, reading, cycle } => {
+                Some(TemplateSink::open(path.as_deref(), reading.as_deref(), cycle.as_deref()).map(Sink::Template))
+            }
+            #[cfg(not(feature = "templates"))]
+            SinkConfig::Template { .. } => {
+                Some(Err("Template sinks need a build with the templates feature".to_string()))
+            }
+            #[cfg(feature = "sqlite")]
+            SinkConfig::Sqlite { path } => Some(SqliteSink::open(path).map(Sink::Sqlite)),
+            #[cfg(not(feature = "sqlite"))]
+            SinkConfig::Sqlite { .. } => Some(Err("SQLite sinks need a build with the sqlite feature".to_string())),
+            #[cfg(feature = "postgres")]
+            SinkConfig::Postgres { url, table, batch } => {
+                Some(PostgresSink::new(url, table, *batch).map(Sink::Postgres))
+            }
+            #[cfg(not(feature = "postgres"))]
+            SinkConfig::Postgres { .. } => {
+                Some(Err("PostgreSQL sinks need a build with the postgres feature".to_string()))
+            }
+            #[cfg(feature = "mysql")]
+            SinkConfig::Mysql { url, table, batch } => Some(MysqlSink::new(url, table, *batch).map(Sink::Mysql)),
+            #[cfg(not(feature = "mysql"))]
+            SinkConfig::Mysql { .. } => Some(Err("MySQL sinks need a build with the mysql feature".to_string())),
+            #[cfg(feature = "parquet")]
+            SinkConfig::Parquet { directory, rotate } => Some(ParquetSink::open(directory, *rotate).map(Sink::Parquet)),
+            #[cfg(not(feature = "parquet"))]
+            SinkConfig::Parquet { .. } => Some(Err("Parquet sinks need a build with the parquet feature".to_string())),
+            #[cfg(feature = "mqtt")]
+            SinkConfig::Mqtt(mqtt) => Some(MqttSink::new(mqtt).map(Sink::Mqtt)),
+            #[cfg(not(feature = "mqtt"))]
+            SinkConfig::Mqtt(_) => Some(Err("MQTT sinks need a build with the mqtt feature".to_string())),
+            SinkConfig::Nats { server, subject, token, username, password, spool, spool_max_mb } => {
+                let auth = NatsAuth { token: token.clone(), username: username.clone(), password: password.clone() };
+                Some(open_spool(spool.as_deref(), *spool_max_mb)
+                    .and_then(|spool| NatsSink::new(server, subject, auth, spool).map(Sink::Nats)))
+            }
+            #[cfg(feature = "kafka")]
+            SinkConfig::Kafka { brokers, topic, format, schema_id, timeout, properties } => {
+                Some(KafkaSink::new(brokers, topic, *format, *schema_id, *timeout, properties).map(Sink::Kafka))
+            }
+            #[cfg(not(feature = "kafka"))]
+            SinkConfig::Kafka { .. } => Some(Err("Kafka sinks need a build with the kafka feature".to_string())),
+            SinkConfig::Redis { server, key, channel, ttl, username, password, database } => {
+                let server = RedisServer {
+                    address: server.clone(),
+                    username: username.clone(),
+                    password: password.clone(),
+                    database: *database,
+                };
+                let ttl = (!ttl.is_zero()).then_some(*ttl);
+                Some(RedisSink::new(server, key, channel, ttl).map(Sink::Redis))
+            }
+            #[cfg(feature = "zeromq")]
+            SinkConfig::Zeromq { endpoint, connect, topic } => {
+                Some(ZeromqSink::open(endpoint, *connect, topic).map(Sink::Zeromq))
+            }
+            #[cfg(not(feature = "zeromq"))]
+            SinkConfig::Zeromq { .. } => Some(Err("ZeroMQ sinks need a build with the zeromq feature".to_string())),
+            #[cfg(feature = "webhook")]
+            SinkConfig::Webhook { urls, each, headers, retries, backoff, dead_letter } => {
+                let policy = RetryPolicy { retries: *retries, backoff: *backoff, dead_letter: dead_letter.clone() };
+                Some(WebhookSink::new(urls, *each, headers, policy).map(Sink::Webhook))
+            }
+            #[cfg(not(feature = "webhook"))]
+            SinkConfig::Webhook { .. } => {
+                Some(Err("Webhook sinks need a build with the webhook feature".to_string()))
+            }
+            SinkConfig::Exec { command, on, timeout } => Some(ExecSink::new(command, on, *timeout).map(Sink::Exec)),
+            #[cfg(feature = "chat")]
+            SinkConfig::Chat { service, url, token, chat_id } => {
+                Some(ChatSink::new(*service, url.as_deref(), token.as_deref(), chat_id.as_deref()).map(Sink::Chat))
+            }
+            #[cfg(not(feature = "chat"))]
+            SinkConfig::Chat { .. } => Some(Err("Chat sinks need a build with the chat feature".to_string())),
+            #[cfg(feature = "dbus")]
+            SinkConfig::Dbus { bus, name } => Some(DbusSink::open(*bus, name).map(Sink::Dbus)),
+            #[cfg(not(feature = "dbus"))]
+            SinkConfig::Dbus { .. } => Some(Err("D-Bus sinks need a build with the dbus feature".to_string())),
+        }
+    }
+
+    pub fn jsonl(path: Option<&Path>) -> Result<Sink, String> {
+        Destination::open(path, None).map(Sink::Jsonl)
+            .map_err(|e| format!("Cannot open jsonl sink {}: {}", path.unwrap_or(Path::new("-")).display(), e))
+    }
+
+    // Whether the sink takes over stdout, replacing the normal reading lines
+    pub fn uses_stdout(&self) -> bool {
+        match self {
+            Sink::Jsonl(destination) => matches!(destination, Destination::Stdout),
+            Sink::Csv(csv) => csv.uses_stdout(),
+            #[cfg(feature = "templates")]
+            Sink::Template(template) => template.uses_stdout(),
+            Sink::Prometheus(_) | Sink::OpenMetrics(_) | Sink::Graphite(_) | Sink::Zabbix(_) | Sink::Rrd(_)
+            | Sink::History(_) | Sink::Nats(_) | Sink::Redis(_) | Sink::Exec(_) | Sink::Api(_) => false,
+            #[cfg(feature = "influx")]
+            Sink::Influx(_) => false,
+            #[cfg(feature = "remote_write")]
+            Sink::RemoteWrite(_) => false,
+            #[cfg(feature = "sqlite")]
+            Sink::Sqlite(_) => false,
+            #[cfg(feature = "postgres")]
+            Sink::Postgres(_) => false,
+            #[cfg(feature = "mysql")]
+            Sink::Mysql(_) => false,
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(_) => false,
+            #[cfg(feature = "mqtt")]
+            Sink::Mqtt(_) => false,
+            #[cfg(feature = "kafka")]
+            Sink::Kafka(_) => false,
+            #[cfg(feature = "zeromq")]
+            Sink::Zeromq(_) => false,
+            #[cfg(feature = "webhook")]
+            Sink::Webhook(_) => false,
+            #[cfg(feature = "chat")]
+            Sink::Chat(_) => false,
+            #[cfg(feature = "dbus")]
+            Sink::Dbus(_) => false,
+        }
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading,
+                   decimals: Option<usize>) {
+        match self {
+            Sink::Jsonl(destination) => destination.write_line(&json::jsonl_reading(bus, index, sensor, reading,
+                decimals)),
+            Sink::Csv(csv) => csv.reading(bus, index, sensor, reading, decimals),
+            Sink::Prometheus(textfile) => textfile.reading(bus, index, sensor, reading.celsius),
+            Sink::OpenMetrics(openmetrics) => openmetrics.reading(bus, index, sensor, reading),
+            Sink::Graphite(graphite) => graphite.reading(sensor, reading.celsius),
+            #[cfg(feature = "influx")]
+            Sink::Influx(influx) => influx.reading(bus, sensor, reading),
+            Sink::Zabbix(zabbix) => zabbix.reading(bus, index, sensor, reading.celsius),
+            #[cfg(feature = "remote_write")]
+            Sink::RemoteWrite(remote_write) => remote_write.reading(bus, sensor, reading),
+            Sink::Rrd(rrd) => rrd.reading(bus, index, sensor, reading.celsius),
+            Sink::History(history) => history.reading(sensor, reading.celsius),
+            #[cfg(feature = "templates")]
+            Sink::Template(template) => template.reading(bus, index, sensor, reading, decimals),
+            #[cfg(feature = "sqlite")]
+            Sink::Sqlite(sqlite) => sqlite.reading(bus, sensor, reading),
+            #[cfg(feature = "postgres")]
+            Sink::Postgres(postgres) => postgres.reading(bus, sensor, reading),
+            #[cfg(feature = "mysql")]
+            Sink::Mysql(mysql) => mysql.reading(bus, sensor, reading),
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(parquet) => parquet.reading(bus, index, sensor, reading, decimals),
+            #[cfg(feature = "mqtt")]
+            Sink::Mqtt(mqtt) => mqtt.reading(bus, index, sensor, reading.celsius, decimals),
+            Sink::Nats(nats) => nats.reading(bus, index, sensor, reading, decimals),
+            #[cfg(feature = "kafka")]
+            Sink::Kafka(kafka) => kafka.reading(bus, index, sensor, reading, decimals),
+            Sink::Redis(redis) => redis.reading(bus, index, sensor, reading, decimals),
+            #[cfg(feature = "zeromq")]
+            Sink::Zeromq(zeromq) => zeromq.reading(bus, index, sensor, reading, decimals),
+            #[cfg(feature = "webhook")]
+            Sink::Webhook(webhook) => webhook.reading(bus, index, sensor, reading, decimals),
+            Sink::Exec(exec) => exec.reading(bus, index, sensor, reading, decimals),
+            // Alarms only
+            #[cfg(feature = "chat")]
+            Sink::Chat(_) => {}
+            #[cfg(feature = "dbus")]
+            Sink::Dbus(dbus) => dbus.reading(bus, index, sensor, reading),
+            Sink::Api(api) => api.reading(bus, index, sensor, reading),
+        }
+    }
+
+    // A failed reading, or a discarded one without error
+    pub fn error(&mut self, bus: &str, index: usize, sensor: &SensorConfig, error: Option<&OneWireError>) {
+        match self {
+            Sink::Prometheus(textfile) => textfile.error(bus, index, sensor),
+            Sink::OpenMetrics(openmetrics) => openmetrics.error(bus, index, sensor, error),
+            #[cfg(feature = "templates")]
+            Sink::Template(template) => template.error(bus, index, sensor),
+            Sink::Api(api) => api.error(bus, index, sensor, error),
+            #[cfg(feature = "dbus")]
+            Sink::Dbus(dbus) => dbus.error(bus, index, sensor, error),
+            _ => {}
+        }
+    }
+
+    // A software alarm raised, notified again or cleared, for sinks that pass alarms on
+    pub fn alarm(&mut self, index: usize, sensor: &SensorConfig, event: &AlarmEvent) {
+        match self {
+            Sink::Exec(exec) => exec.alarm(index, sensor, event),
+            #[cfg(feature = "chat")]
+            Sink::Chat(chat) => chat.alarm(sensor, event),
+            Sink::Api(api) => api.alarm(index, sensor, event),
+            _ => {}
+        }
+    }
+
+    // Describe the configured sensors to the receiver, for sinks that do: when the sinks are
+    // opened, and again after a scan
+    #[cfg_attr(not(any(feature = "mqtt", feature = "dbus")), allow(unused_variables))]
+    pub fn announce(&mut self, config: &Config) {
+        match self {
+            #[cfg(feature = "mqtt")]
+            Sink::Mqtt(mqtt) => mqtt.announce(config),
+            #[cfg(feature = "dbus")]
+            Sink::Dbus(dbus) => dbus.announce(config),
+            _ => {}
+        }
+    }
+
+    // Called after every read pass, for sinks that write a snapshot of all sensors
+    pub fn pass_done(&mut self) {
+        match self {
+            Sink::Prometheus(textfile) => textfile.pass_done(),
+            Sink::OpenMetrics(openmetrics) => openmetrics.pass_done(),
+            Sink::Graphite(graphite) => graphite.pass_done(),
+            #[cfg(feature = "influx")]
+            Sink::Influx(influx) => influx.pass_done(),
+            Sink::Zabbix(zabbix) => zabbix.pass_done(),
+            Sink::Nats(nats) => nats.pass_done(),
+            Sink::Redis(redis) => redis.pass_done(),
+            #[cfg(feature = "webhook")]
+            Sink::Webhook(webhook) => webhook.pass_done(),
+            #[cfg(feature = "remote_write")]
+            Sink::RemoteWrite(remote_write) => remote_write.pass_done(),
+            Sink::Rrd(rrd) => rrd.pass_done(),
+            Sink::History(history) => history.pass_done(),
+            #[cfg(feature = "templates")]
+            Sink::Template(template) => template.pass_done(),
+            #[cfg(feature = "sqlite")]
+            Sink::Sqlite(sqlite) => sqlite.pass_done(),
+            #[cfg(feature = "postgres")]
+            Sink::Postgres(postgres) => postgres.pass_done(),
+            #[cfg(feature = "mysql")]
+            Sink::Mysql(mysql) => mysql.pass_done(),
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(parquet) => parquet.pass_done(),
+            #[cfg(feature = "mqtt")]
+            Sink::Mqtt(_) => {}
+            #[cfg(feature = "kafka")]
+            Sink::Kafka(_) => {}
+            #[cfg(feature = "zeromq")]
+            Sink::Zeromq(_) => {}
+            #[cfg(feature = "chat")]
+            Sink::Chat(_) => {}
+            #[cfg(feature = "dbus")]
+            Sink::Dbus(_) => {}
+            Sink::Jsonl(_) | Sink::Csv(_) | Sink::Exec(_) | Sink::Api(_) => {}
+        }
+    }
+}
+
+// A stage of the output pipeline: a sink, fed the readings of the sensors and groups of its
+// [[sinks]] entry, at most one per sensor every `interval`, rounded to its own `decimals`.
+// Errors of those sensors are passed on as they come.
+pub struct Route {
+    sink: Sink,
+    roms: Vec<[u8; 8]>,
+    groups: Vec<String>,
+    interval: Option<Duration>,
+    decimals: Option<usize>,
+    escalation: bool,
+    // When each sensor's last reading was given to the sink, for `interval`
+    sent: HashMap<[u8; 8], Instant>,
+}
+
+impl Route {
+    // Every reading, for the sinks of the command line and the HTTP API
+    pub fn all(sink: Sink) -> Self {
+        Route {
+            sink,
+            roms: Vec::new(),
+            groups: Vec::new(),
+            interval: None,
+            decimals: None,
+            escalation: false,
+            sent: HashMap::new(),
+        }
+    }
+
+    // The [[sinks]] of the config that write somewhere other than the plain stdout output.
+    // Sensors are looked up once, so a reload or scan that renumbers them opens the sinks again.
+    pub fn open_configured(config: &Config) -> Result<Vec<Route>, String> {
+        let mut routes = Vec::new();
+        for (i, SinkEntry { route, sink }) in config.sinks.iter().enumerate() {
+            let Some(opened) = Sink::open(sink) else {
+                if *route != Default::default() {
+                    return Err(format!("sink {} (stdout): sensors, groups, interval and decimals are for the \
+                        other sinks; use -t and --decimals", i));
+                }
+                continue;
+            };
+            let roms = route.sensors.iter()
+                .map(|spec| config.resolve_sensor(spec).map(|index| config.sensors[index].rom))
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("sink {}: {}", i, e))?;
+            routes.push(Route {
+                sink: opened?,
+                roms,
+                groups: route.groups.clone(),
+                interval: route.interval,
+                decimals: route.decimals,
+                escalation: route.escalation,
+                sent: HashMap::new(),
+            });
+        }
+        Ok(routes)
+    }
+
+    pub fn uses_stdout(&self) -> bool {
+        self.sink.uses_stdout()
+    }
+
+    fn wants(&self, sensor: &SensorConfig) -> bool {
+        (self.roms.is_empty() && self.groups.is_empty())
+            || self.roms.contains(&sensor.rom)
+            || sensor.group.as_ref().is_some_and(|group| self.groups.contains(group))
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading,
+                   decimals: Option<usize>) {
+        if !self.wants(sensor) || self.escalation {
+            return;
+        }
+        if let (Some(interval), Some(last)) = (self.interval, self.sent.get(&sensor.rom)) {
+            if last.elapsed() < interval {
+                return;
+            }
+        }
+        self.sent.insert(sensor.rom, Instant::now());
+        self.sink.reading(bus, index, sensor, reading, self.decimals.or(decimals));
+    }
+
+    pub fn error(&mut self, bus: &str, index: usize, sensor: &SensorConfig, error: Option<&OneWireError>) {
+        if self.wants(sensor) && !self.escalation {
+            self.sink.error(bus, index, sensor, error);
+        }
+    }
+
+    pub fn alarm(&mut self, index: usize, sensor: &SensorConfig, event: &AlarmEvent) {
+        if self.wants(sensor) && (event.escalated || !self.escalation) {
+            self.sink.alarm(index, sensor, event);
+        }
+    }
+
+    // Sensors outside the route are announced as disabled, which withdraws them where they
+    // were announced before, and keeps the indices of the others
+    pub fn announce(&mut self, config: &Config) {
+        if self.roms.is_empty() && self.groups.is_empty() {
+            return self.sink.announce(config);
+        }
+        let mut routed = config.clone();
+        for sensor in &mut routed.sensors {
+            sensor.enabled &= self.wants(sensor);
+        }
+        self.sink.announce(&routed);
+    }
+
+    pub fn pass_done(&mut self) {
+        self.sink.pass_done();
+    }
+}
+
+fn open_spool(path: Option<&Path>, max_mb: u64) -> Result<Option<Spool>, String> {
+    path.map(|path| Spool::open(path, max_mb.saturating_mul(1 << 20))).transpose()
+}
+
+// Expand {label} (the label, else the ROM), {rom}, {index} and {bus} in a per-sensor template
+pub fn expand(template: &str, bus: &str, index: usize, sensor: &SensorConfig) -> String {
+    template.replace("{label}", &sensor.name())
+        .replace("{rom}", &format_rom(&sensor.rom))
+        .replace("{index}", &index.to_string())
+        .replace("{bus}", bus)
+}
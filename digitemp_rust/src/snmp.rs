@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::ops::Bound;
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use crate::config::{format_rom, Config};
+use crate::state::SensorStates;
+
+// Columns of the sensor table, base.1.1.<column>.<row>
+const COLUMN_INDEX: u32 = 1;
+const COLUMN_NAME: u32 = 2;
+const COLUMN_ROM: u32 = 3;
+const COLUMN_MILLICELSIUS: u32 = 4;
+const COLUMN_AGE: u32 = 5;
+const COLUMN_READS: u32 = 6;
+const COLUMN_ERRORS: u32 = 7;
+
+// An object's type, as snmpd's pass protocol names it, and its value
+type Value = (&'static str, String);
+
+pub fn parse_oid(text: &str) -> Option<Vec<u32>> {
+    let oid: Option<Vec<u32>> = text.trim().trim_start_matches('.').split('.').map(|arc| arc.parse().ok()).collect();
+    oid.filter(|oid| !oid.is_empty())
+}
+
+fn format_oid(oid: &[u32]) -> String {
+    oid.iter().map(|arc| format!(".{}", arc)).collect()
+}
+
+// snmpd pass_persist handler, started by snmpd with its commands on stdin:
+//   PING              answered PONG
+//   get / getnext     followed by an OID; answered with the OID, type and value, or NONE
+//   set               followed by an OID and value; refused as not-writable
+// Each enabled sensor is a row of a table under `base`, numbered from 1 in config order.
+// The values come from the sensor state file that read, monitor and serve keep, which is
+// read again for every request; this handler never touches the buses.
+pub fn pass_persist(config: &Config, state: &Path, base: &[u32]) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut out = io::stdout().lock();
+    while let Some(command) = lines.next().transpose()? {
+        let reply = match command.trim().to_ascii_lowercase().as_str() {
+            "" => return Ok(()),
+            "ping" => "PONG\n".to_string(),
+            command @ ("get" | "getnext") => {
+                let Some(requested) = lines.next().transpose()? else { return Ok(()) };
+                let objects = objects(config, &SensorStates::load(state), base);
+                let found = match (command, parse_oid(&requested)) {
+                    ("get", Some(oid)) => objects.get_key_value(&oid),
+                    (_, Some(oid)) => objects.range((Bound::Excluded(oid), Bound::Unbounded)).next(),
+                    (_, None) => None,
+                };
+                match found {
+                    Some((oid, (kind, value))) => format!("{}\n{}\n{}\n", format_oid(oid), kind, value),
+                    None => "NONE\n".to_string(),
+                }
+            }
+            "set" => {
+                // The OID, then the type and value
+                lines.next().transpose()?;
+                lines.next().transpose()?;
+                "not-writable\n".to_string()
+            }
+            _ => "NONE\n".to_string(),
+        };
+        out.write_all(reply.as_bytes())?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+// Every object of the table, in OID order
+fn objects(config: &Config, states: &SensorStates, base: &[u32]) -> BTreeMap<Vec<u32>, Value> {
+    let mut objects = BTreeMap::new();
+    let now = Utc::now();
+    for (index, sensor) in config.sensors.iter().enumerate().filter(|(_, sensor)| sensor.enabled) {
+        let row = index as u32 + 1;
+        let mut add = |column: u32, value: Value| {
+            objects.insert([base, &[1, 1, column, row]].concat(), value);
+        };
+        add(COLUMN_INDEX, ("integer", row.to_string()));
+        // One value per line, so a name must not break it
+        add(COLUMN_NAME, ("string", sensor.name().replace(['\r', '\n'], " ")));
+        add(COLUMN_ROM, ("string", format_rom(&sensor.rom)));
+        let Some(state) = states.get(sensor) else { continue };
+        if let Some(celsius) = state.celsius {
+            add(COLUMN_MILLICELSIUS, ("integer", ((celsius as f64 * 1000.0).round() as i64).to_string()));
+        }
+        let last_seen = state.last_seen.as_deref().and_then(|time| DateTime::parse_from_rfc3339(time).ok());
+        if let Some(last_seen) = last_seen {
+            let age = (now - last_seen.with_timezone(&Utc)).num_seconds().max(0);
+            add(COLUMN_AGE, ("gauge", age.min(u32::MAX as i64).to_string()));
+        }
+        // Counter32 wraps, as SNMP managers expect
+        add(COLUMN_READS, ("counter", (state.reads as u32).to_string()));
+        add(COLUMN_ERRORS, ("counter", (state.errors as u32).to_string()));
+    }
+    objects
+}
@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::prometheus::replace_file;
+
+// Records sent per request while replaying, so a long backlog does not go out as one huge
+// request (or a datagram too large for UDP)
+const REPLAY_CHUNK: usize = 500;
+
+// Store-and-forward buffer of a network sink: records that could not be delivered are
+// appended to a file, one per line, and sent in order before newer ones once the server is
+// reachable again. Beyond `max_bytes` the oldest records are dropped.
+pub struct Spool {
+    path: PathBuf,
+    max_bytes: u64,
+    records: VecDeque<String>,
+    bytes: u64,
+    // Report dropped records once per outage, not on every pass
+    full: bool,
+}
+
+impl Spool {
+    // Records left by an earlier run are replayed with the first pass
+    pub fn open(path: &Path, max_bytes: u64) -> Result<Self, String> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Cannot create spool directory {}: {}", parent.display(), e))?;
+        }
+        let records: VecDeque<String> = match std::fs::read_to_string(path) {
+            Ok(content) => content.lines().filter(|line| !line.is_empty()).map(str::to_string).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => return Err(format!("Cannot read spool {}: {}", path.display(), e)),
+        };
+        let bytes = records.iter().map(|record| record.len() as u64 + 1).sum();
+        let mut spool = Spool { path: path.to_path_buf(), max_bytes, records, bytes, full: false };
+        if spool.trim() {
+            spool.save();
+        }
+        Ok(spool)
+    }
+
+    // Send `records` after whatever is spooled. Undelivered records stay in the spool, and
+    // the first error is returned.
+    pub fn deliver<F>(&mut self, records: Vec<String>, mut send: F) -> io::Result<()>
+    where
+        F: FnMut(&[String]) -> io::Result<()>,
+    {
+        if self.records.is_empty() {
+            if let Err(e) = send(&records) {
+                self.push(records);
+                return Err(e);
+            }
+            return Ok(());
+        }
+
+        self.push(records);
+        let mut result = Ok(());
+        while !self.records.is_empty() {
+            let count = self.records.len().min(REPLAY_CHUNK);
+            let chunk: Vec<String> = self.records.range(..count).cloned().collect();
+            if let Err(e) = send(&chunk) {
+                result = Err(e);
+                break;
+            }
+            self.bytes -= chunk.iter().map(|record| record.len() as u64 + 1).sum::<u64>();
+            self.records.drain(..count);
+        }
+        if self.records.is_empty() {
+            self.full = false;
+        }
+        self.save();
+        result
+    }
+
+    fn push(&mut self, records: Vec<String>) {
+        let mut lines = String::new();
+        for record in records {
+            lines.push_str(&record);
+            lines.push('\n');
+            self.bytes += record.len() as u64 + 1;
+            self.records.push_back(record);
+        }
+        if self.trim() {
+            self.save();
+            return;
+        }
+        let appended = OpenOptions::new().create(true).append(true).open(&self.path)
+            .and_then(|mut file| file.write_all(lines.as_bytes()));
+        if let Err(e) = appended {
+            eprintln!("Cannot write spool {}: {} (readings are kept in memory)", self.path.display(), e);
+        }
+    }
+
+    // Drop the oldest records beyond the size limit; true when any were dropped
+    fn trim(&mut self) -> bool {
+        let mut dropped = 0;
+        while self.bytes > self.max_bytes {
+            let Some(record) = self.records.pop_front() else { break };
+            self.bytes -= record.len() as u64 + 1;
+            dropped += 1;
+        }
+        if dropped > 0 && !self.full {
+            eprintln!("Spool {} is full ({} bytes); the oldest readings are dropped", self.path.display(),
+                self.max_bytes);
+            self.full = true;
+        }
+        dropped > 0
+    }
+
+    fn save(&self) {
+        let content: String = self.records.iter().map(|record| format!("{}\n", record)).collect();
+        if let Err(e) = replace_file(&self.path, &content) {
+            eprintln!("Cannot write spool {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+// How undelivered readings are handled, for a sink's first error message
+pub fn kept(spool: Option<&Spool>) -> String {
+    match spool {
+        Some(spool) => format!("readings are spooled to {} and replayed", spool.path.display()),
+        None => "readings are dropped until it is reachable".to_string(),
+    }
+}
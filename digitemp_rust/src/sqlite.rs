@@ -0,0 +1,146 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{Local, Offset};
+use rusqlite::{params, params_from_iter, Connection, OpenFlags};
+use crate::config::{format_rom, SensorConfig};
+use crate::history::Summary;
+use crate::{json, Reading};
+
+// Waiting for a lock, e.g. while `history` reads during a write
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS readings (
+        rom TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        bus TEXT NOT NULL,
+        label TEXT,
+        celsius REAL NOT NULL,
+        raw_celsius REAL NOT NULL,
+        PRIMARY KEY (rom, timestamp)
+    ) WITHOUT ROWID;
+";
+
+struct Row {
+    rom: String,
+    timestamp: i64,
+    bus: String,
+    label: Option<String>,
+    celsius: f64,
+    raw_celsius: f64,
+}
+
+// Readings table keyed by ROM and Unix time, written in one transaction per pass
+pub struct SqliteSink {
+    path: PathBuf,
+    connection: Connection,
+    rows: Vec<Row>,
+    // Report a failing database once, not on every pass
+    failing: bool,
+}
+
+impl SqliteSink {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let connection = Connection::open(path)
+            .and_then(|connection| {
+                // WAL lets `history` read while a monitor keeps writing
+                connection.pragma_update(None, "journal_mode", "WAL")?;
+                connection.busy_timeout(BUSY_TIMEOUT)?;
+                connection.execute_batch(SCHEMA)?;
+                Ok(connection)
+            })
+            .map_err(|e| format!("Cannot open SQLite database {}: {}", path.display(), e))?;
+        Ok(SqliteSink { path: path.to_path_buf(), connection, rows: Vec::new(), failing: false })
+    }
+
+    pub fn reading(&mut self, bus: &str, sensor: &SensorConfig, reading: &Reading) {
+        self.rows.push(Row {
+            rom: format_rom(&sensor.rom),
+            timestamp: chrono::Utc::now().timestamp(),
+            bus: bus.to_string(),
+            label: sensor.label.clone(),
+            // Four decimals keep the sensor's 1/16 °C steps without float noise
+            celsius: json::round(reading.celsius, Some(4)),
+            raw_celsius: json::round(reading.raw_celsius, Some(4)),
+        });
+    }
+
+    // A failed write keeps the rows for the next pass
+    pub fn pass_done(&mut self) {
+        if self.rows.is_empty() {
+            return;
+        }
+        match self.insert() {
+            Ok(()) => {
+                self.rows.clear();
+                if self.failing {
+                    eprintln!("SQLite {}: writing again", self.path.display());
+                    self.failing = false;
+                }
+            }
+            Err(e) => {
+                if !self.failing {
+                    eprintln!("Cannot write {}: {} (readings are kept and retried)", self.path.display(), e);
+                }
+                self.failing = true;
+            }
+        }
+    }
+
+    fn insert(&mut self) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            // A second reading within the same second replaces the first
+            let mut insert = transaction.prepare_cached(
+                "INSERT OR REPLACE INTO readings (rom, timestamp, bus, label, celsius, raw_celsius)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+            for row in &self.rows {
+                insert.execute(params![row.rom, row.timestamp, row.bus, row.label, row.celsius, row.raw_celsius])?;
+            }
+        }
+        transaction.commit()
+    }
+}
+
+// Min/max/avg of the readings in [since, until) per sensor, and per period of `every` seconds
+// (aligned to local midnight) when given. An empty `roms` means every sensor in the database.
+pub fn summarize(path: &Path, roms: &[String], since: i64, until: i64, every: Option<i64>)
+                 -> Result<Vec<Summary>, String> {
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Cannot open SQLite database {}: {}", path.display(), e))?;
+    connection.busy_timeout(BUSY_TIMEOUT).map_err(|e| e.to_string())?;
+
+    let offset = i64::from(Local::now().offset().fix().local_minus_utc());
+    let period = match every {
+        Some(every) => format!("((timestamp + {offset}) / {every}) * {every} - {offset}"),
+        None => since.to_string(),
+    };
+    let filter = if roms.is_empty() {
+        String::new()
+    } else {
+        format!(" AND rom IN ({})", vec!["?"; roms.len()].join(", "))
+    };
+    let sql = format!(
+        "SELECT rom, {} AS period, count(*), min(celsius), max(celsius), round(avg(celsius), 4) FROM readings
+         WHERE timestamp >= {} AND timestamp < {}{} GROUP BY rom, period ORDER BY rom, period",
+        period, since, until, filter);
+
+    let query = || -> rusqlite::Result<Vec<Summary>> {
+        let mut statement = connection.prepare(&sql)?;
+        let rows = statement.query_map(params_from_iter(roms), |row| {
+            let from: i64 = row.get(1)?;
+            Ok(Summary {
+                rom: row.get(0)?,
+                // The first and last period are cut to the range
+                from: from.max(since),
+                to: every.map_or(until, |every| (from + every).min(until)),
+                count: row.get::<_, i64>(2)? as u64,
+                min: row.get(3)?,
+                max: row.get(4)?,
+                avg: row.get(5)?,
+            })
+        })?;
+        rows.collect()
+    };
+    query().map_err(|e| format!("Cannot query {}: {}", path.display(), e))
+}
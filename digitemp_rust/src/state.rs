@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use crate::alarm::{AlarmEvent, AlarmKind, AlarmState};
+use crate::config::{format_duration, format_rom, SensorConfig};
+use crate::prometheus::replace_file;
+use crate::{stats, OneWireError, Reading, MAX_CRC_RETRIES};
+
+// Last good reading and error counters of one sensor. Times are RFC 3339.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SensorState {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    // Last accepted reading (°C) and when it was taken
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub celsius: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<String>,
+    // When the sensor was first read, which counts as last seen until it answers once
+    pub since: String,
+    pub reads: u64,
+    pub errors: u64,
+    pub consecutive_errors: u64,
+    pub crc_errors: u64,
+    // Reported missing and not back yet, so a restart does not report it again
+    pub missing: bool,
+    // Software alarms raised and not cleared yet, which a restart carries on with
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alarms: Vec<RaisedAlarm>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RaisedAlarm {
+    pub kind: AlarmKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f32>,
+    // When it was raised
+    pub since: String,
+    // Notified again this many times since
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub repeats: u32,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub escalated: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub acknowledged: bool,
+}
+
+impl RaisedAlarm {
+    // None when the time cannot be read
+    pub fn since(&self) -> Option<DateTime<Local>> {
+        DateTime::parse_from_rfc3339(&self.since).ok().map(|since| since.with_timezone(&Local))
+    }
+
+    pub fn age(&self) -> Option<Duration> {
+        self.since().map(age)
+    }
+}
+
+impl SensorState {
+    // Time of the last good reading, or of the first attempt when there was none
+    fn last_seen(&self) -> Option<DateTime<Local>> {
+        let time = self.last_seen.as_deref().unwrap_or(&self.since);
+        DateTime::parse_from_rfc3339(time).ok().map(|time| time.with_timezone(&Local))
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StateContent {
+    sensors: BTreeMap<String, SensorState>,
+}
+
+// Per-sensor state kept across runs, keyed by ROM and rewritten after every pass. On
+// startup it provides each sensor's last known value, and the last-seen times let a
+// sensor that stopped answering before a restart be reported missing in time.
+pub struct SensorStates {
+    path: PathBuf,
+    content: StateContent,
+    // Report a failing write once, not on every pass
+    failing: bool,
+}
+
+impl SensorStates {
+    // The stored state, or none when the file does not exist yet or cannot be read
+    pub fn load(path: &Path) -> Self {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                eprintln!("Ignoring sensor state {}: {}", path.display(), e);
+                StateContent::default()
+            }),
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    eprintln!("Ignoring sensor state {}: {}", path.display(), e);
+                }
+                StateContent::default()
+            }
+        };
+        SensorStates { path: path.to_path_buf(), content, failing: false }
+    }
+
+    pub fn get(&self, sensor: &SensorConfig) -> Option<&SensorState> {
+        self.content.sensors.get(&format_rom(&sensor.rom))
+    }
+
+    fn entry(&mut self, sensor: &SensorConfig) -> &mut SensorState {
+        let state = self.content.sensors.entry(format_rom(&sensor.rom)).or_insert_with(|| SensorState {
+            since: now(),
+            ..SensorState::default()
+        });
+        state.label.clone_from(&sensor.label);
+        state
+    }
+
+    // Record an accepted reading, reporting a sensor that had gone missing as back
+    pub fn reading(&mut self, sensor: &SensorConfig, reading: &Reading) {
+        let state = self.entry(sensor);
+        if state.missing {
+            if let Some(last_seen) = state.last_seen() {
+                // Whole minutes are precise enough, and read better than e.g. 3725s
+                let gone = age(last_seen).as_secs();
+                let gone = Duration::from_secs(if gone >= 60 { gone / 60 * 60 } else { gone });
+                eprintln!("Sensor {} is back after {}", sensor.name(), format_duration(gone));
+            }
+        }
+        state.celsius = Some(reading.celsius);
+        state.last_seen = Some(now());
+        state.reads += 1;
+        state.consecutive_errors = 0;
+        state.crc_errors += u64::from(reading.crc_failures);
+        state.missing = false;
+    }
+
+    // A failed read, or a discarded reading when `error` is None
+    pub fn error(&mut self, sensor: &SensorConfig, error: Option<&OneWireError>) {
+        let state = self.entry(sensor);
+        state.reads += 1;
+        state.errors += 1;
+        state.consecutive_errors += 1;
+        if let Some(OneWireError::CrcMismatch) = error {
+            state.crc_errors += u64::from(MAX_CRC_RETRIES) + 1;
+        }
+    }
+
+    // Note a software alarm raised, notified again or cleared
+    pub fn alarm(&mut self, sensor: &SensorConfig, event: &AlarmEvent) {
+        let state = self.entry(sensor);
+        if let AlarmState::Raised | AlarmState::Cleared = event.state {
+            state.alarms.retain(|alarm| alarm.kind != event.kind);
+        }
+        let raised = state.alarms.iter_mut().find(|alarm| alarm.kind == event.kind);
+        match (event.state, raised) {
+            (AlarmState::Raised, _) => state.alarms.push(RaisedAlarm {
+                kind: event.kind,
+                threshold: event.threshold,
+                since: now(),
+                repeats: 0,
+                escalated: false,
+                acknowledged: false,
+            }),
+            (AlarmState::Repeated(repeats), Some(raised)) => raised.repeats = repeats,
+            (AlarmState::Escalated, Some(raised)) => raised.escalated = true,
+            _ => {}
+        }
+    }
+
+    // Note a sensor's raised alarms acknowledged
+    pub fn acknowledge(&mut self, sensor: &SensorConfig) {
+        for alarm in &mut self.entry(sensor).alarms {
+            alarm.acknowledged = true;
+        }
+    }
+
+    // When the sensor was last seen, if it has just gone longer than `after`, plus `gap`
+    // between its scheduled reads, without a good reading; a sensor is reported once until it
+    // is back
+    pub fn check_missing(&mut self, sensor: &SensorConfig, after: Duration, gap: Duration)
+                         -> Option<DateTime<Local>> {
+        let state = self.content.sensors.get_mut(&format_rom(&sensor.rom))?;
+        if state.missing {
+            return None;
+        }
+        // A sensor read less often than the threshold gets one interval more
+        let last_seen = state.last_seen()?;
+        if age(last_seen) <= after + gap {
+            return None;
+        }
+        state.missing = true;
+        Some(last_seen)
+    }
+
+    pub fn save(&mut self) {
+        let json = serde_json::to_string_pretty(&self.content).expect("sensor state always serializes");
+        let written = self.path.parent().filter(|dir| !dir.as_os_str().is_empty())
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| replace_file(&self.path, &(json + "\n")));
+        match written {
+            Ok(()) if self.failing => {
+                eprintln!("Sensor state {}: writing again", self.path.display());
+                self.failing = false;
+            }
+            Ok(()) => {}
+            Err(e) => {
+                if !self.failing {
+                    eprintln!("Cannot write sensor state {}: {}", self.path.display(), e);
+                }
+                self.failing = true;
+            }
+        }
+    }
+}
+
+fn now() -> String {
+    Local::now().to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+// Time since `time`, whole seconds
+fn age(time: DateTime<Local>) -> Duration {
+    Duration::from_secs((Local::now() - time).num_seconds().max(0) as u64)
+}
+
+// Default location: next to the bus statistics, $XDG_STATE_HOME/digitemp_rust/sensors.json
+pub fn default_path() -> Option<PathBuf> {
+    stats::default_path().map(|path| path.with_file_name("sensors.json"))
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::config;
+use crate::{OneWireError, Reading, MAX_CRC_RETRIES};
+
+// Bus health counters of one adapter, for deciding whether wiring needs attention
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BusStats {
+    pub resets: u64,
+    // Resets no device answered with a presence pulse
+    pub presence_failures: u64,
+    pub crc_errors: u64,
+    pub retries: u64,
+    pub reads: u64,
+    pub failed_reads: u64,
+    // Sum over all reads, for the average latency
+    pub read_time_ms: u64,
+}
+
+impl BusStats {
+    pub fn record_reset(&mut self, presence: bool) {
+        self.resets += 1;
+        if !presence {
+            self.presence_failures += 1;
+        }
+    }
+
+    pub fn record_read(&mut self, elapsed: Duration, result: &Result<Reading, OneWireError>) {
+        self.reads += 1;
+        self.read_time_ms += elapsed.as_millis() as u64;
+        match result {
+            Ok(reading) => {
+                self.crc_errors += reading.crc_failures as u64;
+                self.retries += reading.retries as u64;
+            }
+            Err(OneWireError::CrcMismatch) => {
+                self.crc_errors += MAX_CRC_RETRIES as u64 + 1;
+                self.retries += MAX_CRC_RETRIES as u64;
+                self.failed_reads += 1;
+            }
+            Err(_) => self.failed_reads += 1,
+        }
+    }
+
+    pub fn add(&mut self, other: &BusStats) {
+        self.resets += other.resets;
+        self.presence_failures += other.presence_failures;
+        self.crc_errors += other.crc_errors;
+        self.retries += other.retries;
+        self.reads += other.reads;
+        self.failed_reads += other.failed_reads;
+        self.read_time_ms += other.read_time_ms;
+    }
+
+    pub fn average_latency_ms(&self) -> Option<f64> {
+        (self.reads > 0).then(|| self.read_time_ms as f64 / self.reads as f64)
+    }
+}
+
+// Per-bus counters accumulated across runs, keyed by adapter name
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StatsFile {
+    // When counting started (RFC 3339)
+    pub since: String,
+    pub buses: BTreeMap<String, BusEntry>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BusEntry {
+    pub tty: String,
+    #[serde(flatten)]
+    pub stats: BusStats,
+}
+
+impl StatsFile {
+    // The stored counters, or an empty set when the file does not exist yet
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(StatsFile {
+                since: chrono::Local::now().to_rfc3339(),
+                buses: BTreeMap::new(),
+            }),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let content = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        std::fs::write(path, content + "\n")
+    }
+
+    pub fn add(&mut self, name: &str, tty: &str, stats: &BusStats) {
+        let entry = self.buses.entry(name.to_string()).or_default();
+        entry.tty = tty.to_string();
+        entry.stats.add(stats);
+    }
+}
+
+// Fold one session's counters into the state file; a failure only costs the statistics
+pub fn accumulate(path: &Path, session: &[(&str, &str, &BusStats)]) {
+    if session.iter().all(|(_, _, stats)| stats.resets == 0) {
+        return;
+    }
+    let result = StatsFile::load(path).and_then(|mut file| {
+        for (name, tty, stats) in session {
+            file.add(name, tty, stats);
+        }
+        file.save(path).map_err(|e| format!("{}: {}", path.display(), e))
+    });
+    if let Err(e) = result {
+        eprintln!("Cannot update bus statistics: {}", e);
+    }
+}
+
+// Default location: $XDG_STATE_HOME (or ~/.local/state)/digitemp_rust/bus-stats.json
+pub fn default_path() -> Option<std::path::PathBuf> {
+    std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(std::path::PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| std::path::PathBuf::from(home).join(".local/state")))
+        .map(|dir| dir.join(config::APP_DIR).join("bus-stats.json"))
+}
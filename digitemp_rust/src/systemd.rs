@@ -0,0 +1,209 @@
+use std::net::{TcpListener, UdpSocket};
+use std::time::Duration;
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+#[cfg(unix)]
+use std::sync::Mutex;
+
+// Notifications to the service manager of a Type=notify unit, as sd_notify(3) sends them:
+// newline-separated assignments in one datagram to the socket named in NOTIFY_SOCKET.
+// Outside systemd there is no such socket and nothing is sent.
+pub struct Notifier {
+    #[cfg(unix)]
+    socket: UnixDatagram,
+    #[cfg(unix)]
+    address: SocketAddr,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    #[cfg(unix)]
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os("NOTIFY_SOCKET")?;
+        let address = match path.to_str().and_then(|path| path.strip_prefix('@')) {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                SocketAddr::from_abstract_name(name)
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => return None,
+            None => SocketAddr::from_pathname(&path),
+        };
+        let address = address.map_err(|e| eprintln!("Ignoring NOTIFY_SOCKET {:?}: {}", path, e)).ok()?;
+        let socket = UnixDatagram::unbound().map_err(|e| eprintln!("Cannot notify systemd: {}", e)).ok()?;
+        Some(Notifier { socket, address, watchdog: watchdog() })
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_env() -> Option<Self> {
+        None
+    }
+
+    // WatchdogSec of the unit, when it applies to this process: a keep-alive is due more often
+    pub fn watchdog(&self) -> Option<Duration> {
+        self.watchdog
+    }
+
+    // Startup is over, or a reload is
+    pub fn ready(&self, status: &str) {
+        self.send(&format!("READY=1\nSTATUS={}", status));
+    }
+
+    pub fn status(&self, status: &str) {
+        self.send(&format!("STATUS={}", status));
+    }
+
+    // Proof of life; only sent after a pass that read something, so a daemon stuck on a dead
+    // bus is restarted by systemd as one that hangs is
+    pub fn alive(&self, status: &str) {
+        self.send(&format!("WATCHDOG=1\nSTATUS={}", status));
+    }
+
+    // systemd 253 and later want the monotonic time with RELOADING=1
+    #[cfg(unix)]
+    pub fn reloading(&self) {
+        let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+        #[allow(clippy::unnecessary_cast)] // time_t and c_long are 32 bits on some targets
+        let usec = now.tv_sec as i64 * 1_000_000 + now.tv_nsec as i64 / 1_000;
+        self.send(&format!("RELOADING=1\nMONOTONIC_USEC={}\nSTATUS=Reloading the configuration", usec));
+    }
+
+    #[cfg(not(unix))]
+    pub fn reloading(&self) {}
+
+    pub fn stopping(&self) {
+        self.send("STOPPING=1\nSTATUS=Stopping");
+    }
+
+    #[cfg(unix)]
+    fn send(&self, state: &str) {
+        // A lost notification is retried with the next pass; a missed READY=1 fails the unit anyway
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.address) {
+            eprintln!("Cannot notify systemd: {}", e);
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn send(&self, _: &str) {}
+}
+
+// WATCHDOG_USEC, unless WATCHDOG_PID names another process, e.g. the parent of a -D daemon
+#[cfg(unix)]
+fn watchdog() -> Option<Duration> {
+    let pid = std::env::var("WATCHDOG_PID").ok();
+    if pid.is_some_and(|pid| pid.parse::<u32>().ok() != Some(std::process::id())) {
+        return None;
+    }
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+// Listening sockets passed by socket activation, as sd_listen_fds(3) receives them: LISTEN_FDS
+// descriptors from 3 on, named in LISTEN_FDNAMES. A server given the address `systemd` takes
+// the one named after it (FileDescriptorName=http), or the only one; `systemd:NAME` takes the
+// one named NAME. Each is taken once.
+#[cfg(unix)]
+static LISTEN_FDS: Mutex<Option<Vec<ListenFd>>> = Mutex::new(None);
+
+// A passed socket by name, None once taken
+#[cfg(unix)]
+type ListenFd = (String, Option<OwnedFd>);
+
+// The socket systemd passed for `address`, or None for an address to bind
+#[cfg(unix)]
+pub fn activated_tcp(address: &str, server: &str) -> Option<Result<TcpListener, String>> {
+    let fd = match activated(address, server, libc::SOCK_STREAM)? {
+        Ok(fd) => fd,
+        Err(e) => return Some(Err(e)),
+    };
+    let listener = TcpListener::from(fd);
+    // A ListenStream= path is a Unix socket, which std cannot tell a TCP address for
+    Some(listener.local_addr().map(|_| listener)
+        .map_err(|_| format!("--{} {}: not a TCP socket", server, address)))
+}
+
+#[cfg(unix)]
+pub fn activated_udp(address: &str, server: &str) -> Option<Result<UdpSocket, String>> {
+    let fd = match activated(address, server, libc::SOCK_DGRAM)? {
+        Ok(fd) => fd,
+        Err(e) => return Some(Err(e)),
+    };
+    let socket = UdpSocket::from(fd);
+    Some(socket.local_addr().map(|_| socket)
+        .map_err(|_| format!("--{} {}: not a UDP socket", server, address)))
+}
+
+#[cfg(not(unix))]
+pub fn activated_tcp(address: &str, server: &str) -> Option<Result<TcpListener, String>> {
+    is_activated(address).then(|| Err(format!("Socket activation of {} needs systemd", server)))
+}
+
+#[cfg(not(unix))]
+pub fn activated_udp(address: &str, server: &str) -> Option<Result<UdpSocket, String>> {
+    is_activated(address).then(|| Err(format!("Socket activation of {} needs systemd", server)))
+}
+
+fn is_activated(address: &str) -> bool {
+    address == "systemd" || address.starts_with("systemd:")
+}
+
+#[cfg(unix)]
+fn activated(address: &str, server: &str, kind: libc::c_int) -> Option<Result<OwnedFd, String>> {
+    if !is_activated(address) {
+        return None;
+    }
+    let mut fds = LISTEN_FDS.lock().unwrap_or_else(|e| e.into_inner());
+    let fds = fds.get_or_insert_with(listen_fds);
+    let name = address.strip_prefix("systemd:");
+    let names = || fds.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", ");
+    let position = match name {
+        Some(name) => fds.iter().position(|(fd_name, _)| fd_name == name),
+        None => fds.iter().position(|(fd_name, _)| fd_name == server).or((fds.len() == 1).then_some(0)),
+    };
+    let Some(position) = position else {
+        return Some(Err(match (fds.is_empty(), name) {
+            (true, _) => format!("--{} {}: systemd passed no sockets to this process", server, address),
+            (false, Some(name)) => format!("--{} {}: no socket named {} among {}", server, address, name, names()),
+            (false, None) => format!("--{} systemd: no socket named {} among {}", server, server, names()),
+        }));
+    };
+    let (fd_name, fd) = &mut fds[position];
+    let Some(fd) = fd.take() else {
+        return Some(Err(format!("--{} {}: socket {} is already in use", server, address, fd_name)));
+    };
+    let mut actual: libc::c_int = 0;
+    let mut length = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let found = unsafe {
+        libc::getsockopt(fd.as_raw_fd(), libc::SOL_SOCKET, libc::SO_TYPE, (&mut actual as *mut libc::c_int).cast(),
+            &mut length)
+    };
+    if found != 0 || actual != kind {
+        let wanted = if kind == libc::SOCK_STREAM { "ListenStream=" } else { "ListenDatagram=" };
+        return Some(Err(format!("--{} {}: socket {} is not a {} socket", server, address, fd_name, wanted)));
+    }
+    Some(Ok(fd))
+}
+
+// The descriptors passed to this process. After a fork, as with -D, LISTEN_PID names the
+// parent and there are none.
+#[cfg(unix)]
+fn listen_fds() -> Vec<ListenFd> {
+    const FIRST: RawFd = 3;
+    let pid = std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    let count = std::env::var("LISTEN_FDS").ok().and_then(|count| count.parse::<RawFd>().ok()).unwrap_or(0);
+    if pid != Some(std::process::id()) || count <= 0 {
+        return Vec::new();
+    }
+    let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut names = names.split(':');
+    (FIRST..FIRST + count).map(|fd| {
+        // As sd_listen_fds does, so child processes do not inherit them
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        let name = names.next().filter(|name| !name.is_empty()).unwrap_or("unknown").to_string();
+        (name, Some(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }).collect()
+}
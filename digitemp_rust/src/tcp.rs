@@ -0,0 +1,93 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use chrono::{DateTime, Local};
+use crate::api::{self, ApiSink, LineFormat, SensorView};
+use crate::config::parse_rom;
+use crate::format::{self, FormatContext};
+use crate::systemd;
+
+// A connection is closed after this long without a command
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Line commands over TCP for old monitoring scripts and netcat. Each command is answered
+// with a single line:
+//   LIST       the enabled sensors' names, comma-separated
+//   READ ID    the sensor's last reading, formatted as `read` prints it (LOG_FORMAT)
+//   QUIT       close the connection
+// Commands are case-insensitive. Failures answer "ERROR " and the reason. A bare port
+// listens on every interface.
+pub fn start(address: &str, sink: ApiSink) -> Result<(), String> {
+    let listener = systemd::activated_tcp(address, "tcp").unwrap_or_else(|| {
+        let address = match address.parse::<u16>() {
+            Ok(port) => format!("0.0.0.0:{}", port),
+            Err(_) => address.to_string(),
+        };
+        TcpListener::bind(&address).map_err(|e| format!("Cannot listen on {}: {}", address, e))
+    })?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let sink = sink.clone();
+            thread::spawn(move || {
+                if let Err(e) = handle(stream, &sink) {
+                    if !matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            | io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset) {
+                        eprintln!("TCP: {}", e);
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn handle(stream: TcpStream, sink: &ApiSink) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    stream.set_write_timeout(Some(IDLE_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let line = line.trim();
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+        let reply = match (command.to_ascii_uppercase().as_str(), argument) {
+            ("", _) => continue,
+            ("QUIT", _) => return Ok(()),
+            ("LIST", "") => Ok(sink.views().iter().filter(|view| view.enabled).map(name).collect::<Vec<_>>().join(",")),
+            ("READ", "") => Err("READ needs a sensor".to_string()),
+            ("READ", id) => match api::find(&sink.views(), id) {
+                Some(view) => reading(view, &sink.line_format()),
+                None => Err(format!("no sensor '{}'", id)),
+            },
+            (command, _) => Err(format!("unknown command '{}'", command)),
+        };
+        let reply = reply.unwrap_or_else(|e| format!("ERROR {}", e));
+        writer.write_all(format!("{}\n", reply).as_bytes())?;
+    }
+    Ok(())
+}
+
+// As %s of LOG_FORMAT: the label, or the index
+fn name(view: &SensorView) -> String {
+    view.label.clone().unwrap_or_else(|| view.sensor.to_string())
+}
+
+fn reading(view: &SensorView, line_format: &LineFormat) -> Result<String, String> {
+    let Some(celsius) = view.celsius else {
+        return Err(view.error.clone().unwrap_or_else(|| "no reading yet".to_string()));
+    };
+    let rom = parse_rom(&view.rom)?;
+    let time = view.timestamp.as_deref().and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        .map_or_else(Local::now, |time| time.with_timezone(&Local));
+    Ok(format::render(&line_format.template, &FormatContext {
+        sensor: &name(view),
+        rom: &rom,
+        celsius: celsius as f32,
+        time,
+        decimals: line_format.decimals,
+    }))
+}
@@ -0,0 +1,160 @@
+use std::path::Path;
+use minijinja::Environment;
+use serde::Serialize;
+use crate::config::{format_rom, SensorConfig};
+use crate::sink::Destination;
+use crate::{json, Reading};
+
+const READING: &str = "reading";
+const CYCLE: &str = "cycle";
+
+// What a per-reading template sees; the cycle template gets a list of these
+#[derive(Serialize)]
+struct ReadingContext {
+    timestamp: String,
+    unix: i64,
+    bus: String,
+    index: usize,
+    rom: String,
+    label: Option<String>,
+    // The label, else the ROM
+    name: String,
+    celsius: f64,
+    fahrenheit: f64,
+    raw_celsius: f64,
+    retries: u32,
+    crc_failures: u32,
+    conversion_ms: u128,
+}
+
+// A sensor whose read failed or was discarded in this pass
+#[derive(Serialize)]
+struct ErrorContext {
+    bus: String,
+    index: usize,
+    rom: String,
+    label: Option<String>,
+    name: String,
+}
+
+#[derive(Serialize)]
+struct CycleContext<'a> {
+    timestamp: String,
+    unix: i64,
+    readings: &'a [ReadingContext],
+    errors: &'a [ErrorContext],
+}
+
+// User-defined output: a minijinja template rendered for every accepted reading, and/or
+// one rendered after every pass with all of its readings. Empty renders write nothing.
+pub struct TemplateSink {
+    env: Environment<'static>,
+    destination: Destination,
+    has_reading: bool,
+    has_cycle: bool,
+    readings: Vec<ReadingContext>,
+    errors: Vec<ErrorContext>,
+    // Report a failing template once, not on every reading
+    failing: bool,
+}
+
+impl TemplateSink {
+    pub fn open(path: Option<&Path>, reading: Option<&str>, cycle: Option<&str>) -> Result<Self, String> {
+        if reading.is_none() && cycle.is_none() {
+            return Err("A template sink needs a reading or a cycle template".to_string());
+        }
+        let mut env = Environment::new();
+        // `{{ celsius | fixed(1) }}` always shows that many decimals, unlike round
+        env.add_filter("fixed", |value: f64, decimals: usize| format!("{:.*}", decimals, value));
+        for (name, source) in [(READING, reading), (CYCLE, cycle)] {
+            if let Some(source) = source {
+                env.add_template_owned(name, source.to_string())
+                    .map_err(|e| format!("Invalid {} template: {}", name, e))?;
+            }
+        }
+        let destination = Destination::open(path, None)
+            .map_err(|e| format!("Cannot open template sink {}: {}", path.unwrap_or(Path::new("-")).display(), e))?;
+        Ok(TemplateSink {
+            env,
+            destination,
+            has_reading: reading.is_some(),
+            has_cycle: cycle.is_some(),
+            readings: Vec::new(),
+            errors: Vec::new(),
+            failing: false,
+        })
+    }
+
+    pub fn uses_stdout(&self) -> bool {
+        matches!(self.destination, Destination::Stdout)
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading,
+                   decimals: Option<usize>) {
+        let context = ReadingContext {
+            timestamp: json::timestamp(),
+            unix: chrono::Utc::now().timestamp(),
+            bus: bus.to_string(),
+            index,
+            rom: format_rom(&sensor.rom),
+            label: sensor.label.clone(),
+            name: sensor.name(),
+            celsius: json::round(reading.celsius, decimals),
+            fahrenheit: json::round(reading.celsius * 9.0 / 5.0 + 32.0, decimals),
+            raw_celsius: json::round(reading.raw_celsius, decimals),
+            retries: reading.retries,
+            crc_failures: reading.crc_failures,
+            conversion_ms: reading.conversion_time.as_millis(),
+        };
+        if self.has_reading {
+            self.render(READING, &context);
+        }
+        if self.has_cycle {
+            self.readings.push(context);
+        }
+    }
+
+    pub fn error(&mut self, bus: &str, index: usize, sensor: &SensorConfig) {
+        if self.has_cycle {
+            self.errors.push(ErrorContext {
+                bus: bus.to_string(),
+                index,
+                rom: format_rom(&sensor.rom),
+                label: sensor.label.clone(),
+                name: sensor.name(),
+            });
+        }
+    }
+
+    pub fn pass_done(&mut self) {
+        if !self.has_cycle {
+            return;
+        }
+        let readings = std::mem::take(&mut self.readings);
+        let errors = std::mem::take(&mut self.errors);
+        self.render(CYCLE, &CycleContext {
+            timestamp: json::timestamp(),
+            unix: chrono::Utc::now().timestamp(),
+            readings: &readings,
+            errors: &errors,
+        });
+    }
+
+    fn render<S: Serialize>(&mut self, name: &str, context: S) {
+        let rendered = self.env.get_template(name).and_then(|template| template.render(context));
+        match rendered {
+            Ok(text) => {
+                self.failing = false;
+                if !text.is_empty() {
+                    self.destination.write_line(&text);
+                }
+            }
+            Err(e) => {
+                if !self.failing {
+                    eprintln!("Cannot render {} template: {}", name, e);
+                }
+                self.failing = true;
+            }
+        }
+    }
+}
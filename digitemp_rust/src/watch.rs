@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::Constraint;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Block, Borders, Cell, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use crate::config::Unit;
+use crate::format::format_temperature;
+
+// Readings kept per sensor for the sparkline
+const HISTORY: usize = 40;
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+struct SensorRow {
+    label: String,
+    rom: String,
+    current: Option<f32>,
+    min: Option<f32>,
+    max: Option<f32>,
+    history: VecDeque<f32>,
+    // Set by a failed read, cleared by the next good one
+    failed: bool,
+    last_error: Option<(DateTime<Local>, String)>,
+}
+
+// State of the `watch` dashboard: one row per sensor, temperatures in Celsius
+pub struct Dashboard {
+    rows: Vec<SensorRow>,
+    unit: Unit,
+    decimals: usize,
+    interval: Duration,
+    // Row whose sensor is being read right now
+    reading: Option<usize>,
+}
+
+impl Dashboard {
+    // `sensors` holds the label and ROM shown for each row
+    pub fn new(sensors: Vec<(String, String)>, unit: Unit, decimals: usize, interval: Duration) -> Self {
+        let rows = sensors.into_iter().map(|(label, rom)| SensorRow {
+            label,
+            rom,
+            current: None,
+            min: None,
+            max: None,
+            history: VecDeque::with_capacity(HISTORY),
+            failed: false,
+            last_error: None,
+        }).collect();
+        Dashboard { rows, unit, decimals, interval, reading: None }
+    }
+
+    fn record(&mut self, row: usize, result: Result<f32, String>) {
+        let row = &mut self.rows[row];
+        match result {
+            Ok(celsius) => {
+                row.current = Some(celsius);
+                row.min = Some(row.min.map_or(celsius, |min| min.min(celsius)));
+                row.max = Some(row.max.map_or(celsius, |max| max.max(celsius)));
+                if row.history.len() == HISTORY {
+                    row.history.pop_front();
+                }
+                row.history.push_back(celsius);
+                row.failed = false;
+            }
+            Err(message) => {
+                row.failed = true;
+                row.last_error = Some((Local::now(), message));
+            }
+        }
+    }
+
+    fn temperature(&self, celsius: Option<f32>) -> String {
+        celsius.map_or_else(|| "-".to_string(), |c| format_temperature(c, self.unit, self.decimals))
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let header = Row::new(["Sensor", "ROM", "Current", "Min", "Max", "History", "Last error"])
+            .style(Style::default().add_modifier(Modifier::BOLD));
+        let rows = self.rows.iter().enumerate().map(|(i, row)| {
+            let current = Cell::from(self.temperature(row.current)).style(if row.failed {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default()
+            });
+            let error = row.last_error.as_ref()
+                .map(|(time, message)| format!("{} {}", time.format("%H:%M:%S"), message))
+                .unwrap_or_default();
+            let style = if self.reading == Some(i) {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::default()
+            };
+            Row::new([
+                Cell::from(row.label.as_str()),
+                Cell::from(row.rom.as_str()),
+                current,
+                Cell::from(self.temperature(row.min)),
+                Cell::from(self.temperature(row.max)),
+                Cell::from(sparkline(&row.history)).style(Style::default().fg(Color::Cyan)),
+                Cell::from(error).style(Style::default().fg(Color::Red)),
+            ]).style(style)
+        });
+
+        let widths = [
+            Constraint::Length(12),
+            Constraint::Length(16),
+            Constraint::Length(15),
+            Constraint::Length(15),
+            Constraint::Length(15),
+            Constraint::Length(HISTORY as u16),
+            Constraint::Min(20),
+        ];
+        let title = format!(" digitemp watch: {} sensor(s), every {}s, q to quit ",
+            self.rows.len(), self.interval.as_secs_f32());
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::default().borders(Borders::ALL).title(title));
+        frame.render_widget(table, frame.area());
+    }
+}
+
+// Recent values as block characters scaled between their minimum and maximum
+fn sparkline(history: &VecDeque<f32>) -> String {
+    let low = history.iter().copied().fold(f32::INFINITY, f32::min);
+    let high = history.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let span = high - low;
+    history.iter().map(|&value| {
+        let level = if span > 0.0 { ((value - low) / span * 7.0).round() as usize } else { 3 };
+        BARS[level.min(7)]
+    }).collect()
+}
+
+// Show the dashboard until q, Esc or Ctrl-C, reading every sensor once per interval.
+// `read` reads the sensor of one row and returns its temperature or an error message.
+pub fn run(mut dashboard: Dashboard, mut read: impl FnMut(usize) -> Result<f32, String>) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = event_loop(&mut terminal, &mut dashboard, &mut read);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, dashboard: &mut Dashboard,
+              read: &mut impl FnMut(usize) -> Result<f32, String>) -> io::Result<()> {
+    loop {
+        let started = Instant::now();
+        for row in 0..dashboard.rows.len() {
+            dashboard.reading = Some(row);
+            terminal.draw(|frame| dashboard.draw(frame))?;
+            if quit_requested(Duration::ZERO)? {
+                return Ok(());
+            }
+            let result = read(row);
+            dashboard.record(row, result);
+        }
+        dashboard.reading = None;
+
+        // Wait out the interval, redrawing on resizes and other events
+        let deadline = started + dashboard.interval;
+        loop {
+            terminal.draw(|frame| dashboard.draw(frame))?;
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            if quit_requested(deadline - now)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Wait up to `timeout` for an event; true when it is a quit key
+fn quit_requested(timeout: Duration) -> io::Result<bool> {
+    if !event::poll(timeout)? {
+        return Ok(false);
+    }
+    Ok(match event::read()? {
+        Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => true,
+            KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+            _ => false,
+        },
+        _ => false,
+    })
+}
@@ -0,0 +1,70 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use serialport::SerialPort;
+use crate::exit_code;
+
+struct State {
+    armed: Option<(Instant, &'static str)>,
+    tripped: bool,
+    // Cloned handle used to flush the port while the owner is blocked on it
+    port: Box<dyn SerialPort>,
+}
+
+// Watches bus operations from a separate thread. An operation running past
+// the limit gets its port flushed and is reported as tripped so the owner can
+// reopen the port; one still blocked after twice the limit aborts the process. The thread
+// ends, and lets go of the port, once the Watchdog is dropped.
+pub struct Watchdog {
+    state: Arc<Mutex<State>>,
+}
+
+impl Watchdog {
+    pub fn spawn(limit: Duration, port: Box<dyn SerialPort>) -> Self {
+        let state = Arc::new(Mutex::new(State { armed: None, tripped: false, port }));
+        let watched = Arc::downgrade(&state);
+        let tick = (limit / 10).max(Duration::from_millis(50));
+
+        thread::spawn(move || loop {
+            thread::sleep(tick);
+            let Some(watched) = watched.upgrade() else { break };
+            let mut state = watched.lock().unwrap();
+            let (started, operation) = match state.armed {
+                Some(armed) => armed,
+                None => continue,
+            };
+
+            let elapsed = started.elapsed();
+            if elapsed >= limit * 2 {
+                eprintln!("Watchdog: {} still blocked after {:.1}s, aborting",
+                    operation, elapsed.as_secs_f32());
+                std::process::exit(exit_code::WATCHDOG);
+            }
+            if elapsed >= limit && !state.tripped {
+                eprintln!("Watchdog: {} blocked for {:.1}s, flushing port",
+                    operation, elapsed.as_secs_f32());
+                state.tripped = true;
+                let _ = state.port.clear(serialport::ClearBuffer::All);
+            }
+        });
+
+        Watchdog { state }
+    }
+
+    pub fn arm(&self, operation: &'static str) {
+        let mut state = self.state.lock().unwrap();
+        state.armed = Some((Instant::now(), operation));
+        state.tripped = false;
+    }
+
+    // Returns true if the operation overran the limit while armed
+    pub fn disarm(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.armed = None;
+        std::mem::take(&mut state.tripped)
+    }
+
+    pub fn replace_port(&self, port: Box<dyn SerialPort>) {
+        self.state.lock().unwrap().port = port;
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use ureq::Agent;
+use crate::config::{SensorConfig, WebhookEach};
+use crate::{json, sink, Reading};
+
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(10);
+// Requests waiting for a URL; past this, new ones go to the dead-letter log at once
+const QUEUE: usize = 1000;
+// How often a backoff checks whether the sink is closing
+const BACKOFF_STEP: Duration = Duration::from_millis(100);
+
+// How failed requests are retried, and where they end up
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub backoff: Duration,
+    pub dead_letter: Option<PathBuf>,
+}
+
+// A request body with its expanded headers
+struct Delivery {
+    body: String,
+    headers: Vec<(String, String)>,
+}
+
+// Why a request failed, and whether sending it again may help
+struct Failure {
+    message: String,
+    retry: bool,
+}
+
+struct Worker {
+    url: String,
+    sender: Option<SyncSender<Delivery>>,
+    thread: Option<JoinHandle<()>>,
+    // Report a full queue once, not for every request
+    dropping: bool,
+}
+
+// Webhooks: every reading, or every pass as a JSON array, is POSTed as JSON to each URL.
+// Header values are templates as for zabbix, expanded per reading, and take {env:NAME}
+// from the environment. Each URL has a thread and a queue of its own, so a slow or failing
+// endpoint never holds up a pass or the other URLs. Failed requests are retried with
+// exponential backoff; those still failing after `retries`, and those the endpoint rejects
+// with a 4xx status, are appended to the dead-letter log when there is one.
+pub struct WebhookSink {
+    each: WebhookEach,
+    headers: BTreeMap<String, String>,
+    workers: Vec<Worker>,
+    // Records of the current pass, for each = "pass"
+    records: Vec<String>,
+    policy: Arc<RetryPolicy>,
+    // Set when the sink is dropped: queued requests get one attempt, and no more backoff
+    closing: Arc<AtomicBool>,
+}
+
+impl WebhookSink {
+    pub fn new(urls: &[String], each: WebhookEach, headers: &BTreeMap<String, String>, policy: RetryPolicy)
+               -> Result<Self, String> {
+        if urls.is_empty() {
+            return Err("Webhook sinks need at least one url".to_string());
+        }
+        if let Some(url) = urls.iter().find(|url| !url.starts_with("http://") && !url.starts_with("https://")) {
+            return Err(format!("Invalid webhook url '{}' (expected e.g. https://hooks.lan/readings)", url));
+        }
+        let agent = Agent::new_with_config(Agent::config_builder()
+            .timeout_global(Some(NETWORK_TIMEOUT))
+            // Status codes are sorted into retried and rejected below
+            .http_status_as_error(false)
+            .build());
+        let policy = Arc::new(policy);
+        let closing = Arc::new(AtomicBool::new(false));
+        let workers = urls.iter().map(|url| {
+            let (sender, receiver) = mpsc::sync_channel(QUEUE);
+            let (agent, url_, policy, closing) = (agent.clone(), url.clone(), policy.clone(), closing.clone());
+            let thread = thread::spawn(move || deliver(&agent, &url_, receiver, &policy, &closing));
+            Worker { url: url.clone(), sender: Some(sender), thread: Some(thread), dropping: false }
+        }).collect();
+        Ok(WebhookSink { each, headers: headers.clone(), workers, records: Vec::new(), policy, closing })
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading,
+                   decimals: Option<usize>) {
+        let record = json::jsonl_reading(bus, index, sensor, reading, decimals);
+        match self.each {
+            WebhookEach::Reading => {
+                let headers = self.headers.iter()
+                    .map(|(name, value)| (name.clone(), expand_env(&sink::expand(value, bus, index, sensor))))
+                    .collect();
+                self.send(record, headers);
+            }
+            WebhookEach::Pass => self.records.push(record),
+        }
+    }
+
+    pub fn pass_done(&mut self) {
+        if self.each != WebhookEach::Pass || self.records.is_empty() {
+            return;
+        }
+        let body = format!("[{}]", std::mem::take(&mut self.records).join(","));
+        let headers = self.headers.iter().map(|(name, value)| (name.clone(), expand_env(value))).collect();
+        self.send(body, headers);
+    }
+
+    fn send(&mut self, body: String, headers: Vec<(String, String)>) {
+        for worker in &mut self.workers {
+            let Some(sender) = &worker.sender else { continue };
+            match sender.try_send(Delivery { body: body.clone(), headers: headers.clone() }) {
+                Ok(()) => worker.dropping = false,
+                Err(TrySendError::Full(delivery) | TrySendError::Disconnected(delivery)) => {
+                    if !worker.dropping {
+                        eprintln!("Webhook {}: the endpoint is not keeping up, requests go to the dead-letter log",
+                            worker.url);
+                    }
+                    worker.dropping = true;
+                    dead_letter(&self.policy, &worker.url, "queue full", &delivery.body);
+                }
+            }
+        }
+    }
+}
+
+// Stopping or reloading sends what is queued, without waiting out backoffs
+impl Drop for WebhookSink {
+    fn drop(&mut self) {
+        self.pass_done();
+        self.closing.store(true, Ordering::Relaxed);
+        for worker in &mut self.workers {
+            worker.sender = None;
+        }
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+fn deliver(agent: &Agent, url: &str, receiver: Receiver<Delivery>, policy: &RetryPolicy, closing: &AtomicBool) {
+    // Report a failing endpoint once, not for every request
+    let mut failing = false;
+    for delivery in receiver {
+        let mut backoff = policy.backoff;
+        let mut attempt = 0;
+        loop {
+            match post(agent, url, &delivery) {
+                Ok(()) => {
+                    if failing {
+                        eprintln!("Webhook {}: delivering again", url);
+                        failing = false;
+                    }
+                    break;
+                }
+                Err(failure) => {
+                    if !failing {
+                        eprintln!("Webhook {}: {}", url, failure.message);
+                        failing = true;
+                    }
+                    if !failure.retry || attempt >= policy.retries || closing.load(Ordering::Relaxed) {
+                        dead_letter(policy, url, &failure.message, &delivery.body);
+                        break;
+                    }
+                    let until = Instant::now() + backoff;
+                    while Instant::now() < until && !closing.load(Ordering::Relaxed) {
+                        thread::sleep(BACKOFF_STEP.min(until - Instant::now()));
+                    }
+                    backoff = backoff.saturating_mul(2);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+fn post(agent: &Agent, url: &str, delivery: &Delivery) -> Result<(), Failure> {
+    let mut request = agent.post(url).content_type("application/json");
+    for (name, value) in &delivery.headers {
+        request = request.header(name, value);
+    }
+    let response = request.send(&delivery.body)
+        .map_err(|e| Failure { message: e.to_string(), retry: true })?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    // Timeouts and rate limits pass; other client errors would be rejected again
+    let retry = !status.is_client_error() || matches!(status.as_u16(), 408 | 429);
+    Err(Failure { message: format!("HTTP {}", status), retry })
+}
+
+fn dead_letter(policy: &RetryPolicy, url: &str, error: &str, body: &str) {
+    let Some(path) = &policy.dead_letter else { return };
+    let entry = serde_json::json!({
+        "timestamp": json::timestamp(),
+        "url": url,
+        "error": error,
+        "body": serde_json::from_str::<serde_json::Value>(body).unwrap_or_else(|_| body.into()),
+    });
+    if let Err(e) = append_line(path, &json::to_line(&entry)) {
+        eprintln!("Webhook {}: cannot write dead-letter log {}: {}", url, path.display(), e);
+    }
+}
+
+fn append_line(path: &Path, line: &str) -> std::io::Result<()> {
+    // One write per line, so the threads of several URLs do not interleave them
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(format!("{}\n", line).as_bytes())
+}
+
+// {env:NAME} is the environment variable, or empty when unset
+fn expand_env(template: &str) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{env:") {
+        let Some(end) = rest[start..].find('}') else { break };
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&std::env::var(&rest[start + 5..start + end]).unwrap_or_default());
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
@@ -0,0 +1,129 @@
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use crate::api::Event;
+
+// From RFC 6455, appended to the client's key for the accept hash
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// An idle connection is pinged, so dead clients are noticed and proxies keep it open
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+// How often client frames are checked for while no messages come
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+// Longest client frame accepted; clients only send control frames and short messages
+const MAX_FRAME: u64 = 65536;
+
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+// Value of Sec-WebSocket-Accept for a Sec-WebSocket-Key
+pub fn accept_key(key: &str) -> String {
+    let digest = Sha1::digest(format!("{}{}", key.trim(), ACCEPT_GUID).as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(digest)
+}
+
+// Stream the records of `events` to a client after the 101 response, one text frame each,
+// until either side closes. Client frames are read on a second thread: pings are answered,
+// and anything else but a close is ignored.
+pub fn stream(mut stream: TcpStream, reader: BufReader<TcpStream>, events: Receiver<Event>) -> io::Result<()> {
+    stream.set_read_timeout(None)?;
+    let (control, frames) = mpsc::channel();
+    thread::spawn(move || read_frames(reader, control));
+    let result = pump(&mut stream, &events, &frames);
+    // Also ends the reading thread
+    let _ = stream.shutdown(Shutdown::Both);
+    result
+}
+
+fn pump(stream: &mut TcpStream, events: &Receiver<Event>, frames: &Receiver<(u8, Vec<u8>)>) -> io::Result<()> {
+    let mut last_sent = Instant::now();
+    loop {
+        // Client frames first, so a close is seen before more messages go out
+        match frames.try_recv() {
+            Ok((OP_PING, payload)) => write_frame(stream, OP_PONG, &payload)?,
+            Ok((OP_CLOSE, payload)) => {
+                // Echo the status code, as the closing handshake expects
+                return write_frame(stream, OP_CLOSE, &payload[..payload.len().min(2)]);
+            }
+            Ok(_) | Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => return Ok(()),
+        }
+        match events.recv_timeout(POLL_INTERVAL) {
+            Ok((_, data)) => write_frame(stream, OP_TEXT, data.as_bytes())?,
+            Err(RecvTimeoutError::Timeout) if last_sent.elapsed() >= PING_INTERVAL => {
+                write_frame(stream, OP_PING, b"")?
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            // The server is going away
+            Err(RecvTimeoutError::Disconnected) => return write_frame(stream, OP_CLOSE, &1001u16.to_be_bytes()),
+        }
+        last_sent = Instant::now();
+    }
+}
+
+fn read_frames(mut reader: BufReader<TcpStream>, control: Sender<(u8, Vec<u8>)>) {
+    while let Ok(frame) = read_frame(&mut reader) {
+        let close = frame.0 == OP_CLOSE;
+        if control.send(frame).is_err() || close {
+            return;
+        }
+    }
+}
+
+// One client frame, unmasked; fragments are returned as they come, as only control frames matter
+fn read_frame(reader: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head)?;
+    let opcode = head[0] & 0x0F;
+    let length = match head[1] & 0x7F {
+        126 => {
+            let mut length = [0u8; 2];
+            reader.read_exact(&mut length)?;
+            u16::from_be_bytes(length) as u64
+        }
+        127 => {
+            let mut length = [0u8; 8];
+            reader.read_exact(&mut length)?;
+            u64::from_be_bytes(length)
+        }
+        length => length as u64,
+    };
+    if length > MAX_FRAME {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+    }
+    // Client frames are always masked
+    let mut mask = [0u8; 4];
+    if head[1] & 0x80 != 0 {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0u8; length as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((opcode, payload))
+}
+
+// A final, unmasked server frame
+fn write_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        length @ 0..=125 => frame.push(length as u8),
+        length @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
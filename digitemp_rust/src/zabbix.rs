@@ -0,0 +1,139 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::config::SensorConfig;
+use crate::sink;
+use crate::spool::{self, Spool};
+
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(5);
+const HEADER: &[u8; 5] = b"ZBXD\x01";
+// Zabbix rejects larger packets; a pass never comes close
+const MAX_RESPONSE: u64 = 1 << 20;
+
+#[derive(Serialize)]
+struct SenderData<'a> {
+    request: &'static str,
+    data: &'a [Item],
+    clock: i64,
+}
+
+// One trapper item value, spooled as a JSON line
+#[derive(Serialize, Deserialize)]
+struct Item {
+    host: String,
+    key: String,
+    value: String,
+    clock: i64,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    response: String,
+    #[serde(default)]
+    info: String,
+}
+
+// Zabbix sender (trapper) protocol, as zabbix_sender speaks it. Host and key are
+// templates expanded per sensor; the values of a pass go out in one request. Values that
+// cannot be delivered are dropped, or kept in the spool and replayed when there is one.
+pub struct ZabbixSink {
+    server: String,
+    host: String,
+    key: String,
+    items: Vec<Item>,
+    spool: Option<Spool>,
+    // Report a failing server once, not on every pass
+    failing: bool,
+}
+
+impl ZabbixSink {
+    pub fn new(server: &str, host: &str, key: &str, spool: Option<Spool>) -> Self {
+        ZabbixSink { server: server.to_string(), host: host.to_string(), key: key.to_string(), items: Vec::new(),
+                     spool, failing: false }
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, celsius: f32) {
+        self.items.push(Item {
+            host: sink::expand(&self.host, bus, index, sensor),
+            key: sink::expand(&self.key, bus, index, sensor),
+            value: celsius.to_string(),
+            clock: chrono::Utc::now().timestamp(),
+        });
+    }
+
+    pub fn pass_done(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
+        let items = std::mem::take(&mut self.items);
+        let sent = match self.spool.as_mut() {
+            Some(spool) => {
+                let records = items.iter().filter_map(|item| serde_json::to_string(item).ok()).collect();
+                spool.deliver(records, |records| {
+                    let items: Vec<Item> = records.iter().filter_map(|record| serde_json::from_str(record).ok())
+                        .collect();
+                    send_items(&self.server, &items)
+                })
+            }
+            None => send_items(&self.server, &items),
+        };
+        match sent {
+            Ok(()) if self.failing => {
+                eprintln!("Zabbix {}: delivering again", self.server);
+                self.failing = false;
+            }
+            Ok(()) => {}
+            Err(e) => {
+                if !self.failing {
+                    eprintln!("Zabbix {}: {} ({})", self.server, e, spool::kept(self.spool.as_ref()));
+                }
+                self.failing = true;
+            }
+        }
+    }
+}
+
+// A response other than success is reported but counts as delivered, as sending the same
+// values again would not change it
+fn send_items(server: &str, items: &[Item]) -> io::Result<()> {
+    match send(server, items)? {
+        // "processed: 2; failed: 1; total: 3; ..." - failed items have no matching trapper item
+        response if response.response == "success" => {
+            if !response.info.contains("failed: 0") {
+                eprintln!("Zabbix {}: {}", server, response.info);
+            }
+        }
+        response => eprintln!("Zabbix {}: {} {}", server, response.response, response.info),
+    }
+    Ok(())
+}
+
+fn send(server: &str, items: &[Item]) -> io::Result<Response> {
+    let request = SenderData { request: "sender data", data: items, clock: chrono::Utc::now().timestamp() };
+    let body = serde_json::to_vec(&request).map_err(io::Error::other)?;
+
+    let address = server.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address did not resolve"))?;
+    let mut stream = TcpStream::connect_timeout(&address, NETWORK_TIMEOUT)?;
+    stream.set_read_timeout(Some(NETWORK_TIMEOUT))?;
+    stream.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+
+    let mut packet = HEADER.to_vec();
+    packet.extend_from_slice(&(body.len() as u64).to_le_bytes());
+    packet.extend_from_slice(&body);
+    stream.write_all(&packet)?;
+
+    let mut header = [0u8; 13];
+    stream.read_exact(&mut header)?;
+    if &header[..5] != HEADER {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a Zabbix response"));
+    }
+    let length = u64::from_le_bytes(header[5..].try_into().expect("8 length bytes"));
+    if length > MAX_RESPONSE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "oversized Zabbix response"));
+    }
+    let mut body = vec![0u8; length as usize];
+    stream.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use crate::config::SensorConfig;
+use crate::{json, sink, Reading};
+
+// How long closing a socket waits for messages still queued, e.g. to an unreachable proxy
+const LINGER_MS: i32 = 1000;
+
+type SharedSocket = Arc<Mutex<zmq::Socket>>;
+
+// Bound sockets by endpoint. A reload opens the new sinks before the old ones are dropped,
+// so a sink that binds the same endpoint again takes over the socket, and subscribers stay
+// connected, instead of failing with the address in use.
+fn bound() -> &'static Mutex<HashMap<String, Weak<Mutex<zmq::Socket>>>> {
+    static BOUND: OnceLock<Mutex<HashMap<String, Weak<Mutex<zmq::Socket>>>>> = OnceLock::new();
+    BOUND.get_or_init(Default::default)
+}
+
+// ZeroMQ PUB socket: every reading is a two-part message, the topic (a per-sensor template,
+// the label by default) and the jsonl record, so SUB sockets can subscribe to sensors by
+// prefix. The socket binds `endpoint`, or connects to it, e.g. to an XSUB proxy. As with
+// any PUB socket, subscribers that are not connected miss the messages.
+pub struct ZeromqSink {
+    socket: SharedSocket,
+    endpoint: String,
+    topic: String,
+    // Report a failing socket once, not for every reading
+    failing: bool,
+}
+
+impl ZeromqSink {
+    pub fn open(endpoint: &str, connect: bool, topic: &str) -> Result<Self, String> {
+        let socket = if connect { open_socket(endpoint, true)? } else { bind(endpoint)? };
+        Ok(ZeromqSink { socket, endpoint: endpoint.to_string(), topic: topic.to_string(), failing: false })
+    }
+
+    pub fn reading(&mut self, bus: &str, index: usize, sensor: &SensorConfig, reading: &Reading,
+                   decimals: Option<usize>) {
+        let topic = sink::expand(&self.topic, bus, index, sensor);
+        let payload = json::jsonl_reading(bus, index, sensor, reading, decimals);
+        let socket = self.socket.lock().unwrap_or_else(|e| e.into_inner());
+        // A PUB socket never blocks: messages past the high-water mark of a peer are dropped
+        match socket.send_multipart([topic.as_bytes(), payload.as_bytes()], zmq::DONTWAIT) {
+            Ok(()) => self.failing = false,
+            Err(e) => {
+                if !self.failing {
+                    eprintln!("ZeroMQ {}: {}", self.endpoint, e);
+                }
+                self.failing = true;
+            }
+        }
+    }
+}
+
+fn bind(endpoint: &str) -> Result<SharedSocket, String> {
+    let mut bound = bound().lock().unwrap_or_else(|e| e.into_inner());
+    bound.retain(|_, socket| socket.strong_count() > 0);
+    if let Some(socket) = bound.get(endpoint).and_then(Weak::upgrade) {
+        return Ok(socket);
+    }
+    let socket = open_socket(endpoint, false)?;
+    bound.insert(endpoint.to_string(), Arc::downgrade(&socket));
+    Ok(socket)
+}
+
+fn open_socket(endpoint: &str, connect: bool) -> Result<SharedSocket, String> {
+    let error = |e: zmq::Error| format!("Cannot open ZeroMQ socket on {}: {}", endpoint, e);
+    // Sockets keep their context alive
+    let socket = zmq::Context::new().socket(zmq::PUB).map_err(error)?;
+    socket.set_linger(LINGER_MS).map_err(error)?;
+    if connect {
+        socket.connect(endpoint).map_err(error)?;
+    } else {
+        socket.bind(endpoint).map_err(error)?;
+    }
+    Ok(Arc::new(Mutex::new(socket)))
+}
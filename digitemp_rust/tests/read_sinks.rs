@@ -0,0 +1,24 @@
+use std::fs;
+use std::process::Command;
+
+// `read` of a single sensor prints just its value, and still feeds the configured sinks
+#[test]
+fn single_sensor_read_reaches_sinks() {
+    let dir = std::env::temp_dir().join(format!("digitemp-read-sinks-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (config, jsonl) = (dir.join("digitemp.toml"), dir.join("readings.jsonl"));
+    fs::write(&config, format!("[[sensors]]\nrom = \"2853494D000100CA\"\nlabel = \"freezer\"\n\n\
+        [[sinks]]\ntype = \"jsonl\"\npath = {:?}\n", jsonl)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_digitemp_native"))
+        .args(["--simulate", "3", "--config"]).arg(&config).args(["read", "freezer"])
+        .env("XDG_STATE_HOME", &dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let written = fs::read_to_string(&jsonl).unwrap();
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(written.lines().count(), 1, "{}", written);
+    assert!(written.contains("\"rom\":\"2853494D000100CA\""), "{}", written);
+}